@@ -7,6 +7,7 @@ use uefi::{
     },
 };
 use alloc::string::String;
+use core::fmt;
 
 pub struct FileBuffer {
     buffer: String
@@ -20,7 +21,7 @@ impl FileBuffer {
     }
 
     pub fn write(&mut self, content: &str) {
-        self.buffer = format!("{}{}", self.buffer, content);
+        self.buffer.push_str(content);
     }
 
     pub fn writeln(&mut self, content: &str) {
@@ -32,6 +33,13 @@ impl FileBuffer {
     }
 }
 
+impl fmt::Write for FileBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write(s);
+        return Ok(());
+    }
+}
+
 pub fn open_root(bt: &BootServices, handler: Handle) -> FileSystem {
     let fs = bt.get_image_file_system(handler).expect("can't open filesystem protocol");
     fs
@@ -40,13 +48,13 @@ pub fn open_root(bt: &BootServices, handler: Handle) -> FileSystem {
 #[macro_export]
 macro_rules! fwrite {
     ($file:expr, $format:tt $( $rest:tt )*) => {
-        $file.write(format!($format $( $rest )*))
+        core::fmt::Write::write_fmt($file, format_args!($format $( $rest )*)).unwrap()
     };
 }
 
 #[macro_export]
 macro_rules! fwriteln {
     ($file:expr, $format:tt $( $rest:tt )*) => {
-        fwrite!($file, concat!($format, "\n") $( $rest )*)
+        $crate::fwrite!($file, concat!($format, "\n") $( $rest )*)
     };
 }
\ No newline at end of file
@@ -5,9 +5,11 @@
 
 mod fb;
 mod file;
+mod symbol;
 
 use fb::*;
 use file::*;
+use symbol::collect_symbols;
 
 #[macro_use]
 extern crate alloc;
@@ -16,7 +18,7 @@ use alloc::{
     vec::Vec
 };
 extern crate libloader;
-use libloader::MemoryMap;
+use libloader::{MemoryMap, SymbolTable};
 use log::error;
 use goblin::elf;
 use core::{
@@ -105,7 +107,7 @@ fn efi_main(handler: Handle, st: SystemTable<Boot>) -> Status {
     mmap_file.flush(&mut fs, cstr16!("memmap"));
 
     //load kernel file
-    let entry_point_addr = load_kernel(&mut fs, &st);
+    let (entry_point_addr, symbols) = load_kernel(&mut fs, &st);
     drop(fs);
     let kernel_entry = unsafe {
         transmute::<
@@ -113,14 +115,15 @@ fn efi_main(handler: Handle, st: SystemTable<Boot>) -> Status {
             extern "sysv64" fn(
                 st: SystemTable<Runtime>,
                 fb_config: *mut FrameBufferConfig,
-                memmap: *const MemoryMap) -> (),
+                memmap: *const MemoryMap,
+                symbols: *const SymbolTable) -> (),
         >(entry_point_addr as *const ())
     };
 
     //exit bootservices and get MemoryMap
     let (st, memory_map) = exit_boot_services(st);
 
-    kernel_entry(st, &mut fb_config, &memory_map);
+    kernel_entry(st, &mut fb_config, &memory_map, &symbols);
     uefi::Status::SUCCESS
 }
 
@@ -128,22 +131,31 @@ fn dump(file: &mut FileBuffer, bt: &BootServices) {
     let max_mmap_size = bt.memory_map_size().map_size + BUFFER_MARGIN;
     let mut mmap_buf = vec![0; max_mmap_size];
     let memory_map = bt.memory_map(&mut mmap_buf).expect("failed to get memory map");
-    file.writeln("Index, Type, PhysicalStart, NumberOfPages, Attribute");
+    fwriteln!(file, "Index, Type, PhysicalStart, NumberOfPages, Attribute");
     for (i, d) in memory_map.entries().enumerate() {
-        file.writeln(&format!(
+        fwriteln!(
+            file,
             "{}, {:?}, {:08x}, {:x}, {:x}",
             i,
             d.ty,
             d.phys_start,
             d.page_count,
             d.att.bits() & 0xfffff
-        ))
+        );
     }
 }
 
-fn load_kernel(fs: &mut FileSystem, st: &SystemTable<Boot>) -> usize {
+// returns the entry point and the function symbols of the kernel
+fn load_kernel(fs: &mut FileSystem, st: &SystemTable<Boot>) -> (usize, SymbolTable) {
     //open kernel file
     let buf = fs.read(Path::new(&cstr16!("horse-kernel"))).expect("failed to read kernel file");
+    if let Err(e) = libloader::elf::validate(&buf) {
+        panic!("invalid kernel file: {:?}", e);
+    }
+    // the kernel is linked at fixed addresses
+    if libloader::elf::is_pie(&buf) {
+        panic!("the kernel must not be a PIE");
+    }
     let elf = elf::Elf::parse(&buf).expect("failed to parse ELF");
 
     //find kernel_start and kernel_end
@@ -178,7 +190,7 @@ fn load_kernel(fs: &mut FileSystem, st: &SystemTable<Boot>) -> usize {
         dest[fsize..].fill(0);
     }
 
-    return elf.entry as usize
+    return (elf.entry as usize, collect_symbols(&elf))
 }
 
 #[allow(dead_code)]
@@ -0,0 +1,101 @@
+use alloc::{
+    string::String,
+    vec::Vec
+};
+use goblin::elf::Elf;
+use libloader::{Symbol, SymbolTable};
+
+// collect the function symbols of the kernel for the backtraces.
+// the buffers are allocated from the pool, which is LOADER_DATA, so they survive exit_boot_services.
+// the table is empty when the kernel is stripped
+pub fn collect_symbols(elf: &Elf) -> SymbolTable {
+    let mut symbols = Vec::new();
+    let mut names = Vec::new();
+    for sym in elf.syms.iter() {
+        if !sym.is_function() || sym.st_value == 0 {
+            continue;
+        }
+        let name = match elf.strtab.get_at(sym.st_name) {
+            Some(name) if !name.is_empty() => demangle(name),
+            _ => continue
+        };
+        symbols.push(Symbol {
+            addr: sym.st_value,
+            size: sym.st_size,
+            name_offset: names.len() as u32,
+            name_len: name.len() as u32,
+        });
+        names.extend_from_slice(name.as_bytes());
+    }
+    if symbols.is_empty() {
+        return SymbolTable::EMPTY;
+    }
+    symbols.sort_unstable_by_key(|symbol| symbol.addr);
+    let (symbols, len, _) = symbols.into_raw_parts();
+    let (names, names_len, _) = names.into_raw_parts();
+    return SymbolTable { symbols, len, names, names_len };
+}
+
+// the legacy mangling of Rust, e.g. _ZN4core9panicking5panic17h0123456789abcdefE is core::panicking::panic.
+// the other names are kept as they are
+fn demangle(name: &str) -> String {
+    let mut rest = match name.strip_prefix("_ZN").and_then(|rest| rest.strip_suffix('E')) {
+        Some(rest) => rest,
+        None => return String::from(name)
+    };
+    let mut path: Vec<String> = Vec::new();
+    while !rest.is_empty() {
+        let digits = rest.bytes().take_while(|b| b.is_ascii_digit()).count();
+        let len: usize = match rest[..digits].parse() {
+            Ok(len) if digits + len <= rest.len() => len,
+            _ => return String::from(name)
+        };
+        let ident = &rest[digits..digits + len];
+        rest = &rest[digits + len..];
+        // the hash at the end
+        if rest.is_empty() && ident.len() == 17 && ident.starts_with('h') {
+            break;
+        }
+        path.push(unescape(ident));
+    }
+    return path.join("::");
+}
+
+fn unescape(ident: &str) -> String {
+    // the underscore is added when the identifier starts with an escape
+    let mut rest = if ident.starts_with("_$") { &ident[1..] } else { ident };
+    let mut s = String::new();
+    while let Some(c) = rest.chars().next() {
+        if rest.starts_with("..") {
+            s.push_str("::");
+            rest = &rest[2..];
+            continue;
+        }
+        if c == '$' {
+            if let Some(end) = rest[1..].find('$') {
+                let escaped = match &rest[1..end + 1] {
+                    "SP" => Some('@'),
+                    "BP" => Some('*'),
+                    "RF" => Some('&'),
+                    "LT" => Some('<'),
+                    "GT" => Some('>'),
+                    "LP" => Some('('),
+                    "RP" => Some(')'),
+                    "C" => Some(','),
+                    code => code
+                        .strip_prefix('u')
+                        .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                        .and_then(char::from_u32)
+                };
+                if let Some(escaped) = escaped {
+                    s.push(escaped);
+                    rest = &rest[end + 2..];
+                    continue;
+                }
+            }
+        }
+        s.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    return s;
+}
@@ -0,0 +1,233 @@
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+use core::mem::MaybeUninit;
+
+use crate::{
+    errno::{Errno, EINVAL},
+    raw::*,
+    Error, Result,
+};
+
+// the same values as OpenFlags of the kernel
+pub const O_RDONLY: u32 = 0x0000;
+pub const O_WRONLY: u32 = 0x0001;
+pub const O_RDWR: u32 = 0x0002;
+pub const O_CREAT: u32 = 0x0100;
+pub const O_TRUNC: u32 = 0x0200;
+
+const PATH_MAX: usize = 256;
+
+// where File::seek moves the offset from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SeekFrom {
+    Start(u64),
+    End(i64),
+    Current(i64),
+}
+
+impl SeekFrom {
+    // the offset and whence of lseek
+    fn to_raw(self) -> (i64, u64) {
+        return match self {
+            SeekFrom::Start(offset) => (offset as i64, 0),
+            SeekFrom::Current(offset) => (offset, 1),
+            SeekFrom::End(offset) => (offset, 2),
+        };
+    }
+}
+
+// same layout as struct stat of Linux. the kernel fills only mode and size
+#[repr(C)]
+struct Stat {
+    dev: u64,
+    ino: u64,
+    nlink: u64,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    pad: u32,
+    rdev: u64,
+    size: i64,
+    rest: [i64; 11],
+}
+
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Metadata {
+    size: u64,
+    is_dir: bool,
+}
+
+impl Metadata {
+    // the size in bytes. /dev/fb0 has the size of the framebuffer
+    pub fn size(&self) -> u64 {
+        return self.size;
+    }
+
+    pub fn is_dir(&self) -> bool {
+        return self.is_dir;
+    }
+}
+
+// the kernel reads a null-terminated string
+pub(crate) fn to_cpath(path: &str) -> Result<[u8; PATH_MAX]> {
+    let mut cpath = [0u8; PATH_MAX];
+    if path.len() >= PATH_MAX || path.bytes().any(|b| b == 0) {
+        return Err(Errno(EINVAL).into());
+    }
+    cpath[..path.len()].copy_from_slice(path.as_bytes());
+    return Ok(cpath);
+}
+
+pub fn metadata(path: &str) -> Result<Metadata> {
+    let cpath = to_cpath(path)?;
+    let mut stat = MaybeUninit::<Stat>::zeroed();
+    Errno::check(unsafe { syscall3(SYS_STAT, cpath.as_ptr() as u64, stat.as_mut_ptr() as u64, 0) })?;
+    let stat = unsafe { stat.assume_init() };
+    return Ok(Metadata { size: stat.size as u64, is_dir: stat.mode & S_IFMT == S_IFDIR });
+}
+
+// an open file can't be removed (EBUSY)
+pub fn remove_file(path: &str) -> Result<()> {
+    let cpath = to_cpath(path)?;
+    Errno::check(unsafe { syscall3(SYS_UNLINK, cpath.as_ptr() as u64, 0, 0) })?;
+    return Ok(());
+}
+
+// move the file within the file system. the file at `to` is replaced if it exists
+pub fn rename(from: &str, to: &str) -> Result<()> {
+    let cfrom = to_cpath(from)?;
+    let cto = to_cpath(to)?;
+    Errno::check(unsafe { syscall3(SYS_RENAME, cfrom.as_ptr() as u64, cto.as_ptr() as u64, 0) })?;
+    return Ok(());
+}
+
+// an open file. the fd is closed on drop
+pub struct File {
+    fd: i32,
+}
+
+impl File {
+    pub fn open(path: &str, flags: u32) -> Result<Self> {
+        let cpath = to_cpath(path)?;
+        let fd = Errno::check(unsafe { syscall3(SYS_OPEN, cpath.as_ptr() as u64, flags as u64, 0) })?;
+        return Ok(Self { fd: fd as i32 });
+    }
+
+    // wrap an fd which is already open, like stdin
+    pub const fn from_raw_fd(fd: i32) -> Self {
+        return Self { fd };
+    }
+
+    pub fn fd(&self) -> i32 {
+        return self.fd;
+    }
+
+    // returns 0 at EOF
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        return Errno::check(unsafe {
+            syscall3(SYS_READ, self.fd as u64, buf.as_mut_ptr() as u64, buf.len() as u64)
+        });
+    }
+
+    // may write fewer bytes than buf.len()
+    pub fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        return Errno::check(unsafe {
+            syscall3(SYS_WRITE, self.fd as u64, buf.as_ptr() as u64, buf.len() as u64)
+        });
+    }
+
+    // returns the new offset. only the devices in /dev can seek, the other files fail with ESPIPE
+    pub fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        let (offset, whence) = pos.to_raw();
+        let offset = Errno::check(unsafe { syscall3(SYS_LSEEK, self.fd as u64, offset as u64, whence) })?;
+        return Ok(offset as u64);
+    }
+
+    // map len bytes of the file from the offset, which is a multiple of 4096. only /dev/fb0 can be mapped.
+    // the mapping can't be removed and stays valid after the file is closed
+    pub fn map(&self, offset: usize, len: usize) -> Result<*mut u8> {
+        let addr = Errno::check(unsafe {
+            syscall6(SYS_MMAP, 0, len as u64, 0, 0, self.fd as u64, offset as u64)
+        })?;
+        return Ok(addr as *mut u8);
+    }
+
+    // shrink the file or extend it with zeros. the file must be open for writing
+    pub fn set_len(&self, size: u64) -> Result<()> {
+        Errno::check(unsafe { syscall3(SYS_FTRUNCATE, self.fd as u64, size, 0) })?;
+        return Ok(());
+    }
+
+    // fill the whole buffer, or fail with UnexpectedEof
+    pub fn read_exact(&mut self, mut buf: &mut [u8]) -> Result<()> {
+        while !buf.is_empty() {
+            match self.read(buf)? {
+                0 => return Err(Error::UnexpectedEof),
+                n => buf = &mut buf[n..],
+            }
+        }
+        return Ok(());
+    }
+
+    // read until EOF, appending to buf. returns the number of bytes read
+    #[cfg(feature = "alloc")]
+    pub fn read_to_end(&mut self, buf: &mut Vec<u8>) -> Result<usize> {
+        const CHUNK_SIZE: usize = 512;
+        let start = buf.len();
+        loop {
+            let len = buf.len();
+            buf.resize(len + CHUNK_SIZE, 0);
+            match self.read(&mut buf[len..]) {
+                Ok(0) => {
+                    buf.truncate(len);
+                    return Ok(len - start);
+                }
+                Ok(n) => buf.truncate(len + n),
+                Err(e) => {
+                    buf.truncate(len);
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    // retry short writes until every byte is written
+    pub fn write_all(&mut self, mut buf: &[u8]) -> Result<()> {
+        while !buf.is_empty() {
+            match self.write(buf)? {
+                0 => return Err(Error::WriteZero),
+                n => buf = &buf[n..],
+            }
+        }
+        return Ok(());
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        unsafe { syscall3(SYS_CLOSE, self.fd as u64, 0, 0) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::mem::{offset_of, size_of};
+
+    #[test]
+    fn stat_has_the_layout_of_linux() {
+        assert_eq!(size_of::<Stat>(), 144);
+        assert_eq!(offset_of!(Stat, mode), 24);
+        assert_eq!(offset_of!(Stat, size), 48);
+    }
+
+    #[test]
+    fn seek_from_maps_to_whence() {
+        assert_eq!(SeekFrom::Start(5).to_raw(), (5, 0));
+        assert_eq!(SeekFrom::Current(-5).to_raw(), (-5, 1));
+        assert_eq!(SeekFrom::End(-5).to_raw(), (-5, 2));
+    }
+}
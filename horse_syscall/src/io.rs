@@ -0,0 +1,330 @@
+use core::cmp::min;
+
+use crate::{fs::File, Result};
+
+pub trait Read {
+    // returns 0 at EOF
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize>;
+}
+
+pub trait Write {
+    // may write fewer bytes than buf.len()
+    fn write(&mut self, buf: &[u8]) -> Result<usize>;
+    fn flush(&mut self) -> Result<()>;
+}
+
+impl Read for File {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        return File::read(self, buf);
+    }
+}
+
+impl Write for File {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        return File::write(self, buf);
+    }
+    // files aren't buffered in user space
+    fn flush(&mut self) -> Result<()> {
+        return Ok(());
+    }
+}
+
+// reads N bytes at once from the inner reader to save syscalls
+pub struct BufReader<R: Read, const N: usize> {
+    inner: R,
+    buf: [u8; N],
+    pos: usize,
+    filled: usize,
+}
+
+impl<R: Read, const N: usize> BufReader<R, N> {
+    pub fn new(inner: R) -> Self {
+        return Self {
+            inner,
+            buf: [0; N],
+            pos: 0,
+            filled: 0,
+        };
+    }
+
+    pub fn get_ref(&self) -> &R {
+        return &self.inner;
+    }
+
+    pub fn into_inner(self) -> R {
+        return self.inner;
+    }
+
+    // returns the buffered data, reading from the inner reader only when the buffer is empty.
+    // an empty slice means EOF
+    pub fn fill_buf(&mut self) -> Result<&[u8]> {
+        if self.pos >= self.filled {
+            self.filled = self.inner.read(&mut self.buf)?;
+            self.pos = 0;
+        }
+        return Ok(&self.buf[self.pos..self.filled]);
+    }
+
+    pub fn consume(&mut self, amount: usize) {
+        self.pos = min(self.pos + amount, self.filled);
+    }
+
+    // copy bytes into buf until the delimiter (included) is found, buf is full or EOF.
+    // returns the number of bytes copied
+    pub fn read_until(&mut self, delimiter: u8, buf: &mut [u8]) -> Result<usize> {
+        let mut total = 0;
+        while total < buf.len() {
+            let available = self.fill_buf()?;
+            if available.is_empty() {
+                break;
+            }
+            let (len, found) = match available.iter().position(|&b| b == delimiter) {
+                Some(i) => (min(i + 1, buf.len() - total), i < buf.len() - total),
+                None => (min(available.len(), buf.len() - total), false),
+            };
+            buf[total..total + len].copy_from_slice(&available[..len]);
+            self.consume(len);
+            total += len;
+            if found {
+                break;
+            }
+        }
+        return Ok(total);
+    }
+}
+
+impl<R: Read, const N: usize> Read for BufReader<R, N> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        // large reads bypass the buffer when it's empty
+        if self.pos >= self.filled && buf.len() >= N {
+            return self.inner.read(buf);
+        }
+        let available = self.fill_buf()?;
+        let len = min(available.len(), buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        return Ok(len);
+    }
+}
+
+// collects small writes and passes them to the inner writer N bytes at once.
+// the buffer is flushed on drop, but errors there are ignored
+pub struct BufWriter<W: Write, const N: usize> {
+    inner: W,
+    buf: [u8; N],
+    len: usize,
+}
+
+impl<W: Write, const N: usize> BufWriter<W, N> {
+    pub fn new(inner: W) -> Self {
+        return Self {
+            inner,
+            buf: [0; N],
+            len: 0,
+        };
+    }
+
+    pub fn get_ref(&self) -> &W {
+        return &self.inner;
+    }
+
+    // write out the buffer, retrying short writes
+    fn flush_buf(&mut self) -> Result<()> {
+        let mut written = 0;
+        while written < self.len {
+            match self.inner.write(&self.buf[written..self.len]) {
+                Ok(0) => break,
+                Ok(n) => written += n,
+                Err(e) => {
+                    self.buf.copy_within(written..self.len, 0);
+                    self.len -= written;
+                    return Err(e);
+                }
+            }
+        }
+        self.buf.copy_within(written..self.len, 0);
+        self.len -= written;
+        return Ok(());
+    }
+}
+
+impl<W: Write, const N: usize> Write for BufWriter<W, N> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if self.len + buf.len() > N {
+            self.flush_buf()?;
+        }
+        // too large to be buffered
+        if buf.len() >= N {
+            return self.inner.write(buf);
+        }
+        let len = min(buf.len(), N - self.len);
+        self.buf[self.len..self.len + len].copy_from_slice(&buf[..len]);
+        self.len += len;
+        return Ok(len);
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.flush_buf()?;
+        return self.inner.flush();
+    }
+}
+
+impl<W: Write, const N: usize> Drop for BufWriter<W, N> {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::{cell::RefCell, rc::Rc, vec::Vec};
+
+    // returns at most chunk bytes per read, as a pipe or the console does
+    struct MockReader {
+        data: Vec<u8>,
+        pos: usize,
+        chunk: usize,
+        reads: usize,
+    }
+
+    impl MockReader {
+        fn new(data: &[u8], chunk: usize) -> Self {
+            return Self { data: data.to_vec(), pos: 0, chunk, reads: 0 };
+        }
+    }
+
+    impl Read for MockReader {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            self.reads += 1;
+            let len = min(min(buf.len(), self.chunk), self.data.len() - self.pos);
+            buf[..len].copy_from_slice(&self.data[self.pos..self.pos + len]);
+            self.pos += len;
+            return Ok(len);
+        }
+    }
+
+    // accepts at most chunk bytes per write. the output outlives the writer
+    struct MockWriter {
+        out: Rc<RefCell<Vec<u8>>>,
+        chunk: usize,
+        writes: Rc<RefCell<usize>>,
+    }
+
+    impl Write for MockWriter {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            *self.writes.borrow_mut() += 1;
+            let len = min(buf.len(), self.chunk);
+            self.out.borrow_mut().extend_from_slice(&buf[..len]);
+            return Ok(len);
+        }
+        fn flush(&mut self) -> Result<()> {
+            return Ok(());
+        }
+    }
+
+    fn mock_writer(chunk: usize) -> (MockWriter, Rc<RefCell<Vec<u8>>>, Rc<RefCell<usize>>) {
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let writes = Rc::new(RefCell::new(0));
+        return (MockWriter { out: out.clone(), chunk, writes: writes.clone() }, out, writes);
+    }
+
+    #[test]
+    fn reader_returns_short_reads() {
+        let mut reader = BufReader::<_, 8>::new(MockReader::new(b"hello world", 3));
+        let mut buf = [0; 8];
+        assert_eq!(reader.read(&mut buf).unwrap(), 3);
+        assert_eq!(&buf[..3], b"hel");
+    }
+
+    #[test]
+    fn reader_refills_when_consumed() {
+        let mut reader = BufReader::<_, 4>::new(MockReader::new(b"abcdefgh", 8));
+        let mut buf = [0; 3];
+        assert_eq!(reader.read(&mut buf).unwrap(), 3);
+        assert_eq!(reader.get_ref().reads, 1);
+        // the rest of the buffer comes without reading
+        assert_eq!(reader.read(&mut buf).unwrap(), 1);
+        assert_eq!(&buf[..1], b"d");
+        assert_eq!(reader.get_ref().reads, 1);
+        assert_eq!(reader.read(&mut buf).unwrap(), 3);
+        assert_eq!(&buf, b"efg");
+        assert_eq!(reader.get_ref().reads, 2);
+    }
+
+    #[test]
+    fn reader_bypasses_buffer_for_large_reads() {
+        let mut reader = BufReader::<_, 4>::new(MockReader::new(b"abcdefgh", 8));
+        let mut buf = [0; 8];
+        assert_eq!(reader.read(&mut buf).unwrap(), 8);
+        assert_eq!(&buf, b"abcdefgh");
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn read_until_spans_refills() {
+        let mut reader = BufReader::<_, 4>::new(MockReader::new(b"first line\nsecond", 2));
+        let mut buf = [0; 32];
+        let len = reader.read_until(b'\n', &mut buf).unwrap();
+        assert_eq!(&buf[..len], b"first line\n");
+        let len = reader.read_until(b'\n', &mut buf).unwrap();
+        assert_eq!(&buf[..len], b"second");
+        assert_eq!(reader.read_until(b'\n', &mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn read_until_stops_when_buf_is_full() {
+        let mut reader = BufReader::<_, 16>::new(MockReader::new(b"abcdef\n", 16));
+        let mut buf = [0; 4];
+        assert_eq!(reader.read_until(b'\n', &mut buf).unwrap(), 4);
+        assert_eq!(&buf, b"abcd");
+        assert_eq!(reader.read_until(b'\n', &mut buf).unwrap(), 3);
+        assert_eq!(&buf[..3], b"ef\n");
+    }
+
+    #[test]
+    fn writer_buffers_small_writes() {
+        let (mock, out, writes) = mock_writer(usize::MAX);
+        let mut writer = BufWriter::<_, 8>::new(mock);
+        assert_eq!(writer.write(b"abc").unwrap(), 3);
+        assert_eq!(writer.write(b"def").unwrap(), 3);
+        assert_eq!(*writes.borrow(), 0);
+        // doesn't fit, so the buffer goes first
+        assert_eq!(writer.write(b"ghi").unwrap(), 3);
+        assert_eq!(*out.borrow(), b"abcdef");
+        writer.flush().unwrap();
+        assert_eq!(*out.borrow(), b"abcdefghi");
+    }
+
+    #[test]
+    fn writer_retries_short_writes() {
+        let (mock, out, writes) = mock_writer(2);
+        let mut writer = BufWriter::<_, 8>::new(mock);
+        writer.write(b"abcde").unwrap();
+        writer.flush().unwrap();
+        assert_eq!(*out.borrow(), b"abcde");
+        assert_eq!(*writes.borrow(), 3);
+    }
+
+    #[test]
+    fn writer_flushes_on_drop() {
+        let (mock, out, _) = mock_writer(usize::MAX);
+        {
+            let mut writer = BufWriter::<_, 8>::new(mock);
+            writer.write(b"bye").unwrap();
+            assert!(out.borrow().is_empty());
+        }
+        assert_eq!(*out.borrow(), b"bye");
+    }
+
+    #[test]
+    fn writer_passes_large_writes_through() {
+        let (mock, out, _) = mock_writer(usize::MAX);
+        let mut writer = BufWriter::<_, 4>::new(mock);
+        writer.write(b"ab").unwrap();
+        assert_eq!(writer.write(b"cdefgh").unwrap(), 6);
+        assert_eq!(*out.borrow(), b"abcdefgh");
+    }
+}
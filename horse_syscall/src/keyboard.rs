@@ -0,0 +1,20 @@
+use crate::{
+    errno::{Errno, EINVAL},
+    raw::*,
+    Result,
+};
+
+const LAYOUT_NAME_MAX: usize = 32;
+
+// select the keyboard layout by the name, like "us", "jis" or "us-intl".
+// an unknown name selects US and fails with EINVAL
+pub fn set_layout(name: &str) -> Result<()> {
+    // the kernel reads a null-terminated string
+    let mut cname = [0u8; LAYOUT_NAME_MAX];
+    if name.len() >= LAYOUT_NAME_MAX || name.bytes().any(|b| b == 0) {
+        return Err(Errno(EINVAL).into());
+    }
+    cname[..name.len()].copy_from_slice(name.as_bytes());
+    Errno::check(unsafe { syscall3(SYS_SET_KEYBOARD_LAYOUT, cname.as_ptr() as u64, 0, 0) })?;
+    return Ok(());
+}
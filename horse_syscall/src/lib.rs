@@ -0,0 +1,35 @@
+#![no_std]
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+// system call interface of Horse for user programs
+pub mod errno;
+pub mod fs;
+pub mod io;
+pub mod keyboard;
+pub mod poll;
+pub mod process;
+pub mod shm;
+pub mod signal;
+mod raw;
+
+pub use errno::Errno;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    // the kernel returned an error
+    Os(Errno),
+    // EOF came before the buffer was filled
+    UnexpectedEof,
+    // the file accepted no more bytes
+    WriteZero,
+}
+
+impl From<Errno> for Error {
+    fn from(errno: Errno) -> Self {
+        return Error::Os(errno);
+    }
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
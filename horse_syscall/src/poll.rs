@@ -0,0 +1,34 @@
+use crate::{errno::Errno, raw::*, Result};
+
+// the same values as the kernel
+pub const POLLIN: i16 = 0x1;
+pub const POLLOUT: i16 = 0x4;
+pub const POLLERR: i16 = 0x8;
+// the fd isn't open
+pub const POLLHUP: i16 = 0x10;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct PollFd {
+    pub fd: i32,
+    pub events: i16,
+    pub revents: i16,
+}
+
+impl PollFd {
+    pub const fn new(fd: i32, events: i16) -> Self {
+        return Self {
+            fd,
+            events,
+            revents: 0,
+        };
+    }
+}
+
+// wait until any of the fds is ready and return the number of them. revents of each PollFd is set.
+// timeout_ms = 0 only checks the fds and a negative value waits forever
+pub fn poll(fds: &mut [PollFd], timeout_ms: i32) -> Result<usize> {
+    return Errno::check(unsafe {
+        syscall3(SYS_POLL, fds.as_mut_ptr() as u64, fds.len() as u64, timeout_ms as i64 as u64)
+    });
+}
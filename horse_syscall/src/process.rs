@@ -0,0 +1,53 @@
+use crate::{
+    errno::Errno,
+    fs::to_cpath,
+    raw::{syscall3, SYS_CHDIR, SYS_EXIT, SYS_GETCWD, SYS_GETRLIMIT, SYS_SETRLIMIT},
+    Result,
+};
+
+// the resources of getrlimit and setrlimit
+pub const RLIMIT_NPROC: u32 = 6;
+pub const RLIMIT_NOFILE: u32 = 7;
+pub const RLIMIT_AS: u32 = 9;
+pub const RLIM_INFINITY: u64 = u64::MAX;
+
+// the same layout as Rlimit of the kernel
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rlimit {
+    pub cur: u64,
+    pub max: u64,
+}
+
+// terminate the calling process. nobody can get the status yet
+pub fn exit(status: i32) -> ! {
+    unsafe { syscall3(SYS_EXIT, status as u64, 0, 0) };
+    unreachable!()
+}
+
+// relative paths are resolved against the current directory. fails with ENOTDIR for a file
+pub fn chdir(path: &str) -> Result<()> {
+    let cpath = to_cpath(path)?;
+    Errno::check(unsafe { syscall3(SYS_CHDIR, cpath.as_ptr() as u64, 0, 0) })?;
+    return Ok(());
+}
+
+// the current directory is written into buf. fails with ERANGE when buf is too small
+pub fn getcwd(buf: &mut [u8]) -> Result<&str> {
+    let len = Errno::check(unsafe { syscall3(SYS_GETCWD, buf.as_mut_ptr() as u64, buf.len() as u64, 0) })?;
+    // the length includes the terminating null, and the kernel keeps the path in UTF-8
+    return Ok(core::str::from_utf8(&buf[..len - 1]).unwrap_or("/"));
+}
+
+// RLIMIT_NPROC limits the children which aren't terminated yet, and RLIMIT_AS the memory of a spawned program
+pub fn getrlimit(resource: u32) -> Result<Rlimit> {
+    let mut limit = Rlimit { cur: 0, max: 0 };
+    Errno::check(unsafe { syscall3(SYS_GETRLIMIT, resource as u64, &mut limit as *mut Rlimit as u64, 0) })?;
+    return Ok(limit);
+}
+
+// the hard limit can only be lowered (EPERM)
+pub fn setrlimit(resource: u32, limit: &Rlimit) -> Result<()> {
+    Errno::check(unsafe { syscall3(SYS_SETRLIMIT, resource as u64, limit as *const Rlimit as u64, 0) })?;
+    return Ok(());
+}
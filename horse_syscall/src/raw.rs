@@ -0,0 +1,60 @@
+use core::arch::asm;
+
+// must be the same as SyscallNumber of the kernel
+pub const SYS_READ: u64 = 0;
+pub const SYS_WRITE: u64 = 1;
+pub const SYS_OPEN: u64 = 2;
+pub const SYS_CLOSE: u64 = 3;
+pub const SYS_STAT: u64 = 4;
+pub const SYS_POLL: u64 = 7;
+pub const SYS_LSEEK: u64 = 8;
+pub const SYS_MMAP: u64 = 9;
+pub const SYS_SIGACTION: u64 = 13;
+pub const SYS_SIGRETURN: u64 = 15;
+pub const SYS_FTRUNCATE: u64 = 77;
+pub const SYS_GETCWD: u64 = 79;
+pub const SYS_CHDIR: u64 = 80;
+pub const SYS_RENAME: u64 = 82;
+pub const SYS_UNLINK: u64 = 87;
+pub const SYS_GETRLIMIT: u64 = 97;
+pub const SYS_SETRLIMIT: u64 = 160;
+pub const SYS_EXIT: u64 = 60;
+pub const SYS_SET_KEYBOARD_LAYOUT: u64 = 514;
+pub const SYS_SHM_CREATE: u64 = 515;
+pub const SYS_SHM_MAP: u64 = 516;
+pub const SYS_SHM_UNMAP: u64 = 517;
+
+// the arguments are passed in the same registers as Linux
+pub unsafe fn syscall3(number: u64, arg1: u64, arg2: u64, arg3: u64) -> isize {
+    let ret: isize;
+    asm!(
+        "syscall",
+        inlateout("rax") number as isize => ret,
+        in("rdi") arg1,
+        in("rsi") arg2,
+        in("rdx") arg3,
+        out("rcx") _,
+        out("r11") _,
+        options(nostack)
+    );
+    return ret;
+}
+
+// the fourth argument is passed in r10, since syscall overwrites rcx
+pub unsafe fn syscall6(number: u64, arg1: u64, arg2: u64, arg3: u64, arg4: u64, arg5: u64, arg6: u64) -> isize {
+    let ret: isize;
+    asm!(
+        "syscall",
+        inlateout("rax") number as isize => ret,
+        in("rdi") arg1,
+        in("rsi") arg2,
+        in("rdx") arg3,
+        in("r10") arg4,
+        in("r8") arg5,
+        in("r9") arg6,
+        out("rcx") _,
+        out("r11") _,
+        options(nostack)
+    );
+    return ret;
+}
@@ -0,0 +1,24 @@
+use crate::{
+    errno::Errno,
+    raw::*,
+    Result,
+};
+
+// create a zero filled segment of at least size bytes and map it. returns the id,
+// which other processes pass to map. the mapped segments count in RLIMIT_AS
+pub fn create(size: usize) -> Result<usize> {
+    return Ok(Errno::check(unsafe { syscall3(SYS_SHM_CREATE, size as u64, 0, 0) })?);
+}
+
+// the address of the segment is the same in every process. mapping it twice returns the same address
+pub fn map(id: usize) -> Result<*mut u8> {
+    let addr = Errno::check(unsafe { syscall3(SYS_SHM_MAP, id as u64, 0, 0) })?;
+    return Ok(addr as *mut u8);
+}
+
+// the memory is freed when the last process unmaps it. exiting unmaps every segment.
+// the pages can still be accessed until then, so don't rely on this to protect the memory
+pub fn unmap(id: usize) -> Result<()> {
+    Errno::check(unsafe { syscall3(SYS_SHM_UNMAP, id as u64, 0, 0) })?;
+    return Ok(());
+}
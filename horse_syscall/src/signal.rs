@@ -0,0 +1,38 @@
+use core::arch::global_asm;
+
+use crate::{
+    errno::Errno,
+    raw::*,
+    Result,
+};
+
+// only SIGCHLD can be caught for now
+pub const SIGCHLD: u32 = 17;
+
+// the handler returns into this, which restores the registers of the interrupted program
+global_asm!(
+    ".global horse_sigreturn",
+    "horse_sigreturn:",
+    "mov eax, {sigreturn}",
+    "syscall",
+    "ud2",
+    sigreturn = const SYS_SIGRETURN,
+);
+
+extern "C" {
+    fn horse_sigreturn();
+}
+
+// the handler is called with the signal number as a syscall returns. it isn't called again
+// until it returns, and the signals which come meanwhile are merged into one
+pub fn sigaction(signal: u32, handler: extern "C" fn(u32)) -> Result<()> {
+    let restorer = horse_sigreturn as *const () as u64;
+    Errno::check(unsafe { syscall3(SYS_SIGACTION, signal as u64, handler as *const () as u64, restorer) })?;
+    return Ok(());
+}
+
+// the signal is discarded
+pub fn ignore(signal: u32) -> Result<()> {
+    Errno::check(unsafe { syscall3(SYS_SIGACTION, signal as u64, 0, 0) })?;
+    return Ok(());
+}
@@ -136,3 +136,125 @@ global set_cr3 ; fn set_cr3(u64)
 set_cr3:
   mov cr3, rdi
   ret
+
+extern syscall_dispatch
+; entry of the syscall instruction. RCX = user RIP, R11 = user RFLAGS, RAX = syscall number
+; and the arguments are in RDI, RSI, RDX, R10, R8 and R9. syscall doesn't switch the stack,
+; so the kernel stack is taken from SyscallCpuData pointed by the kernel GS base
+global syscall_entry
+syscall_entry:
+  swapgs
+  mov [gs:0x08], rsp ; SyscallCpuData.user_stack
+  mov rsp, [gs:0x00] ; SyscallCpuData.kernel_stack
+  push qword [gs:0x08]
+  swapgs
+  sti
+
+  push rcx
+  push r11
+  push rdi
+  push rsi
+  push rdx
+  push r10
+  push r8
+  push r9
+
+  ; fn syscall_dispatch(number, arg1, arg2, arg3, arg4, arg5, arg6) -> isize
+  push r9          ; arg6 on the stack, which is 16 bytes aligned here
+  mov r9, r8       ; arg5
+  mov r8, r10      ; arg4
+  mov rcx, rdx     ; arg3
+  mov rdx, rsi     ; arg2
+  mov rsi, rdi     ; arg1
+  mov rdi, rax     ; number
+  call syscall_dispatch
+  add rsp, 8
+
+  pop r9
+  pop r8
+  pop r10
+  pop rdx
+  pop rsi
+  pop rdi
+  pop r11
+  pop rcx
+
+  cli
+  pop rsp ; user stack
+  o64 sysret
+
+; trampoline for application processors. it is copied to AP_TRAMPOLINE_BASE and
+; started by SIPI in real mode, so every address is relative to the copy.
+AP_TRAMPOLINE_BASE equ 0x8000
+%define AP_ADDR(label) (AP_TRAMPOLINE_BASE + (label - ap_trampoline_start))
+
+global ap_trampoline_start
+global ap_trampoline_end
+global ap_trampoline_cr3
+global ap_trampoline_stack
+global ap_trampoline_entry
+
+bits 16
+ap_trampoline_start:
+  cli
+  cld
+  xor ax, ax
+  mov ds, ax
+  lgdt [AP_ADDR(ap_trampoline_gdtr)]
+  mov eax, cr0
+  or eax, 1 ; PE
+  mov cr0, eax
+  jmp dword 0x08:AP_ADDR(ap_trampoline_protected)
+
+bits 32
+ap_trampoline_protected:
+  mov ax, 0x10
+  mov ds, ax
+  mov es, ax
+  mov ss, ax
+  mov eax, cr4
+  or eax, 1 << 5 | 1 << 9 | 1 << 10 ; PAE, OSFXSR and OSXMMEXCPT for SSE
+  mov cr4, eax
+  mov eax, [AP_ADDR(ap_trampoline_cr3)]
+  mov cr3, eax
+  mov ecx, 0xc0000080 ; EFER
+  rdmsr
+  or eax, 1 << 8 ; LME
+  wrmsr
+  mov eax, cr0
+  and eax, ~(1 << 2) ; EM
+  or eax, 1 << 31 | 1 << 1 ; PG and MP
+  mov cr0, eax
+  jmp 0x18:AP_ADDR(ap_trampoline_long)
+
+bits 64
+ap_trampoline_long:
+  xor ax, ax
+  mov ds, ax
+  mov es, ax
+  mov ss, ax
+  mov rsp, [AP_ADDR(ap_trampoline_stack)]
+  mov rax, [AP_ADDR(ap_trampoline_entry)]
+  call rax
+.fin:
+  cli
+  hlt
+  jmp .fin
+
+align 8
+ap_trampoline_gdt:
+  dq 0
+  dq 0x00cf9a000000ffff ; 32-bit code
+  dq 0x00cf92000000ffff ; 32-bit data
+  dq 0x00af9a000000ffff ; 64-bit code
+ap_trampoline_gdtr:
+  dw ap_trampoline_gdtr - ap_trampoline_gdt - 1
+  dd AP_ADDR(ap_trampoline_gdt)
+align 8
+ap_trampoline_cr3:
+  dq 0
+ap_trampoline_stack:
+  dq 0
+ap_trampoline_entry:
+  dq 0
+ap_trampoline_end:
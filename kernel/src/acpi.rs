@@ -1,10 +1,14 @@
-use crate::{error, fftimer::FFTimer, info, initialize_lapic_itmer, horse_lib::bytes::*};
+use crate::{
+    error, fftimer::FFTimer, info, initialize_lapic_itmer, horse_lib::bytes::*,
+    memory_manager::frame_manager_instance,
+};
 
-use alloc::vec::Vec;
+use alloc::{vec, vec::Vec};
 use core::{
     mem::size_of,
     ptr::{null, read_unaligned},
 };
+use spin::Once;
 use uefi::{
     table::{Runtime, SystemTable},
     Guid,
@@ -91,6 +95,12 @@ impl DescriptionHeader {
     }
 }
 
+// local APIC ids of the enabled processors, taken from MADT
+pub static PROCESSOR_APIC_IDS: Once<Vec<u32>> = Once::new();
+
+const MADT_LOCAL_APIC: u8 = 0;
+const MADT_LOCAL_X2APIC: u8 = 9;
+
 struct Xsdt {
     header: DescriptionHeader,
     entries: Vec<u64>,
@@ -138,6 +148,46 @@ impl Xsdt {
         }
         return FFTimer::new(hpet.unwrap()); //hpet
     }
+
+    fn get_apic_ids(&self) -> Option<Vec<u32>> {
+        for i in 0..self.entries.len() {
+            let header = unsafe { read_unaligned(self.entries[i] as *const DescriptionHeader) };
+            if &bytes2str(&header.signature) != "APIC" {
+                continue;
+            }
+            // entries follow the header, local interrupt controller address and flags
+            let mut ids = vec![];
+            let end = self.entries[i] + header.length as u64;
+            let mut entry = self.entries[i] + size_of::<DescriptionHeader>() as u64 + 8;
+            while entry + 2 <= end {
+                let (ty, length) = unsafe { (*(entry as *const u8), *((entry + 1) as *const u8)) };
+                if length == 0 {
+                    break;
+                }
+                match ty {
+                    MADT_LOCAL_APIC => {
+                        let apic_id = unsafe { *((entry + 3) as *const u8) };
+                        let flags = unsafe { read_unaligned((entry + 4) as *const u32) };
+                        // enabled or online capable
+                        if flags & 0b11 != 0 {
+                            ids.push(apic_id as u32);
+                        }
+                    }
+                    MADT_LOCAL_X2APIC => {
+                        let apic_id = unsafe { read_unaligned((entry + 4) as *const u32) };
+                        let flags = unsafe { read_unaligned((entry + 8) as *const u32) };
+                        if flags & 0b11 != 0 {
+                            ids.push(apic_id);
+                        }
+                    }
+                    _ => {}
+                }
+                entry += length as u64;
+            }
+            return Some(ids);
+        }
+        return None;
+    }
 }
 
 fn get_rsdp(st: SystemTable<Runtime>) -> Option<RSDP> {
@@ -165,4 +215,19 @@ pub fn initialize_acpi(st: SystemTable<Runtime>) {
     let xsdt = unsafe { Xsdt::new(rsdp.xsdt_address).unwrap() };
     let fftimer = xsdt.get_timer().unwrap();
     initialize_lapic_itmer(fftimer);
+    match xsdt.get_apic_ids() {
+        Some(ids) => {
+            PROCESSOR_APIC_IDS.call_once(|| ids);
+        }
+        None => error!("MADT is not found"),
+    }
+
+    // the tables are no longer referenced, so give ACPI_RECLAIM memory back to the frame manager
+    let mut frame_manager = frame_manager_instance();
+    let mut reclaimed = 0;
+    while let Some(region) = frame_manager.pop_reclaimable_region() {
+        reclaimed += frame_manager.reclaim(region);
+    }
+    drop(frame_manager);
+    info!("reclaimed {} frames from ACPI memory", reclaimed);
 }
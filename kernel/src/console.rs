@@ -1,28 +1,64 @@
-use alloc::{vec, vec::Vec};
-use core::fmt::Write;
+use alloc::{collections::VecDeque, vec::Vec};
+use core::{
+    cmp::min,
+    fmt::Write,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 use spin::{Mutex, MutexGuard};
 
 use crate::{
     ascii_font::FONTS,
     graphics::{Coord, PixelColor},
+    layer::{LayerHeight, LAYER_MANAGER},
     window::WindowWriter,
 };
 
-static RAW_CONSOLE: Mutex<Option<Console>> = Mutex::new(None);
+// the number of the virtual terminals, which are switched by Alt+F1..F4
+pub const VT_COUNT: usize = 4;
+
+static TERMINALS: Mutex<Option<Terminals>> = Mutex::new(None);
+// kept out of TERMINALS so that it can be read while the consoles are locked
+static ACTIVE_TERMINAL: AtomicUsize = AtomicUsize::new(0);
 
 pub const LINE_HEIGHT: usize = 18;
 pub const MARGIN: usize = 8;
+// the lines which scrolled out of the screen and can be seen by Shift+PageUp, for each terminal
+const SCROLLBACK_LINES: usize = 500;
+
+// colors for the ANSI SGR codes 30-37
+const ANSI_COLORS: [PixelColor; 8] = [
+    PixelColor(0, 0, 0),
+    PixelColor(255, 85, 85),
+    PixelColor(85, 255, 85),
+    PixelColor(255, 255, 85),
+    PixelColor(85, 85, 255),
+    PixelColor(255, 85, 255),
+    PixelColor(85, 255, 255),
+    PixelColor(255, 255, 255),
+];
+
+// state of parsing an escape sequence like "\x1b[31m"
+#[derive(Debug, Clone, Copy)]
+enum Escape {
+    None,
+    Esc,
+    Csi(u32),
+}
 
 #[derive(Debug, Clone)]
 pub struct Console {
     pixel_writer: usize,
-    pub buffer: Vec<Vec<char>>,
+    // the scrollback and then the screen, which is the last rows() lines. the characters are kept with their colors
+    lines: VecDeque<Vec<(char, PixelColor)>>,
+    // the lines which the view is scrolled back by. the output isn't drawn until it's scrolled to the bottom
+    scroll: usize,
     size: (usize, usize),
     fg_color: PixelColor,
+    default_fg_color: PixelColor,
     bg_color: PixelColor,
+    escape: Escape,
     pub cursor_row: usize,
     cursor_column: usize,
-    buffer_row_offset: usize,
 }
 
 impl Console {
@@ -36,29 +72,18 @@ impl Console {
         let size = (resolution.0 / MARGIN, resolution.1 / LINE_HEIGHT);
         Console {
             pixel_writer: pixel_writer as *const WindowWriter as usize,
-            buffer: vec![vec![0.into(); size.0 + 1]; size.1],
+            lines: (0..size.1).map(|_| Vec::new()).collect(),
+            scroll: 0,
             size,
             fg_color: *fg_color,
+            default_fg_color: *fg_color,
             bg_color: *bg_color,
+            escape: Escape::None,
             cursor_row: 0,
             cursor_column: 0,
-            buffer_row_offset: 0,
         }
     }
 
-    pub fn initialize(
-        pixel_writer: &WindowWriter,
-        resolution: (usize, usize),
-        fg_color: &PixelColor,
-        bg_color: &PixelColor,
-    ) {
-        *RAW_CONSOLE.lock() = Some(Console::new(pixel_writer, resolution, fg_color, bg_color));
-    }
-
-    pub fn instance() -> MutexGuard<'static, Option<Console>> {
-        RAW_CONSOLE.lock()
-    }
-
     pub fn pixel_writer(&self) -> &WindowWriter {
         unsafe { &*(self.pixel_writer as *const WindowWriter) }
     }
@@ -70,45 +95,136 @@ impl Console {
         self.size.1
     }
 
-    pub fn actual_row(&self, row: usize) -> usize {
-        (row + self.buffer_row_offset) % self.rows()
+    // the line of the screen where the cursor is
+    fn cursor_line(&mut self) -> &mut Vec<(char, PixelColor)> {
+        let idx = self.lines.len() - self.rows() + self.cursor_row;
+        &mut self.lines[idx]
+    }
+
+    fn max_scroll(&self) -> usize {
+        self.lines.len() - self.rows()
     }
 
-    pub fn actual_cursor_row(&self) -> usize {
-        self.actual_row(self.cursor_row)
+    // positive lines scroll back to the older output. returns false when the view didn't move
+    pub fn scroll_by(&mut self, lines: isize) -> bool {
+        let scroll = min(self.scroll.saturating_add_signed(lines), self.max_scroll());
+        if scroll == self.scroll {
+            return false;
+        }
+        self.scroll = scroll;
+        self.redraw();
+        return true;
+    }
+
+    // draw the lines in the view from scratch
+    fn redraw(&self) {
+        let (width, height) = self.pixel_writer().size();
+        self.pixel_writer().fill_rect(Coord::new(0, 0), Coord::new(width, height), &self.bg_color);
+        let top = self.lines.len() - self.rows() - self.scroll;
+        for (row, line) in self.lines.range(top..top + self.rows()).enumerate() {
+            for (column, (c, color)) in line.iter().enumerate() {
+                write_ascii(self.pixel_writer(), MARGIN * column, LINE_HEIGHT * row, *c, color);
+            }
+        }
     }
 
     pub fn newline(&mut self) {
         self.cursor_column = 0;
         if self.cursor_row < self.rows() - 1 {
             self.cursor_row += 1;
+            return;
+        }
+        self.lines.push_back(Vec::new());
+        if self.lines.len() > self.rows() + SCROLLBACK_LINES {
+            self.lines.pop_front();
+        }
+        if self.scroll > 0 {
+            // the view stays on the same lines
+            self.scroll = min(self.scroll + 1, self.max_scroll());
         } else {
             self.pixel_writer().move_buffer(
                 Coord::new(0, 0),
                 Coord::new(0, LINE_HEIGHT),
                 Coord::new(8 * self.columns(), LINE_HEIGHT * (self.rows() - 1)),
             );
-            fill_rectangle(
-                self.pixel_writer(),
+            self.pixel_writer().fill_rect(
                 Coord::new(0, LINE_HEIGHT * (self.rows() - 1)),
                 Coord::new(8 * self.columns(), LINE_HEIGHT),
                 &self.bg_color,
             );
         }
     }
+
+    // only SGR is supported: 0 and 39 reset the color, 30-37 set it
+    fn select_graphic_rendition(&mut self, param: u32) {
+        match param {
+            0 | 39 => self.fg_color = self.default_fg_color,
+            30..=37 => self.fg_color = ANSI_COLORS[(param - 30) as usize],
+            _ => {}
+        }
+    }
+
+    // returns true while c is a part of an escape sequence
+    fn handle_escape(&mut self, c: char) -> bool {
+        match (self.escape, c) {
+            (Escape::None, '\x1b') => self.escape = Escape::Esc,
+            (Escape::None, _) => return false,
+            (Escape::Esc, '[') => self.escape = Escape::Csi(0),
+            (Escape::Csi(param), '0'..='9') => {
+                self.escape = Escape::Csi(param * 10 + c.to_digit(10).unwrap())
+            }
+            (Escape::Csi(param), ';') => {
+                self.select_graphic_rendition(param);
+                self.escape = Escape::Csi(0);
+            }
+            (Escape::Csi(param), 'm') => {
+                self.select_graphic_rendition(param);
+                self.escape = Escape::None;
+            }
+            // unsupported sequence
+            _ => self.escape = Escape::None,
+        }
+        return true;
+    }
+
     pub fn put_string(&mut self, s: &str) {
         for c in s.chars() {
+            if self.handle_escape(c) {
+                continue;
+            }
             if c == '\n' {
                 self.newline();
             }
+            // backspace erases the previous character in the line
+            if c == '\x08' {
+                if self.cursor_column > 0 {
+                    self.cursor_column -= 1;
+                    let column = self.cursor_column;
+                    self.cursor_line().truncate(column);
+                    if self.scroll == 0 {
+                        self.pixel_writer().fill_rect(
+                            Coord::new(MARGIN * self.cursor_column, LINE_HEIGHT * self.cursor_row),
+                            Coord::new(MARGIN, LINE_HEIGHT),
+                            &self.bg_color,
+                        );
+                    }
+                }
+                continue;
+            }
             if self.cursor_column < self.columns() && c as u32 >= 0x20 {
-                write_ascii(
-                    self.pixel_writer(),
-                    MARGIN * self.cursor_column,
-                    LINE_HEIGHT * self.cursor_row,
-                    c,
-                    &self.fg_color,
-                );
+                let (column, color) = (self.cursor_column, self.fg_color);
+                let line = self.cursor_line();
+                line.truncate(column);
+                line.push((c, color));
+                if self.scroll == 0 {
+                    write_ascii(
+                        self.pixel_writer(),
+                        MARGIN * self.cursor_column,
+                        LINE_HEIGHT * self.cursor_row,
+                        c,
+                        &self.fg_color,
+                    );
+                }
                 self.cursor_column += 1;
                 if self.cursor_column == self.columns() - 1 {
                     self.newline();
@@ -125,6 +241,79 @@ impl Write for Console {
     }
 }
 
+// the consoles of the virtual terminals. each of them has its own window and layer,
+// and only the layer of the active one is shown
+pub struct Terminals {
+    consoles: Vec<Console>,
+    layer_ids: Vec<u32>,
+}
+
+impl Terminals {
+    pub fn get_mut(&mut self, vt: usize) -> Option<&mut Console> {
+        self.consoles.get_mut(vt)
+    }
+
+    pub fn active_mut(&mut self) -> &mut Console {
+        &mut self.consoles[active_terminal()]
+    }
+}
+
+// the layers must be registered to LAYER_MANAGER and hidden. the first terminal becomes active
+pub fn initialize_terminals(consoles: Vec<Console>, layer_ids: Vec<u32>) {
+    let layer_manager = unsafe { LAYER_MANAGER.get_mut().unwrap() };
+    let _ = layer_manager.up_down(layer_ids[0], LayerHeight::Height(0));
+    ACTIVE_TERMINAL.store(0, Ordering::Relaxed);
+    *TERMINALS.lock() = Some(Terminals {
+        consoles,
+        layer_ids,
+    });
+}
+
+pub fn terminals() -> MutexGuard<'static, Option<Terminals>> {
+    TERMINALS.lock()
+}
+
+pub fn active_terminal() -> usize {
+    ACTIVE_TERMINAL.load(Ordering::Relaxed)
+}
+
+// the background terminals keep drawing to their windows, so the new one is just brought to the front
+pub fn switch_terminal(vt: usize) -> bool {
+    let old = active_terminal();
+    // the lock is released before touching the layers, which may log an error
+    let (old_layer, new_layer) = match TERMINALS.lock().as_ref() {
+        Some(terminals) if vt < terminals.layer_ids.len() => {
+            (terminals.layer_ids[old], terminals.layer_ids[vt])
+        }
+        _ => return false,
+    };
+    if vt == old {
+        return true;
+    }
+    let layer_manager = unsafe { LAYER_MANAGER.get_mut().unwrap() };
+    let _ = layer_manager.hide(old_layer);
+    let _ = layer_manager.up_down(new_layer, LayerHeight::Height(0));
+    ACTIVE_TERMINAL.store(vt, Ordering::Relaxed);
+    layer_manager.draw();
+    return true;
+}
+
+// scroll the view of the active terminal by half the screen, back to the older output or forward
+pub fn scroll_terminal(back: bool) {
+    // the lock is released before drawing the layers, which may log an error
+    let moved = match TERMINALS.lock().as_mut() {
+        Some(terminals) => {
+            let console = terminals.active_mut();
+            let lines = (console.rows() / 2).max(1) as isize;
+            console.scroll_by(if back { lines } else { -lines })
+        }
+        None => return,
+    };
+    if moved {
+        unsafe { LAYER_MANAGER.get_mut().unwrap().draw() };
+    }
+}
+
 fn clear(pixel_writer: &WindowWriter, color: &PixelColor) {
     let (width, height) = pixel_writer.size();
     for y in 0..height {
@@ -134,14 +323,6 @@ fn clear(pixel_writer: &WindowWriter, color: &PixelColor) {
     }
 }
 
-fn fill_rectangle(pixel_writer: &WindowWriter, pos: Coord, size: Coord, color: &PixelColor) {
-    for y in 0..size.y {
-        for x in 0..size.x {
-            pixel_writer.write(pos.x + x, pos.y + y, color)
-        }
-    }
-}
-
 fn write_ascii(pixel_writer: &WindowWriter, x: usize, y: usize, c: char, color: &PixelColor) {
     if (c as u32) > 0x7f {
         return;
@@ -155,3 +336,72 @@ fn write_ascii(pixel_writer: &WindowWriter, x: usize, y: usize, c: char, color:
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::window::Window;
+    use alloc::{format, string::String};
+    use libloader::PixelFormat;
+
+    const FG: PixelColor = PixelColor(255, 255, 255);
+    const BG: PixelColor = PixelColor(0, 0, 0);
+
+    fn text(console: &Console, idx: usize) -> String {
+        return console.lines[idx].iter().map(|(c, _)| c).collect();
+    }
+
+    // the first line in the view
+    fn top_line(console: &Console) -> String {
+        return text(console, console.lines.len() - console.rows() - console.scroll);
+    }
+
+    #[test]
+    fn scrolled_lines_are_kept() {
+        // 3 rows of 10 columns
+        let mut window = Window::new(80, 3 * LINE_HEIGHT, PixelFormat::Bgr);
+        let mut console = Console::new(window.writer(), (80, 3 * LINE_HEIGHT), &FG, &BG);
+        for i in 0..5 {
+            console.put_string(&format!("line {}\n", i));
+        }
+        assert_eq!(top_line(&console), "line 3");
+        assert!(console.scroll_by(2));
+        assert_eq!(top_line(&console), "line 1");
+        // it can't go further than the oldest line
+        assert!(console.scroll_by(10));
+        assert_eq!(top_line(&console), "line 0");
+        assert!(!console.scroll_by(1));
+        assert!(console.scroll_by(-10));
+        assert_eq!(top_line(&console), "line 3");
+    }
+
+    #[test]
+    fn view_stays_while_output_comes() {
+        let mut window = Window::new(80, 3 * LINE_HEIGHT, PixelFormat::Bgr);
+        let mut console = Console::new(window.writer(), (80, 3 * LINE_HEIGHT), &FG, &BG);
+        for i in 0..5 {
+            console.put_string(&format!("line {}\n", i));
+        }
+        console.scroll_by(1);
+        console.put_string("new\nnewer\n");
+        assert_eq!(top_line(&console), "line 2");
+    }
+
+    #[test]
+    fn backspace_is_kept_in_the_line() {
+        let mut window = Window::new(80, 3 * LINE_HEIGHT, PixelFormat::Bgr);
+        let mut console = Console::new(window.writer(), (80, 3 * LINE_HEIGHT), &FG, &BG);
+        console.put_string("abc\x08d");
+        assert_eq!(text(&console, console.lines.len() - console.rows()), "abd");
+    }
+
+    #[test]
+    fn scrollback_is_limited() {
+        let mut window = Window::new(80, 3 * LINE_HEIGHT, PixelFormat::Bgr);
+        let mut console = Console::new(window.writer(), (80, 3 * LINE_HEIGHT), &FG, &BG);
+        for _ in 0..SCROLLBACK_LINES + 10 {
+            console.put_string("x\n");
+        }
+        assert_eq!(console.lines.len(), console.rows() + SCROLLBACK_LINES);
+    }
+}
@@ -0,0 +1,76 @@
+use alloc::string::String;
+use core::arch::x86_64::{CpuidResult, __cpuid};
+
+#[derive(Clone, Copy, Debug)]
+pub enum Feature {
+    SSE,
+    SSE2,
+    APIC,
+    PAT,
+    X2APIC,
+    NX,
+    Page1GB,
+    Syscall,
+}
+
+impl Feature {
+    // (leaf, register index of ebx/ecx/edx, bit)
+    fn location(&self) -> (u32, usize, u32) {
+        return match self {
+            Feature::SSE => (0x00000001, 2, 25),
+            Feature::SSE2 => (0x00000001, 2, 26),
+            Feature::APIC => (0x00000001, 2, 9),
+            Feature::PAT => (0x00000001, 2, 16),
+            Feature::X2APIC => (0x00000001, 1, 21),
+            Feature::NX => (0x80000001, 2, 20),
+            Feature::Page1GB => (0x80000001, 2, 26),
+            Feature::Syscall => (0x80000001, 2, 11),
+        };
+    }
+}
+
+// the kernel can't run without these
+const REQUIRED_FEATURES: [Feature; 3] = [Feature::SSE, Feature::SSE2, Feature::APIC];
+
+// __cpuid is an unsafe fn on older toolchains
+#[allow(unused_unsafe)]
+fn cpuid(leaf: u32) -> CpuidResult {
+    return unsafe { __cpuid(leaf) };
+}
+
+fn max_leaf(leaf: u32) -> u32 {
+    return cpuid(leaf & 0x80000000).eax;
+}
+
+pub fn has_feature(feature: Feature) -> bool {
+    let (leaf, reg, bit) = feature.location();
+    if max_leaf(leaf) < leaf {
+        return false;
+    }
+    let result = cpuid(leaf);
+    let value = [result.ebx, result.ecx, result.edx][reg];
+    return (value >> bit) & 1 == 1;
+}
+
+pub fn check_required_features() {
+    for feature in REQUIRED_FEATURES {
+        if !has_feature(feature) {
+            panic!("this CPU doesn't support {:?}, which is required by Horse", feature);
+        }
+    }
+}
+
+pub fn brand_string() -> String {
+    if max_leaf(0x80000004) < 0x80000004 {
+        return String::from("unknown");
+    }
+    let mut bytes = [0u8; 48];
+    for (i, leaf) in (0x80000002..=0x80000004).enumerate() {
+        let result = cpuid(leaf);
+        for (j, reg) in [result.eax, result.ebx, result.ecx, result.edx].iter().enumerate() {
+            bytes[16 * i + 4 * j..16 * i + 4 * (j + 1)].copy_from_slice(&reg.to_le_bytes());
+        }
+    }
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    return String::from(String::from_utf8_lossy(&bytes[..len]).trim());
+}
@@ -1,5 +1,6 @@
 mod definition;
 use definition::*;
+pub use definition::{IdeDevice, InterfaceType};
 
 use crate::{
     drivers::{
@@ -11,12 +12,16 @@ use crate::{
         bytes::{bytes2str, negative},
         io::*,
         storage::*,
+        time::Duration,
     },
     print, println, sleep,
+    status::StatusCode,
 };
 
 use core::arch::asm;
 
+const ATAPI_SECTOR_SIZE: usize = 2048;
+
 const DEFAULT_IDE_DEVICE: IdeDevice = IdeDevice {
     reserved: 0,
     channel: 0,
@@ -38,6 +43,7 @@ const DEFAULT_CHANNEL_REGISTER: IdeChannelRegister = IdeChannelRegister {
 pub struct IdeController {
     pub ide_devices: [IdeDevice; 4],
     pub channels: [IdeChannelRegister; 2],
+    selected: usize,
 }
 
 impl IdeController {
@@ -45,8 +51,17 @@ impl IdeController {
         return Self {
             ide_devices: [DEFAULT_IDE_DEVICE; 4],
             channels: [DEFAULT_CHANNEL_REGISTER; 2],
+            selected: 0,
         };
     }
+    // iterate over the detected drives with their index
+    pub fn drives(&self) -> impl Iterator<Item = (usize, &IdeDevice)> {
+        return self
+            .ide_devices
+            .iter()
+            .enumerate()
+            .filter(|(_, device)| device.reserved == 1);
+    }
     fn ide_read(&self, channel: usize, reg: u16) -> u8 {
         let result: u8;
         if 0x07 < reg && reg < 0x0c {
@@ -385,48 +400,176 @@ impl IdeController {
         }
         return 0;
     }
+    fn ide_atapi_read(&mut self, drive: usize, lba: u32, numsects: u8, buf: &mut [u8]) -> u8 {
+        let channel: usize = self.ide_devices[drive].channel;
+        let slavebit: u8 = self.ide_devices[drive].drive;
+        let bus: u16 = self.channels[channel].base;
+        let mut err: u8;
+
+        // SCSI READ(12) command block
+        let packet: [u8; 12] = [
+            0xa8,
+            0,
+            ((lba >> 24) & 0xff) as u8,
+            ((lba >> 16) & 0xff) as u8,
+            ((lba >> 8) & 0xff) as u8,
+            (lba & 0xff) as u8,
+            0,
+            0,
+            0,
+            numsects,
+            0,
+            0,
+        ];
+        let mut packet_words = [0u16; 6];
+        for i in 0..6 {
+            packet_words[i] = u16::from_le_bytes([packet[2 * i], packet[2 * i + 1]]);
+        }
+
+        // Disable IRQs
+        self.channels[channel].no_int = 0x02;
+        self.ide_write(channel, Register::AtaRegControlAltstatus as u16, 0x02);
+
+        // Select the drive
+        self.ide_write(channel, Register::AtaRegHddevsel as u16, slavebit << 4);
+        for _ in 0..4 {
+            self.ide_read(channel, Register::AtaRegControlAltstatus as u16);
+        }
+
+        // PIO mode and the size of the transfer unit
+        self.ide_write(channel, Register::AtaRegErrorFeatures as u16, 0);
+        self.ide_write(channel, Register::AtaRegLba1 as u16, (ATAPI_SECTOR_SIZE & 0xff) as u8);
+        self.ide_write(channel, Register::AtaRegLba2 as u16, (ATAPI_SECTOR_SIZE >> 8) as u8);
+
+        // Send the packet command
+        self.ide_write(
+            channel,
+            Register::AtaRegCommandStatus as u16,
+            Command::AtaCmdPacket as u8,
+        );
+        err = self.ide_polling(channel, true);
+        if err != 0 {
+            return err;
+        }
+        unsafe {
+            outsw(bus, &packet_words, 6);
+        }
+
+        // Receive sectors
+        let mut sector = [0u16; ATAPI_SECTOR_SIZE / 2];
+        let mut offset = 0;
+        for _ in 0..numsects {
+            err = self.ide_polling(channel, true);
+            if err != 0 {
+                return err;
+            }
+            unsafe {
+                insw(bus, &mut sector, (ATAPI_SECTOR_SIZE / 2) as u32);
+            }
+            for word in sector.iter() {
+                for byte in word.to_le_bytes() {
+                    if offset < buf.len() {
+                        buf[offset] = byte;
+                    }
+                    offset += 1;
+                }
+            }
+        }
+
+        // Wait until the drive finishes the command
+        while self.ide_read(channel, Register::AtaRegCommandStatus as u16)
+            & (Status::AtaSrBsy as u8 | Status::AtaSrDrq as u8)
+            != 0
+        {}
+        return 0;
+    }
 }
 
 // TODO/ I have to adjust the size of buf because when it's not multiple of 512. this function may cause the Segment fault.
 impl Storage for IdeController {
-    fn read(&mut self, buf: &mut [u8], lba: u32, nbytes: usize) -> u8 {
-        let device = self.ide_devices[0];
+    fn read(&mut self, buf: &mut [u8], lba: u32, nbytes: usize) -> Result<usize, StatusCode> {
+        let drive = self.selected;
+        let device = self.ide_devices[drive];
         if device.reserved == 0 {
-            return 1;
+            return Err(StatusCode::NoDevice);
         }
-        let numsects: u8 = ((nbytes + 512) / 512 - 1).try_into().unwrap();
-        if lba + numsects as u32 > device.size && device.ata_type == InterfaceType::IdeAta as u16 {
-            return 2;
-        }
-        let mut err = 0;
+        let err;
         if device.ata_type == InterfaceType::IdeAta as u16 {
-            err = self.ide_access(Directions::Read as u8, 0, lba, numsects, buf.as_mut_ptr() as u32);
-        } else {
-            for i in 0..numsects {
-                err = self.ide_access(Directions::Read as u8, 0, lba + i as u32, 1, buf.as_mut_ptr() as u32);
+            let numsects: u8 = ((nbytes + 512) / 512 - 1).try_into().unwrap();
+            if lba + numsects as u32 > device.size {
+                return Err(StatusCode::IndexOutOfRange);
             }
+            err = self.ide_access(Directions::Read as u8, drive, lba, numsects, buf.as_mut_ptr() as u32);
+        } else {
+            // ATAPI devices use 2048-byte sectors, so lba is also counted in that unit
+            let numsects: u8 = ((nbytes + ATAPI_SECTOR_SIZE - 1) / ATAPI_SECTOR_SIZE)
+                .try_into()
+                .unwrap();
+            err = self.ide_atapi_read(drive, lba, numsects, &mut buf[..nbytes]);
         }
-        return self.ide_print_error(0, err);
+        return match self.ide_print_error(drive, err) {
+            0 => Ok(nbytes),
+            code => Err(ide_error_to_status(code)),
+        };
     }
-    fn write(&mut self, buf: &[u8], lba: u32, nbytes: usize) -> u8 {
-        let device = self.ide_devices[0];
+    fn write(&mut self, buf: &[u8], lba: u32, nbytes: usize) -> Result<usize, StatusCode> {
+        let drive = self.selected;
+        let device = self.ide_devices[drive];
         if device.reserved == 0 {
-            return 1;
-        }
-        if lba * 512 + nbytes as u32 > device.size && device.ata_type == InterfaceType::IdeAta as u16 {
-            return 2;
+            return Err(StatusCode::NoDevice);
         }
-        let mut err = 0;
-        let numsects: u8 = ((nbytes + 512) / 512 - 1).try_into().unwrap();
+        let err;
         if device.ata_type == InterfaceType::IdeAta as u16 {
-            err = self.ide_access(Directions::Write as u8, 0, lba, numsects, buf.as_ptr() as u32);
+            let numsects = sector_count(lba, nbytes, device.size)?;
+            err = self.ide_access(Directions::Write as u8, drive, lba, numsects, buf.as_ptr() as u32);
         } else {
-            return 4; // Write Protected
+            err = 4; // Write Protected
+        }
+        return match self.ide_print_error(drive, err) {
+            0 => Ok(nbytes),
+            code => Err(ide_error_to_status(code)),
+        };
+    }
+    fn select_drive(&mut self, drive: usize) -> Result<(), StatusCode> {
+        if drive >= self.ide_devices.len() {
+            return Err(StatusCode::IndexOutOfRange);
+        }
+        if self.ide_devices[drive].reserved != 1 {
+            return Err(StatusCode::NoDevice);
         }
-        return self.ide_print_error(0, err);
+        self.selected = drive;
+        return Ok(());
     }
 }
 
+// the sectors to write nbytes from the lba, rounded up to whole sectors.
+// the size of the device is in sectors too
+fn sector_count(lba: u32, nbytes: usize, size: u32) -> Result<u8, StatusCode> {
+    let numsects: u8 = ((nbytes + 511) / 512)
+        .try_into()
+        .map_err(|_| StatusCode::IndexOutOfRange)?;
+    if lba.checked_add(numsects as u32).map_or(true, |end| end > size) {
+        return Err(StatusCode::IndexOutOfRange);
+    }
+    return Ok(numsects);
+}
+
+// map the numeric codes returned by ide_print_error to StatusCode
+fn ide_error_to_status(err: u8) -> StatusCode {
+    return match err {
+        3 => StatusCode::MediaError,
+        7 => StatusCode::NoAddressMark,
+        8 => StatusCode::WriteProtected,
+        13 => StatusCode::BadSectors,
+        19 => StatusCode::DeviceFault,
+        20 => StatusCode::CommandAborted,
+        21 => StatusCode::IdMarkNotFound,
+        22 => StatusCode::UncorrectableData,
+        25 => StatusCode::ReadsNothing,
+        _ => StatusCode::Failure,
+    };
+}
+
 impl StorageController for IdeController {}
 
 pub fn initialize_ide(dev: &Device) -> IdeController {
@@ -476,7 +619,7 @@ pub fn initialize_ide(dev: &Device) -> IdeController {
 
             // Select Drive
             controller.ide_write(i, Register::AtaRegHddevsel as u16, 0xa0 | (j << 4));
-            sleep(1);
+            sleep(Duration::from_secs(1));
 
             status = controller.ide_read(i, Register::AtaRegCommandStatus as u16);
 
@@ -486,7 +629,7 @@ pub fn initialize_ide(dev: &Device) -> IdeController {
                 Register::AtaRegCommandStatus as u16,
                 Command::AtaCmdIdentify as u8,
             );
-            sleep(1);
+            sleep(Duration::from_secs(1));
 
             status = controller.ide_read(i, Register::AtaRegCommandStatus as u16);
 
@@ -504,7 +647,7 @@ pub fn initialize_ide(dev: &Device) -> IdeController {
                 {
                     break;
                 }
-                sleep(1);
+                sleep(Duration::from_secs(1));
             }
 
             // Probe for ATAPI device
@@ -524,7 +667,7 @@ pub fn initialize_ide(dev: &Device) -> IdeController {
                     Register::AtaRegCommandStatus as u16,
                     Command::AtaCmdIdentifyPacket as u8,
                 );
-                sleep(1);
+                sleep(Duration::from_secs(1));
             }
 
             // Read identification space of the device
@@ -589,25 +732,59 @@ pub fn initialize_ide(dev: &Device) -> IdeController {
     }
 
     // Print summary
-    for i in 0..4 {
-        let ide_device = controller.ide_devices[i];
-        if ide_device.reserved == 1 {
-            if ide_device.size < 1024 * 1024 * 2 {
-                println!(
-                    "found {} drive {}MB - {}",
-                    ["ATA", "ATAPI"][ide_device.ata_type as usize],
-                    ide_device.size / 1024 / 2,
-                    bytes2str(&ide_device.model)
-                );
-            } else {
-                println!(
-                    "found {} drive {}GB - {}",
-                    ["ATA", "ATAPI"][ide_device.ata_type as usize],
-                    ide_device.size / 1024 / 1024 / 2,
-                    bytes2str(&ide_device.model)
-                );
-            }
+    for (i, ide_device) in controller.drives() {
+        if ide_device.size < 1024 * 1024 * 2 {
+            println!(
+                "found {} drive {}MB - {} (drive {})",
+                ["ATA", "ATAPI"][ide_device.ata_type as usize],
+                ide_device.size / 1024 / 2,
+                bytes2str(&ide_device.model),
+                i
+            );
+        } else {
+            println!(
+                "found {} drive {}GB - {} (drive {})",
+                ["ATA", "ATAPI"][ide_device.ata_type as usize],
+                ide_device.size / 1024 / 1024 / 2,
+                bytes2str(&ide_device.model),
+                i
+            );
         }
     }
+
+    // Use the first detected drive by default
+    let first = controller.drives().next().map(|(i, _)| i);
+    if let Some(i) = first {
+        controller.selected = i;
+    }
     return controller;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 1GiB, with the partition at LBA 2048 as the FAT image has
+    const SIZE: u32 = 2 * 1024 * 1024;
+
+    #[test]
+    fn writes_past_the_partition_start_are_in_range() {
+        assert!(matches!(sector_count(2048, 512, SIZE), Ok(1)));
+        assert!(matches!(sector_count(SIZE / 2, 4096, SIZE), Ok(8)));
+        assert!(matches!(sector_count(SIZE - 8, 4096, SIZE), Ok(8)));
+    }
+
+    #[test]
+    fn partial_sectors_are_rounded_up() {
+        assert!(matches!(sector_count(0, 1, SIZE), Ok(1)));
+        assert!(matches!(sector_count(0, 513, SIZE), Ok(2)));
+    }
+
+    #[test]
+    fn writes_beyond_the_device_are_rejected() {
+        assert!(sector_count(SIZE - 7, 4096, SIZE).is_err());
+        assert!(sector_count(u32::MAX, 512, SIZE).is_err());
+        // more than a command can transfer
+        assert!(sector_count(0, 256 * 512, SIZE).is_err());
+    }
+}
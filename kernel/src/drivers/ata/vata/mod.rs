@@ -1,4 +1,4 @@
-use crate::{horse_lib::storage::Storage, memory_manager::*, drivers::fs::core::StorageController};
+use crate::{horse_lib::storage::Storage, memory_manager::*, drivers::fs::core::StorageController, status::StatusCode};
 
 use alloc::{vec, vec::Vec};
 
@@ -15,24 +15,24 @@ impl VataController {
 }
 
 impl Storage for VataController {
-    fn read(&mut self, buf: &mut [u8], lba: u32, nbytes: usize) -> u8 {
+    fn read(&mut self, buf: &mut [u8], lba: u32, nbytes: usize) -> Result<usize, StatusCode> {
         let idx = 512 * lba as usize;
         let idx_end = idx + nbytes;
-        if idx_end > self.data.len() {
-            buf.copy_from_slice(&self.data[idx..idx_end]);
-            return 0
+        if idx_end <= self.data.len() {
+            buf[..nbytes].copy_from_slice(&self.data[idx..idx_end]);
+            return Ok(nbytes)
         } else {
-            return 1
+            return Err(StatusCode::IndexOutOfRange)
         }
     }
-    fn write(&mut self, buf: &[u8], lba: u32, nbytes: usize) -> u8 {
+    fn write(&mut self, buf: &[u8], lba: u32, nbytes: usize) -> Result<usize, StatusCode> {
         let idx = 512 * lba as usize;
         let idx_end = idx + nbytes;
-        if idx_end as usize > self.data.len() {
-            self.data[idx..idx_end].copy_from_slice(buf);
-            return 0;
+        if idx_end <= self.data.len() {
+            self.data[idx..idx_end].copy_from_slice(&buf[..nbytes]);
+            return Ok(nbytes);
         } else {
-            return 1;
+            return Err(StatusCode::IndexOutOfRange);
         }
     }
 }
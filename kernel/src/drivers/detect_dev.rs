@@ -6,11 +6,12 @@ use super::{
         vata::VataController
     },
     fs::core::STORAGE_CONTROLLERS,
+    nvme::initialize_nvme,
     pci::{switch_echi2xhci, PciDevices},
     usb::xhci::{initialize_xhci, Controller},
     video::qemu::setup_qemu_card,
 };
-use crate::{info, warn};
+use crate::{error, info, warn};
 
 pub fn initialize_pci_devices(pci_devices: &PciDevices) -> Option<Controller> {
     let mut xhc = None;
@@ -21,6 +22,11 @@ pub fn initialize_pci_devices(pci_devices: &PciDevices) -> Option<Controller> {
                 0x01 => {
                     STORAGE_CONTROLLERS.lock().push(Box::new(initialize_ide(&dev)));
                 }
+                // Non-Volatile Memory Controller
+                0x08 => match initialize_nvme(&dev) {
+                    Ok(controller) => STORAGE_CONTROLLERS.lock().push(Box::new(controller)),
+                    Err(code) => error!("failed to initialize NVMe controller: {}", code),
+                },
                 _ => {
                     info!(
                         "pci device isn't supported. Class Code: {:?}",
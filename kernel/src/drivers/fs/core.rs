@@ -1,5 +1,6 @@
 use alloc::{
     boxed::Box,
+    string::String,
     vec::Vec
 };
 use spin::Mutex;
@@ -12,7 +13,8 @@ use crate::{
     drivers::ata::{
         pata::IdeController,
         vata::VataController
-    }
+    },
+    syscall::errno::{EINVAL, ENODEV, ENOTDIR, EPERM, ESPIPE},
 };
 
 pub enum DiskType {
@@ -24,10 +26,87 @@ pub trait StorageController: Storage + Send + Sync {}
 pub static STORAGE_CONTROLLERS: Mutex<Vec<Box<dyn StorageController>>> = Mutex::new(Vec::new());
 pub static FILE_DESCRIPTOR_TABLE: Mutex<FDTable> = Mutex::new(FDTable::DEFAULT_TABLE);
 
+// whence of lseek
+pub const SEEK_SET: u32 = 0;
+pub const SEEK_CUR: u32 = 1;
+pub const SEEK_END: u32 = 2;
+
+// the offset which lseek moves to in a file of the size. the offset can be beyond the end
+pub fn seek_offset(current: usize, size: usize, offset: i64, whence: u32) -> Result<usize, i32> {
+    let base = match whence {
+        SEEK_SET => 0,
+        SEEK_CUR => current,
+        SEEK_END => size,
+        _ => return Err(EINVAL)
+    };
+    let new = base as i64 + offset;
+    if new < 0 {
+        return Err(EINVAL)
+    }
+    return Ok(new as usize)
+}
+
+// an entry listed by read_dir
+pub struct DirEntry {
+    pub name: String,
+    pub is_dir: bool,
+    pub size: usize
+}
+
 pub trait FileSystem {
     //fn create();
-    //fn remove();
+    fn mount_point(&self) -> &str;
     fn open(&self, path: &str, flags: u32) -> i32;
     fn close(&self, fd: i32);
+    // write back the buffered data of the file. called before the last fd of the file is closed
+    fn flush(&self, _fd: i32) -> isize {
+        return 0
+    }
     fn read(&self, fd: i32, buf: &mut [u8], nbytes: usize) -> isize;
+    fn write(&self, fd: i32, buf: &[u8], nbytes: usize) -> isize;
+    // set the length of the file, shrinking or growing it with zeros. the error is errno
+    fn truncate(&self, _fd: i32, _length: usize) -> Result<(), i32> {
+        return Err(EINVAL)
+    }
+    // remove the file at the path. the error is errno
+    fn unlink(&self, _path: &str) -> Result<(), i32> {
+        return Err(EPERM)
+    }
+    // move the file to the path in the same file system, replacing the file there
+    fn rename(&self, _from: &str, _to: &str) -> Result<(), i32> {
+        return Err(EPERM)
+    }
+    // the entries of the directory at the path. the error is errno
+    fn read_dir(&self, _path: &str) -> Result<Vec<DirEntry>, i32> {
+        return Err(ENOTDIR)
+    }
+    // move the offset of the fd and return the new one. the file systems which don't use
+    // the offset can't seek. the error is errno
+    fn seek(&self, _fd: i32, _offset: i64, _whence: u32) -> Result<usize, i32> {
+        return Err(ESPIPE)
+    }
+    // make the range of the file accessible from ring 3 and return its address. the error is errno
+    fn mmap(&self, _fd: i32, _offset: usize, _len: usize) -> Result<u64, i32> {
+        return Err(ENODEV)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seek_is_relative_to_whence() {
+        assert_eq!(seek_offset(10, 100, 5, SEEK_SET), Ok(5));
+        assert_eq!(seek_offset(10, 100, 5, SEEK_CUR), Ok(15));
+        assert_eq!(seek_offset(10, 100, -5, SEEK_END), Ok(95));
+        // beyond the end is allowed
+        assert_eq!(seek_offset(10, 100, 5, SEEK_END), Ok(105));
+    }
+
+    #[test]
+    fn invalid_seeks_are_rejected() {
+        assert_eq!(seek_offset(10, 100, -11, SEEK_CUR), Err(EINVAL));
+        assert_eq!(seek_offset(10, 100, 0, 3), Err(EINVAL));
+    }
 }
\ No newline at end of file
@@ -0,0 +1,174 @@
+use alloc::{string::String, vec::Vec};
+use core::{cmp::min, ptr::copy_nonoverlapping};
+
+use crate::{
+    drivers::fs::core::{seek_offset, DirEntry, FileSystem, FILE_DESCRIPTOR_TABLE},
+    graphics::Graphics,
+    horse_lib::fd::File,
+    memory_manager::BYTES_PER_FRAME,
+    paging::set_user_accessible,
+    syscall::errno::{EINVAL, ENODEV, ENOENT, ENOMEM, ENOTDIR},
+};
+
+const MOUNT_POINT: &str = "/dev";
+
+#[derive(Clone, Copy, PartialEq)]
+enum Device {
+    Null,
+    Zero,
+    FrameBuffer,
+}
+
+impl Device {
+    const ALL: [Device; 3] = [Device::Null, Device::Zero, Device::FrameBuffer];
+
+    fn from_name(name: &str) -> Option<Self> {
+        return Self::ALL.into_iter().find(|device| device.name() == name);
+    }
+    // the devices are directly under /dev, so /dev/x/null isn't null
+    fn from_path(path: &str) -> Option<Self> {
+        let name = path.strip_prefix(MOUNT_POINT)?.strip_prefix('/')?;
+        return Self::from_name(name);
+    }
+    fn name(&self) -> &'static str {
+        return match self {
+            Device::Null => "null",
+            Device::Zero => "zero",
+            Device::FrameBuffer => "fb0",
+        };
+    }
+    // the size of the device file in bytes. only the framebuffer has its size
+    fn size(&self) -> usize {
+        return match self {
+            Device::FrameBuffer => Graphics::instance().frame_buffer().size(),
+            _ => 0,
+        };
+    }
+}
+
+// file system for the devices provided by the kernel, mounted at /dev
+pub struct DevFS;
+
+impl DevFS {
+    pub fn new() -> Self {
+        return Self;
+    }
+    fn device(fd: i32) -> Option<Device> {
+        let file = FILE_DESCRIPTOR_TABLE.lock().get(fd);
+        return Device::from_path(&file.path.as_string());
+    }
+    // the part of the framebuffer from the offset of the fd, which is advanced by the length
+    fn frame_buffer_range(fd: i32, nbytes: usize) -> (*mut u8, usize) {
+        let fb = Graphics::instance().frame_buffer();
+        let mut table = FILE_DESCRIPTOR_TABLE.lock();
+        let offset = min(table.offset(fd), fb.size());
+        let nbytes = min(nbytes, fb.size() - offset);
+        table.set_offset(fd, offset + nbytes);
+        return (unsafe { fb.config.fb.add(offset) }, nbytes);
+    }
+}
+
+impl FileSystem for DevFS {
+    fn mount_point(&self) -> &str {
+        return MOUNT_POINT;
+    }
+    fn open(&self, path: &str, flags: u32) -> i32 {
+        if Device::from_path(path).is_none() {
+            return -ENOENT;
+        }
+        return FILE_DESCRIPTOR_TABLE.lock().add(File::new(flags, path));
+    }
+    fn close(&self, fd: i32) {
+        FILE_DESCRIPTOR_TABLE.lock().remove(fd);
+    }
+    fn read(&self, fd: i32, buf: &mut [u8], nbytes: usize) -> isize {
+        let nbytes = min(nbytes, buf.len());
+        match Self::device(fd) {
+            // always EOF
+            Some(Device::Null) => return 0,
+            Some(Device::Zero) => {
+                buf[..nbytes].fill(0);
+                return nbytes as isize;
+            }
+            // 0 at the end of the framebuffer
+            Some(Device::FrameBuffer) => {
+                let (src, nbytes) = Self::frame_buffer_range(fd, nbytes);
+                unsafe { copy_nonoverlapping(src, buf.as_mut_ptr(), nbytes) };
+                return nbytes as isize;
+            }
+            None => return -1,
+        }
+    }
+    fn write(&self, fd: i32, buf: &[u8], nbytes: usize) -> isize {
+        let nbytes = min(nbytes, buf.len());
+        match Self::device(fd) {
+            // written data is discarded
+            Some(Device::Null) | Some(Device::Zero) => return nbytes as isize,
+            // nothing is written at the end of the framebuffer
+            Some(Device::FrameBuffer) => {
+                let (dst, nbytes) = Self::frame_buffer_range(fd, nbytes);
+                unsafe { copy_nonoverlapping(buf.as_ptr(), dst, nbytes) };
+                return nbytes as isize;
+            }
+            None => return -1,
+        }
+    }
+    // there are no subdirectories
+    fn read_dir(&self, path: &str) -> Result<Vec<DirEntry>, i32> {
+        if path.trim_end_matches('/') != self.mount_point() {
+            return Err(if Device::from_path(path).is_some() { ENOTDIR } else { ENOENT });
+        }
+        return Ok(Device::ALL
+            .iter()
+            .map(|device| DirEntry {
+                name: String::from(device.name()),
+                is_dir: false,
+                size: device.size(),
+            })
+            .collect());
+    }
+    // null and zero stay at 0 as on Linux
+    fn seek(&self, fd: i32, offset: i64, whence: u32) -> Result<usize, i32> {
+        let size = match Self::device(fd) {
+            Some(Device::FrameBuffer) => Device::FrameBuffer.size(),
+            Some(_) => return Ok(0),
+            None => return Err(ENOENT),
+        };
+        let mut table = FILE_DESCRIPTOR_TABLE.lock();
+        let new = seek_offset(table.offset(fd), size, offset, whence)?;
+        table.set_offset(fd, new);
+        return Ok(new);
+    }
+    // only the framebuffer can be mapped. the processes share the identity mapped page table,
+    // so the address is the framebuffer itself and it stays accessible from ring 3 after unmapping
+    fn mmap(&self, fd: i32, offset: usize, len: usize) -> Result<u64, i32> {
+        if Self::device(fd) != Some(Device::FrameBuffer) {
+            return Err(ENODEV);
+        }
+        let fb = Graphics::instance().frame_buffer();
+        if offset % BYTES_PER_FRAME != 0 || offset.checked_add(len).map_or(true, |end| end > fb.size()) {
+            return Err(EINVAL);
+        }
+        let start = fb.config.fb as u64 + offset as u64;
+        let first_page = start & !(BYTES_PER_FRAME as u64 - 1);
+        for page in (first_page..start + len as u64).step_by(BYTES_PER_FRAME) {
+            unsafe { set_user_accessible(page, true).map_err(|_| ENOMEM)? };
+        }
+        return Ok(start);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn devices_are_found_by_the_full_path() {
+        assert!(Device::from_path("/dev/null") == Some(Device::Null));
+        assert!(Device::from_path("/dev/fb0") == Some(Device::FrameBuffer));
+        assert!(Device::from_path("/dev/x/null").is_none());
+        assert!(Device::from_path("/tmp/null").is_none());
+        assert!(Device::from_path("/devnull").is_none());
+        assert!(Device::from_path("/dev").is_none());
+    }
+}
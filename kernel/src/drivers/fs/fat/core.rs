@@ -1,23 +1,34 @@
 use alloc::{
     format,
-    slice,
     vec,
+    vec::Vec,
     string::String,
 };
-use core::mem::size_of;
+use core::{
+    cmp::min,
+    mem::{size_of, take},
+};
 
 use crate::{
     drivers::fs::core::{
-        FileSystem,
+        DirEntry, FileSystem,
         STORAGE_CONTROLLERS, FILE_DESCRIPTOR_TABLE
     },
-    horse_lib::{fd::{
+    horse_lib::fd::{
         File,
+        OpenFlags,
         Path
-    }, bytes::bytes2str}
+    },
+    status::StatusCode,
+    syscall::errno::{
+        EBUSY, EFBIG, EINVAL, EIO, EISDIR, ENAMETOOLONG, ENOENT, ENOSPC, ENOTDIR, ENOTEMPTY
+    },
 };
 
 const END_OF_CLUSTER_CHAIN: u32 = 0x0fffffff;
+const FREE_CLUSTER: u32 = 0;
+// the upper 4 bits of a FAT entry are reserved and must be kept
+const FAT_ENTRY_MASK: u32 = 0x0fffffff;
 
 #[repr(C, packed)]
 #[derive(Clone, Copy, Debug)]
@@ -73,6 +84,34 @@ impl DirectoryEntry {
         return self.fst_clus_lo as u32 | ((self.fst_clus_hi as u32) << 16) 
     }
     pub fn file_size(&self) -> u32 { self.file_size }
+    fn set_first_cluster(&mut self, cluster: u32) {
+        self.fst_clus_lo = cluster as u16;
+        self.fst_clus_hi = (cluster >> 16) as u16;
+    }
+}
+
+// where a directory entry is stored, to rewrite or delete it
+#[derive(Clone, Debug)]
+struct EntryLocation {
+    cluster: u32,
+    index: usize,
+    // (cluster, index) of the Long File Name entries before it
+    lfn_slots: Vec<(u32, usize)>
+}
+
+impl EntryLocation {
+    // all the slots of the entry in the order on the disk
+    fn slots(&self) -> Vec<(u32, usize)> {
+        let mut slots = self.lfn_slots.clone();
+        slots.push((self.cluster, self.index));
+        return slots
+    }
+}
+
+// copy an entry into a 32 bytes slot of a directory
+fn write_slot<T: Copy>(slot: &mut [u8], value: T) {
+    debug_assert_eq!(slot.len(), size_of::<T>());
+    unsafe { (slot.as_mut_ptr() as *mut T).write_unaligned(value) };
 }
 
 #[repr(C, packed)]
@@ -89,11 +128,48 @@ struct LFNEntry {
 }
 
 impl LFNEntry {
-    pub fn ord(&self) -> usize {
-        return (self.ord ^ 0x40) as usize
-    }
+    // the entry of the last part, which comes first in the directory
     pub fn is_end(&self) -> bool {
-        return (self.ord & 0x40) != 1
+        return (self.ord & 0x40) != 0
+    }
+    // the parts of the name in the order on the disk, which is from the last part.
+    // the name is terminated by 0x0000 when it has room and padded with 0xffff
+    fn from_name(name: &str, sfn: &[u8; 11]) -> Vec<Self> {
+        let mut units: Vec<u16> = name.encode_utf16().collect();
+        let count = (units.len() + 12) / 13;
+        if units.len() % 13 != 0 {
+            units.push(0x0000);
+        }
+        units.resize(count * 13, 0xffff);
+        let checksum = sfn.iter().fold(0u8, |sum, &c| sum.rotate_right(1).wrapping_add(c));
+        return units
+            .chunks(13)
+            .enumerate()
+            .rev()
+            .map(|(i, part)| {
+                let mut bytes = [0u8; 26];
+                for (j, unit) in part.iter().enumerate() {
+                    bytes[2 * j..2 * j + 2].copy_from_slice(&unit.to_le_bytes());
+                }
+                let mut entry = Self {
+                    ord: i as u8 + 1,
+                    name1: [0; 10],
+                    attr: FATFileAttribute::LongName as u8,
+                    lfn_type: 0,
+                    checksum,
+                    name2: [0; 12],
+                    fst_cluster: 0,
+                    name3: [0; 4]
+                };
+                if i == count - 1 {
+                    entry.ord |= 0x40;
+                }
+                entry.name1.copy_from_slice(&bytes[..10]);
+                entry.name2.copy_from_slice(&bytes[10..22]);
+                entry.name3.copy_from_slice(&bytes[22..]);
+                entry
+            })
+            .collect()
     }
     pub fn get_name(&self) -> [u8; 26] {
         let mut name = [0u8; 26];
@@ -102,6 +178,17 @@ impl LFNEntry {
         name[22..26].copy_from_slice(&self.name3);
         return name;
     }
+    // the name is UTF-16 which ends with 0x0000 and is padded with 0xffff
+    pub fn name_part(&self) -> String {
+        let name = self.get_name();
+        let units = name
+            .chunks(2)
+            .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+            .take_while(|&unit| unit != 0x0000 && unit != 0xffff);
+        return char::decode_utf16(units)
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect()
+    }
 }
 
 
@@ -134,23 +221,94 @@ impl FAT {
         let sector_num = self.bpb.rsvd_sec_cnt as u32  + self.bpb.num_fats as u32 * self.bpb.fatsz32 + (cluster - 2) * self.bpb.sec_per_clus as u32;
         return sector_num * self.bpb.bytes_per_sec as u32
     }
-    pub fn get_cluster(&self, cluster: u32, buf: &mut [u8]) {
+    pub fn get_cluster(&self, cluster: u32, buf: &mut [u8]) -> Result<usize, StatusCode> {
         let offset = self.get_cluster_offset(cluster);
         let lba = offset / 512;
         let nbytes = self.bpc;
-        STORAGE_CONTROLLERS.lock()[self.storage_id].read(buf, lba, nbytes);
+        return STORAGE_CONTROLLERS.lock()[self.storage_id].read(buf, lba, nbytes)
     }
-    fn next_cluster(&self, cluster: u32) -> u32 {
+    fn write_cluster(&self, cluster: u32, buf: &[u8]) -> Result<(), i32> {
+        let lba = self.get_cluster_offset(cluster) / 512;
+        return STORAGE_CONTROLLERS.lock()[self.storage_id]
+            .write(buf, lba, self.bpc)
+            .map(|_| ())
+            .map_err(|_| EIO)
+    }
+    // a failed read is EIO, so that the chain isn't cut short silently
+    fn next_cluster(&self, cluster: u32) -> Result<u32, i32> {
         let offset = self.bpb.rsvd_sec_cnt as u32 * self.bpb.bytes_per_sec as u32 + 4 * cluster;
         let lba = offset / 512;
         let padding = offset as usize % 512;
         let mut buf = vec![0; 512];
-        STORAGE_CONTROLLERS.lock()[self.storage_id].read(&mut buf, lba, 512);
+        STORAGE_CONTROLLERS.lock()[self.storage_id].read(&mut buf, lba, 512).map_err(|_| EIO)?;
         let next = u32::from_le_bytes(buf[padding..padding+4].try_into().unwrap());
         if next >= 0x0ffffff8 {
-            return END_OF_CLUSTER_CHAIN
+            return Ok(END_OF_CLUSTER_CHAIN)
+        }
+        return Ok(next)
+    }
+    // the value is written to every copy of the FAT
+    fn set_fat_entry(&self, cluster: u32, value: u32) -> Result<(), i32> {
+        let fat_bytes = self.bpb.fatsz32 * self.bpb.bytes_per_sec as u32;
+        let mut buf = vec![0; 512];
+        for i in 0..self.bpb.num_fats as u32 {
+            let offset = self.bpb.rsvd_sec_cnt as u32 * self.bpb.bytes_per_sec as u32 + i * fat_bytes + 4 * cluster;
+            let lba = offset / 512;
+            let padding = offset as usize % 512;
+            let mut storage = STORAGE_CONTROLLERS.lock();
+            storage[self.storage_id].read(&mut buf, lba, 512).map_err(|_| EIO)?;
+            let old = u32::from_le_bytes(buf[padding..padding+4].try_into().unwrap());
+            let new = (old & !FAT_ENTRY_MASK) | (value & FAT_ENTRY_MASK);
+            buf[padding..padding+4].copy_from_slice(&new.to_le_bytes());
+            storage[self.storage_id].write(&buf, lba, 512).map_err(|_| EIO)?;
+        }
+        return Ok(())
+    }
+    fn count_of_clusters(&self) -> u32 {
+        let data_sectors = self.bpb.tot_sec32
+            - (self.bpb.rsvd_sec_cnt as u32 + self.bpb.num_fats as u32 * self.bpb.fatsz32);
+        return data_sectors / self.bpb.sec_per_clus as u32
+    }
+    // find a free cluster in the first FAT, mark it as the end of a chain and fill it with zeros.
+    // FSInfo isn't updated because its hints are optional
+    fn allocate_cluster(&self) -> Result<u32, i32> {
+        let fat_start = self.bpb.rsvd_sec_cnt as u32 * self.bpb.bytes_per_sec as u32;
+        let last_cluster = self.count_of_clusters() + 1;
+        let mut buf = vec![0u8; 512];
+        let mut cluster = 2;
+        while cluster <= last_cluster {
+            let offset = fat_start + 4 * cluster;
+            let padding = offset as usize % 512;
+            if padding == 0 || cluster == 2 {
+                STORAGE_CONTROLLERS.lock()[self.storage_id]
+                    .read(&mut buf, offset / 512, 512)
+                    .map_err(|_| EIO)?;
+            }
+            let entry = u32::from_le_bytes(buf[padding..padding+4].try_into().unwrap());
+            if entry & FAT_ENTRY_MASK == FREE_CLUSTER {
+                self.set_fat_entry(cluster, END_OF_CLUSTER_CHAIN)?;
+                self.write_cluster(cluster, &vec![0u8; self.bpc])?;
+                return Ok(cluster)
+            }
+            cluster += 1;
+        }
+        return Err(ENOSPC)
+    }
+    fn free_chain(&self, first_cluster: u32) -> Result<(), i32> {
+        for cluster in self.cluster_chain(first_cluster)? {
+            self.set_fat_entry(cluster, FREE_CLUSTER)?;
+        }
+        return Ok(())
+    }
+    // the clusters of the chain from the first one. 0 means the empty file
+    fn cluster_chain(&self, first_cluster: u32) -> Result<Vec<u32>, i32> {
+        let mut chain = Vec::new();
+        let mut cluster = first_cluster;
+        while cluster != FREE_CLUSTER && cluster != END_OF_CLUSTER_CHAIN {
+            chain.push(cluster);
+            cluster = self.next_cluster(cluster)?;
         }
-        return next
+        return Ok(chain)
     }
     
     fn sfn_cmp(sfn: [u8; 11], name: &str) -> bool {
@@ -177,68 +335,313 @@ impl FAT {
         }
         return name.chars().count() == i && sfn[..] == name83[..]
     }
-    pub fn find_file(&self, full_path: &Path) -> Result<DirectoryEntry, u8> {
-        let mut entry: DirectoryEntry;
-        let mut dir_clus = self.bpb.root_clus;
-        let mut lfn_flag = false;
-        let mut lfn = String::from("");
-        let mut i = 0;
-        let mut name = &full_path.path[i];
+    fn is_sfn_char(c: char) -> bool {
+        return c.is_ascii_uppercase() || c.is_ascii_digit() || "!#$%&'()-@^_`{}~".contains(c)
+    }
+    // the 8.3 name which is the same as the name. None when the name needs Long File Name,
+    // including the lowercase names whose case would be lost
+    fn exact_sfn(name: &str) -> Option<[u8; 11]> {
+        let (base, ext) = name.split_once('.').unwrap_or((name, ""));
+        if base.is_empty() || base.len() > 8 || ext.len() > 3 || name.ends_with('.') {
+            return None
+        }
+        if !base.chars().chain(ext.chars()).all(Self::is_sfn_char) {
+            return None
+        }
+        let mut sfn = [0x20; 11];
+        sfn[..base.len()].copy_from_slice(base.as_bytes());
+        sfn[8..8 + ext.len()].copy_from_slice(ext.as_bytes());
+        return Some(sfn)
+    }
+    // "BASE~N  EXT" for the long name, which isn't used in the directory
+    fn alias_sfn(name: &str, used: &[[u8; 11]]) -> Result<[u8; 11], i32> {
+        let (base, ext) = match name.rfind('.') {
+            Some(i) if i > 0 => (&name[..i], &name[i + 1..]),
+            _ => (name, "")
+        };
+        let to_sfn = |s: &str| -> Vec<u8> {
+            s.chars()
+                .map(|c| c.to_ascii_uppercase())
+                .filter(|&c| Self::is_sfn_char(c))
+                .map(|c| c as u8)
+                .collect()
+        };
+        let (base, ext) = (to_sfn(base), to_sfn(ext));
+        let ext_len = min(ext.len(), 3);
+        for n in 1..1000000 {
+            let tail = format!("~{}", n);
+            let base_len = min(base.len(), 8 - tail.len());
+            let mut sfn = [0x20; 11];
+            sfn[..base_len].copy_from_slice(&base[..base_len]);
+            sfn[base_len..base_len + tail.len()].copy_from_slice(tail.as_bytes());
+            sfn[8..8 + ext_len].copy_from_slice(&ext[..ext_len]);
+            if !used.contains(&sfn) {
+                return Ok(sfn)
+            }
+        }
+        return Err(ENOSPC)
+    }
+    fn validate_name(name: &str) -> Result<(), i32> {
+        if name.encode_utf16().count() > 255 {
+            return Err(ENAMETOOLONG)
+        }
+        if name.is_empty() || name == "." || name == ".." || name.chars().any(|c| c < ' ' || "\"*/:<>?\\|".contains(c)) {
+            return Err(EINVAL)
+        }
+        return Ok(())
+    }
+    // "NAME    EXT" to "NAME.EXT"
+    fn sfn_to_string(sfn: [u8; 11]) -> String {
+        let base = String::from_utf8_lossy(&sfn[..8]);
+        let ext = String::from_utf8_lossy(&sfn[8..]);
+        let (base, ext) = (base.trim_end(), ext.trim_end());
+        if ext.is_empty() {
+            return String::from(base)
+        }
+        return format!("{}.{}", base, ext)
+    }
+    // the entries in the directory with their names. the long name is used when the entry has it.
+    // deleted entries, the volume label, "." and ".." are skipped
+    fn list_directory(&self, first_cluster: u32) -> Result<Vec<(String, DirectoryEntry, EntryLocation)>, u8> {
+        let mut entries = Vec::new();
+        let mut lfn = String::new();
+        let mut lfn_slots = Vec::new();
+        let mut dir_clus = first_cluster;
         let mut buf = vec![0u8; self.bpc];
         while dir_clus != END_OF_CLUSTER_CHAIN {
-            self.get_cluster(dir_clus, &mut buf);
-            dir_clus = self.next_cluster(dir_clus);
-            for c in 0..self.bpc as usize / size_of::<DirectoryEntry>() {
+            if self.get_cluster(dir_clus, &mut buf).is_err() {
+                return Err(2) // failed to read the directory
+            }
+            let cluster = dir_clus;
+            dir_clus = match self.next_cluster(dir_clus) {
+                Ok(next) => next,
+                Err(_) => return Err(2)
+            };
+            for c in 0..self.bpc / size_of::<DirectoryEntry>() {
                 let entry_ptr = unsafe { (buf.as_ptr() as *const DirectoryEntry).add(c) };
-                unsafe { entry = *entry_ptr; }
-                // Long File Name
+                let entry = unsafe { *entry_ptr };
+                match entry.name[0] {
+                    // the rest of the directory is unused
+                    0x00 => return Ok(entries),
+                    // deleted
+                    0xe5 => {
+                        lfn.clear();
+                        lfn_slots.clear();
+                        continue
+                    }
+                    _ => {}
+                }
+                // Long File Name. the parts come from the last one
                 if entry.attr == (FATFileAttribute::LongName as u8) {
-                    crate::debug!("Long File Name");
                     let lfn_entry = unsafe { *(entry_ptr as *const LFNEntry) };
-                        if lfn_entry.is_end() {
-                            lfn = String::new();
-                        } else if lfn_entry.ord() == 1 {
-                            lfn_flag = true;
-                        }
-                    lfn = format!("{}{}", bytes2str(&lfn_entry.get_name()), lfn);
-                    continue
-                // Volumen ID
-                } else if entry.attr & 0x08 != 0 {
-                    crate::debug!("Volume Name");
-                // Directory
-                } else if entry.attr & 0x10 != 0 {
-                    crate::debug!("Directory");
-                    if (lfn_flag && &lfn == name) || (!lfn_flag && Self::sfn_cmp(entry.name, &name)) {
-                        if i == full_path.path.len()-1 {
-                            return Ok(entry)
-                        } else {
-                            dir_clus = entry.first_cluster();
-                            i += 1;
-                            name = &full_path.path[i];
-                            break
-                        }
+                    if lfn_entry.is_end() {
+                        lfn.clear();
+                        lfn_slots.clear();
                     }
-                // Regular File
+                    lfn.insert_str(0, &lfn_entry.name_part());
+                    lfn_slots.push((cluster, c));
+                    continue
+                }
+                let name = if lfn.is_empty() {
+                    Self::sfn_to_string(entry.name)
                 } else {
-                    crate::debug!("Regular File");
-                    if (lfn_flag && &lfn == name) || (!lfn_flag && Self::sfn_cmp(entry.name, &name)) {
-                        if i == full_path.path.len()-1 {
-                            return Ok(entry)
-                        } else {
-                            return Err(1)
-                        }
-                    }
+                    take(&mut lfn)
+                };
+                let location = EntryLocation { cluster, index: c, lfn_slots: take(&mut lfn_slots) };
+                if entry.attr & (FATFileAttribute::VolumeId as u8) != 0 || name == "." || name == ".." {
+                    continue
                 }
-                if lfn_flag {lfn_flag = false}
+                entries.push((name, entry, location));
+            }
+        }
+        return Ok(entries)
+    }
+    pub fn find_file(&self, full_path: &Path) -> Result<DirectoryEntry, u8> {
+        return self.find_entry(full_path).map(|(entry, _)| entry)
+    }
+    fn find_entry(&self, full_path: &Path) -> Result<(DirectoryEntry, EntryLocation), u8> {
+        // absolute paths begin with an empty component
+        let names: Vec<&String> = full_path.path.iter().filter(|name| !name.is_empty()).collect();
+        let mut dir_clus = self.bpb.root_clus;
+        for (i, name) in names.iter().enumerate() {
+            let found = self.list_directory(dir_clus)?
+                .into_iter()
+                .find(|(entry_name, entry, _)| entry_name == *name || Self::sfn_cmp(entry.name, name));
+            let (entry, location) = match found {
+                Some((_, entry, location)) => (entry, location),
+                None => return Err(3)
+            };
+            if i == names.len() - 1 {
+                return Ok((entry, location))
+            }
+            if entry.attr & (FATFileAttribute::Directory as u8) == 0 {
+                return Err(1) // a regular file in the middle of the path
             }
+            dir_clus = entry.first_cluster();
         }
+        // the root directory has no entry
         return Err(3)
     }
+    // the cluster of the directory which has the last component of the path, and the component
+    fn parent_directory(&self, path: &Path) -> Result<(u32, String), i32> {
+        let names: Vec<&str> = path.path.iter().map(|name| name.as_str()).filter(|name| !name.is_empty()).collect();
+        // the root directory has no parent
+        let (name, parents) = names.split_last().ok_or(EINVAL)?;
+        if parents.is_empty() {
+            return Ok((self.bpb.root_clus, String::from(*name)))
+        }
+        let parent = self.find_file(&Path::new(parents.join("/"))).map_err(Self::errno)?;
+        if parent.attr & (FATFileAttribute::Directory as u8) == 0 {
+            return Err(ENOTDIR)
+        }
+        return Ok((parent.first_cluster(), String::from(*name)))
+    }
+    // apply f to each slot with its index in slots. the slots must be in the order on the disk
+    fn update_slots(&self, slots: &[(u32, usize)], mut f: impl FnMut(usize, &mut [u8])) -> Result<(), i32> {
+        let entry_size = size_of::<DirectoryEntry>();
+        let mut buf = vec![0u8; self.bpc];
+        let mut i = 0;
+        while i < slots.len() {
+            let cluster = slots[i].0;
+            self.get_cluster(cluster, &mut buf).map_err(|_| EIO)?;
+            while i < slots.len() && slots[i].0 == cluster {
+                let offset = slots[i].1 * entry_size;
+                f(i, &mut buf[offset..offset + entry_size]);
+                i += 1;
+            }
+            self.write_cluster(cluster, &buf)?;
+        }
+        return Ok(())
+    }
+    fn write_entry(&self, location: &EntryLocation, entry: &DirectoryEntry) -> Result<(), i32> {
+        return self.update_slots(&[(location.cluster, location.index)], |_, slot| write_slot(slot, *entry))
+    }
+    fn delete_entry(&self, location: &EntryLocation) -> Result<(), i32> {
+        return self.update_slots(&location.slots(), |_, slot| slot[0] = 0xe5)
+    }
+    // free slots in a row. the directory is extended when it doesn't have enough of them
+    fn free_slots(&self, dir_cluster: u32, count: usize) -> Result<Vec<(u32, usize)>, i32> {
+        let per_cluster = self.bpc / size_of::<DirectoryEntry>();
+        let mut run = Vec::new();
+        let mut buf = vec![0u8; self.bpc];
+        let mut last = dir_cluster;
+        for cluster in self.cluster_chain(dir_cluster)? {
+            self.get_cluster(cluster, &mut buf).map_err(|_| EIO)?;
+            for i in 0..per_cluster {
+                match buf[i * size_of::<DirectoryEntry>()] {
+                    0x00 | 0xe5 => run.push((cluster, i)),
+                    _ => run.clear()
+                }
+                if run.len() == count {
+                    return Ok(run)
+                }
+            }
+            last = cluster;
+        }
+        while run.len() < count {
+            let cluster = self.allocate_cluster()?;
+            self.set_fat_entry(last, cluster)?;
+            last = cluster;
+            run.extend((0..per_cluster).map(|i| (cluster, i)).take(count - run.len()));
+        }
+        return Ok(run)
+    }
+    // write the entry with the name into the directory. Long File Name is added when the name needs it
+    fn create_entry(&self, dir_cluster: u32, name: &str, mut entry: DirectoryEntry) -> Result<(), i32> {
+        let (sfn, lfn) = match Self::exact_sfn(name) {
+            Some(sfn) => (sfn, Vec::new()),
+            None => {
+                let used: Vec<[u8; 11]> = self.list_directory(dir_cluster)
+                    .map_err(Self::errno)?
+                    .iter()
+                    .map(|(_, entry, _)| entry.name)
+                    .collect();
+                let sfn = Self::alias_sfn(name, &used)?;
+                (sfn, LFNEntry::from_name(name, &sfn))
+            }
+        };
+        entry.name = sfn;
+        let slots = self.free_slots(dir_cluster, lfn.len() + 1)?;
+        return self.update_slots(&slots, |i, slot| match lfn.get(i) {
+            Some(lfn_entry) => write_slot(slot, *lfn_entry),
+            None => write_slot(slot, entry)
+        })
+    }
+    // ".." is the second entry of a directory, and it has 0 for the root directory
+    fn set_parent(&self, dir_cluster: u32, parent_cluster: u32) -> Result<(), i32> {
+        let parent_cluster = if parent_cluster == self.bpb.root_clus { 0 } else { parent_cluster };
+        return self.update_slots(&[(dir_cluster, 1)], |_, slot| {
+            let mut entry = unsafe { (slot.as_ptr() as *const DirectoryEntry).read_unaligned() };
+            entry.set_first_cluster(parent_cluster);
+            write_slot(slot, entry);
+        })
+    }
+    // set the length of the file. the clusters beyond it are freed, and the file grows with zeros.
+    // the new clusters are allocated rather than leaving a hole because FAT has no sparse files
+    fn set_len(&self, path: &Path, length: usize) -> Result<(), i32> {
+        let length: u32 = length.try_into().map_err(|_| EFBIG)?;
+        let (mut entry, location) = self.find_entry(path).map_err(Self::errno)?;
+        if entry.attr & (FATFileAttribute::Directory as u8) != 0 {
+            return Err(EINVAL)
+        }
+        let old_length = entry.file_size();
+        let mut chain = self.cluster_chain(entry.first_cluster())?;
+        let needed = (length as usize + self.bpc - 1) / self.bpc;
+        if needed < chain.len() {
+            if needed == 0 {
+                entry.set_first_cluster(FREE_CLUSTER);
+            } else {
+                self.set_fat_entry(chain[needed - 1], END_OF_CLUSTER_CHAIN)?;
+            }
+            for &cluster in &chain[needed..] {
+                self.set_fat_entry(cluster, FREE_CLUSTER)?;
+            }
+            chain.truncate(needed);
+        }
+        // the bytes after the old end in its cluster may be left from before
+        if length > old_length && old_length as usize % self.bpc != 0 {
+            if let Some(&cluster) = chain.get(old_length as usize / self.bpc) {
+                let mut buf = vec![0u8; self.bpc];
+                self.get_cluster(cluster, &mut buf).map_err(|_| EIO)?;
+                buf[old_length as usize % self.bpc..].fill(0);
+                self.write_cluster(cluster, &buf)?;
+            }
+        }
+        while chain.len() < needed {
+            let cluster = self.allocate_cluster()?;
+            match chain.last() {
+                Some(&last) => self.set_fat_entry(last, cluster)?,
+                None => entry.set_first_cluster(cluster),
+            }
+            chain.push(cluster);
+        }
+        entry.file_size = length;
+        return self.write_entry(&location, &entry)
+    }
+    fn errno(code: u8) -> i32 {
+        return match code {
+            1 => ENOTDIR,
+            2 => EIO,
+            _ => ENOENT
+        }
+    }
 }
 
 impl FileSystem for FAT {
+    fn mount_point(&self) -> &str {
+        return "/"
+    }
     fn open(&self, path: &str, flags: u32) -> i32 {
         let file = File::new(flags, path);
+        if let Err(code) = self.find_file(&file.path) {
+            return -Self::errno(code)
+        }
+        let writable = flags & (OpenFlags::WROnly as u32 | OpenFlags::RDWR as u32) != 0;
+        if flags & OpenFlags::Truncate as u32 != 0 && writable {
+            if let Err(errno) = self.set_len(&file.path, 0) {
+                return -errno
+            }
+        }
         return FILE_DESCRIPTOR_TABLE.lock().add(file)
     }
     fn close(&self, fd: i32) {
@@ -246,24 +649,133 @@ impl FileSystem for FAT {
     }
     fn read(&self, fd: i32, buf: &mut [u8], nbytes: usize) -> isize {
         let file = FILE_DESCRIPTOR_TABLE.lock().get(fd);
-        let entry = self.find_file(&file.path).unwrap();
+        let entry = match self.find_file(&file.path) {
+            Ok(entry) => entry,
+            Err(_) => return -1
+        };
         if entry.attr & 0x08 != 0 || entry.attr & 0x10 != 0 {
             return -1
         }
+        // the last cluster may have bytes after the end of the file
+        let nbytes = min(nbytes, entry.file_size() as usize);
         let mut cluster = entry.first_cluster();
-        let mut i = 0;
+        let mut total = 0;
         let mut bytes_buf = vec![0u8; self.bpc];
-        while cluster != END_OF_CLUSTER_CHAIN {
-            self.get_cluster(cluster, &mut bytes_buf);
-            if self.bpc*(i+1) <= nbytes {
-                buf[self.bpc*i..self.bpc*(i+1)].copy_from_slice(&bytes_buf);
-            } else {
-                buf[self.bpc*i..nbytes].copy_from_slice(&bytes_buf[..(nbytes-self.bpc*i)]);
+        while cluster != END_OF_CLUSTER_CHAIN && total < nbytes {
+            let nread = match self.get_cluster(cluster, &mut bytes_buf) {
+                Ok(n) => n,
+                Err(_) => return -1
+            };
+            let len = min(nread, nbytes - total);
+            buf[total..total+len].copy_from_slice(&bytes_buf[..len]);
+            total += len;
+            if nread < self.bpc {
                 break
             }
-            cluster = self.next_cluster(cluster);
-            i += 1;
+            cluster = match self.next_cluster(cluster) {
+                Ok(next) => next,
+                Err(_) => return -1
+            };
+        }
+        return total as isize
+    }
+    // TODO: implement writing to FAT
+    fn write(&self, _fd: i32, _buf: &[u8], _nbytes: usize) -> isize {
+        return -1
+    }
+    fn truncate(&self, fd: i32, length: usize) -> Result<(), i32> {
+        let file = FILE_DESCRIPTOR_TABLE.lock().get(fd);
+        return self.set_len(&file.path, length)
+    }
+    // the file must not be open, because its clusters are freed at once
+    fn unlink(&self, path: &str) -> Result<(), i32> {
+        let path = Path::new(String::from(path));
+        let (entry, location) = self.find_entry(&path).map_err(Self::errno)?;
+        if entry.attr & (FATFileAttribute::Directory as u8) != 0 {
+            return Err(EISDIR)
+        }
+        if FILE_DESCRIPTOR_TABLE.lock().is_path_open(&path) {
+            return Err(EBUSY)
         }
-        return nbytes as isize
+        // the clusters are freed after the entry, so a failure in between only leaks them
+        self.delete_entry(&location)?;
+        return self.free_chain(entry.first_cluster())
     }
-}
\ No newline at end of file
+    // an existing file at the new path is replaced by rewriting its entry in place,
+    // so the new path always refers to either the old file or the moved one
+    fn rename(&self, from: &str, to: &str) -> Result<(), i32> {
+        let from = Path::new(String::from(from));
+        let to = Path::new(String::from(to));
+        let (entry, location) = self.find_entry(&from).map_err(Self::errno)?;
+        let is_dir = entry.attr & (FATFileAttribute::Directory as u8) != 0;
+        let (from_parent, _) = self.parent_directory(&from)?;
+        let (to_parent, name) = self.parent_directory(&to)?;
+        Self::validate_name(&name)?;
+        // a directory can't be moved into itself
+        let components = |path: &Path| -> Vec<String> {
+            path.path.iter().filter(|name| !name.is_empty()).cloned().collect()
+        };
+        if is_dir && components(&to).starts_with(&components(&from)) && components(&to) != components(&from) {
+            return Err(EINVAL)
+        }
+        {
+            let table = FILE_DESCRIPTOR_TABLE.lock();
+            if table.is_path_open(&from) || table.is_path_open(&to) {
+                return Err(EBUSY)
+            }
+        }
+        match self.find_entry(&to) {
+            Ok((target, target_location)) => {
+                if (target_location.cluster, target_location.index) == (location.cluster, location.index) {
+                    return Ok(())
+                }
+                let target_is_dir = target.attr & (FATFileAttribute::Directory as u8) != 0;
+                if target_is_dir && !is_dir {
+                    return Err(EISDIR)
+                }
+                if !target_is_dir && is_dir {
+                    return Err(ENOTDIR)
+                }
+                if target_is_dir && !self.list_directory(target.first_cluster()).map_err(Self::errno)?.is_empty() {
+                    return Err(ENOTEMPTY)
+                }
+                // the target keeps its name and takes the rest from the source
+                let mut replaced = entry;
+                replaced.name = target.name;
+                self.write_entry(&target_location, &replaced)?;
+                self.delete_entry(&location)?;
+                self.free_chain(target.first_cluster())?;
+            }
+            Err(3) => {
+                self.create_entry(to_parent, &name, entry)?;
+                self.delete_entry(&location)?;
+            }
+            Err(code) => return Err(Self::errno(code))
+        }
+        if is_dir && from_parent != to_parent {
+            self.set_parent(entry.first_cluster(), to_parent)?;
+        }
+        return Ok(())
+    }
+    fn read_dir(&self, path: &str) -> Result<Vec<DirEntry>, i32> {
+        let path = Path::new(String::from(path));
+        let cluster = if path.path.iter().all(|name| name.is_empty()) {
+            self.bpb.root_clus
+        } else {
+            let entry = self.find_file(&path).map_err(Self::errno)?;
+            if entry.attr & (FATFileAttribute::Directory as u8) == 0 {
+                return Err(ENOTDIR)
+            }
+            entry.first_cluster()
+        };
+        let entries = self.list_directory(cluster).map_err(Self::errno)?;
+        return Ok(entries
+            .into_iter()
+            .map(|(name, entry, _)| DirEntry {
+                name,
+                is_dir: entry.attr & (FATFileAttribute::Directory as u8) != 0,
+                size: entry.file_size() as usize
+            })
+            .collect())
+    }
+}
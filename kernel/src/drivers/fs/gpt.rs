@@ -52,14 +52,18 @@ impl GPT {
     // TODO: I have to implement process fpr the recovery field
     pub fn new(id: usize) -> Option<Self> {
         let mut header_buf = [0; 512];
-        STORAGE_CONTROLLERS.lock()[id].read(&mut header_buf, 1, 512);
+        if STORAGE_CONTROLLERS.lock()[id].read(&mut header_buf, 1, 512).is_err() {
+            return None
+        }
         let header = unsafe { *(header_buf.as_mut_ptr() as *mut PartitionTableHeader) };
         if !header.validate() {
             return None
         }
 
         let mut array_buf = [0; 128*128];
-        STORAGE_CONTROLLERS.lock()[id].read(&mut array_buf, 2, header.num_entries as usize * 128);
+        if STORAGE_CONTROLLERS.lock()[id].read(&mut array_buf, 2, header.num_entries as usize * 128).is_err() {
+            return None
+        }
         let mut entries = vec![];
         for i in 0..header.num_entries {
             entries.push(unsafe { *(array_buf[128*i as usize..128*(i as usize +1)].as_mut_ptr() as *mut PartitionEntry) })
@@ -1,5 +1,7 @@
 use alloc::{
     boxed::Box,
+    string::String,
+    vec,
     vec::Vec
 };
 use spin::Mutex;
@@ -12,22 +14,130 @@ use crate::{
         storage::Storage
     }
 };
+use crate::syscall::errno::{EBUSY, EINVAL, EIO, EMFILE, ENOENT};
 use super::{
-    core::{FileSystem, STORAGE_CONTROLLERS},
+    core::{DirEntry, FileSystem, FILE_DESCRIPTOR_TABLE, STORAGE_CONTROLLERS},
+    dev::DevFS,
     fat::core::{
         BPB,
         FAT,
     },
-    gpt::GPT
+    gpt::GPT,
+    tmp::TmpFS
 };
 
 pub static mut FILESYSTEM_TABLE: Mutex<Vec<Box<dyn FileSystem>>> = Mutex::new(Vec::new());
 
 pub fn initialize_filesystem() {
+    unsafe { FILESYSTEM_TABLE.lock().push(Box::new(DevFS::new())) };
     let nstorage = STORAGE_CONTROLLERS.lock().len();
     for id in 0..nstorage {
         initialize_storage(id);
     }
+    if let Err(errno) = mount(Box::new(TmpFS::new("/tmp"))) {
+        error!("failed to mount tmpfs: {}", errno);
+    }
+}
+
+// add the file system to the table. the error is errno
+pub fn mount(fs: Box<dyn FileSystem>) -> Result<(), i32> {
+    let mut table = unsafe { FILESYSTEM_TABLE.lock() };
+    let mount_point = fs.mount_point().trim_end_matches('/');
+    if table.iter().any(|mounted| mounted.mount_point().trim_end_matches('/') == mount_point) {
+        return Err(EBUSY)
+    }
+    table.push(fs);
+    return Ok(())
+}
+
+// remove the file system mounted at the path and drop it, which frees its memory.
+// it fails with EBUSY while any file in it is open
+pub fn unmount(mount_point: &str) -> Result<(), i32> {
+    let mount_point = mount_point.trim_end_matches('/');
+    // the root file system can't be unmounted
+    if mount_point.is_empty() {
+        return Err(EBUSY)
+    }
+    let mut table = unsafe { FILESYSTEM_TABLE.lock() };
+    let idx = table
+        .iter()
+        .position(|fs| fs.mount_point().trim_end_matches('/') == mount_point)
+        .ok_or(EINVAL)?;
+    if FILE_DESCRIPTOR_TABLE.lock().is_open_under(mount_point) {
+        return Err(EBUSY)
+    }
+    drop(table.remove(idx));
+    return Ok(())
+}
+
+// find the file system whose mount point is the longest prefix of the path
+pub fn find_filesystem(path: &str) -> Option<usize> {
+    let table = unsafe { FILESYSTEM_TABLE.lock() };
+    let mut found: Option<(usize, usize)> = None;
+    for (i, fs) in table.iter().enumerate() {
+        let mount_point = fs.mount_point().trim_end_matches('/');
+        let matched = path.starts_with(mount_point)
+            && (path.len() == mount_point.len() || path[mount_point.len()..].starts_with('/'));
+        if matched && found.map_or(true, |(_, len)| len < mount_point.len()) {
+            found = Some((i, mount_point.len()));
+        }
+    }
+    return found.map(|(i, _)| i)
+}
+
+// read the whole file. the file systems read from the start of the file every time,
+// so the buffer is grown until the file fits in it. the error is errno
+pub fn read_file(path: &str) -> Result<Vec<u8>, i32> {
+    let idx = find_filesystem(path).ok_or(ENOENT)?;
+    let table = unsafe { FILESYSTEM_TABLE.lock() };
+    let fd = table[idx].open(path, 0);
+    if fd == -1 {
+        return Err(EMFILE)
+    } else if fd < 0 {
+        return Err(-fd)
+    }
+    let mut buf = vec![0; 4096];
+    let result = loop {
+        let len = buf.len();
+        let nread = table[idx].read(fd, &mut buf, len);
+        if nread < 0 {
+            break Err(EIO)
+        }
+        if (nread as usize) < len {
+            buf.truncate(nread as usize);
+            break Ok(buf)
+        }
+        buf.resize(len * 2, 0);
+    };
+    table[idx].close(fd);
+    return result
+}
+
+// the entries of the directory, including the mount points in it
+pub fn read_dir(path: &str) -> Result<Vec<DirEntry>, i32> {
+    let idx = find_filesystem(path).ok_or(ENOENT)?;
+    let table = unsafe { FILESYSTEM_TABLE.lock() };
+    let mut entries = table[idx].read_dir(path)?;
+    let dir = path.trim_end_matches('/');
+    for fs in table.iter() {
+        let mount_point = fs.mount_point().trim_end_matches('/');
+        if let Some((parent, name)) = mount_point.rsplit_once('/') {
+            if parent == dir && !entries.iter().any(|entry| entry.name == name) {
+                entries.push(DirEntry { name: String::from(name), is_dir: true, size: 0 });
+            }
+        }
+    }
+    return Ok(entries)
+}
+
+// the entry of the file in its directory, so the mount points are found too. the error is errno
+pub fn stat(path: &str) -> Result<DirEntry, i32> {
+    let (dir, name) = match path.trim_end_matches('/').rsplit_once('/') {
+        Some((dir, name)) => (if dir.is_empty() { "/" } else { dir }, name),
+        // the root directory isn't in any directory
+        None => return Ok(DirEntry { name: String::new(), is_dir: true, size: 0 })
+    };
+    return read_dir(dir)?.into_iter().find(|entry| entry.name == name).ok_or(ENOENT)
 }
 
 pub fn initialize_storage(id: usize) {
@@ -51,7 +161,10 @@ fn initialize_gpt(gpt: GPT, id: usize) {
 
 fn initialize_partition(id: usize) -> Option<Box<dyn FileSystem>> {
     let mut buf = [0; 512];
-    STORAGE_CONTROLLERS.lock()[id].read(&mut buf, 0, 512);
+    if let Err(code) = STORAGE_CONTROLLERS.lock()[id].read(&mut buf, 0, 512) {
+        error!("failed to read the boot sector: {}", code);
+        return None
+    }
     let bpb = unsafe { *(buf.as_mut_ptr() as *mut BPB) };
     let fsys = &bytes2str(&bpb.fil_sys_type);
     if &fsys[0..5] == "FAT32" {
@@ -1,4 +1,6 @@
 pub mod core;
+pub mod dev;
 pub mod fat;
 pub mod gpt;
 pub mod init;
+pub mod tmp;
@@ -0,0 +1,245 @@
+use alloc::{string::String, vec::Vec};
+use core::cmp::min;
+use spin::Mutex;
+
+use crate::{
+    drivers::fs::core::{DirEntry, FileSystem, FILE_DESCRIPTOR_TABLE},
+    horse_lib::{
+        fd::{File, OpenFlags, Path},
+        rbtree::RBTree,
+    },
+    syscall::errno::{EBUSY, EISDIR, ENOENT, ENOTDIR},
+};
+
+// file system in the heap, which has only the files in its root directory.
+// the files are lost when it's unmounted
+pub struct TmpFS {
+    mount_point: String,
+    // the contents of the files by the names
+    files: Mutex<RBTree<String, Vec<u8>>>,
+}
+
+impl TmpFS {
+    pub fn new(mount_point: &str) -> Self {
+        return Self {
+            mount_point: String::from(mount_point.trim_end_matches('/')),
+            files: Mutex::new(RBTree::new()),
+        };
+    }
+    // the name of the file in the root directory. the root directory itself is ""
+    fn name<'a>(&self, path: &'a str) -> &'a str {
+        return path[self.mount_point.len()..].trim_matches('/');
+    }
+    fn name_of(&self, fd: i32) -> String {
+        let path = FILE_DESCRIPTOR_TABLE.lock().get(fd).path.as_string();
+        return String::from(self.name(&path));
+    }
+    fn is_open(path: &str) -> bool {
+        return FILE_DESCRIPTOR_TABLE.lock().is_path_open(&Path::new(String::from(path)));
+    }
+}
+
+impl FileSystem for TmpFS {
+    fn mount_point(&self) -> &str {
+        return &self.mount_point;
+    }
+    fn open(&self, path: &str, flags: u32) -> i32 {
+        let name = String::from(self.name(path));
+        if name.is_empty() {
+            return -EISDIR;
+        }
+        // there are no subdirectories
+        if name.contains('/') {
+            return -ENOENT;
+        }
+        let writable = flags & (OpenFlags::WROnly as u32 | OpenFlags::RDWR as u32) != 0;
+        let mut files = self.files.lock();
+        match files.get_mut(&name) {
+            Some(data) if flags & OpenFlags::Truncate as u32 != 0 && writable => data.clear(),
+            Some(_) => {}
+            None if flags & OpenFlags::Create as u32 != 0 => files.insert(name, Vec::new()),
+            None => return -ENOENT,
+        }
+        return FILE_DESCRIPTOR_TABLE.lock().add(File::new(flags, path));
+    }
+    fn close(&self, fd: i32) {
+        FILE_DESCRIPTOR_TABLE.lock().remove(fd);
+    }
+    // always from the start of the file as the other file systems do
+    fn read(&self, fd: i32, buf: &mut [u8], nbytes: usize) -> isize {
+        let name = self.name_of(fd);
+        let files = self.files.lock();
+        let data = match files.get(&name) {
+            Some(data) => data,
+            None => return -1,
+        };
+        let nbytes = min(min(nbytes, buf.len()), data.len());
+        buf[..nbytes].copy_from_slice(&data[..nbytes]);
+        return nbytes as isize;
+    }
+    // the bytes are appended to the file, so open it with O_TRUNC to overwrite it
+    fn write(&self, fd: i32, buf: &[u8], nbytes: usize) -> isize {
+        let file = FILE_DESCRIPTOR_TABLE.lock().get(fd);
+        if file.f_mode & (OpenFlags::WROnly as u32 | OpenFlags::RDWR as u32) == 0 {
+            return -1;
+        }
+        let name = String::from(self.name(&file.path.as_string()));
+        let mut files = self.files.lock();
+        let data = match files.get_mut(&name) {
+            Some(data) => data,
+            None => return -1,
+        };
+        let nbytes = min(nbytes, buf.len());
+        data.extend_from_slice(&buf[..nbytes]);
+        return nbytes as isize;
+    }
+    fn truncate(&self, fd: i32, length: usize) -> Result<(), i32> {
+        let name = self.name_of(fd);
+        let mut files = self.files.lock();
+        let data = files.get_mut(&name).ok_or(ENOENT)?;
+        data.resize(length, 0);
+        data.shrink_to_fit();
+        return Ok(());
+    }
+    // the file must not be open as FAT, and its memory is freed at once
+    fn unlink(&self, path: &str) -> Result<(), i32> {
+        let name = String::from(self.name(path));
+        if name.is_empty() {
+            return Err(EISDIR);
+        }
+        let mut files = self.files.lock();
+        if !files.contains_key(&name) {
+            return Err(ENOENT);
+        }
+        if Self::is_open(path) {
+            return Err(EBUSY);
+        }
+        files.remove(&name);
+        return Ok(());
+    }
+    fn rename(&self, from: &str, to: &str) -> Result<(), i32> {
+        let from_name = String::from(self.name(from));
+        let to_name = String::from(self.name(to));
+        // the root directory is the mount point
+        if from_name.is_empty() {
+            return Err(EBUSY);
+        }
+        if to_name.is_empty() {
+            return Err(EISDIR);
+        }
+        if to_name.contains('/') {
+            return Err(ENOENT);
+        }
+        let mut files = self.files.lock();
+        if !files.contains_key(&from_name) {
+            return Err(ENOENT);
+        }
+        if from_name == to_name {
+            return Ok(());
+        }
+        if Self::is_open(from) || Self::is_open(to) {
+            return Err(EBUSY);
+        }
+        // the tree can have the same key twice, so the replaced file is removed first
+        files.remove(&to_name);
+        let data = files.remove(&from_name).unwrap();
+        files.insert(to_name, data);
+        return Ok(());
+    }
+    fn read_dir(&self, path: &str) -> Result<Vec<DirEntry>, i32> {
+        let name = self.name(path);
+        let files = self.files.lock();
+        if !name.is_empty() {
+            return Err(if files.contains_key(&String::from(name)) { ENOTDIR } else { ENOENT });
+        }
+        return Ok(files
+            .iter()
+            .map(|(name, data)| DirEntry {
+                name: name.clone(),
+                is_dir: false,
+                size: data.len(),
+            })
+            .collect());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::boxed::Box;
+    use crate::drivers::fs::init::{find_filesystem, mount, read_file, unmount, FILESYSTEM_TABLE};
+
+    const RDWR_CREATE: u32 = OpenFlags::RDWR as u32 | OpenFlags::Create as u32;
+
+    // the table is shared by the tests, so every test uses its own mount point
+    fn tmpfs(mount_point: &str) -> TmpFS {
+        let mut table = FILE_DESCRIPTOR_TABLE.lock();
+        if !table.is_open(0) {
+            table.initialize();
+        }
+        return TmpFS::new(mount_point);
+    }
+
+    #[test]
+    fn written_bytes_are_read_back() {
+        let fs = tmpfs("/tmp_rw");
+        let fd = fs.open("/tmp_rw/a", RDWR_CREATE);
+        assert!(fd >= 0);
+        assert_eq!(fs.write(fd, b"hello", 5), 5);
+        assert_eq!(fs.write(fd, b" world", 6), 6);
+        fs.close(fd);
+        let fd = fs.open("/tmp_rw/a", OpenFlags::RDOnly as u32);
+        let mut buf = [0; 16];
+        assert_eq!(fs.read(fd, &mut buf, 16), 11);
+        assert_eq!(&buf[..11], b"hello world");
+        // read only
+        assert_eq!(fs.write(fd, b"!", 1), -1);
+        fs.close(fd);
+    }
+
+    #[test]
+    fn missing_files_are_created_only_with_o_creat() {
+        let fs = tmpfs("/tmp_create");
+        assert_eq!(fs.open("/tmp_create/a", OpenFlags::RDWR as u32), -ENOENT);
+        assert_eq!(fs.open("/tmp_create/d/a", RDWR_CREATE), -ENOENT);
+        assert_eq!(fs.open("/tmp_create", RDWR_CREATE), -EISDIR);
+        let fd = fs.open("/tmp_create/a", RDWR_CREATE);
+        assert!(fd >= 0);
+        fs.close(fd);
+        let entries = fs.read_dir("/tmp_create").unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].name, "a");
+        assert_eq!(entries[0].size, 0);
+    }
+
+    #[test]
+    fn open_files_cant_be_unlinked() {
+        let fs = tmpfs("/tmp_unlink");
+        let fd = fs.open("/tmp_unlink/a", RDWR_CREATE);
+        assert_eq!(fs.unlink("/tmp_unlink/a"), Err(EBUSY));
+        fs.close(fd);
+        assert_eq!(fs.unlink("/tmp_unlink/a"), Ok(()));
+        assert_eq!(fs.unlink("/tmp_unlink/a"), Err(ENOENT));
+        assert_eq!(fs.open("/tmp_unlink/a", OpenFlags::RDOnly as u32), -ENOENT);
+        assert!(fs.read_dir("/tmp_unlink").unwrap().is_empty());
+    }
+
+    #[test]
+    fn files_are_reached_through_the_mount_table() {
+        let mount_point = "/tmp_mounted";
+        mount(Box::new(tmpfs(mount_point))).unwrap();
+        let path = "/tmp_mounted/a";
+        let idx = find_filesystem(path).unwrap();
+        {
+            let table = unsafe { FILESYSTEM_TABLE.lock() };
+            let fd = table[idx].open(path, RDWR_CREATE);
+            assert_eq!(table[idx].write(fd, b"data", 4), 4);
+            table[idx].close(fd);
+        }
+        assert_eq!(read_file(path), Ok(b"data".to_vec()));
+        unsafe { FILESYSTEM_TABLE.lock()[idx].unlink(path).unwrap() };
+        assert_eq!(read_file(path), Err(ENOENT));
+        assert_eq!(unmount(mount_point), Ok(()));
+        assert!(find_filesystem(path).is_none());
+    }
+}
@@ -1,7 +1,10 @@
 pub mod ata;
 pub mod detect_dev;
 pub mod fs;
+pub mod nvme;
 pub mod pci;
+pub mod ps2;
+pub mod serial;
 pub mod timer;
 pub mod usb;
 pub mod video;
@@ -0,0 +1,382 @@
+use core::{
+    cmp::min,
+    hint::spin_loop,
+    mem::size_of,
+    ptr::{read_volatile, write_bytes, write_volatile},
+};
+
+use crate::{
+    drivers::{fs::core::StorageController, pci::*},
+    horse_lib::storage::Storage,
+    info,
+    lapic::LocalApic,
+    memory_manager::{frame_manager_instance, BYTES_PER_FRAME},
+    status::StatusCode,
+    status_log, InterruptVector,
+};
+
+// controller registers
+const REG_CAP: usize = 0x00;
+const REG_VS: usize = 0x08;
+const REG_CC: usize = 0x14;
+const REG_CSTS: usize = 0x1c;
+const REG_AQA: usize = 0x24;
+const REG_ASQ: usize = 0x28;
+const REG_ACQ: usize = 0x30;
+const DOORBELL_BASE: usize = 0x1000;
+
+const CC_ENABLE: u32 = 1;
+// 64-byte submission entries and 16-byte completion entries
+const CC_IOSQES: u32 = 6 << 16;
+const CC_IOCQES: u32 = 4 << 20;
+const CSTS_READY: u32 = 1;
+const CSTS_FATAL: u32 = 1 << 1;
+
+// admin commands
+const ADMIN_CREATE_IO_SQ: u8 = 0x01;
+const ADMIN_CREATE_IO_CQ: u8 = 0x05;
+const ADMIN_IDENTIFY: u8 = 0x06;
+const IDENTIFY_NAMESPACE: u32 = 0x00;
+const IDENTIFY_CONTROLLER: u32 = 0x01;
+
+// I/O commands
+const IO_WRITE: u8 = 0x01;
+const IO_READ: u8 = 0x02;
+
+const ADMIN_QUEUE_ID: u16 = 0;
+const IO_QUEUE_ID: u16 = 1;
+// one frame holds each queue
+const QUEUE_SIZE: u16 = (BYTES_PER_FRAME / size_of::<SubmissionEntry>()) as u16;
+const COMMAND_TIMEOUT: usize = 10_000_000;
+// the lba of Storage is counted in this unit
+const SECTOR_SIZE: usize = 512;
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct SubmissionEntry {
+    cdw0: u32,
+    nsid: u32,
+    reserved: u64,
+    mptr: u64,
+    prp1: u64,
+    prp2: u64,
+    cdw10: u32,
+    cdw11: u32,
+    cdw12: u32,
+    cdw13: u32,
+    cdw14: u32,
+    cdw15: u32,
+}
+
+impl SubmissionEntry {
+    fn new(opcode: u8, nsid: u32) -> Self {
+        return Self {
+            cdw0: opcode as u32,
+            nsid,
+            ..Default::default()
+        };
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct CompletionEntry {
+    dw0: u32,
+    dw1: u32,
+    sq_head: u16,
+    sq_id: u16,
+    cid: u16,
+    // bit 0 is the phase tag
+    status: u16,
+}
+
+// a submission queue and its completion queue, each of them is placed in a frame
+struct QueuePair {
+    id: u16,
+    sq: usize,
+    cq: usize,
+    sq_tail: u16,
+    cq_head: u16,
+    phase: u16,
+    next_cid: u16,
+}
+
+impl QueuePair {
+    fn new(id: u16) -> Result<Self, StatusCode> {
+        return Ok(Self {
+            id,
+            sq: allocate_frame()?,
+            cq: allocate_frame()?,
+            sq_tail: 0,
+            cq_head: 0,
+            phase: 1,
+            next_cid: 0,
+        });
+    }
+}
+
+fn allocate_frame() -> Result<usize, StatusCode> {
+    let frame = frame_manager_instance().allocate(1)?;
+    let addr = frame.phys_addr();
+    unsafe { write_bytes(addr, 0, BYTES_PER_FRAME) };
+    return Ok(addr as usize);
+}
+
+pub struct NvmeController {
+    mmio_base: usize,
+    doorbell_stride: usize,
+    admin: QueuePair,
+    io: QueuePair,
+    nsid: u32,
+    // size of a logical block in bytes, 512 or 4096 for most devices
+    block_size: usize,
+    nblocks: u64,
+    // DMA buffer of a frame for the data transfer
+    buffer: usize,
+}
+
+impl NvmeController {
+    fn read_reg32(&self, offset: usize) -> u32 {
+        return unsafe { read_volatile((self.mmio_base + offset) as *const u32) };
+    }
+    fn write_reg32(&self, offset: usize, value: u32) {
+        unsafe { write_volatile((self.mmio_base + offset) as *mut u32, value) };
+    }
+    fn read_reg64(&self, offset: usize) -> u64 {
+        return unsafe { read_volatile((self.mmio_base + offset) as *const u64) };
+    }
+    fn write_reg64(&self, offset: usize, value: u64) {
+        unsafe { write_volatile((self.mmio_base + offset) as *mut u64, value) };
+    }
+
+    fn wait_ready(&self, ready: bool) -> Result<(), StatusCode> {
+        for _ in 0..COMMAND_TIMEOUT {
+            let csts = self.read_reg32(REG_CSTS);
+            if csts & CSTS_FATAL != 0 {
+                return Err(StatusCode::DeviceFault);
+            }
+            if (csts & CSTS_READY != 0) == ready {
+                return Ok(());
+            }
+            spin_loop();
+        }
+        return Err(StatusCode::Timeout);
+    }
+
+    // submit a command and poll its completion. returns dw0 of the completion entry
+    fn execute(&mut self, io: bool, mut command: SubmissionEntry) -> Result<u32, StatusCode> {
+        let stride = self.doorbell_stride;
+        let mmio_base = self.mmio_base;
+        let queue = if io { &mut self.io } else { &mut self.admin };
+
+        let cid = queue.next_cid;
+        queue.next_cid = queue.next_cid.wrapping_add(1);
+        command.cdw0 |= (cid as u32) << 16;
+        unsafe {
+            write_volatile((queue.sq as *mut SubmissionEntry).add(queue.sq_tail as usize), command);
+        }
+        queue.sq_tail = (queue.sq_tail + 1) % QUEUE_SIZE;
+        let sq_doorbell = mmio_base + DOORBELL_BASE + 2 * queue.id as usize * stride;
+        unsafe { write_volatile(sq_doorbell as *mut u32, queue.sq_tail as u32) };
+
+        let entry = queue.cq as *const CompletionEntry;
+        for _ in 0..COMMAND_TIMEOUT {
+            let completion = unsafe { read_volatile(entry.add(queue.cq_head as usize)) };
+            if completion.status & 1 != queue.phase {
+                spin_loop();
+                continue;
+            }
+            queue.cq_head += 1;
+            if queue.cq_head == QUEUE_SIZE {
+                queue.cq_head = 0;
+                queue.phase ^= 1;
+            }
+            let cq_doorbell = mmio_base + DOORBELL_BASE + (2 * queue.id as usize + 1) * stride;
+            unsafe { write_volatile(cq_doorbell as *mut u32, queue.cq_head as u32) };
+
+            let status = completion.status >> 1;
+            if status != 0 {
+                return Err(StatusCode::NvmeCommandFailed { status });
+            }
+            return Ok(completion.dw0);
+        }
+        return Err(StatusCode::Timeout);
+    }
+
+    fn identify(&mut self, cns: u32, nsid: u32) -> Result<(), StatusCode> {
+        let mut command = SubmissionEntry::new(ADMIN_IDENTIFY, nsid);
+        command.prp1 = self.buffer as u64;
+        command.cdw10 = cns;
+        self.execute(false, command)?;
+        return Ok(());
+    }
+
+    fn create_io_queues(&mut self) -> Result<(), StatusCode> {
+        let size = (QUEUE_SIZE as u32 - 1) << 16;
+
+        let mut command = SubmissionEntry::new(ADMIN_CREATE_IO_CQ, 0);
+        command.prp1 = self.io.cq as u64;
+        command.cdw10 = size | IO_QUEUE_ID as u32;
+        // physically contiguous, interrupts enabled on vector 0
+        command.cdw11 = 0b11;
+        self.execute(false, command)?;
+
+        let mut command = SubmissionEntry::new(ADMIN_CREATE_IO_SQ, 0);
+        command.prp1 = self.io.sq as u64;
+        command.cdw10 = size | IO_QUEUE_ID as u32;
+        // bound to the completion queue of the same id, physically contiguous
+        command.cdw11 = (IO_QUEUE_ID as u32) << 16 | 1;
+        self.execute(false, command)?;
+        return Ok(());
+    }
+
+    // read the controller and namespace identify data, and use the first namespace
+    fn identify_namespace(&mut self) -> Result<(), StatusCode> {
+        self.identify(IDENTIFY_CONTROLLER, 0)?;
+        let data = self.buffer as *const u8;
+        let nn = unsafe { read_volatile(data.add(516) as *const u32) };
+        if nn == 0 {
+            return Err(StatusCode::NoDevice);
+        }
+
+        self.nsid = 1;
+        self.identify(IDENTIFY_NAMESPACE, self.nsid)?;
+        unsafe {
+            self.nblocks = read_volatile(data as *const u64);
+            let format = (read_volatile(data.add(26)) & 0xf) as usize;
+            let lbaf = read_volatile(data.add(128 + 4 * format) as *const u32);
+            self.block_size = 1 << ((lbaf >> 16) & 0xff);
+        }
+        if self.nblocks == 0 || self.block_size < SECTOR_SIZE || self.block_size > BYTES_PER_FRAME {
+            return Err(StatusCode::UnknownDevice);
+        }
+        return Ok(());
+    }
+
+    fn reset(&mut self) -> Result<(), StatusCode> {
+        let cap = self.read_reg64(REG_CAP);
+        self.doorbell_stride = 4 << ((cap >> 32) & 0xf);
+        let max_entries = (cap & 0xffff) as u16 + 1;
+        if max_entries < QUEUE_SIZE {
+            return Err(StatusCode::UnknownDevice);
+        }
+
+        // the controller must be disabled to set the admin queues
+        self.write_reg32(REG_CC, self.read_reg32(REG_CC) & !CC_ENABLE);
+        self.wait_ready(false)?;
+
+        let queue_size = QUEUE_SIZE as u32 - 1;
+        self.write_reg32(REG_AQA, queue_size << 16 | queue_size);
+        self.write_reg64(REG_ASQ, self.admin.sq as u64);
+        self.write_reg64(REG_ACQ, self.admin.cq as u64);
+        // NVM command set, 4KiB pages, round robin
+        self.write_reg32(REG_CC, CC_IOCQES | CC_IOSQES | CC_ENABLE);
+        return self.wait_ready(true);
+    }
+
+    // transfer blocks between the device and the DMA buffer. the size must fit in the buffer
+    fn transfer_blocks(&mut self, opcode: u8, block: u64, nblocks: usize) -> Result<(), StatusCode> {
+        if block + nblocks as u64 > self.nblocks {
+            return Err(StatusCode::IndexOutOfRange);
+        }
+        let mut command = SubmissionEntry::new(opcode, self.nsid);
+        command.prp1 = self.buffer as u64;
+        command.cdw10 = block as u32;
+        command.cdw11 = (block >> 32) as u32;
+        command.cdw12 = nblocks as u32 - 1;
+        self.execute(true, command)?;
+        return Ok(());
+    }
+
+    // split the byte range into chunks which fit in the DMA buffer, aligned to the block size.
+    // f receives (offset in the buffer, offset in the user data, length) for each chunk
+    fn for_each_chunk<F>(&mut self, lba: u32, nbytes: usize, write: bool, mut f: F) -> Result<usize, StatusCode>
+    where
+        F: FnMut(*mut u8, usize, usize),
+    {
+        let mut offset = lba as usize * SECTOR_SIZE;
+        let end = offset + nbytes;
+        let mut done = 0;
+        while offset < end {
+            let block = (offset / self.block_size) as u64;
+            let in_block = offset % self.block_size;
+            let len = min(end - offset, BYTES_PER_FRAME - in_block);
+            let nblocks = (in_block + len + self.block_size - 1) / self.block_size;
+            // a partial block has to be read before it's overwritten
+            let partial = in_block != 0 || len % self.block_size != 0;
+            if !write || partial {
+                self.transfer_blocks(IO_READ, block, nblocks)?;
+            }
+            f((self.buffer + in_block) as *mut u8, done, len);
+            if write {
+                self.transfer_blocks(IO_WRITE, block, nblocks)?;
+            }
+            offset += len;
+            done += len;
+        }
+        return Ok(done);
+    }
+}
+
+impl Storage for NvmeController {
+    fn read(&mut self, buf: &mut [u8], lba: u32, nbytes: usize) -> Result<usize, StatusCode> {
+        let nbytes = min(nbytes, buf.len());
+        let dst = buf.as_mut_ptr();
+        return self.for_each_chunk(lba, nbytes, false, |src, offset, len| unsafe {
+            dst.add(offset).copy_from_nonoverlapping(src, len)
+        });
+    }
+    fn write(&mut self, buf: &[u8], lba: u32, nbytes: usize) -> Result<usize, StatusCode> {
+        let nbytes = min(nbytes, buf.len());
+        let src = buf.as_ptr();
+        return self.for_each_chunk(lba, nbytes, true, |dst, offset, len| unsafe {
+            dst.copy_from_nonoverlapping(src.add(offset), len)
+        });
+    }
+}
+
+impl StorageController for NvmeController {}
+
+pub fn initialize_nvme(dev: &Device) -> Result<NvmeController, StatusCode> {
+    info!(
+        "NVMe controller has been found: {}.{}.{}",
+        dev.bus, dev.device, dev.function
+    );
+    // completions are polled since the commands are issued before the IDT is loaded,
+    // the interrupt only needs to be acknowledged
+    status_log!(
+        configure_msi_fixed_destination(
+            dev,
+            LocalApic::id() as u8,
+            MSITriggerMode::Edge,
+            MSIDeliveryMode::Fixed,
+            InterruptVector::Nvme as u8,
+            0
+        ),
+        "Configure msi"
+    );
+    let mmio_base = (read_bar64(dev, 0)? & !0xf) as usize;
+    let mut controller = NvmeController {
+        mmio_base,
+        doorbell_stride: 4,
+        admin: QueuePair::new(ADMIN_QUEUE_ID)?,
+        io: QueuePair::new(IO_QUEUE_ID)?,
+        nsid: 0,
+        block_size: SECTOR_SIZE,
+        nblocks: 0,
+        buffer: allocate_frame()?,
+    };
+    let version = controller.read_reg32(REG_VS);
+    controller.reset()?;
+    controller.create_io_queues()?;
+    controller.identify_namespace()?;
+    info!(
+        "NVMe {}.{}: namespace {}, {} blocks of {} bytes",
+        version >> 16,
+        (version >> 8) & 0xff,
+        controller.nsid,
+        controller.nblocks,
+        controller.block_size
+    );
+    return Ok(controller);
+}
@@ -1,5 +1,6 @@
+// the only PCI implementation in the kernel. drivers should import everything from drivers::pci
 use crate::{bit_getter, bit_setter, debug, info, status::StatusCode, status_log, trace};
-use core::fmt::Display;
+use core::{fmt::Display, ptr::write_volatile};
 use x86_64::instructions::port::{Port, PortWriteOnly};
 
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -365,13 +366,41 @@ fn configure_msi_register(
 }
 
 fn configure_msix_register(
-    _dev: &Device,
-    _cap_addr: u8,
-    _msg_addr: u32,
-    _msg_data: u32,
-    _num_vector_exponent: u8,
+    dev: &Device,
+    cap_addr: u8,
+    msg_addr: u32,
+    msg_data: u32,
+    num_vector_exponent: u8,
 ) -> StatusCode {
-    return StatusCode::NotImplemented;
+    let mut msix_cap = MSIXCapability::default();
+    msix_cap.data = read_conf_reg(dev, cap_addr);
+    msix_cap.table = read_conf_reg(dev, cap_addr + 4);
+
+    let table_base = match read_bar64(dev, msix_cap.table_bir() as usize) {
+        Ok(bar) => (bar & !0xf) + (msix_cap.table & !0x7) as u64,
+        Err(status) => return status,
+    };
+    let num_vectors = core::cmp::min(1usize << num_vector_exponent, msix_cap.table_size() as usize + 1);
+
+    // mask all the vectors while the table is being programmed
+    msix_cap.set_msix_enable(1);
+    msix_cap.set_function_mask(1);
+    write_conf_reg(dev, cap_addr, msix_cap.data);
+
+    for i in 0..num_vectors {
+        let entry = (table_base + (i * MSIX_TABLE_ENTRY_SIZE) as u64) as *mut u32;
+        unsafe {
+            write_volatile(entry, msg_addr);
+            write_volatile(entry.add(1), 0);
+            write_volatile(entry.add(2), msg_data + i as u32);
+            // clear the per-vector mask
+            write_volatile(entry.add(3), 0);
+        }
+    }
+
+    msix_cap.set_function_mask(0);
+    write_conf_reg(dev, cap_addr, msix_cap.data);
+    return StatusCode::Success;
 }
 
 #[repr(C)]
@@ -407,6 +436,22 @@ impl MSICapability {
     bit_setter!(data: u32; 0x00700000; u8, set_multi_msg_enable);
 }
 
+const MSIX_TABLE_ENTRY_SIZE: usize = 16;
+
+#[repr(C)]
+#[derive(Default)]
+struct MSIXCapability {
+    data: u32,
+    table: u32,
+}
+
+impl MSIXCapability {
+    bit_getter!(data: u32; 0x07FF0000; u16, table_size);
+    bit_setter!(data: u32; 0x40000000; u8, set_function_mask);
+    bit_setter!(data: u32; 0x80000000; u8, set_msix_enable);
+    bit_getter!(table: u32; 0x00000007; u8, table_bir);
+}
+
 #[derive(PartialEq)]
 pub enum MSITriggerMode {
     Edge = 0,
@@ -0,0 +1,198 @@
+use spin::Mutex;
+
+use crate::{
+    drivers::timer::ioapic::route_irq,
+    horse_lib::io::{inb, outb},
+    input::{KeyEvent, KEY_LEFT_CTRL, KEY_RIGHT_GUI},
+    trace, InterruptVector,
+};
+
+// PS/2 keyboard on the i8042 controller. used when no USB keyboard is available
+const DATA_PORT: u16 = 0x60;
+const STATUS_PORT: u16 = 0x64;
+const STATUS_OUTPUT_FULL: u8 = 1;
+
+const KEYBOARD_IRQ: u8 = 1;
+const PIC1_DATA: u16 = 0x21;
+const PIC2_DATA: u16 = 0xa1;
+
+// scancode set 1
+const EXTENDED_PREFIX: u8 = 0xe0;
+const BREAK_BIT: u8 = 0x80;
+
+pub static PS2_KEYBOARD: Mutex<Ps2Keyboard> = Mutex::new(Ps2Keyboard::new());
+
+// returns false when there's no controller
+pub fn initialize_ps2_keyboard() -> bool {
+    unsafe {
+        // a floating bus reads 0xff
+        if inb(STATUS_PORT) == 0xff {
+            return false;
+        }
+        // the legacy PIC would deliver IRQ1 too, on a vector which collides with the exceptions
+        outb(PIC1_DATA, 0xff);
+        outb(PIC2_DATA, 0xff);
+        // drop the bytes left by the firmware
+        while inb(STATUS_PORT) & STATUS_OUTPUT_FULL != 0 {
+            inb(DATA_PORT);
+        }
+    }
+    route_irq(KEYBOARD_IRQ, InterruptVector::PS2Keyboard as u8);
+    return true;
+}
+
+// called from the interrupt handler. the scancode is decoded later in the main loop
+pub fn read_scancode() -> Option<u8> {
+    unsafe {
+        if inb(STATUS_PORT) & STATUS_OUTPUT_FULL == 0 {
+            return None;
+        }
+        return Some(inb(DATA_PORT));
+    }
+}
+
+pub struct Ps2Keyboard {
+    // 0xe0 came and the next scancode is extended
+    extended: bool,
+    modifier: u8,
+}
+
+impl Ps2Keyboard {
+    const fn new() -> Self {
+        return Self {
+            extended: false,
+            modifier: 0,
+        };
+    }
+
+    pub fn on_scancode(&mut self, scancode: u8) -> Option<KeyEvent> {
+        if scancode == EXTENDED_PREFIX {
+            self.extended = true;
+            return None;
+        }
+        let extended = self.extended;
+        self.extended = false;
+
+        let pressed = scancode & BREAK_BIT == 0;
+        let keycode = match scancode_to_usage(scancode & !BREAK_BIT, extended) {
+            Some(keycode) => keycode,
+            None => {
+                trace!("unknown scancode: {:#04x} (extended: {})", scancode, extended);
+                return None;
+            }
+        };
+        if (KEY_LEFT_CTRL..=KEY_RIGHT_GUI).contains(&keycode) {
+            let bit = 1 << (keycode - KEY_LEFT_CTRL);
+            if pressed {
+                self.modifier |= bit;
+            } else {
+                self.modifier &= !bit;
+            }
+        }
+        return Some(KeyEvent {
+            keycode,
+            modifier: self.modifier,
+            pressed,
+        });
+    }
+}
+
+// translate a make code of set 1 to the HID usage ID
+fn scancode_to_usage(code: u8, extended: bool) -> Option<u8> {
+    if extended {
+        return match code {
+            0x1c => Some(0x58), // keypad Enter
+            0x1d => Some(0xe4), // Right Ctrl
+            0x35 => Some(0x54), // keypad /
+            0x38 => Some(0xe6), // Right Alt
+            0x47 => Some(0x4a), // Home
+            0x48 => Some(0x52), // Up
+            0x49 => Some(0x4b), // Page Up
+            0x4b => Some(0x50), // Left
+            0x4d => Some(0x4f), // Right
+            0x4f => Some(0x4d), // End
+            0x50 => Some(0x51), // Down
+            0x51 => Some(0x4e), // Page Down
+            0x52 => Some(0x49), // Insert
+            0x53 => Some(0x4c), // Delete
+            0x5b => Some(0xe3), // Left GUI
+            0x5c => Some(0xe7), // Right GUI
+            // including the fake shifts around Print Screen
+            _ => None,
+        };
+    }
+    return match code {
+        0x01 => Some(0x29), // Escape
+        0x02..=0x0a => Some(0x1e + code - 0x02), // 1-9
+        0x0b => Some(0x27), // 0
+        0x0c => Some(0x2d), // -
+        0x0d => Some(0x2e), // =
+        0x0e => Some(0x2a), // Backspace
+        0x0f => Some(0x2b), // Tab
+        0x10 => Some(0x14), // q
+        0x11 => Some(0x1a), // w
+        0x12 => Some(0x08), // e
+        0x13 => Some(0x15), // r
+        0x14 => Some(0x17), // t
+        0x15 => Some(0x1c), // y
+        0x16 => Some(0x18), // u
+        0x17 => Some(0x0c), // i
+        0x18 => Some(0x12), // o
+        0x19 => Some(0x13), // p
+        0x1a => Some(0x2f), // [
+        0x1b => Some(0x30), // ]
+        0x1c => Some(0x28), // Enter
+        0x1d => Some(0xe0), // Left Ctrl
+        0x1e => Some(0x04), // a
+        0x1f => Some(0x16), // s
+        0x20 => Some(0x07), // d
+        0x21 => Some(0x09), // f
+        0x22 => Some(0x0a), // g
+        0x23 => Some(0x0b), // h
+        0x24 => Some(0x0d), // j
+        0x25 => Some(0x0e), // k
+        0x26 => Some(0x0f), // l
+        0x27 => Some(0x33), // ;
+        0x28 => Some(0x34), // '
+        0x29 => Some(0x35), // `
+        0x2a => Some(0xe1), // Left Shift
+        0x2b => Some(0x31), // \
+        0x2c => Some(0x1d), // z
+        0x2d => Some(0x1b), // x
+        0x2e => Some(0x06), // c
+        0x2f => Some(0x19), // v
+        0x30 => Some(0x05), // b
+        0x31 => Some(0x11), // n
+        0x32 => Some(0x10), // m
+        0x33 => Some(0x36), // ,
+        0x34 => Some(0x37), // .
+        0x35 => Some(0x38), // /
+        0x36 => Some(0xe5), // Right Shift
+        0x37 => Some(0x55), // keypad *
+        0x38 => Some(0xe2), // Left Alt
+        0x39 => Some(0x2c), // Space
+        0x3a => Some(0x39), // Caps Lock
+        0x3b..=0x44 => Some(0x3a + code - 0x3b), // F1-F10
+        0x45 => Some(0x53), // Num Lock
+        0x46 => Some(0x47), // Scroll Lock
+        0x47 => Some(0x5f), // keypad 7
+        0x48 => Some(0x60), // keypad 8
+        0x49 => Some(0x61), // keypad 9
+        0x4a => Some(0x56), // keypad -
+        0x4b => Some(0x5c), // keypad 4
+        0x4c => Some(0x5d), // keypad 5
+        0x4d => Some(0x5e), // keypad 6
+        0x4e => Some(0x57), // keypad +
+        0x4f => Some(0x59), // keypad 1
+        0x50 => Some(0x5a), // keypad 2
+        0x51 => Some(0x5b), // keypad 3
+        0x52 => Some(0x62), // keypad 0
+        0x53 => Some(0x63), // keypad .
+        0x56 => Some(0x64), // the extra key of the ISO keyboards
+        0x57 => Some(0x44), // F11
+        0x58 => Some(0x45), // F12
+        0x73 => Some(0x87), // JIS \ _
+        0x7d => Some(0x89), // JIS yen
+        _ => None,
+    };
+}
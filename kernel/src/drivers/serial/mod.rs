@@ -0,0 +1,117 @@
+use core::fmt;
+
+use crate::horse_lib::{
+    io::{inb, outb},
+    irq_mutex::IrqMutex,
+};
+
+pub const COM1: u16 = 0x3f8;
+
+// offsets from the base port
+const DATA: u16 = 0;
+const INTERRUPT_ENABLE: u16 = 1;
+const FIFO_CONTROL: u16 = 2;
+const LINE_CONTROL: u16 = 3;
+const MODEM_CONTROL: u16 = 4;
+const LINE_STATUS: u16 = 5;
+
+const LINE_STATUS_DATA_READY: u8 = 1;
+const LINE_STATUS_TRANSMIT_EMPTY: u8 = 1 << 5;
+
+// the port which the log is mirrored to. None until initialize_serial succeeds
+pub static SERIAL: IrqMutex<Option<SerialPort>> = IrqMutex::new(None);
+
+pub struct SerialPort {
+    base: u16,
+}
+
+impl SerialPort {
+    pub const fn new(base: u16) -> Self {
+        return Self { base };
+    }
+
+    // 115200 baud, 8N1. returns false when the port doesn't exist or is broken
+    pub fn initialize(&mut self) -> bool {
+        unsafe {
+            outb(self.base + INTERRUPT_ENABLE, 0x00);
+            // DLAB on to set the divisor
+            outb(self.base + LINE_CONTROL, 0x80);
+            outb(self.base + DATA, 0x01);
+            outb(self.base + INTERRUPT_ENABLE, 0x00);
+            outb(self.base + LINE_CONTROL, 0x03);
+            // enable and clear FIFO, 14-byte threshold
+            outb(self.base + FIFO_CONTROL, 0xc7);
+            // check the chip with the loopback mode
+            outb(self.base + MODEM_CONTROL, 0x1e);
+            outb(self.base + DATA, 0xae);
+            if inb(self.base + DATA) != 0xae {
+                return false;
+            }
+            // normal mode: DTR, RTS, OUT1 and OUT2
+            outb(self.base + MODEM_CONTROL, 0x0f);
+        }
+        return true;
+    }
+
+    // interrupts are raised when data is received. routing IRQ4 is up to the caller
+    pub fn enable_receive_interrupt(&mut self) {
+        unsafe { outb(self.base + INTERRUPT_ENABLE, 0x01) };
+    }
+
+    pub fn write_byte(&mut self, byte: u8) {
+        // the byte would be dropped if the holding register is still full
+        while unsafe { inb(self.base + LINE_STATUS) } & LINE_STATUS_TRANSMIT_EMPTY == 0 {
+            core::hint::spin_loop();
+        }
+        unsafe { outb(self.base + DATA, byte) };
+    }
+
+    pub fn read_byte(&mut self) -> Option<u8> {
+        if unsafe { inb(self.base + LINE_STATUS) } & LINE_STATUS_DATA_READY == 0 {
+            return None;
+        }
+        return Some(unsafe { inb(self.base + DATA) });
+    }
+}
+
+impl fmt::Write for SerialPort {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            if byte == b'\n' {
+                self.write_byte(b'\r');
+            }
+            self.write_byte(byte);
+        }
+        return Ok(());
+    }
+}
+
+pub fn initialize_serial() -> bool {
+    let mut port = SerialPort::new(COM1);
+    if !port.initialize() {
+        return false;
+    }
+    *SERIAL.lock() = Some(port);
+    return true;
+}
+
+pub fn is_serial_initialized() -> bool {
+    return SERIAL.lock().is_some();
+}
+
+pub fn _serial_print(args: fmt::Arguments) {
+    if let Some(port) = SERIAL.lock().as_mut() {
+        let _ = fmt::Write::write_fmt(port, args);
+    }
+}
+
+#[macro_export]
+macro_rules! serial_print {
+    ($($arg:tt)*) => ($crate::drivers::serial::_serial_print(format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! serial_println {
+    ($fmt:expr) => ($crate::serial_print!(concat!($fmt, "\n")));
+    ($fmt:expr, $($arg:tt)*) => ($crate::serial_print!(concat!($fmt, "\n"), $($arg)*));
+}
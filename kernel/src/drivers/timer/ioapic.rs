@@ -1,11 +1,10 @@
-use crate::{bit_getter, bit_setter, println, InterruptVector};
+use crate::{bit_getter, bit_setter, lapic::LocalApic, println, InterruptVector};
 
 use core::ptr::{read, write};
 
 //TODO: These Register address should be got from MADT in XSDT
 const INDEX_REGISTER: *mut u8 = 0xfec00000 as *mut u8;
 const DATA_REGISTER: *mut u32 = 0xfec00010 as *mut u32;
-const LAPIC_ID_REGISTER: *mut u8 = 0xfee00020 as *mut u8;
 
 struct RedirectionTable {
     pub data: u64,
@@ -29,7 +28,7 @@ pub fn configure_redirection_table(idx: u8) {
         };
     }
     rt.set_destination_mode(1);
-    let apic_id = unsafe { read(LAPIC_ID_REGISTER) };
+    let apic_id = LocalApic::id() as u8;
     rt.set_destination(apic_id);
     //rt.set_vector(InterruptVector::Hpet as u8);
     unsafe {
@@ -42,3 +41,19 @@ pub fn configure_redirection_table(idx: u8) {
         write(DATA_REGISTER, upper_bit);
     }
 }
+
+const REDIRECTION_TABLE_BASE: u8 = 0x10;
+
+// deliver the legacy IRQ to this CPU with the vector: fixed, physical destination, edge-triggered and unmasked.
+// ISA IRQs are assumed to be identity-mapped to the GSIs, which holds for IRQ1 on QEMU and most machines
+pub fn route_irq(irq: u8, vector: u8) {
+    let mut rt = RedirectionTable { data: 0 };
+    rt.set_vector(vector);
+    rt.set_destination(LocalApic::id() as u8);
+    unsafe {
+        write(INDEX_REGISTER, REDIRECTION_TABLE_BASE + 2 * irq);
+        write(DATA_REGISTER, rt.data as u32);
+        write(INDEX_REGISTER, REDIRECTION_TABLE_BASE + 2 * irq + 1);
+        write(DATA_REGISTER, (rt.data >> 32) as u32);
+    }
+}
@@ -1,35 +1,118 @@
-use super::FFTimer;
-use crate::{println, Message, INTERRUPTION_QUEUE};
+use super::{duration_to_ticks, FFTimer, TICKS};
+use crate::{
+    horse_lib::time::Duration, println, queue::SpscQueue, Message, StatusCode,
+    INTERRUPTION_QUEUE,
+};
 
-use alloc::collections::BinaryHeap;
-use core::cmp::{Ord, Ordering};
+use alloc::{collections::BinaryHeap, vec::Vec};
+use core::{
+    cmp::{Ord, Ordering},
+    sync::atomic,
+};
+
+// the fixed frequency timer waits for a while at once, since the PM timer wraps around in about 4 seconds
+const MAX_WAIT_MILLISECONDS: u128 = 1000;
 
 pub struct TimerManager {
     tick: u64,
     timers: BinaryHeap<Timer>,
+    // (absolute tick, process id) of the processes sleeping with a timeout
+    wakeups: Vec<(u128, usize)>,
+    periodics: Vec<Periodic>,
+    next_periodic_id: usize,
     fft: FFTimer,
 }
 
+// returned by register_periodic to unregister the callback
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PeriodicHandle(usize);
+
+// the callback is called in the main loop, so that it can take time and lock TIMER_MANAGER
+struct Periodic {
+    handle: PeriodicHandle,
+    period: u64,
+    next: u128,
+    callback: fn(),
+}
+
 impl TimerManager {
     pub fn new(fft: FFTimer) -> Self {
         return Self {
             tick: 0,
             timers: BinaryHeap::new(),
+            wakeups: Vec::new(),
+            periodics: Vec::new(),
+            next_periodic_id: 0,
             fft,
         };
     }
-    pub fn add_timer(&mut self, timeout: u64, value: i32, periodic: bool) {
-        self.timers.push(Timer::new(self.tick, timeout, value, periodic));
+    pub fn add_timer(&mut self, timeout: Duration, value: i32, periodic: bool) {
+        self.push_timer(duration_to_ticks(timeout), value, periodic);
+    }
+    fn push_timer(&mut self, ticks: u64, value: i32, periodic: bool) {
+        self.timers.push(Timer::new(self.tick, ticks, value, periodic));
+    }
+    // wake up the process after the timeout. the previous one of the process is replaced
+    pub fn add_wakeup(&mut self, timeout: Duration, id: usize) {
+        self.cancel_wakeup(id);
+        let ticks = duration_to_ticks(timeout);
+        self.wakeups.push(((self.tick as u128) + (ticks as u128), id));
+    }
+    pub fn cancel_wakeup(&mut self, id: usize) {
+        self.wakeups.retain(|&(_, waiter)| waiter != id);
+    }
+    // the period is rounded up to the ticks
+    pub fn register_periodic(&mut self, period: Duration, callback: fn()) -> PeriodicHandle {
+        let period = duration_to_ticks(period).max(1);
+        let handle = PeriodicHandle(self.next_periodic_id);
+        self.next_periodic_id += 1;
+        self.periodics.push(Periodic {
+            handle,
+            period,
+            next: (self.tick as u128) + (period as u128),
+            callback,
+        });
+        return handle;
+    }
+    // returns false when the callback isn't registered
+    pub fn unregister_periodic(&mut self, handle: PeriodicHandle) -> bool {
+        let len = self.periodics.len();
+        self.periodics.retain(|periodic| periodic.handle != handle);
+        return self.periodics.len() != len;
+    }
+    // None after the callback is unregistered, even if its message is still in the queue
+    pub fn periodic_callback(&self, handle: PeriodicHandle) -> Option<fn()> {
+        return self
+            .periodics
+            .iter()
+            .find(|periodic| periodic.handle == handle)
+            .map(|periodic| periodic.callback);
+    }
+    pub fn current_tick(&self) -> u64 {
+        return self.tick;
     }
     pub fn tick(&mut self) -> bool {
         let mut proc = false;
         self.tick = self.tick.wrapping_add(1);
+        TICKS.store(self.tick, atomic::Ordering::Relaxed);
+        let now = self.tick as u128;
+        fire_wakeups(&mut self.wakeups, now, &INTERRUPTION_QUEUE);
+        for periodic in self.periodics.iter_mut() {
+            if periodic.next > now {
+                continue;
+            }
+            INTERRUPTION_QUEUE.push(Message::Periodic {
+                handle: periodic.handle,
+            });
+            // the missed periods aren't made up for
+            periodic.next = now + periodic.period as u128;
+        }
         loop {
             if let Some(t) = self.timers.peek() {
                 if t.absolute_timeout > (self.tick as u128) {
                     break;
                 }
-                INTERRUPTION_QUEUE.lock().push(Message::TimerTimeout {
+                INTERRUPTION_QUEUE.push(Message::TimerTimeout {
                     timeout: t.timeout,
                     value: t.value,
                 });
@@ -37,7 +120,7 @@ impl TimerManager {
                     proc = true;
                 }
                 if t.periodic != 0 {
-                    self.add_timer(t.periodic, t.value, true)
+                    self.push_timer(t.periodic, t.value, true)
                 }
                 self.timers.pop();
             } else {
@@ -46,13 +129,32 @@ impl TimerManager {
         }
         return proc
     }
-    pub fn wait_seconds(&self, sec: u64) {
-        for i in 0..sec {
-            self.fft.wait_milliseconds(1000);
+    // rounded up to milliseconds
+    pub fn wait(&self, duration: Duration) {
+        let mut msec = (duration.as_nanos() + 999_999) / 1_000_000;
+        while msec > 0 {
+            let chunk = msec.min(MAX_WAIT_MILLISECONDS);
+            self.fft.wait_milliseconds(chunk as u32);
+            msec -= chunk;
         }
     }
 }
 
+// the wakeup is kept when the queue is full and retried on the next tick,
+// otherwise the process would sleep forever
+fn fire_wakeups<const N: usize>(
+    wakeups: &mut Vec<(u128, usize)>,
+    now: u128,
+    queue: &SpscQueue<Message, N>,
+) {
+    wakeups.retain(|&(timeout, id)| {
+        if timeout > now {
+            return true;
+        }
+        return !matches!(queue.push(Message::WakeUp { id }), StatusCode::Success);
+    });
+}
+
 //Logical Timer
 #[derive(Eq)]
 struct Timer {
@@ -102,3 +204,32 @@ impl PartialEq for Timer {
         return self.timeout == other.timeout;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn only_expired_wakeups_are_fired() {
+        let queue = SpscQueue::<Message, 4>::new();
+        let mut wakeups = vec![(5, 1), (10, 2)];
+        fire_wakeups(&mut wakeups, 5, &queue);
+        assert_eq!(wakeups, vec![(10, 2)]);
+        assert!(matches!(queue.pop(), Some(Message::WakeUp { id: 1 })));
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn wakeups_are_retried_when_the_queue_is_full() {
+        let queue = SpscQueue::<Message, 2>::new();
+        let mut wakeups = vec![(1, 1), (1, 2), (1, 3)];
+        fire_wakeups(&mut wakeups, 1, &queue);
+        assert_eq!(wakeups, vec![(1, 3)]);
+        queue.pop();
+        fire_wakeups(&mut wakeups, 2, &queue);
+        assert!(wakeups.is_empty());
+        assert!(matches!(queue.pop(), Some(Message::WakeUp { id: 2 })));
+        assert!(matches!(queue.pop(), Some(Message::WakeUp { id: 3 })));
+    }
+}
@@ -1,40 +1,47 @@
 pub mod fftimer;
 pub mod hpet;
-mod ioapic;
+pub mod ioapic;
 mod manager;
 
 use fftimer::*;
 use hpet::*;
 use manager::*;
+pub use manager::PeriodicHandle;
 
 use alloc::string::String;
 use core::{
     mem::size_of,
-    ptr::{read, read_unaligned, write},
+    ptr::read_unaligned,
+    sync::atomic::{AtomicU64, Ordering},
 };
-use spin::{Mutex, Once};
+use spin::Once;
 
-use crate::{error, println, DescriptionHeader, InterruptVector};
+use crate::{
+    error,
+    horse_lib::{irq_mutex::IrqMutex, time::Duration},
+    lapic::{LapicRegister, LocalApic},
+    println, DescriptionHeader, InterruptVector,
+};
 
 const PM_TIMER_FREQ: u32 = 3579545;
 const COUNT_MAX: u32 = 1000000;
-const LVT_TIMER: *mut u32 = 0xfee00320 as *mut u32;
-const INITIAL_COUNT: *mut u32 = 0xfee00380 as *mut u32;
-const CURRENT_COUNT: *const u32 = 0xfee00390 as *const u32;
-const DIVIDE_CONFIG: *mut u32 = 0xfee003e0 as *mut u32;
+// the LAPIC timer is reloaded with the count for a second
+pub const TICKS_PER_SECOND: u64 = 1;
+
+const NANOS_PER_SECOND: u128 = 1_000_000_000;
 
 static LAPIC_FREQUENCY: Once<u32> = Once::new();
-pub static TIMER_MANAGER: Mutex<Once<TimerManager>> = Mutex::new(Once::new());
+// copy of the tick of TIMER_MANAGER, which can be read without the lock
+static TICKS: AtomicU64 = AtomicU64::new(0);
+pub static TIMER_MANAGER: IrqMutex<Once<TimerManager>> = IrqMutex::new(Once::new());
 
 pub fn initialize_lapic_itmer(fftimer: FFTimer) {
     TIMER_MANAGER
         .lock()
         .call_once(|| TimerManager::new(fftimer));
 
-    unsafe {
-        write(DIVIDE_CONFIG, 0b1011); //divide 1:1
-        write(LVT_TIMER, 0b001 << 16); //masked, one-shot
-    }
+    LocalApic::write(LapicRegister::DivideConfig, 0b1011); //divide 1:1
+    LocalApic::write(LapicRegister::LvtTimer, 0b001 << 16); //masked, one-shot
 
     start_lapic_timer();
     fftimer.wait_milliseconds(100);
@@ -42,32 +49,78 @@ pub fn initialize_lapic_itmer(fftimer: FFTimer) {
     stop_lapic_timer();
     LAPIC_FREQUENCY.call_once(|| elapsed * 10);
 
-    unsafe {
-        write(DIVIDE_CONFIG, 0b1011); //divide 1:1
-        write(
-            LVT_TIMER,
-            (0b010 << 16) | InterruptVector::LAPICTimer as u32,
-        ); //not-masked, periodic
-        write(INITIAL_COUNT, *LAPIC_FREQUENCY.get().unwrap());
-    }
+    LocalApic::write(LapicRegister::DivideConfig, 0b1011); //divide 1:1
+    LocalApic::write(
+        LapicRegister::LvtTimer,
+        (0b010 << 16) | InterruptVector::LAPICTimer as u32,
+    ); //not-masked, periodic
+    LocalApic::write(LapicRegister::InitialCount, *LAPIC_FREQUENCY.get().unwrap());
 }
 
 pub fn start_lapic_timer() {
-    unsafe {
-        write(INITIAL_COUNT, COUNT_MAX);
-    }
+    LocalApic::write(LapicRegister::InitialCount, COUNT_MAX);
 }
 
 pub fn lapic_timer_elapsed() -> u32 {
-    return COUNT_MAX - unsafe { read(CURRENT_COUNT) };
+    return COUNT_MAX - LocalApic::read(LapicRegister::CurrentCount);
 }
 
 pub fn stop_lapic_timer() {
-    unsafe {
-        write(INITIAL_COUNT, 0);
-    }
+    LocalApic::write(LapicRegister::InitialCount, 0);
+}
+
+// the time taken by f, counted by the LAPIC timer which is reloaded every tick.
+// f must return within a tick
+pub fn measure(f: impl FnOnce()) -> Duration {
+    let period = *LAPIC_FREQUENCY.get().unwrap() as u64 / TICKS_PER_SECOND;
+    let start = LocalApic::read(LapicRegister::CurrentCount) as u64;
+    f();
+    let end = LocalApic::read(LapicRegister::CurrentCount) as u64;
+    let count = if end <= start { start - end } else { start + period - end };
+    let nanos = count as u128 * NANOS_PER_SECOND / *LAPIC_FREQUENCY.get().unwrap() as u128;
+    return Duration::from_nanos(nanos as u64);
+}
+
+// returns 0 before the timer is initialized
+pub fn current_tick() -> u64 {
+    return TICKS.load(Ordering::Relaxed);
 }
 
-pub fn sleep(t: u64) {
-    TIMER_MANAGER.lock().get_mut().unwrap().wait_seconds(t);
+// rounded up, so that waiting for the ticks takes the duration at least
+pub fn duration_to_ticks(duration: Duration) -> u64 {
+    let nanos = duration.as_nanos() * TICKS_PER_SECOND as u128;
+    return ((nanos + NANOS_PER_SECOND - 1) / NANOS_PER_SECOND) as u64;
+}
+
+pub fn ticks_to_duration(ticks: u64) -> Duration {
+    let nanos = ticks as u128 * NANOS_PER_SECOND / TICKS_PER_SECOND as u128;
+    return Duration::from_nanos(nanos as u64);
+}
+
+// busy-wait, so this can be used before the interrupts are enabled
+pub fn sleep(duration: Duration) {
+    TIMER_MANAGER.lock().get().unwrap().wait(duration);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn duration_to_ticks_rounds_up() {
+        let tick = ticks_to_duration(1);
+        assert_eq!(duration_to_ticks(Duration::ZERO), 0);
+        assert_eq!(duration_to_ticks(Duration::from_nanos(1)), 1);
+        assert_eq!(duration_to_ticks(tick), 1);
+        assert_eq!(duration_to_ticks(tick - Duration::from_nanos(1)), 1);
+        assert_eq!(duration_to_ticks(tick + Duration::from_nanos(1)), 2);
+    }
+
+    #[test]
+    fn ticks_round_trip() {
+        for ticks in [0, 1, 2, 1000, u32::MAX as u64] {
+            assert_eq!(duration_to_ticks(ticks_to_duration(ticks)), ticks);
+        }
+        assert_eq!(ticks_to_duration(TICKS_PER_SECOND), Duration::from_secs(1));
+    }
 }
@@ -67,4 +67,20 @@ where
         }
         None
     }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.table
+            .iter()
+            .filter_map(|elem| elem.as_ref().map(|(k, v)| (k, v)))
+    }
+
+    pub fn retain<F: FnMut(&K, &V) -> bool>(&mut self, mut f: F) {
+        for elem in self.table.iter_mut() {
+            if let Some((k, v)) = elem.as_ref() {
+                if !f(k, v) {
+                    *elem = None;
+                }
+            }
+        }
+    }
 }
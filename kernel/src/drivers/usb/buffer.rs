@@ -8,16 +8,16 @@ pub struct Buffer {
 }
 impl Buffer {
     pub fn new(size: usize, align: usize) -> Self {
-        let buf = unsafe {
-            usballoc()
-                .alloc(size, align, None)
-                .expect("no enough memory")
-                .as_mut()
-        };
-        Self {
+        Self::try_new(size, align, None).expect("no enough memory")
+    }
+
+    /// `boundary` is the address boundary which the buffer must not cross
+    pub fn try_new(size: usize, align: usize, boundary: Option<usize>) -> Option<Self> {
+        let buf = unsafe { usballoc().alloc(size, align, boundary)?.as_mut() };
+        Some(Self {
             ptr: Some(unsafe { NonNull::new_unchecked(buf.as_mut_ptr()) }),
             size,
-        }
+        })
     }
 
     pub fn detach(&mut self) -> NonNull<u8> {
@@ -53,3 +53,12 @@ where
         unsafe { &mut from_raw_parts_mut(ptr.as_ptr(), self.size)[range] }
     }
 }
+
+// a buffer detached for a transfer is left to the transfer
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        if let Some(ptr) = self.ptr.take() {
+            unsafe { usballoc().free(ptr, self.size) };
+        }
+    }
+}
@@ -42,6 +42,14 @@ impl HidDriver {
         core::mem::swap(buf, prev_buf);
     }
 
+    pub fn interface_idx(&self) -> u8 {
+        self.interface_idx
+    }
+
+    pub fn interrupt_out_endpoint(&self) -> Option<EndpointId> {
+        self.ep_interrupt_out
+    }
+
     pub fn buffer(&mut self) -> &mut [u8] {
         &mut self.prev_buf[..]
     }
@@ -1,111 +1,111 @@
 use super::{Driver, HidDriver, TransferRequest};
 use crate::{
     drivers::usb::{
+        buffer::Buffer,
         endpoint::{EndpointConfig, EndpointId},
-        setupdata::SetupData,
+        setupdata::{request_type, HidRequest, SetupData, HID_REPORT_TYPE_OUTPUT},
     },
-    println,
+    input::{push_key_event, KeyEvent},
     status::Result,
-    trace,
+    trace, warn,
 };
 use core::ptr::NonNull;
 
+// bits of the LED output report
+pub const LED_NUM_LOCK: u8 = 1 << 0;
+pub const LED_CAPS_LOCK: u8 = 1 << 1;
+pub const LED_SCROLL_LOCK: u8 = 1 << 2;
+
+const KEY_CAPS_LOCK: u8 = 0x39;
+const KEY_SCROLL_LOCK: u8 = 0x47;
+const KEY_NUM_LOCK: u8 = 0x53;
+
 pub struct HidKeyboardDriver {
     hid_driver: HidDriver,
     prev: [u8; 6],
+    leds: u8,
+    // holds the LED report while it's transferred
+    led_buf: Buffer,
+    led_in_flight: bool,
+    // some keyboards accept the report only on the interrupt OUT endpoint
+    led_on_interrupt_out: bool,
+    request: Option<TransferRequest>,
 }
 impl HidKeyboardDriver {
     pub fn new(interface_idx: u8) -> Result<Self> {
+        let mut led_buf = Buffer::new(8, 64);
+        led_buf[..][0] = 0;
         Ok(Self {
             hid_driver: HidDriver::new(interface_idx, 8)?,
             prev: [0; 6],
+            leds: 0,
+            led_buf,
+            led_in_flight: false,
+            led_on_interrupt_out: false,
+            request: None,
         })
     }
-    fn key2ascii(shift: bool, keycode: u8) -> Option<char> {
-        match (shift, keycode) {
-            (false, 0x04) => Some('a'),
-            (false, 0x05) => Some('b'),
-            (false, 0x06) => Some('c'),
-            (false, 0x07) => Some('d'),
-            (false, 0x08) => Some('e'),
-            (false, 0x09) => Some('f'),
-            (false, 0x0a) => Some('g'),
-            (false, 0x0b) => Some('h'),
-            (false, 0x0c) => Some('i'),
-            (false, 0x0d) => Some('j'),
-            (false, 0x0e) => Some('k'),
-            (false, 0x0f) => Some('l'),
-            (false, 0x10) => Some('m'),
-            (false, 0x11) => Some('n'),
-            (false, 0x12) => Some('o'),
-            (false, 0x13) => Some('p'),
-            (false, 0x14) => Some('q'),
-            (false, 0x15) => Some('r'),
-            (false, 0x16) => Some('s'),
-            (false, 0x17) => Some('t'),
-            (false, 0x18) => Some('u'),
-            (false, 0x19) => Some('v'),
-            (false, 0x1a) => Some('w'),
-            (false, 0x1b) => Some('x'),
-            (false, 0x1c) => Some('y'),
-            (false, 0x1d) => Some('z'),
-            (true, 0x04) => Some('A'),
-            (true, 0x05) => Some('B'),
-            (true, 0x06) => Some('C'),
-            (true, 0x07) => Some('D'),
-            (true, 0x08) => Some('E'),
-            (true, 0x09) => Some('F'),
-            (true, 0x0a) => Some('G'),
-            (true, 0x0b) => Some('H'),
-            (true, 0x0c) => Some('I'),
-            (true, 0x0d) => Some('J'),
-            (true, 0x0e) => Some('K'),
-            (true, 0x0f) => Some('L'),
-            (true, 0x10) => Some('M'),
-            (true, 0x11) => Some('N'),
-            (true, 0x12) => Some('O'),
-            (true, 0x13) => Some('P'),
-            (true, 0x14) => Some('Q'),
-            (true, 0x15) => Some('R'),
-            (true, 0x16) => Some('S'),
-            (true, 0x17) => Some('T'),
-            (true, 0x18) => Some('U'),
-            (true, 0x19) => Some('V'),
-            (true, 0x1a) => Some('W'),
-            (true, 0x1b) => Some('X'),
-            (true, 0x1c) => Some('Y'),
-            (true, 0x1d) => Some('Z'),
 
-            (false, 0x1E) => Some('1'),
-            (false, 0x1F) => Some('2'),
-            (false, 0x20) => Some('3'),
-            (false, 0x21) => Some('4'),
-            (false, 0x22) => Some('5'),
-            (false, 0x23) => Some('6'),
-            (false, 0x24) => Some('7'),
-            (false, 0x25) => Some('8'),
-            (false, 0x26) => Some('9'),
-            (false, 0x27) => Some('0'),
+    pub fn leds(&self) -> u8 {
+        self.leds
+    }
 
-            (true, 0x1E) => Some('!'),
-            (true, 0x1F) => Some('@'),
-            (true, 0x20) => Some('#'),
-            (true, 0x21) => Some('$'),
-            (true, 0x22) => Some('%'),
-            (true, 0x23) => Some('^'),
-            (true, 0x24) => Some('&'),
-            (true, 0x25) => Some('*'),
-            (true, 0x26) => Some('('),
-            (true, 0x27) => Some(')'),
+    /// turn on the LEDs in `mask` and turn off the others.
+    /// the report is sent after the current one completes if it's still in flight
+    pub fn set_leds(&mut self, mask: u8) {
+        self.leds = mask;
+        if let Some(req) = self.next_led_request() {
+            self.request = Some(req);
+        }
+    }
 
-            (false, 0x36) => Some(','),
-            (false, 0x37) => Some('.'),
+    fn next_led_request(&mut self) -> Option<TransferRequest> {
+        if self.led_in_flight || self.led_buf[..][0] == self.leds {
+            return None;
+        }
+        Some(self.led_request())
+    }
 
-            (false, 0x2A) => Some('\x08'), // backspace
+    fn led_request(&mut self) -> TransferRequest {
+        self.led_buf[..][0] = self.leds;
+        self.led_in_flight = true;
+        let buf_ptr = NonNull::new(self.led_buf[..].as_mut_ptr()).unwrap();
+        match self.hid_driver.interrupt_out_endpoint() {
+            Some(ep_id) if self.led_on_interrupt_out => TransferRequest::InterruptOut {
+                ep_id,
+                buf_ptr,
+                size: 1,
+            },
+            _ => TransferRequest::ControlOutData {
+                setup_data: self.set_report_setup_data(),
+                buf_ptr,
+                size: 1,
+            },
+        }
+    }
 
-            (false, 0x2C) => Some(' '),
-            (false, 0x28) => Some('\n'),
+    fn set_report_setup_data(&self) -> SetupData {
+        let mut setup_data = SetupData::default();
+        setup_data.set_direction(request_type::Direction::HostToDevice as u8);
+        setup_data.set_typ(request_type::Type::Class as u8);
+        setup_data.set_recipient(request_type::Recipient::Interface as u8);
+        setup_data.request = HidRequest::SetReport as u8;
+        setup_data.value = HID_REPORT_TYPE_OUTPUT << 8; // report ID 0
+        setup_data.index = self.hid_driver.interface_idx() as u16;
+        setup_data.length = 1;
+        setup_data
+    }
 
+    fn is_set_report(setup_data: &SetupData) -> bool {
+        setup_data.request == HidRequest::SetReport as u8
+            && setup_data.typ() == request_type::Type::Class as u8
+    }
+
+    fn lock_key_led(keycode: u8) -> Option<u8> {
+        match keycode {
+            KEY_NUM_LOCK => Some(LED_NUM_LOCK),
+            KEY_CAPS_LOCK => Some(LED_CAPS_LOCK),
+            KEY_SCROLL_LOCK => Some(LED_SCROLL_LOCK),
             _ => None,
         }
     }
@@ -121,9 +121,29 @@ impl Driver for HidKeyboardDriver {
         buf_ptr: Option<NonNull<u8>>,
         transfered_size: usize,
     ) -> Result<TransferRequest> {
+        if Self::is_set_report(&setup_data) {
+            self.led_in_flight = false;
+            return Ok(self.next_led_request().unwrap_or(TransferRequest::NoOp));
+        }
         self.hid_driver
             .on_control_completed(ep_id, setup_data, buf_ptr, transfered_size)
     }
+    fn on_control_failed(&mut self, setup_data: SetupData) -> Result<TransferRequest> {
+        if !Self::is_set_report(&setup_data) {
+            return Ok(TransferRequest::NoOp);
+        }
+        self.led_in_flight = false;
+        if self.led_on_interrupt_out || self.hid_driver.interrupt_out_endpoint().is_none() {
+            warn!("keyboard rejected the LED report");
+            return Ok(TransferRequest::NoOp);
+        }
+        trace!("SET_REPORT failed, retrying on the interrupt OUT endpoint");
+        self.led_on_interrupt_out = true;
+        Ok(self.led_request())
+    }
+    fn take_request(&mut self) -> Option<TransferRequest> {
+        self.request.take()
+    }
     fn on_interrupt_completed(
         &mut self,
         ep_id: EndpointId,
@@ -134,34 +154,42 @@ impl Driver for HidKeyboardDriver {
             "HidKeyboardDriver::on_interrupt_completed ep_id = {:?}",
             ep_id
         );
+        // the LED report sent on the interrupt OUT endpoint
+        if !ep_id.is_in() {
+            self.led_in_flight = false;
+            return Ok(self.next_led_request().unwrap_or(TransferRequest::NoOp));
+        }
         let req = self
             .hid_driver
             .on_interrupt_completed(ep_id, buf_ptr, transfered_size)?;
 
-        // FIXME
-        {
-            const SHIFT_MASK: u8 = 0b00100010;
-            let modifier = self.hid_driver.buffer()[0];
-            let shift = modifier & SHIFT_MASK != 0;
-            for i in 2..8 {
-                let key = self.hid_driver.buffer()[i];
-                if key == 0 || self.prev.contains(&key) {
-                    continue;
-                }
-
-                let ch = Self::key2ascii(shift, key);
-                println!(
-                    "key down: {:?} (mod: {:02x}, key: {:02x})",
-                    ch, modifier, key
-                );
+        // the boot protocol report: modifier, reserved and up to 6 keys being pressed
+        let modifier = self.hid_driver.buffer()[0];
+        for i in 2..8 {
+            let key = self.hid_driver.buffer()[i];
+            if key == 0 || self.prev.contains(&key) {
+                continue;
             }
-            for key in self.prev.iter() {
-                if !self.hid_driver.buffer()[2..8].contains(&key) {
-                    println!("  key up: {:02x}", key);
-                }
+            if let Some(led) = Self::lock_key_led(key) {
+                self.set_leds(self.leds ^ led);
+            }
+            push_key_event(KeyEvent {
+                keycode: key,
+                modifier,
+                pressed: true,
+            });
+        }
+        for i in 0..6 {
+            let key = self.prev[i];
+            if key != 0 && !self.hid_driver.buffer()[2..8].contains(&key) {
+                push_key_event(KeyEvent {
+                    keycode: key,
+                    modifier,
+                    pressed: false,
+                });
             }
-            self.prev.copy_from_slice(&self.hid_driver.buffer()[2..8]);
         }
+        self.prev.copy_from_slice(&self.hid_driver.buffer()[2..8]);
 
         Ok(req)
     }
@@ -19,11 +19,21 @@ use core::ptr::NonNull;
 pub enum TransferRequest {
     NoOp,
     ControlOut(SetupData),
+    ControlOutData {
+        setup_data: SetupData,
+        buf_ptr: NonNull<u8>,
+        size: usize,
+    },
     InterruptIn {
         ep_id: EndpointId,
         buf_ptr: Option<NonNull<u8>>,
         size: usize,
     },
+    InterruptOut {
+        ep_id: EndpointId,
+        buf_ptr: NonNull<u8>,
+        size: usize,
+    },
 }
 
 pub trait Driver {
@@ -42,4 +52,14 @@ pub trait Driver {
         buf_ptr: NonNull<u8>,
         transfered_size: usize,
     ) -> Result<TransferRequest>;
+
+    /// called when the control transfer issued by this driver failed
+    fn on_control_failed(&mut self, _setup_data: SetupData) -> Result<TransferRequest> {
+        Ok(TransferRequest::NoOp)
+    }
+
+    /// transfer the driver starts by itself, not as a reply to a completion
+    fn take_request(&mut self) -> Option<TransferRequest> {
+        None
+    }
 }
@@ -21,6 +21,8 @@ pub struct USBAlloc<const BUF_SIZE: usize> {
     ptr: usize,
     end: usize,
     boundary: usize,
+    // (start, size) of the freed blocks, reused before the pool
+    freed: Vec<(usize, usize)>,
 }
 
 impl<const BUF_SIZE: usize> USBAlloc<BUF_SIZE> {
@@ -34,6 +36,7 @@ impl<const BUF_SIZE: usize> USBAlloc<BUF_SIZE> {
             ptr,
             end,
             boundary: 4096,
+            freed: Vec::new(),
         }
     }
 
@@ -48,6 +51,10 @@ impl<const BUF_SIZE: usize> USBAlloc<BUF_SIZE> {
         align: usize,
         boundary: Option<usize>,
     ) -> Option<NonNull<[u8]>> {
+        if let Some(ptr) = self.alloc_freed(size, align, boundary) {
+            return Some(ptr);
+        }
+
         let mut ptr = Self::ceil(self.ptr, align);
         let next_boundary = Self::ceil(self.ptr, boundary.unwrap_or(self.boundary));
         if next_boundary < ptr + size {
@@ -67,6 +74,43 @@ impl<const BUF_SIZE: usize> USBAlloc<BUF_SIZE> {
         }
     }
 
+    // first fit from the freed blocks. the rest of the block is kept for the next allocation
+    fn alloc_freed(
+        &mut self,
+        size: usize,
+        align: usize,
+        boundary: Option<usize>,
+    ) -> Option<NonNull<[u8]>> {
+        let boundary = boundary.unwrap_or(self.boundary);
+        let (idx, ptr) = self.freed.iter().enumerate().find_map(|(i, &(start, len))| {
+            let ptr = Self::ceil(start, align);
+            let crosses = size <= boundary && Self::ceil(ptr + 1, boundary) < ptr + size;
+            if ptr + size <= start + len && !crosses {
+                Some((i, ptr))
+            } else {
+                None
+            }
+        })?;
+        let (start, len) = self.freed.swap_remove(idx);
+        if start < ptr {
+            self.freed.push((start, ptr - start));
+        }
+        if ptr + size < start + len {
+            self.freed.push((ptr + size, start + len - ptr - size));
+        }
+        trace!("memory reused(usb): start={:#x}, size={:#x}", ptr, size);
+
+        // the callers expect zeroed memory as the pool
+        unsafe { (ptr as *mut u8).write_bytes(0, size) };
+        Some(unsafe { NonNull::new_unchecked(slice_from_raw_parts_mut(ptr as *mut u8, size)) })
+    }
+
+    /// Safety: `ptr` and `size` must be of a block allocated by this allocator and no longer used
+    pub unsafe fn free(&mut self, ptr: NonNull<u8>, size: usize) {
+        trace!("memory freed(usb): start={:p}, size={:#x}", ptr, size);
+        self.freed.push((ptr.as_ptr() as usize, size));
+    }
+
     pub fn alloc_slice<T: 'static>(&mut self, len: usize) -> Option<NonNull<[T]>> {
         unsafe { self.alloc_slice_ext::<T>(len, align_of::<T>(), None) }
     }
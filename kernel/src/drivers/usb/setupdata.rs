@@ -30,9 +30,13 @@ pub enum Request {
 }
 #[repr(u8)]
 pub enum HidRequest {
+    SetReport = 9,
     SetProtocol = 11,
 }
 
+// the report type in the high byte of wValue of SET_REPORT
+pub const HID_REPORT_TYPE_OUTPUT: u16 = 2;
+
 #[derive(Default, Clone, PartialEq, Eq)]
 #[repr(C)]
 pub struct SetupData {
@@ -2,7 +2,10 @@ use super::{
     context::*,
     speed::PortSpeed,
     trb,
-    trb::{DataStage, EvaluateContextCommand, GenericTrb, Normal, SetupStage, StatusStage, Trb},
+    trb::{
+        DataStage, EvaluateContextCommand, GenericTrb, Normal, SetTrDequeuePointerCommand,
+        SetupStage, StatusStage, StopEndpointCommand, Trb,
+    },
     DoorbellRegister, Port, TransferRing,
 };
 use crate::{
@@ -19,16 +22,34 @@ use crate::{
         setupdata::request_type,
         setupdata::*,
     },
+    drivers::timer::current_tick,
     fixed_vec::FixedVec,
     info,
-    status::{Result, StatusCode},
+    status::{PortConfigPhase, Result, StatusCode},
     trace, warn,
 };
 use core::{
-    mem::size_of,
+    mem::{size_of, size_of_val},
     ptr::{addr_of_mut, null, null_mut, NonNull},
 };
 
+// ticks to wait for a control transfer before cancelling it
+const TRANSFER_TIMEOUT_TICKS: u64 = 3;
+
+/// control transfer waiting for the completion
+struct PendingControl {
+    setup_data: SetupData,
+    dci: DeviceContextIndex,
+    deadline: u64,
+}
+
+/// the step of the enumeration which failed last, shown in the device listing
+#[derive(Debug, Clone, Copy)]
+pub struct EnumerationFailure {
+    pub phase: PortConfigPhase,
+    pub error: StatusCode,
+}
+
 pub struct Device {
     ctx: *const DeviceContext,
     input_ctx: InputContext,
@@ -36,6 +57,7 @@ pub struct Device {
     transfer_rings: [Option<TransferRing>; 31],
     pub command_trb: Option<GenericTrb>,
     slot_id: u8,
+    port_num: u8,
     speed: PortSpeed,
 
     buf: Buffer,
@@ -52,11 +74,15 @@ pub struct Device {
     event_waiters: ArrayMap<SetupData, usize, 4>,
 
     /// {DataStage,StatusStage} TRB --> SetupData
-    setup_data_map: ArrayMap<*const GenericTrb, SetupData, 16>,
+    setup_data_map: ArrayMap<*const GenericTrb, PendingControl, 16>,
+
+    failure: Option<EnumerationFailure>,
 }
 
 impl Device {
     const BUF_SIZE: usize = 1024;
+    // the data buffer of a TRB must not cross 64KiB boundary
+    const MAX_TRB_BUFFER_BOUNDARY: usize = 64 * 1024;
 
     unsafe fn initialize_ptr(
         ptr: *mut Self,
@@ -87,6 +113,10 @@ impl Device {
             let slot_id_ptr = addr_of_mut!((*ptr).slot_id);
             slot_id_ptr.write(slot_id);
 
+            // the device context doesn't have the port number until the device is addressed
+            let port_num_ptr = addr_of_mut!((*ptr).port_num);
+            port_num_ptr.write(port.number());
+
             let speed_ptr = addr_of_mut!((*ptr).speed);
             speed_ptr.write(port.speed());
 
@@ -119,6 +149,9 @@ impl Device {
 
             let setup_data_map_ptr = addr_of_mut!((*ptr).setup_data_map);
             ArrayMap::initialize_ptr(setup_data_map_ptr);
+
+            let failure_ptr = addr_of_mut!((*ptr).failure);
+            failure_ptr.write(None);
         }
         let device = &mut *ptr;
 
@@ -161,8 +194,20 @@ impl Device {
         Ok(self.transfer_rings[i].as_ref().unwrap())
     }
 
+    pub fn slot_id(&self) -> u8 {
+        self.slot_id
+    }
+
     pub fn port_num(&self) -> u8 {
-        unsafe { (*self.ctx).slot_context.root_hub_port_number() }
+        self.port_num
+    }
+
+    pub fn failure(&self) -> Option<EnumerationFailure> {
+        self.failure
+    }
+
+    pub fn set_failure(&mut self, failure: EnumerationFailure) {
+        self.failure = Some(failure);
     }
 
     pub fn is_initialized(&self) -> bool {
@@ -224,15 +269,33 @@ impl Device {
     fn initialize_phase2(&mut self, transfered_size: usize) -> Result<()> {
         trace!("initialize_phase2 on slot_id={}", self.slot_id);
 
-        // TODO: handle the case where total_length > BUF_SIZE
-        assert_eq!(
-            descriptor::from_bytes::<ConfigurationDescriptor>(&self.buf[..])
-                .unwrap()
-                .total_length as usize,
-            transfered_size
-        );
+        let total_length = descriptor::from_bytes::<ConfigurationDescriptor>(&self.buf[..])
+            .unwrap()
+            .total_length as usize;
+        if total_length > self.buf[..].len() {
+            // the descriptors don't fit: request them again with a buffer large enough
+            trace!(
+                "config descriptor is {} bytes, reissuing Get Config Descriptor",
+                total_length
+            );
+            self.buf = Buffer::try_new(total_length, 64, Some(Self::MAX_TRB_BUFFER_BOUNDARY))
+                .ok_or(StatusCode::NoEnoughMemory)?;
+            return self.get_descriptor(
+                EndpointId::DEFAULT_CONTROL_PIPE,
+                ConfigurationDescriptor::TYPE,
+                self.config_index,
+                total_length,
+            );
+        }
+        if transfered_size < total_length {
+            warn!(
+                "config descriptor is truncated: {} of {} bytes",
+                transfered_size, total_length
+            );
+        }
 
-        let mut desc_itr = DescIter::new(&self.buf[..transfered_size]);
+        let parse_size = transfered_size.min(total_length);
+        let mut desc_itr = DescIter::new(&self.buf[..parse_size]);
         while let Some(if_desc) = desc_itr.next::<InterfaceDescriptor>() {
             let class_driver = match Self::new_class_driver(if_desc) {
                 Ok(driver) => driver,
@@ -240,7 +303,22 @@ impl Device {
                 Err(e) => return Err(e),
             };
 
-            let class_driver_idx = self.class_drivers.push(class_driver).unwrap();
+            // the endpoints of the skipped interface are skipped by the next search
+            let class_driver_idx = match self
+                .class_drivers
+                .push(class_driver)
+                .map_err(|_| StatusCode::TooManyDrivers)
+            {
+                Ok(idx) => idx,
+                Err(e) => {
+                    warn!(
+                        "{}: interface {} is ignored",
+                        e.to_string(),
+                        if_desc.interface_number
+                    );
+                    continue;
+                }
+            };
 
             let mut num_endpoints = 0;
             trace!("if_desc.num_endpoints = {}", if_desc.num_endpoints);
@@ -249,9 +327,20 @@ impl Device {
                     let conf = EndpointConfig::from(ep_desc);
                     trace!("{:?}", conf);
                     let ep_id = conf.ep_id;
-                    self.ep_configs.push(conf);
                     num_endpoints += 1;
-                    self.class_driver_idxs[ep_id.number() as usize] = Some(class_driver_idx);
+                    // class_driver_idxs is indexed by the endpoint number
+                    let ep_num = ep_id.number() as usize;
+                    let result = if ep_num >= self.class_driver_idxs.len() {
+                        Err(StatusCode::InvalidEndpointNumber)
+                    } else {
+                        self.ep_configs
+                            .push(conf)
+                            .map_err(|_| StatusCode::TooManyEndpoints)
+                    };
+                    match result {
+                        Ok(_) => self.class_driver_idxs[ep_num] = Some(class_driver_idx),
+                        Err(e) => warn!("{}: endpoint {:?} is ignored", e.to_string(), ep_id),
+                    }
                 } else if let Some(hid_desc) = desc_itr.next::<HidDescriptor>() {
                     trace!("{:?}", hid_desc);
                 }
@@ -275,12 +364,22 @@ impl Device {
         }
     }
 
+    fn class_driver_idx(&self, ep_id: EndpointId) -> Option<usize> {
+        self.class_driver_idxs
+            .get(ep_id.number() as usize)
+            .copied()
+            .flatten()
+    }
+
     fn initialize_phase3(&mut self) -> Result<()> {
         trace!("initialize_phase3 on slot_id={}", self.slot_id);
-        for conf in self.ep_configs.iter() {
-            let driver_idx = self.class_driver_idxs[conf.ep_id.number() as usize];
+        for i in 0..self.ep_configs.len() {
+            let conf = self.ep_configs.get(i).unwrap();
+            let driver_idx = self
+                .class_driver_idx(conf.ep_id)
+                .ok_or(StatusCode::InvalidEndpointNumber)?;
             self.class_drivers
-                .get_mut(driver_idx.unwrap())
+                .get_mut(driver_idx)
                 .unwrap()
                 .set_endpoint(conf)?;
         }
@@ -403,23 +502,50 @@ impl Device {
     pub fn on_endpoints_configured(&mut self) -> Result<()> {
         for idx in 0..self.class_drivers.len() {
             let class_driver = self.class_drivers.get_mut(idx).unwrap();
-            match class_driver.on_endpoints_configured()? {
-                classdriver::TransferRequest::NoOp => continue,
-                classdriver::TransferRequest::ControlOut(setup_data) => {
-                    self.control_out(
-                        EndpointId::DEFAULT_CONTROL_PIPE,
-                        setup_data,
-                        Some(idx),
-                        None,
-                        0,
-                    )?;
-                }
-                _ => unimplemented!(),
-            }
+            let req = class_driver.on_endpoints_configured()?;
+            self.issue_request(idx, req)?;
         }
         Ok(())
     }
 
+    fn issue_request(
+        &mut self,
+        driver_idx: usize,
+        req: classdriver::TransferRequest,
+    ) -> Result<()> {
+        match req {
+            classdriver::TransferRequest::NoOp => Ok(()),
+            classdriver::TransferRequest::ControlOut(setup_data) => self.control_out(
+                EndpointId::DEFAULT_CONTROL_PIPE,
+                setup_data,
+                Some(driver_idx),
+                None,
+                0,
+            ),
+            classdriver::TransferRequest::ControlOutData {
+                setup_data,
+                buf_ptr,
+                size,
+            } => self.control_out(
+                EndpointId::DEFAULT_CONTROL_PIPE,
+                setup_data,
+                Some(driver_idx),
+                Some(buf_ptr),
+                size,
+            ),
+            classdriver::TransferRequest::InterruptIn {
+                ep_id,
+                buf_ptr,
+                size,
+            } => self.interrupt_in(ep_id, buf_ptr, size),
+            classdriver::TransferRequest::InterruptOut {
+                ep_id,
+                buf_ptr,
+                size,
+            } => self.interrupt_out(ep_id, buf_ptr, size),
+        }
+    }
+
     pub fn on_command_completion_event_received(&mut self, issuer_type: u8) -> Result<()> {
         match issuer_type {
             trb::AddressDeviceCommand::TYPE => {
@@ -472,7 +598,7 @@ impl Device {
                 warn!("No Correspoinding Setup Stage TRB");
                 return Err(StatusCode::NoCorrespondingSetupStage);
             }
-            Some((_, setup_data)) => setup_data,
+            Some((_, pending)) => pending.setup_data,
         };
 
         let (buf, transfered_size) =
@@ -489,6 +615,30 @@ impl Device {
         self.on_control_completed(trb.endpoint_id(), setup_data, buf, transfered_size)
     }
 
+    /// let the class driver know its control transfer failed.
+    /// the event may point any TRB of the transfer, so the pending one is looked up by the endpoint
+    pub fn on_transfer_failed(&mut self, trb: &trb::TransferEvent) -> Result<()> {
+        let dci = DeviceContextIndex::from(trb.endpoint_id());
+        let key = match self
+            .setup_data_map
+            .iter()
+            .find(|(_, pending)| pending.dci.0 == dci.0)
+        {
+            Some((&key, _)) => key,
+            None => return Ok(()),
+        };
+        let (_, pending) = self.setup_data_map.remove(&key).unwrap();
+        if let Some((_, w_idx)) = self.event_waiters.remove(&pending.setup_data) {
+            let w = self
+                .class_drivers
+                .get_mut(w_idx)
+                .expect("uninitialized class driver");
+            let req = w.on_control_failed(pending.setup_data)?;
+            self.issue_request(w_idx, req)?;
+        }
+        Ok(())
+    }
+
     fn on_control_completed(
         &mut self,
         ep_id: EndpointId,
@@ -547,20 +697,15 @@ impl Device {
                     Err(StatusCode::InvalidPhase)
                 }
             }
-            _ => match self.event_waiters.get(&setup_data) {
-                Some(&w_idx) => {
+            _ => match self.event_waiters.remove(&setup_data) {
+                Some((_, w_idx)) => {
                     let w = self
                         .class_drivers
                         .get_mut(w_idx)
                         .expect("uninitialized class driver");
-                    match w.on_control_completed(ep_id, setup_data, buf_ptr, transfered_size)? {
-                        classdriver::TransferRequest::InterruptIn {
-                            ep_id,
-                            buf_ptr,
-                            size,
-                        } => self.interrupt_in(ep_id, buf_ptr, size),
-                        _ => unimplemented!(),
-                    }
+                    let req =
+                        w.on_control_completed(ep_id, setup_data, buf_ptr, transfered_size)?;
+                    self.issue_request(w_idx, req)
                 }
                 None => Err(StatusCode::NoWaiter),
             },
@@ -577,17 +722,13 @@ impl Device {
             "Device::on_interrupt_completed: EP addr = {}",
             ep_id.address()
         );
-        if let Some(driver_idx) = self.class_driver_idxs[ep_id.number() as usize] {
+        if let Some(driver_idx) = self.class_driver_idx(ep_id) {
             let w = self.class_drivers.get_mut(driver_idx).unwrap();
-            match w.on_interrupt_completed(ep_id, buf_ptr, transfered_size)? {
-                classdriver::TransferRequest::InterruptIn {
-                    ep_id,
-                    buf_ptr,
-                    size,
-                } => {
-                    self.interrupt_in(ep_id, buf_ptr, size)?;
-                }
-                _ => unimplemented!(),
+            let req = w.on_interrupt_completed(ep_id, buf_ptr, transfered_size)?;
+            let extra_req = w.take_request();
+            self.issue_request(driver_idx, req)?;
+            if let Some(req) = extra_req {
+                self.issue_request(driver_idx, req)?;
             }
         } else {
             trace!("class driver not found");
@@ -595,6 +736,44 @@ impl Device {
         Ok(())
     }
 
+    /// cancel the control transfers which passed the deadline.
+    /// returns the commands to stop the endpoint and to skip the TRBs left on its ring
+    pub fn expire_transfers(
+        &mut self,
+        now: u64,
+    ) -> Option<(StopEndpointCommand, SetTrDequeuePointerCommand)> {
+        let dci = self
+            .setup_data_map
+            .iter()
+            .find(|(_, pending)| pending.deadline <= now)
+            .map(|(_, pending)| pending.dci)?;
+
+        // every transfer on the endpoint is dropped together with the ring contents
+        let event_waiters = &mut self.event_waiters;
+        self.setup_data_map.retain(|_, pending| {
+            if pending.dci.0 != dci.0 {
+                return true;
+            }
+            warn!(
+                "slot_id = {}: request {} timed out",
+                self.slot_id, pending.setup_data.request
+            );
+            event_waiters.remove(&pending.setup_data);
+            false
+        });
+
+        let tr = self.transfer_rings[dci.0 - 1].as_ref()?;
+        let mut stop = StopEndpointCommand::default();
+        stop.set_slot_id(self.slot_id);
+        stop.set_endpoint_id(dci.0 as u8);
+        let mut set_deq = SetTrDequeuePointerCommand::default();
+        set_deq.set_slot_id(self.slot_id);
+        set_deq.set_endpoint_id(dci.0 as u8);
+        set_deq.set_dequeue_ptr(tr.enqueue_ptr());
+        set_deq.set_dequeue_cycle_state(tr.cycle_bit as u8);
+        Some((stop, set_deq))
+    }
+
     fn ring_doorbell(&mut self, dci: DeviceContextIndex) {
         trace!("ring the doorbell with target {}", dci.0);
         unsafe { (*self.doorbell).ring(dci.0 as u8) };
@@ -643,8 +822,13 @@ impl Device {
             let status_stage_trb_ptr = tr.push(status_stage.upcast());
             trace!("status_stage_trb = {:p}", status_stage_trb_ptr);
 
+            let pending = PendingControl {
+                setup_data,
+                dci,
+                deadline: current_tick() + TRANSFER_TIMEOUT_TICKS,
+            };
             self.setup_data_map
-                .insert(data_stage_trb_ptr, setup_data)
+                .insert(data_stage_trb_ptr, pending)
                 .map_err(|e| match e {
                     ArrayMapError::NoSpace => StatusCode::TooManyWaiters,
                     ArrayMapError::SameKeyRegistered => {
@@ -689,9 +873,34 @@ impl Device {
             .as_mut()
             .ok_or(StatusCode::TransferRingNotSet)?;
 
-        if let Some(_buf_ptr) = buf_ptr {
-            let _size = size;
-            unimplemented!();
+        if let Some(buf_ptr) = buf_ptr {
+            let setup_stage = SetupStage::new_out_data_stage(setup_data.clone());
+            tr.push(setup_stage.upcast());
+
+            let data_stage = DataStage::new_out(buf_ptr.as_ptr(), size);
+            tr.push(data_stage.upcast());
+
+            let mut status_stage = StatusStage::default();
+            status_stage.set_direction(1);
+            status_stage.set_interrupt_on_completion(1);
+            let status_stage_trb_ptr = tr.push(status_stage.upcast());
+            trace!("status_stage_trb = {:p}", status_stage_trb_ptr);
+
+            let pending = PendingControl {
+                setup_data,
+                dci,
+                deadline: current_tick() + TRANSFER_TIMEOUT_TICKS,
+            };
+            self.setup_data_map
+                .insert(status_stage_trb_ptr, pending)
+                .map_err(|e| match e {
+                    ArrayMapError::NoSpace => StatusCode::TooManyWaiters,
+                    ArrayMapError::SameKeyRegistered => {
+                        panic!("same status_stage_trb_ptr registered")
+                    }
+                })?;
+
+            self.ring_doorbell(dci);
         } else {
             let setup_stage = SetupStage::new_no_data_stage(setup_data.clone());
             tr.push(setup_stage.upcast());
@@ -702,8 +911,13 @@ impl Device {
             let status_stage_trb_ptr = tr.push(status_stage.upcast());
             trace!("status_stage_trb = {:p}", status_stage_trb_ptr);
 
+            let pending = PendingControl {
+                setup_data,
+                dci,
+                deadline: current_tick() + TRANSFER_TIMEOUT_TICKS,
+            };
             self.setup_data_map
-                .insert(status_stage_trb_ptr, setup_data)
+                .insert(status_stage_trb_ptr, pending)
                 .map_err(|e| match e {
                     ArrayMapError::NoSpace => StatusCode::TooManyWaiters,
                     ArrayMapError::SameKeyRegistered => {
@@ -739,6 +953,23 @@ impl Device {
         Ok(())
     }
 
+    fn interrupt_out(&mut self, ep_id: EndpointId, buf_ptr: NonNull<u8>, size: usize) -> Result<()> {
+        let dci = DeviceContextIndex::from(ep_id);
+        let tr = self.transfer_rings[dci.0 - 1]
+            .as_mut()
+            .ok_or(StatusCode::TransferRingNotSet)?;
+
+        let mut normal = Normal::default();
+        normal.set_data_buffer(buf_ptr.as_ptr());
+        normal.set_trb_transfer_length(size as u32);
+        normal.set_interrupt_on_completion(1);
+
+        tr.push(normal.upcast());
+        self.ring_doorbell(dci);
+
+        Ok(())
+    }
+
     fn get_descriptor(
         &mut self,
         ep_id: EndpointId,
@@ -772,6 +1003,26 @@ impl Device {
     }
 }
 
+// the slot must be disabled before dropping, since the rings are given back to usballoc
+impl Drop for Device {
+    fn drop(&mut self) {
+        for ring in self.transfer_rings.iter_mut() {
+            if let Some(ring) = ring.take() {
+                unsafe { ring.free() };
+            }
+        }
+        // torn down in the order they were created
+        while let Some(driver) = self.class_drivers.remove(0) {
+            let size = size_of_val(driver);
+            let ptr = NonNull::from(driver);
+            unsafe {
+                ptr.as_ptr().drop_in_place();
+                usballoc().free(ptr.cast(), size);
+            }
+        }
+    }
+}
+
 pub struct DeviceManager {
     devices: &'static mut [Option<&'static mut Device>],
     device_context_pointers: *mut [*const DeviceContext],
@@ -856,6 +1107,36 @@ impl DeviceManager {
         Ok(input_ctx)
     }
 
+    /// free the device and its slot. the slot must have been disabled by Disable Slot Command
+    pub fn remove_device(&mut self, slot_id: u8) -> Result<()> {
+        let device = self
+            .devices
+            .get_mut(slot_id as usize)
+            .and_then(|dev| dev.take())
+            .ok_or(StatusCode::InvalidSlotId)?;
+
+        unsafe {
+            (*self.device_context_pointers)
+                .as_mut_ptr()
+                .add(slot_id as usize)
+                .write(null())
+        };
+
+        let device_ctx = device.ctx as *mut u8;
+        let device = NonNull::from(device);
+        unsafe {
+            device.as_ptr().drop_in_place();
+            usballoc().free(device.cast(), size_of::<Device>());
+            usballoc().free(
+                NonNull::new_unchecked(device_ctx),
+                size_of::<DeviceContext>(),
+            );
+        }
+        trace!("remove_device: slot_id = {}", slot_id);
+
+        Ok(())
+    }
+
     pub fn dcbaap(&self) -> *const *const DeviceContext {
         let ptr = unsafe { (*self.device_context_pointers).as_ptr() };
         debug_assert!((ptr as usize) % 64 == 0);
@@ -868,6 +1149,17 @@ impl DeviceManager {
             .and_then(|dev| dev.as_deref())
     }
 
+    pub fn find_by_port(&self, port_num: u8) -> Option<&Device> {
+        self.devices
+            .iter()
+            .filter_map(|dev| dev.as_deref())
+            .find(|dev| dev.port_num() == port_num)
+    }
+
+    pub fn devices_mut(&mut self) -> impl Iterator<Item = &mut Device> {
+        self.devices.iter_mut().filter_map(|dev| dev.as_deref_mut())
+    }
+
     pub fn find_by_slot_mut(&mut self, slot_id: u8) -> Option<&mut Device> {
         self.devices
             .get_mut(slot_id as usize)
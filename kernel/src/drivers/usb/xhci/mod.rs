@@ -7,21 +7,28 @@ mod speed;
 mod trb;
 
 use crate::{
-    drivers::{pci::*, usb::memory::*},
-    error, info,
+    drivers::{pci::*, timer::current_tick, usb::memory::*},
+    error,
+    fixed_vec::FixedVec,
+    info,
+    lapic::LocalApic,
     status::{PortConfigPhase, Result, StatusCode},
     status_log, trace,
     volatile::Volatile,
-    warn, InterruptVector, XHC,
+    warn, InterruptVector,
 };
+use alloc::vec::Vec;
 use core::ptr::{addr_of_mut, null, null_mut};
+use spin::Mutex;
 use devmgr::DeviceManager;
+pub use devmgr::EnumerationFailure;
 use port::*;
 use registers::*;
 use ring::*;
 use trb::{
-    AddressDeviceCommand, CommandCompletionEvent, ConfigureEndpointCommand, EnableSlotCommand,
-    EvaluateContextCommand, PortStatusChangeEvent, TransferEvent, Trb,
+    AddressDeviceCommand, CommandCompletionEvent, ConfigureEndpointCommand, DisableSlotCommand,
+    EnableSlotCommand, EvaluateContextCommand, GenericTrb, PortStatusChangeEvent,
+    SetTrDequeuePointerCommand, StopEndpointCommand, TransferEvent, Trb,
 };
 
 pub fn initialize_xhci(dev: &Device) -> Controller {
@@ -29,7 +36,7 @@ pub fn initialize_xhci(dev: &Device) -> Controller {
         "xHC has been found: {}.{}.{}",
         dev.bus, dev.device, dev.function
     );
-    let bsp_local_apic_id: u8 = unsafe { (*(0xFEE00020 as *const u32) >> 24) as u8 };
+    let bsp_local_apic_id: u8 = LocalApic::id() as u8;
     status_log!(
         configure_msi_fixed_destination(
             dev,
@@ -51,6 +58,19 @@ pub fn initialize_xhci(dev: &Device) -> Controller {
     return xhc;
 }
 
+/// the ports published by the main loop, which owns the controller. None until it's found
+pub static PORT_INFOS: Mutex<Option<Vec<PortInfo>>> = Mutex::new(None);
+
+/// state of a connected port, for the device listing
+#[derive(Clone)]
+pub struct PortInfo {
+    pub port_num: u8,
+    pub phase: PortConfigPhase,
+    pub retries: u8,
+    pub slot_id: Option<u8>,
+    pub failure: Option<EnumerationFailure>,
+}
+
 pub struct Controller {
     op_regs: *mut OperationalRegisters,
     devmgr: DeviceManager,
@@ -64,6 +84,8 @@ pub struct Controller {
 
 impl Controller {
     const DEVICES_SIZE: usize = 16;
+    // a failed device is reset and enumerated again this many times before giving up
+    const MAX_ENUMERATION_RETRIES: u8 = 1;
     /// # Safety
     /// mmio_base must be a valid base address for xHCI device MMIO
     pub unsafe fn new(mmio_base: usize) -> Result<Self> {
@@ -331,6 +353,39 @@ impl Controller {
         Ok(())
     }
 
+    fn disable_slot(&mut self, slot_id: u8) {
+        let mut cmd = DisableSlotCommand::default();
+        cmd.set_slot_id(slot_id);
+        self.cr.push(cmd.upcast());
+        Self::ring_doorbell(self.doorbell_first);
+    }
+
+    /// reset the port which waits for the address next
+    unsafe fn reset_waiting_port(&mut self) -> Result<()> {
+        for i in 1..=self.max_ports {
+            if self.ports[i as usize].config_phase() == PortConfigPhase::WaitingAddressed {
+                trace!("the next port is port {}!", i);
+                return self.reset_port(i);
+            }
+        }
+        Ok(())
+    }
+
+    /// the device is freed after its slot is disabled
+    fn on_port_disconnected(&mut self, port_num: u8) -> Result<()> {
+        info!("Port {}: disconnected", port_num);
+        self.ports[port_num as usize].set_config_phase(PortConfigPhase::NotConnected);
+        self.ports[port_num as usize].set_retries(0);
+        if let Some(slot_id) = self.devmgr.find_by_port(port_num).map(|dev| dev.slot_id()) {
+            self.disable_slot(slot_id);
+        }
+        if self.addressing_port == Some(port_num) {
+            self.addressing_port = None;
+            unsafe { self.reset_waiting_port()? };
+        }
+        Ok(())
+    }
+
     fn address_device(&mut self, port_num: u8, slot_id: u8) -> Result<()> {
         trace!("address_device: port = {}, slot = {}", port_num, slot_id);
         let port = &self.ports[port_num as usize];
@@ -349,20 +404,156 @@ impl Controller {
     pub fn process_event(&mut self) -> Result<()> {
         if let Some(trb) = self.er.front() {
             trace!("event found: TRB type = {}", trb.trb_type());
+            let slot_id = Self::event_slot_id(trb);
 
-            match trb.trb_type() {
-                TransferEvent::TYPE => self.on_transfer_event()?,
-                CommandCompletionEvent::TYPE => self.on_command_completion_event()?,
-                PortStatusChangeEvent::TYPE => self.on_port_status_change_event()?,
-                _ => {}
-            }
+            let result = match trb.trb_type() {
+                TransferEvent::TYPE => self.on_transfer_event(),
+                CommandCompletionEvent::TYPE => self.on_command_completion_event(),
+                PortStatusChangeEvent::TYPE => self.on_port_status_change_event(),
+                _ => Ok(()),
+            };
 
+            // the failed event has to be popped too, otherwise it is processed forever
             self.er.pop();
             trace!("event popped");
+            if let Err(e) = result {
+                self.on_enumeration_failed(slot_id, e);
+            }
+            return result;
         }
         Ok(())
     }
 
+    /// cancel the control transfers which aren't completed in time,
+    /// so that they don't occupy the event waiters forever
+    pub fn poll_timeouts(&mut self) -> Result<()> {
+        let now = current_tick();
+        let mut result = Ok(());
+        let mut timed_out = FixedVec::<u8, { Self::DEVICES_SIZE }>::new();
+        for dev in self.devmgr.devices_mut() {
+            while let Some((stop, set_deq)) = dev.expire_transfers(now) {
+                // the commands are processed in order, so the endpoint is stopped before moving the dequeue pointer
+                self.cr.push(stop.upcast());
+                self.cr.push(set_deq.upcast());
+                Self::ring_doorbell(self.doorbell_first);
+                result = Err(StatusCode::TransferTimeout {
+                    slot_id: dev.slot_id(),
+                });
+                if timed_out.as_slice().last() != Some(&dev.slot_id()) {
+                    let _ = timed_out.push(dev.slot_id());
+                }
+            }
+        }
+        for &slot_id in timed_out.as_slice() {
+            self.on_enumeration_failed(slot_id, StatusCode::TransferTimeout { slot_id });
+        }
+        result
+    }
+
+    /// the slot the event is about, or 0 if it isn't about a slot
+    fn event_slot_id(trb: &GenericTrb) -> u8 {
+        if let Some(event) = trb.downcast_ref::<TransferEvent>() {
+            event.slot_id()
+        } else if let Some(event) = trb.downcast_ref::<CommandCompletionEvent>() {
+            event.slot_id()
+        } else {
+            0
+        }
+    }
+
+    /// tear down the device whose enumeration failed and enumerate it again from the port reset.
+    /// once the retries run out, the device is left as it is with the failure recorded.
+    /// the errors of the configured devices are left to their class drivers
+    fn on_enumeration_failed(&mut self, slot_id: u8, error: StatusCode) {
+        let has_device = self.devmgr.find_by_slot(slot_id).is_some();
+        let port_num = match self.devmgr.find_by_slot(slot_id) {
+            Some(dev) => dev.port_num(),
+            // Enable Slot Command failed, so there is no device yet
+            None => match (error, self.addressing_port) {
+                (StatusCode::CommandCompletionFailed { .. }, Some(port_num))
+                    if self.ports[port_num as usize].config_phase()
+                        == PortConfigPhase::EnablingSlot =>
+                {
+                    port_num
+                }
+                _ => return,
+            },
+        };
+        let phase = self.ports[port_num as usize].config_phase();
+        if !matches!(
+            phase,
+            PortConfigPhase::EnablingSlot
+                | PortConfigPhase::AddressingDevice
+                | PortConfigPhase::InitializingDevice
+                | PortConfigPhase::ConfiguringEndpoints
+        ) {
+            return;
+        }
+        warn!(
+            "Port {}: enumeration failed in {:?}: {}",
+            port_num,
+            phase,
+            error.to_string()
+        );
+        if let Some(dev) = self.devmgr.find_by_slot_mut(slot_id) {
+            dev.set_failure(EnumerationFailure { phase, error });
+        }
+
+        if self.addressing_port == Some(port_num) {
+            self.addressing_port = None;
+        }
+        let retries = self.ports[port_num as usize].retries();
+        if retries >= Self::MAX_ENUMERATION_RETRIES {
+            error!(
+                "Port {}: the device is given up after {} retries",
+                port_num, retries
+            );
+            self.ports[port_num as usize].set_config_phase(PortConfigPhase::Failed);
+            if let Err(e) = unsafe { self.reset_waiting_port() } {
+                error!("Failed to reset the next port: {:?}", e);
+            }
+            return;
+        }
+
+        // the slot is disabled before the new one is enabled, because the commands are processed in order
+        if has_device {
+            self.disable_slot(slot_id);
+        }
+        let port = &mut self.ports[port_num as usize];
+        port.set_retries(retries + 1);
+        port.set_config_phase(PortConfigPhase::NotConnected);
+        info!("Port {}: resetting for the retry {}", port_num, retries + 1);
+        if let Err(e) = unsafe { self.reset_port(port_num) } {
+            error!("Failed to configure the port {}: {:?}", port_num, e);
+        }
+    }
+
+    /// the ports which have a device, including the failed ones
+    pub fn port_infos(&self) -> Vec<PortInfo> {
+        let mut infos = Vec::new();
+        for port_num in 1..=self.max_ports {
+            let port = &self.ports[port_num as usize];
+            if port.config_phase() == PortConfigPhase::NotConnected {
+                continue;
+            }
+            let dev = self.devmgr.find_by_port(port_num);
+            infos.push(PortInfo {
+                port_num,
+                phase: port.config_phase(),
+                retries: port.retries(),
+                slot_id: dev.map(|dev| dev.slot_id()),
+                failure: dev.and_then(|dev| dev.failure()),
+            });
+        }
+        infos
+    }
+
+    /// update PORT_INFOS, after the events changed the ports
+    pub fn publish_port_infos(&self) {
+        let infos = self.port_infos();
+        *PORT_INFOS.lock() = Some(infos);
+    }
+
     fn on_transfer_event(&mut self) -> Result<()> {
         let trb = self
             .er
@@ -373,6 +564,14 @@ impl Controller {
 
         let slot_id = trb.slot_id();
 
+        // reported for the TRB which was running when the endpoint was stopped by poll_timeouts
+        if trb.completion_code() == 26 /* Stopped */ ||
+           trb.completion_code() == 27 /* Stopped - Length Invalid */
+        {
+            trace!("transfer stopped: slot_id = {}", slot_id);
+            return Ok(());
+        }
+
         if !(trb.completion_code() == 1 /* Success */ ||
              trb.completion_code() == 13/* Short Packet */)
         {
@@ -383,6 +582,9 @@ impl Controller {
             );
             let issuer_trb = unsafe { &*trb.trb_pointer() };
             trace!("issuer = {:?}", issuer_trb);
+            if let Some(dev) = self.devmgr.find_by_slot_mut(slot_id) {
+                dev.on_transfer_failed(trb)?;
+            }
             return Err(StatusCode::TransferFailed {
                 slot_id: trb.slot_id(),
             });
@@ -431,6 +633,14 @@ impl Controller {
         let issuer_type = unsafe { (*trb.command_trb_pointer()).trb_type() };
         let slot_id = trb.slot_id();
 
+        // the endpoint may have already stopped or halted before Stop Endpoint
+        if issuer_type == StopEndpointCommand::TYPE && trb.completion_code() == 19
+        /* Context State Error */
+        {
+            trace!("endpoint is not running: slot_id = {}", slot_id);
+            return Ok(());
+        }
+
         if trb.completion_code() != 1 {
             return Err(StatusCode::CommandCompletionFailed {
                 slot_id: trb.slot_id(),
@@ -453,10 +663,17 @@ impl Controller {
                     self.address_device(port_num, slot_id)
                 }
                 _ => {
-                    warn!("addressing_port is None");
-                    Err(StatusCode::InvalidPhase)
+                    // the port has been disconnected while the slot was being enabled
+                    warn!("no port waits for slot {}, disabling it", slot_id);
+                    self.disable_slot(slot_id);
+                    Ok(())
                 }
             },
+            DisableSlotCommand::TYPE => {
+                self.devmgr.remove_device(slot_id)?;
+                info!("slot {} has been disabled", slot_id);
+                Ok(())
+            }
             AddressDeviceCommand::TYPE => {
                 let dev = self
                     .devmgr
@@ -483,17 +700,7 @@ impl Controller {
                 } else {
                     self.addressing_port = None;
                     trace!("looking for the next port to address ...");
-                    for i in 1..=self.max_ports {
-                        if self.ports[i as usize].config_phase()
-                            == PortConfigPhase::WaitingAddressed
-                        {
-                            trace!("the next port is port {}!", i);
-                            unsafe {
-                                self.reset_port(i)?;
-                            }
-                            break;
-                        }
-                    }
+                    unsafe { self.reset_waiting_port()? };
                     let dev = self
                         .devmgr
                         .find_by_slot_mut(slot_id)
@@ -547,6 +754,10 @@ impl Controller {
                     Ok(())
                 }
             }
+            StopEndpointCommand::TYPE | SetTrDequeuePointerCommand::TYPE => {
+                trace!("transfer ring has been cleaned up: slot_id = {}", slot_id);
+                Ok(())
+            }
             _ => {
                 warn!("unexpected Event");
                 Err(StatusCode::InvalidPhase)
@@ -569,6 +780,10 @@ impl Controller {
             port.config_phase(),
             port.bits(),
         );
+        if port.is_connect_status_changed() && !unsafe { port.is_connected() } {
+            port.clear_connect_status_change();
+            return self.on_port_disconnected(port_id);
+        }
         match port.config_phase() {
             PortConfigPhase::NotConnected => {
                 if port.is_connect_status_changed() {
@@ -595,6 +810,10 @@ impl Controller {
                 trace!("waiting addressed: port_id = {}", port_id);
                 Ok(())
             }
+            PortConfigPhase::Failed => {
+                trace!("skipping the failed port: port_id = {}", port_id);
+                Ok(())
+            }
             phase => {
                 warn!(
                     "config_phase = {:?} (should be {:?}, {:?}, {:?}, or {:?})",
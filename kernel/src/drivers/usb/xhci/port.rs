@@ -5,6 +5,9 @@ pub struct Port {
     port_num: u8,
     regs: *mut PortRegisterSet,
     config_phase: PortConfigPhase,
+    // the number of the re-enumerations since the device was connected.
+    // this is kept in the port because the device is recreated by each enumeration
+    retries: u8,
 }
 
 impl Port {
@@ -13,6 +16,7 @@ impl Port {
             port_num,
             regs,
             config_phase: PortConfigPhase::NotConnected,
+            retries: 0,
         }
     }
 
@@ -23,6 +27,13 @@ impl Port {
         self.config_phase = cp;
     }
 
+    pub fn retries(&self) -> u8 {
+        self.retries
+    }
+    pub fn set_retries(&mut self, retries: u8) {
+        self.retries = retries;
+    }
+
     pub fn number(&self) -> u8 {
         return self.port_num;
     }
@@ -6,8 +6,8 @@ use crate::{
     bit_getter, bit_setter, drivers::usb::memory::*, status::StatusCode, trace, volatile::Volatile,
 };
 use core::{
-    mem::zeroed,
-    ptr::{addr_of, addr_of_mut, null_mut},
+    mem::{size_of, zeroed},
+    ptr::{addr_of, addr_of_mut, null_mut, NonNull},
 };
 
 pub struct Ring {
@@ -34,10 +34,21 @@ impl Ring {
         })
     }
 
+    /// Safety: the controller must not access the ring anymore
+    pub unsafe fn free(self) {
+        let size = self.buf.len() * size_of::<GenericTrb>();
+        usballoc().free(NonNull::from(self.buf).cast(), size);
+    }
+
     pub fn buffer_ptr(&self) -> *const GenericTrb {
         self.buf.as_ptr()
     }
 
+    // the slot the next TRB is written to
+    pub fn enqueue_ptr(&self) -> *const GenericTrb {
+        &self.buf[self.write_idx]
+    }
+
     pub fn copy_to_last(&mut self, mut trb: GenericTrb) {
         trb.set_cycle_bit(self.cycle_bit as u8);
 
@@ -13,9 +13,12 @@ pub enum TypeId {
     Link = 6,
 
     EnableSlotCommand = 9,
+    DisableSlotCommand = 10,
     AddressDeviceCommand = 11,
     ConfigureEndpointCommand = 12,
     EvaluteContextCommand = 13,
+    StopEndpointCommand = 15,
+    SetTrDequeuePointerCommand = 16,
 
     TransferEvent = 32,
     CommandCompletionEvent = 33,
@@ -328,6 +331,26 @@ impl Trb for EnableSlotCommand {
     const TYPE: u8 = TypeId::EnableSlotCommand as u8;
 }
 
+#[repr(C, align(16))]
+pub struct DisableSlotCommand {
+    data: [u32; 4],
+}
+impl DisableSlotCommand {
+    bit_setter!(data[3]: u32; 0x0000FC00; u8, pub set_trb_type);
+
+    bit_setter!(data[3]: u32; 0xFF000000; u8, pub set_slot_id);
+}
+impl Default for DisableSlotCommand {
+    fn default() -> Self {
+        let mut trb = Self { data: [0; 4] };
+        trb.set_trb_type(Self::TYPE);
+        trb
+    }
+}
+impl Trb for DisableSlotCommand {
+    const TYPE: u8 = TypeId::DisableSlotCommand as u8;
+}
+
 #[repr(C, align(16))]
 pub struct AddressDeviceCommand {
     data: [u32; 4],
@@ -439,6 +462,66 @@ impl Trb for EvaluateContextCommand {
     const TYPE: u8 = TypeId::EvaluteContextCommand as u8;
 }
 
+#[repr(C, align(16))]
+pub struct StopEndpointCommand {
+    data: [u32; 4],
+}
+impl StopEndpointCommand {
+    bit_setter!(data[3]: u32; 0x0000FC00;  u8, pub set_trb_type);
+
+    bit_getter!(data[3]: u32; 0x001F0000;  u8, pub endpoint_id);
+    bit_setter!(data[3]: u32; 0x001F0000;  u8, pub set_endpoint_id);
+    bit_getter!(data[3]: u32; 0xFF000000;  u8, pub slot_id);
+    bit_setter!(data[3]: u32; 0xFF000000;  u8, pub set_slot_id);
+}
+impl Default for StopEndpointCommand {
+    fn default() -> Self {
+        let mut trb = Self { data: [0; 4] };
+        trb.set_trb_type(Self::TYPE);
+        trb
+    }
+}
+impl Trb for StopEndpointCommand {
+    const TYPE: u8 = TypeId::StopEndpointCommand as u8;
+}
+
+#[repr(C, align(16))]
+pub struct SetTrDequeuePointerCommand {
+    data: [u32; 4],
+}
+impl SetTrDequeuePointerCommand {
+    bit_getter!(data[0]: u32; 0x00000001; u8, pub dequeue_cycle_state);
+    bit_setter!(data[0]: u32; 0x00000001; u8, pub set_dequeue_cycle_state);
+    bit_getter!(data[0]: u32; 0xFFFFFFF0; u32, pub dequeue_ptr_lo);
+    bit_setter!(data[0]: u32; 0xFFFFFFF0; u32, pub set_dequeue_ptr_lo);
+    bit_getter!(data[1]: u32; 0xFFFFFFFF; u32, pub dequeue_ptr_hi);
+    bit_setter!(data[1]: u32; 0xFFFFFFFF; u32, pub set_dequeue_ptr_hi);
+
+    bit_setter!(data[3]: u32; 0x0000FC00;  u8, pub set_trb_type);
+
+    bit_getter!(data[3]: u32; 0x001F0000;  u8, pub endpoint_id);
+    bit_setter!(data[3]: u32; 0x001F0000;  u8, pub set_endpoint_id);
+    bit_getter!(data[3]: u32; 0xFF000000;  u8, pub slot_id);
+    bit_setter!(data[3]: u32; 0xFF000000;  u8, pub set_slot_id);
+
+    pub fn set_dequeue_ptr(&mut self, ptr: *const GenericTrb) {
+        let ptr = ptr as usize as u64;
+        debug_assert!(ptr & 0xF == 0);
+        self.set_dequeue_ptr_lo(((ptr & 0x00000000FFFFFFFF) >> 4) as u32);
+        self.set_dequeue_ptr_hi(((ptr & 0xFFFFFFFF00000000) >> 32) as u32);
+    }
+}
+impl Default for SetTrDequeuePointerCommand {
+    fn default() -> Self {
+        let mut trb = Self { data: [0; 4] };
+        trb.set_trb_type(Self::TYPE);
+        trb
+    }
+}
+impl Trb for SetTrDequeuePointerCommand {
+    const TYPE: u8 = TypeId::SetTrDequeuePointerCommand as u8;
+}
+
 #[repr(C, align(16))]
 pub struct TransferEvent {
     data: [u32; 4],
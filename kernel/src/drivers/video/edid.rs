@@ -1,35 +1,57 @@
-use crate::println;
+use alloc::vec::Vec;
 use core::slice::from_raw_parts;
 
+const EDID_HEADER: u64 = 0x00ffffffffffff00;
+const EDID_SIZE: usize = 128;
+const DESCRIPTOR_BASE: usize = 0x36;
+const DESCRIPTOR_SIZE: usize = 18;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdidError {
+    NotFound,
+    InvalidChecksum,
+}
+
 pub struct EDID {
     data: &'static [u8],
 }
 
 impl EDID {
-    pub fn new(base: u32) -> Result<Self, ()> {
-        if unsafe { *(base as *const u64) } != 0x00ffffffffffff00u64 {
-            return Err(());
+    pub fn new(base: u32) -> Result<Self, EdidError> {
+        if unsafe { *(base as *const u64) } != EDID_HEADER {
+            return Err(EdidError::NotFound);
+        }
+        let data = unsafe { from_raw_parts(base as *const u8, EDID_SIZE) };
+        // all the bytes of the block sum to 0
+        if data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) != 0 {
+            return Err(EdidError::InvalidChecksum);
         }
-        return Ok(Self {
-            data: unsafe { from_raw_parts(base as *const u8, 128) },
-        });
+        return Ok(Self { data });
     }
 
     pub fn getter(&self, index: usize) -> u8 {
         return self.data[index];
     }
 
-    pub fn get_resolutions(&self) -> [(u16, u16); 4] {
-        let mut resolutions: [(u16, u16); 4] = [(0, 0); 4];
+    // resolutions in the detailed timing descriptors.
+    // descriptors with pixel clock 0 are display descriptors(name, serial, etc.) and skipped
+    pub fn get_resolutions(&self) -> Vec<(u16, u16)> {
+        let mut resolutions = Vec::new();
         for i in 0..4 {
-            let base_addr = 0x36 + i * 18;
+            let base_addr = DESCRIPTOR_BASE + i * DESCRIPTOR_SIZE;
+            if self.getter(base_addr) == 0 && self.getter(base_addr + 0x01) == 0 {
+                continue;
+            }
             let lower_hor: u16 = self.getter(base_addr + 0x02) as u16;
             let upper_hor: u16 = (self.getter(base_addr + 0x04) >> 4) as u16;
             let hor_res = lower_hor | (upper_hor << 8);
-            let lower_ver: u16 = self.getter(base_addr + 0x08) as u16;
-            let upper_ver: u16 = (self.getter(base_addr + 0x0a) >> 4) as u16;
+            let lower_ver: u16 = self.getter(base_addr + 0x05) as u16;
+            let upper_ver: u16 = (self.getter(base_addr + 0x07) >> 4) as u16;
             let ver_res = lower_ver | (upper_ver << 8);
-            resolutions[i] = (hor_res, ver_res);
+            if hor_res == 0 || ver_res == 0 {
+                continue;
+            }
+            resolutions.push((hor_res, ver_res));
         }
         return resolutions;
     }
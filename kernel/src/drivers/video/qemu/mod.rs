@@ -1,5 +1,10 @@
 use super::edid::*;
-use crate::{drivers::pci::*, println};
+use alloc::vec::Vec;
+use crate::{drivers::pci::*, info, println, warn};
+
+const VBE_DISPI_GETCAPS: u16 = 0x02;
+// used when EDID is unavailable
+const FALLBACK_RESOLUTIONS: [(u16, u16); 3] = [(1024, 768), (1280, 1024), (1920, 1080)];
 
 enum BGARegisters {
     VbeDisplIndexId = 0,
@@ -22,18 +27,57 @@ unsafe fn bga_read_register(mmio_base: u32, index: u32) -> u16 {
     return ((mmio_base + 0x500 + (index << 1)) as *mut u16).read();
 }
 
+// the maximum resolution which BGA accepts
+unsafe fn bga_max_resolution(mmio_base: u32) -> (u16, u16) {
+    bga_write_register(mmio_base, BGARegisters::VbeDisplIndexEnable as u32, VBE_DISPI_GETCAPS);
+    let max_res = (
+        bga_read_register(mmio_base, BGARegisters::VbeDisplIndexXres as u32),
+        bga_read_register(mmio_base, BGARegisters::VbeDisplIndexYres as u32),
+    );
+    bga_write_register(mmio_base, BGARegisters::VbeDisplIndexEnable as u32, 0x00);
+    return max_res;
+}
+
+fn largest(resolutions: &[(u16, u16)]) -> (u16, u16) {
+    let mut max_res = (0, 0);
+    for &res in resolutions {
+        if (res.0 as u32) * (res.1 as u32) > (max_res.0 as u32) * (max_res.1 as u32) {
+            max_res = res;
+        }
+    }
+    return max_res;
+}
+
 pub fn setup_qemu_card(dev: &Device) {
     let mmio_base = read_bar32(&dev, 2).unwrap();
-    let edid = EDID::new(mmio_base).expect("invalid edid");
     unsafe {
         // get resolutions
-        let resolutions = edid.get_resolutions();
-        let mut max_res = (0, 0);
-        for res in resolutions {
-            if (res.0 as u32) * (res.1 as u32) > (max_res.0 as u32) * (max_res.1 as u32) {
-                max_res = res;
+        let max_res = match EDID::new(mmio_base).map(|edid| edid.get_resolutions()) {
+            Ok(resolutions) if !resolutions.is_empty() => {
+                let res = largest(&resolutions);
+                info!("BGA: using the resolution from EDID: {}x{}", res.0, res.1);
+                res
             }
-        }
+            result => {
+                match result {
+                    Err(e) => warn!("BGA: EDID is unavailable({:?}), probing the fallback resolutions", e),
+                    Ok(_) => warn!("BGA: EDID has no detailed timing, probing the fallback resolutions"),
+                }
+                let (max_x, max_y) = bga_max_resolution(mmio_base);
+                let accepted: Vec<(u16, u16)> = FALLBACK_RESOLUTIONS
+                    .iter()
+                    .copied()
+                    .filter(|res| res.0 <= max_x && res.1 <= max_y)
+                    .collect();
+                let res = largest(&accepted);
+                if res == (0, 0) {
+                    warn!("BGA: no fallback resolution is accepted, keeping the current mode");
+                    return;
+                }
+                info!("BGA: using the fallback resolution: {}x{}", res.0, res.1);
+                res
+            }
+        };
         // disable VBE extensions
         bga_write_register(mmio_base, BGARegisters::VbeDisplIndexEnable as u32, 0x00);
         bga_write_register(
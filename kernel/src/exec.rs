@@ -0,0 +1,164 @@
+use alloc::{vec, vec::Vec};
+use core::{
+    cmp::{max, min},
+    ops::Range,
+    ptr::{copy_nonoverlapping, write_bytes},
+};
+
+use libloader::elf::{self, ProgramHeader, PT_LOAD};
+
+use crate::{
+    drivers::fs::init::read_file,
+    memory_manager::BYTES_PER_FRAME,
+    paging::{remap_page, set_page_permissions},
+    proc::{UserMemory, PROCESS_MANAGER},
+    syscall::errno::{EAGAIN, ENOEXEC, ENOMEM},
+};
+
+const USER_STACK_BYTES: usize = 64 * 1024;
+// smaller programs are loaded at once, because the page faults cost more than copying them
+const LAZY_LOAD_THRESHOLD: u64 = 64 * 1024;
+const PAGE_SIZE: u64 = BYTES_PER_FRAME as u64;
+
+// p_flags of the program headers
+const PF_X: u32 = 0x1;
+const PF_W: u32 = 0x2;
+
+// PT_LOAD segments which are copied into memory page by page on the first access
+#[derive(Eq, PartialEq)]
+pub struct LazyImage {
+    // the whole file. the file systems can't be read in the page fault handler
+    bytes: Vec<u8>,
+    bias: u64,
+    pages: Range<u64>,
+    // (address, value) of every relocation, sorted by the address
+    relocations: Vec<(u64, u64)>,
+}
+
+impl LazyImage {
+    fn segments(&self) -> impl Iterator<Item = ProgramHeader> + '_ {
+        return elf::program_headers(&self.bytes).filter(|ph| ph.p_type == PT_LOAD);
+    }
+
+    // load the page at the address. returns false when the address isn't in the image.
+    // the page must not be present
+    pub fn fault_in(&self, addr: u64) -> bool {
+        return self.load_page(addr).is_ok();
+    }
+
+    fn load_page(&self, addr: u64) -> Result<(), i32> {
+        if !self.pages.contains(&addr) {
+            return Err(ENOEXEC);
+        }
+        let page = addr & !(PAGE_SIZE - 1);
+        unsafe {
+            // the page tables are split on the way, which allocates frames
+            remap_page(page).map_err(|_| ENOMEM)?;
+            self.fill_page(page);
+            // a write to a read-only page faults again and the process is terminated
+            let writable = self.segments().any(|ph| self.overlaps(&ph, page) && ph.p_flags & PF_W != 0);
+            let executable = self.segments().any(|ph| self.overlaps(&ph, page) && ph.p_flags & PF_X != 0);
+            return set_page_permissions(page, writable, executable).map_err(|_| ENOMEM);
+        }
+    }
+
+    // spawn has checked that every segment is in the pages, so only the bias wraps
+    fn overlaps(&self, ph: &ProgramHeader, page: u64) -> bool {
+        let start = ph.p_vaddr.wrapping_add(self.bias);
+        return start < page + PAGE_SIZE && page < start + ph.p_memsz;
+    }
+
+    // the parts of the segments in the page are copied, and the rest, including BSS, is zero
+    unsafe fn fill_page(&self, page: u64) {
+        write_bytes(page as *mut u8, 0, PAGE_SIZE as usize);
+        for ph in self.segments() {
+            let start = ph.p_vaddr.wrapping_add(self.bias);
+            let from = max(start, page);
+            let to = min(start + ph.p_filesz, page + PAGE_SIZE);
+            if from < to {
+                let offset = (ph.p_offset + from - start) as usize;
+                copy_nonoverlapping(self.bytes[offset..].as_ptr(), from as *mut u8, (to - from) as usize);
+            }
+        }
+        // a relocation may cross the page boundary, so only its bytes in the page are written
+        let first = self.relocations.partition_point(|&(addr, _)| addr + 8 <= page);
+        for &(addr, value) in self.relocations[first..].iter().take_while(|&&(addr, _)| addr < page + PAGE_SIZE) {
+            for (i, byte) in value.to_le_bytes().iter().enumerate() {
+                let byte_addr = addr + i as u64;
+                if (page..page + PAGE_SIZE).contains(&byte_addr) {
+                    *(byte_addr as *mut u8) = *byte;
+                }
+            }
+        }
+    }
+}
+
+// load the program into new memory and add it to the run queue as a child of the current process.
+// this returns the process id at once, and the scheduler switches to the program later.
+// the memory is identity mapped, so only PIE can be loaded anywhere the frames are free
+pub fn spawn(path: &str) -> Result<usize, i32> {
+    let limits = {
+        let manager = unsafe { PROCESS_MANAGER.get().unwrap() };
+        let current = manager.current();
+        let parent = current.borrow();
+        // checked before loading so that a fork bomb fails fast
+        if !parent.limits().children.allows(manager.count_children(parent.id()) as u64 + 1) {
+            return Err(EAGAIN);
+        }
+        *parent.limits()
+    };
+    let bytes = read_file(path)?;
+    elf::validate(&bytes).map_err(|_| ENOEXEC)?;
+    if !elf::is_pie(&bytes) {
+        return Err(ENOEXEC);
+    }
+    let segments = || elf::program_headers(&bytes).filter(|ph| ph.p_type == PT_LOAD);
+    let lowest = segments().map(|ph| ph.p_vaddr & !(PAGE_SIZE - 1)).min().ok_or(ENOEXEC)?;
+    // validate has checked that the ends don't overflow
+    let highest = segments().map(|ph| ph.p_vaddr + ph.p_memsz).max().ok_or(ENOEXEC)?;
+    // the child inherits the limits, and the memory is rounded up to the frames
+    let frames = |bytes: u64| (bytes + PAGE_SIZE - 1) / PAGE_SIZE * PAGE_SIZE;
+    if !limits.memory.allows(frames(highest - lowest) + frames(USER_STACK_BYTES as u64)) {
+        return Err(ENOMEM);
+    }
+
+    // the frames are reserved now, but the pages are present only after they are loaded
+    let memory = UserMemory::reserve((highest - lowest) as usize).map_err(|_| ENOMEM)?;
+    let bias = elf::load_bias(&bytes, memory.start());
+    let pages = memory.start()..memory.end();
+    // checked once here, so that the pages of the image are found without overflow
+    for ph in segments() {
+        let start = ph.p_vaddr.wrapping_add(bias);
+        if start < pages.start || start.checked_add(ph.p_memsz).map_or(true, |end| end > pages.end) {
+            return Err(ENOEXEC);
+        }
+    }
+    let mut relocations = Vec::new();
+    elf::relative_relocations(&bytes, |offset, addend| {
+        relocations.push((offset.wrapping_add(bias), bias.wrapping_add(addend)));
+    })
+    .map_err(|_| ENOEXEC)?;
+    if relocations.iter().any(|&(addr, _)| addr < pages.start || addr.checked_add(8).map_or(true, |end| end > pages.end)) {
+        return Err(ENOEXEC);
+    }
+    relocations.sort_unstable();
+    let entry = elf::entry(&bytes).wrapping_add(bias);
+    let image = LazyImage { bytes, bias, pages: pages.clone(), relocations };
+    let image = if highest - lowest <= LAZY_LOAD_THRESHOLD {
+        for page in pages.step_by(PAGE_SIZE as usize) {
+            image.load_page(page)?;
+        }
+        None
+    } else {
+        Some(image)
+    };
+    let stack = UserMemory::new(USER_STACK_BYTES).map_err(|_| ENOMEM)?;
+
+    let manager = unsafe { PROCESS_MANAGER.get_mut().unwrap() };
+    let proc = manager.new_proc();
+    let id = proc.borrow().id();
+    let stack_end = stack.end();
+    proc.borrow_mut().init_user_context(entry, stack_end, vec![memory, stack], image);
+    manager.wake_up(proc);
+    return Ok(id);
+}
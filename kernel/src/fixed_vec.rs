@@ -1,5 +1,10 @@
 use core::mem::MaybeUninit;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FixedVecError {
+    Full,
+}
+
 pub struct FixedVec<T, const CAPACITY: usize> {
     buf: [MaybeUninit<T>; CAPACITY],
     len: usize,
@@ -50,15 +55,16 @@ impl<T, const CAPACITY: usize> FixedVec<T, CAPACITY> {
             None
         }
     }
-    pub fn push(&mut self, val: T) -> Option<usize> {
+    /// returns the index of the pushed value. the value is dropped when the vec is full
+    pub fn push(&mut self, val: T) -> Result<usize, FixedVecError> {
         if self.len < CAPACITY {
             let p = self.buf.as_mut_ptr();
             let idx = self.len;
             unsafe { (p as *mut T).add(idx).write(val) };
             self.len += 1;
-            Some(idx)
+            Ok(idx)
         } else {
-            None
+            Err(FixedVecError::Full)
         }
     }
     pub fn pop(&mut self) -> Option<T> {
@@ -70,6 +76,18 @@ impl<T, const CAPACITY: usize> FixedVec<T, CAPACITY> {
             None
         }
     }
+    /// shifts the following values to the left
+    pub fn remove(&mut self, idx: usize) -> Option<T> {
+        if idx < self.len {
+            let p = self.buf.as_mut_ptr() as *mut T;
+            let val = unsafe { p.add(idx).read() };
+            unsafe { core::ptr::copy(p.add(idx + 1), p.add(idx), self.len - idx - 1) };
+            self.len -= 1;
+            Some(val)
+        } else {
+            None
+        }
+    }
     pub fn clear(&mut self) {
         while let Some(x) = self.pop() {
             drop(x);
@@ -1,4 +1,4 @@
-use crate::{Coord, FrameBufferWriter, StatusCode};
+use crate::{horse_lib::simd::fast_copy, Coord, FrameBufferWriter, StatusCode};
 
 use alloc::{vec, vec::Vec};
 use core::{
@@ -97,7 +97,7 @@ impl FrameBuffer {
         let mut src_buf: *const u8 = src.config.fb;
 
         for dy in 0..(copy_end_dst_y - copy_start_dst_y) {
-            copy_nonoverlapping(src_buf, dst_buf, stride);
+            fast_copy(dst_buf, src_buf, stride);
             dst_buf = dst_buf.add(Self::bytes_per_scan_line(&self.config));
             src_buf = src_buf.add(Self::bytes_per_scan_line(&src.config));
         }
@@ -130,7 +130,7 @@ impl FrameBuffer {
         }
     }
 
-    fn bytes_per_pixel(format: PixelFormat) -> usize {
+    pub fn bytes_per_pixel(format: PixelFormat) -> usize {
         return match format {
             PixelFormat::Rgb => 4,
             PixelFormat::Bgr => 4,
@@ -138,11 +138,15 @@ impl FrameBuffer {
         };
     }
 
+    pub fn size(&self) -> usize {
+        return Self::bytes_per_scan_line(&self.config) * self.config.resolution.1;
+    }
+
     fn bytes_per_scan_line(config: &FrameBufferConfig) -> usize {
         Self::bytes_per_pixel(config.format) * config.stride
     }
 
-    unsafe fn frame_addr_at(pos: Coord, config: &FrameBufferConfig) -> *mut u8 {
+    pub unsafe fn frame_addr_at(pos: Coord, config: &FrameBufferConfig) -> *mut u8 {
         config
             .fb
             .add(Self::bytes_per_pixel(config.format) * (config.stride * pos.y + pos.x))
@@ -1,8 +1,11 @@
 use crate::{
     ascii_font::FONTS,
     framebuffer::{FrameBuffer, FrameBufferConfig},
+    horse_lib::simd::{fast_copy, fast_fill},
     println,
+    status::StatusCode,
 };
+use alloc::vec;
 use core::{
     mem::MaybeUninit,
     ops::{Add, AddAssign, Sub},
@@ -12,6 +15,41 @@ use libloader::{PixelFormat, TSFrameBuffer};
 #[derive(Copy, Clone, Debug, Default, PartialEq)]
 pub struct PixelColor(pub u8, pub u8, pub u8); // RGB
 
+impl PixelColor {
+    // a pixel in the framebuffer format, whose 4th byte is padding
+    pub fn decode(format: PixelFormat, pixel: &[u8]) -> Self {
+        match format {
+            PixelFormat::Rgb => PixelColor(pixel[0], pixel[1], pixel[2]),
+            PixelFormat::Bgr => PixelColor(pixel[2], pixel[1], pixel[0]),
+            _ => panic!("not supported"),
+        }
+    }
+
+    pub fn encode(&self, format: PixelFormat) -> u32 {
+        match format {
+            PixelFormat::Rgb => u32::from_le_bytes([self.0, self.1, self.2, 0]),
+            PixelFormat::Bgr => u32::from_le_bytes([self.2, self.1, self.0, 0]),
+            _ => panic!("not supported"),
+        }
+    }
+}
+
+// how set_background places an image whose size differs from the screen
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BackgroundMode {
+    Center,
+    Tile,
+    Stretch,
+}
+
+// layout of the pixels given to set_background. a pixel is 4 bytes in the format as the framebuffer
+#[derive(Copy, Clone, Debug)]
+pub struct ImageInfo {
+    pub width: usize,
+    pub height: usize,
+    pub format: PixelFormat,
+}
+
 #[derive(Copy, Clone, Debug, Default, PartialEq)]
 pub struct Coord {
     pub x: usize,
@@ -68,6 +106,22 @@ impl Sub for Coord {
     }
 }
 
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Rect {
+    pub pos: Coord,
+    pub size: Coord,
+}
+
+impl Rect {
+    pub const fn new(pos: Coord, size: Coord) -> Self {
+        Self { pos, size }
+    }
+
+    pub fn end(&self) -> Coord {
+        self.pos + self.size
+    }
+}
+
 pub trait PixelWriter {
     fn write(&mut self, x: usize, y: usize, c: &PixelColor);
 }
@@ -87,6 +141,21 @@ impl FrameBufferWriter {
             fb: unsafe { TSFrameBuffer::new(fb) },
         }
     }
+
+    // writes len pixels of the same color from (x, y) to the right
+    pub fn write_row(&mut self, x: usize, y: usize, len: usize, c: &PixelColor) {
+        let value = match &self.format {
+            PixelFormat::Rgb => [c.0, c.1, c.2],
+            PixelFormat::Bgr => [c.2, c.1, c.0],
+            _ => panic!("not supported"),
+        };
+        let base = 4 * (y * self.stride + x);
+        for i in 0..len {
+            unsafe {
+                self.fb.write_value(base + 4 * i, value);
+            }
+        }
+    }
 }
 
 impl PixelWriter for FrameBufferWriter {
@@ -219,13 +288,125 @@ impl Graphics {
         }
     }
 
+    // every pixel gets the color, so the rotation and the scaling don't matter
     pub fn clear(&mut self, color: &PixelColor) {
-        let (width, height) = self.resolution();
+        let pixel = color.encode(self.fb.config.format);
+        let (width, height) = self.fb.config.resolution;
+        let bytes_per_pixel = FrameBuffer::bytes_per_pixel(self.fb.config.format);
         for y in 0..height {
-            for x in 0..width {
-                self.write_pixel(x, y, color);
+            unsafe { fast_fill(self.frame_addr(0, y), pixel, bytes_per_pixel * width) };
+        }
+    }
+
+    // the rect is clipped by the screen
+    pub fn clear_rect(&mut self, rect: Rect, color: &PixelColor) {
+        let (width, height) = self.resolution();
+        let end = rect.end().elem_min(Coord::new(width, height));
+        if rect.pos.x >= end.x || rect.pos.y >= end.y {
+            return;
+        }
+        if self.rotated || self.double_scaled {
+            for y in rect.pos.y..end.y {
+                for x in rect.pos.x..end.x {
+                    self.write_pixel(x, y, color);
+                }
+            }
+            return;
+        }
+        let pixel = color.encode(self.fb.config.format);
+        let bytes_per_pixel = FrameBuffer::bytes_per_pixel(self.fb.config.format);
+        for y in rect.pos.y..end.y {
+            unsafe {
+                fast_fill(self.frame_addr(rect.pos.x, y), pixel, bytes_per_pixel * (end.x - rect.pos.x));
+            }
+        }
+    }
+
+    // draw the image over the whole screen. the part which the image doesn't cover gets the color
+    pub fn set_background(
+        &mut self,
+        image: &[u8],
+        info: ImageInfo,
+        mode: BackgroundMode,
+        color: &PixelColor,
+    ) -> Result<(), StatusCode> {
+        let (width, height) = self.resolution();
+        let screen = Rect::new(Coord::new(0, 0), Coord::new(width, height));
+        return self.draw_background(image, info, mode, color, screen);
+    }
+
+    // draw the part of the background in the area, which is clipped by the screen
+    pub fn draw_background(
+        &mut self,
+        image: &[u8],
+        info: ImageInfo,
+        mode: BackgroundMode,
+        color: &PixelColor,
+        area: Rect,
+    ) -> Result<(), StatusCode> {
+        let bytes = info.width.checked_mul(info.height).and_then(|pixels| pixels.checked_mul(4));
+        if info.width == 0 || info.height == 0 || bytes.map_or(true, |bytes| image.len() < bytes) {
+            return Err(StatusCode::BufferTooSmall);
+        }
+        if !matches!(info.format, PixelFormat::Rgb | PixelFormat::Bgr) {
+            return Err(StatusCode::UnknownPixelFormat);
+        }
+        let (width, height) = self.resolution();
+        let end = area.end().elem_min(Coord::new(width, height));
+        if area.pos.x >= end.x || area.pos.y >= end.y {
+            return Ok(());
+        }
+        // negative when the image is larger than the screen
+        let offset_x = (width as isize - info.width as isize) / 2;
+        let offset_y = (height as isize - info.height as isize) / 2;
+        let pixel_at = |x: usize, y: usize| {
+            let src = match mode {
+                BackgroundMode::Center => {
+                    let (sx, sy) = (x as isize - offset_x, y as isize - offset_y);
+                    let inside = 0 <= sx && sx < info.width as isize && 0 <= sy && sy < info.height as isize;
+                    inside.then_some((sx as usize, sy as usize))
+                }
+                BackgroundMode::Tile => Some((x % info.width, y % info.height)),
+                BackgroundMode::Stretch => Some((x * info.width / width, y * info.height / height)),
+            };
+            match src {
+                Some((sx, sy)) => {
+                    let i = 4 * (sy * info.width + sx);
+                    PixelColor::decode(info.format, &image[i..i + 4])
+                }
+                None => *color,
+            }
+        };
+
+        if self.rotated || self.double_scaled {
+            for y in area.pos.y..end.y {
+                for x in area.pos.x..end.x {
+                    self.write_pixel(x, y, &pixel_at(x, y));
+                }
+            }
+            return Ok(());
+        }
+        // each row is composed in the framebuffer format and copied at once
+        let format = self.fb.config.format;
+        let mut row = vec![0u8; 4 * (end.x - area.pos.x)];
+        for y in area.pos.y..end.y {
+            for (i, x) in (area.pos.x..end.x).enumerate() {
+                row[4 * i..4 * i + 4].copy_from_slice(&pixel_at(x, y).encode(format).to_le_bytes());
             }
+            unsafe { fast_copy(self.frame_addr(area.pos.x, y), row.as_ptr(), row.len()) };
         }
+        return Ok(());
+    }
+
+    // the address of the pixel in the framebuffer, without the rotation and the scaling
+    unsafe fn frame_addr(&self, x: usize, y: usize) -> *mut u8 {
+        let config = &self.fb.config;
+        let bytes_per_pixel = FrameBuffer::bytes_per_pixel(config.format);
+        return config.fb.add(bytes_per_pixel * (config.stride * y + x));
+    }
+
+    pub fn frame_buffer(&self) -> &FrameBuffer {
+        &self.fb
     }
 
     pub fn pixel_writer(&self) -> FrameBufferWriter {
@@ -19,20 +19,19 @@ macro_rules! bit_setter {
 }
 
 #[macro_export]
-#[allow(deref_nullptr)]
 macro_rules! container_of {
     ($ptr: expr, $container: path, $field: ident) => {
         unsafe {
             let inner = $ptr as *const _;
-            let outer = &(*(0 as *const $container)).$field as *const _;
-            &*((inner as usize - outer as usize) as *const $container)
+            let offset = core::mem::offset_of!($container, $field);
+            &*((inner as usize - offset) as *const $container)
         }
     };
     ($ptr: expr, mutable $container: path, $field: ident) => {
         unsafe {
             let inner = $ptr as *const _;
-            let outer = &(*(0 as *const $container)).$field as *const _;
-            &mut *((inner as usize - outer as usize) as *mut $container)
+            let offset = core::mem::offset_of!($container, $field);
+            &mut *((inner as usize - offset) as *mut $container)
         }
     };
 }
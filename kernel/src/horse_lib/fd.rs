@@ -1,4 +1,6 @@
 use alloc::{
+    format,
+    sync::Arc,
     string::{
         String,
         ToString
@@ -6,12 +8,34 @@ use alloc::{
     vec::Vec,
     vec
 };
+use core::{
+    mem::take,
+    sync::atomic::{AtomicUsize, Ordering}
+};
 
 pub enum OpenFlags {
     RDOnly = 0x00000000,
     WROnly = 0x00000001,
     RDWR = 0x00000002,
     Create = 0x00000100,
+    Truncate = 0x00000200,
+}
+
+// the absolute path of the path seen from the directory, without "." and ".."
+pub fn absolute_path(cwd: &str, path: &str) -> String {
+    let base = if path.starts_with('/') { "" } else { cwd };
+    let mut names = Vec::new();
+    for name in base.split('/').chain(path.split('/')) {
+        match name {
+            "" | "." => {}
+            // ".." of the root directory is the root directory
+            ".." => {
+                names.pop();
+            }
+            name => names.push(name)
+        }
+    }
+    return format!("/{}", names.join("/"))
 }
 
 #[derive(Clone, PartialEq)]
@@ -26,6 +50,9 @@ impl Path {
     pub fn path_iter(&self) -> Vec<String> {
         return self.path.clone()
     }
+    pub fn as_string(&self) -> String {
+        return self.path.join("/")
+    }
 }
 
 #[derive(Clone, PartialEq)]
@@ -40,9 +67,38 @@ impl File {
     }
 }
 
+// the console which the stdio entries are connected to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stdio {
+    In,
+    Out
+}
+
+// fds duplicated by dup share the same Arc<File> and the offset
+#[derive(Clone)]
+struct FDEntry {
+    file: Arc<File>,
+    // None for the files of the file systems
+    stdio: Option<Stdio>,
+    offset: Arc<AtomicUsize>
+}
+
+impl FDEntry {
+    fn new(file: File) -> Self {
+        return Self { file: Arc::new(file), stdio: None, offset: Arc::new(AtomicUsize::new(0)) }
+    }
+    fn stdio(path: &str, stdio: Stdio) -> Self {
+        let mode = if stdio == Stdio::In { OpenFlags::RDOnly } else { OpenFlags::WROnly };
+        let file = Arc::new(File::new(mode as u32, path));
+        return Self { file, stdio: Some(stdio), offset: Arc::new(AtomicUsize::new(0)) }
+    }
+}
+
+// the open files of every process, which the file systems refer to by the index.
+// the processes have their own fds in ProcessFds, which are mapped to the indices
 pub struct FDTable {
     max_fds: usize,
-    fd_array: Vec<Option<File>>,
+    fd_array: Vec<Option<FDEntry>>,
     empty_idx: usize
 }
 
@@ -56,15 +112,15 @@ impl FDTable {
         self.max_fds = 1024;
         self.empty_idx = 3;
         self.fd_array = vec![None; 1024];
-        self.fd_array[0] = Some(File::new(OpenFlags::RDOnly as u32, "/dev/stdin"));
-        self.fd_array[1] = Some(File::new(OpenFlags::WROnly as u32, "/dev/stdout"));
-        self.fd_array[2] = Some(File::new(OpenFlags::WROnly as u32, "/dev/stderr"));
+        self.fd_array[0] = Some(FDEntry::stdio("/dev/stdin", Stdio::In));
+        self.fd_array[1] = Some(FDEntry::stdio("/dev/stdout", Stdio::Out));
+        self.fd_array[2] = Some(FDEntry::stdio("/dev/stderr", Stdio::Out));
     }
     pub fn new() -> Self {
         let mut fd_array = vec![None; 1024];
-        fd_array[0] = Some(File::new(OpenFlags::RDOnly as u32, "/dev/stdin"));
-        fd_array[1] = Some(File::new(OpenFlags::WROnly as u32, "/dev/stdout"));
-        fd_array[2] = Some(File::new(OpenFlags::WROnly as u32, "/dev/stderr"));
+        fd_array[0] = Some(FDEntry::stdio("/dev/stdin", Stdio::In));
+        fd_array[1] = Some(FDEntry::stdio("/dev/stdout", Stdio::Out));
+        fd_array[2] = Some(FDEntry::stdio("/dev/stderr", Stdio::Out));
         return Self {
             max_fds: 1024,
             fd_array,
@@ -73,30 +129,241 @@ impl FDTable {
     }
     fn update_idx(&mut self) {
         for i in self.empty_idx+1..self.max_fds {
-            if self.fd_array[i] == None {
+            if self.fd_array[i].is_none() {
                 self.empty_idx = i;
-                break
+                return
             }
         }
         self.empty_idx = self.max_fds;
     }
-    pub fn add(&mut self, file: File) -> i32 {
+    fn add_entry(&mut self, entry: FDEntry) -> i32 {
         if self.empty_idx == self.max_fds {
             return -1
         }
         let idx = self.empty_idx;
-        self.fd_array[idx] = Some(file);
+        self.fd_array[idx] = Some(entry);
         self.update_idx();
         return idx as i32
     }
-    pub fn remove(&mut self, fd: i32) {
+    // always the lowest free fd is used
+    pub fn add(&mut self, file: File) -> i32 {
+        return self.add_entry(FDEntry::new(file))
+    }
+    // the new fd refers to the same open file
+    pub fn dup(&mut self, fd: i32) -> i32 {
+        if !self.is_open(fd) {
+            return -1
+        }
+        let entry = self.fd_array[fd as usize].clone().unwrap();
+        return self.add_entry(entry)
+    }
+    // returns the removed file, or None when the fd isn't open
+    pub fn remove(&mut self, fd: i32) -> Option<Arc<File>> {
+        if !self.is_open(fd) {
+            return None
+        }
         let idx = fd as usize;
-        self.fd_array[idx] = None;
         if idx < self.empty_idx {
             self.empty_idx = idx;
         }
+        return self.fd_array[idx].take().map(|entry| entry.file)
+    }
+    // the console which the fd is connected to. None for the files and the closed fds
+    pub fn stdio(&self, fd: i32) -> Option<Stdio> {
+        if !self.is_open(fd) {
+            return None
+        }
+        return self.fd_array[fd as usize].as_ref().unwrap().stdio
+    }
+    // the position in the file which the next read or write starts at.
+    // it's up to the file system to use it, FAT and TmpFS always start at the beginning
+    pub fn offset(&self, fd: i32) -> usize {
+        return self.fd_array[fd as usize].as_ref().unwrap().offset.load(Ordering::Relaxed)
+    }
+    pub fn set_offset(&mut self, fd: i32, offset: usize) {
+        self.fd_array[fd as usize].as_ref().unwrap().offset.store(offset, Ordering::Relaxed);
+    }
+    // whether no other fd shares the open file
+    pub fn is_last_reference(&self, fd: i32) -> bool {
+        return self.is_open(fd) && Arc::strong_count(&self.fd_array[fd as usize].as_ref().unwrap().file) == 1
+    }
+    // whether any fd refers to the file at the path
+    pub fn is_path_open(&self, path: &Path) -> bool {
+        return self.fd_array.iter().flatten().any(|entry| entry.file.path == *path)
+    }
+    // whether any fd refers to a file in the directory, including its subdirectories
+    pub fn is_open_under(&self, dir: &str) -> bool {
+        let dir = dir.trim_end_matches('/');
+        return self.fd_array.iter().flatten().any(|entry| {
+            let path = entry.file.path.as_string();
+            path.starts_with(dir) && path[dir.len()..].starts_with('/')
+        })
+    }
+    pub fn is_open(&self, fd: i32) -> bool {
+        return 0 <= fd && (fd as usize) < self.fd_array.len() && self.fd_array[fd as usize].is_some()
     }
     pub fn get(&self, fd: i32) -> File {
-        return self.fd_array[fd as usize].clone().unwrap()
+        return (*self.fd_array[fd as usize].as_ref().unwrap().file).clone()
+    }
+}
+// the fds of a process, each of which refers to an open file in FDTable.
+// stdio refers to the shared entries 0-2, so closing it doesn't detach the other processes
+#[derive(Clone, Eq, PartialEq)]
+pub struct ProcessFds {
+    fds: Vec<Option<i32>>
+}
+
+impl ProcessFds {
+    pub fn with_stdio() -> Self {
+        return Self { fds: vec![Some(0), Some(1), Some(2)] }
+    }
+    // the number of the open fds, for RLIMIT_NOFILE
+    pub fn len(&self) -> usize {
+        return self.fds.iter().flatten().count()
     }
-}
\ No newline at end of file
+    // the index in FDTable. None when the fd isn't open
+    pub fn get(&self, fd: i32) -> Option<i32> {
+        if fd < 0 {
+            return None
+        }
+        return self.fds.get(fd as usize).copied().flatten()
+    }
+    // always the lowest free fd is used
+    pub fn add(&mut self, file: i32) -> i32 {
+        match self.fds.iter().position(|fd| fd.is_none()) {
+            Some(fd) => {
+                self.fds[fd] = Some(file);
+                return fd as i32
+            }
+            None => {
+                self.fds.push(Some(file));
+                return self.fds.len() as i32 - 1
+            }
+        }
+    }
+    // returns the index in FDTable, or None when the fd isn't open
+    pub fn remove(&mut self, fd: i32) -> Option<i32> {
+        if fd < 0 {
+            return None
+        }
+        return self.fds.get_mut(fd as usize)?.take()
+    }
+    // close every fd, e.g. when the process is reaped. returns the indices in FDTable
+    pub fn take_all(&mut self) -> Vec<i32> {
+        return take(&mut self.fds).into_iter().flatten().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(path: &str) -> File {
+        return File::new(OpenFlags::RDOnly as u32, path)
+    }
+
+    #[test]
+    fn first_fd_follows_stdio() {
+        let mut table = FDTable::new();
+        assert_eq!(table.add(file("/a")), 3);
+        assert_eq!(table.add(file("/b")), 4);
+    }
+
+    #[test]
+    fn closed_fd_is_reused() {
+        let mut table = FDTable::new();
+        let fd = table.add(file("/a"));
+        assert!(table.remove(fd).is_some());
+        assert!(!table.is_open(fd));
+        assert_eq!(table.add(file("/b")), fd);
+        assert_eq!(table.get(fd).path.as_string(), "/b");
+    }
+
+    #[test]
+    fn lowest_free_fd_is_reused() {
+        let mut table = FDTable::new();
+        let fds: Vec<i32> = ["/a", "/b", "/c", "/d"].iter().map(|path| table.add(file(path))).collect();
+        table.remove(fds[2]);
+        table.remove(fds[0]);
+        assert_eq!(table.add(file("/e")), fds[0]);
+        assert_eq!(table.add(file("/f")), fds[2]);
+        assert_eq!(table.add(file("/g")), fds[3] + 1);
+    }
+
+    #[test]
+    fn closing_stdio_frees_its_fd() {
+        let mut table = FDTable::new();
+        table.add(file("/a"));
+        table.remove(0);
+        assert_eq!(table.add(file("/b")), 0);
+    }
+
+    #[test]
+    fn removing_closed_fd_does_nothing() {
+        let mut table = FDTable::new();
+        assert!(table.remove(3).is_none());
+        assert!(table.remove(-1).is_none());
+        assert_eq!(table.add(file("/a")), 3);
+    }
+
+    #[test]
+    fn stdio_entries_are_marked() {
+        let mut table = FDTable::new();
+        assert_eq!(table.stdio(0), Some(Stdio::In));
+        assert_eq!(table.stdio(2), Some(Stdio::Out));
+        table.remove(1);
+        let fd = table.add(file("/a"));
+        assert_eq!(fd, 1);
+        assert_eq!(table.stdio(fd), None);
+    }
+
+    #[test]
+    fn offsets_are_per_open_file() {
+        let mut table = FDTable::new();
+        let a = table.add(file("/a"));
+        let b = table.add(file("/a"));
+        table.set_offset(a, 10);
+        assert_eq!(table.offset(a), 10);
+        assert_eq!(table.offset(b), 0);
+        // dup shares the offset
+        let c = table.dup(a);
+        table.set_offset(c, 20);
+        assert_eq!(table.offset(a), 20);
+        table.remove(a);
+        assert_eq!(table.add(file("/b")), a);
+        assert_eq!(table.offset(a), 0);
+    }
+
+    #[test]
+    fn process_fds_reuse_the_lowest_free_fd() {
+        let mut fds = ProcessFds::with_stdio();
+        assert_eq!(fds.add(10), 3);
+        assert_eq!(fds.remove(1), Some(1));
+        assert_eq!(fds.get(1), None);
+        assert_eq!(fds.add(11), 1);
+        assert_eq!(fds.get(1), Some(11));
+        assert_eq!(fds.add(12), 4);
+        assert_eq!(fds.len(), 5);
+    }
+
+    #[test]
+    fn process_fds_close_only_their_own() {
+        let mut a = ProcessFds::with_stdio();
+        let b = ProcessFds::with_stdio();
+        a.remove(1);
+        assert_eq!(a.get(1), None);
+        assert_eq!(b.get(1), Some(1));
+        assert_eq!(a.remove(1), None);
+        assert_eq!(a.remove(-1), None);
+        assert_eq!(a.remove(100), None);
+    }
+
+    #[test]
+    fn take_all_closes_every_fd() {
+        let mut fds = ProcessFds::with_stdio();
+        fds.add(7);
+        assert_eq!(fds.take_all(), vec![0, 1, 2, 7]);
+        assert_eq!(fds.len(), 0);
+        assert_eq!(fds.add(8), 0);
+    }
+}
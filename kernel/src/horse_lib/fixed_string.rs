@@ -0,0 +1,137 @@
+use core::{fmt, str};
+
+use crate::fixed_vec::FixedVec;
+
+// string on a fixed buffer for formatting without alloc.
+// the output is truncated at a char boundary when it exceeds N bytes
+pub struct FixedString<const N: usize> {
+    buf: FixedVec<u8, N>,
+    truncated: bool,
+}
+
+impl<const N: usize> FixedString<N> {
+    pub const fn new() -> Self {
+        return Self {
+            buf: FixedVec::new(),
+            truncated: false,
+        };
+    }
+
+    pub fn len(&self) -> usize {
+        return self.buf.len();
+    }
+
+    pub fn capacity(&self) -> usize {
+        return N;
+    }
+
+    pub fn is_truncated(&self) -> bool {
+        return self.truncated;
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        return self.buf.as_slice();
+    }
+
+    pub fn as_str(&self) -> &str {
+        // only whole chars are pushed, so the buffer is always valid UTF-8
+        return unsafe { str::from_utf8_unchecked(self.buf.as_slice()) };
+    }
+
+    pub fn clear(&mut self) {
+        self.buf.clear();
+        self.truncated = false;
+    }
+
+    // returns false when the char doesn't fit
+    pub fn push(&mut self, c: char) -> bool {
+        let mut bytes = [0; 4];
+        let encoded = c.encode_utf8(&mut bytes);
+        if self.len() + encoded.len() > N {
+            self.truncated = true;
+            return false;
+        }
+        // the length is checked above
+        for &byte in encoded.as_bytes() {
+            self.buf.push(byte).unwrap();
+        }
+        return true;
+    }
+
+    pub fn push_str(&mut self, s: &str) -> bool {
+        for c in s.chars() {
+            if !self.push(c) {
+                return false;
+            }
+        }
+        return true;
+    }
+}
+
+impl<const N: usize> fmt::Write for FixedString<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if !self.push_str(s) {
+            return Err(fmt::Error);
+        }
+        return Ok(());
+    }
+}
+
+impl<const N: usize> fmt::Display for FixedString<N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return f.write_str(self.as_str());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::fmt::Write;
+
+    #[test]
+    fn fits_exactly() {
+        let mut s = FixedString::<5>::new();
+        assert!(s.push_str("horse"));
+        assert_eq!(s.as_str(), "horse");
+        assert!(!s.is_truncated());
+    }
+
+    #[test]
+    fn truncated_at_capacity() {
+        let mut s = FixedString::<4>::new();
+        assert!(!s.push_str("horse"));
+        assert_eq!(s.as_str(), "hors");
+        assert!(s.is_truncated());
+        assert!(!s.push('!'));
+        assert_eq!(s.len(), 4);
+    }
+
+    #[test]
+    fn truncated_at_char_boundary() {
+        // "é" is 2 bytes and "馬" is 3 bytes
+        let mut s = FixedString::<4>::new();
+        assert!(!s.push_str("aé馬"));
+        assert_eq!(s.as_str(), "aé");
+        assert_eq!(s.len(), 3);
+        // a smaller char still fits after the truncation
+        assert!(s.push('b'));
+        assert_eq!(s.as_str(), "aéb");
+    }
+
+    #[test]
+    fn write_fails_when_truncated() {
+        let mut s = FixedString::<8>::new();
+        assert!(write!(s, "{}", 1234).is_ok());
+        assert!(write!(s, "{}", 56789).is_err());
+        assert_eq!(s.as_str(), "12345678");
+    }
+
+    #[test]
+    fn clear_resets_truncation() {
+        let mut s = FixedString::<2>::new();
+        s.push_str("abc");
+        s.clear();
+        assert!(!s.is_truncated());
+        assert_eq!(s.as_str(), "");
+    }
+}
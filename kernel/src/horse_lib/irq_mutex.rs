@@ -0,0 +1,79 @@
+use core::{
+    mem::ManuallyDrop,
+    ops::{Deref, DerefMut},
+};
+use spin::{Mutex, MutexGuard};
+use x86_64::instructions::interrupts;
+
+// spin::Mutex which disables interrupts while it's locked.
+// locks touched from interrupt handlers (TIMER_MANAGER, KERNEL_LOG) must use this,
+// otherwise the handler spins forever on the lock held by the code it interrupted.
+// the other locks should keep using spin::Mutex not to delay interrupts
+pub struct IrqMutex<T> {
+    inner: Mutex<T>,
+}
+
+pub struct IrqMutexGuard<'a, T> {
+    guard: ManuallyDrop<MutexGuard<'a, T>>,
+    // interrupts are enabled again on drop only when they were enabled before locking
+    was_enabled: bool,
+}
+
+impl<T> IrqMutex<T> {
+    pub const fn new(value: T) -> Self {
+        return Self {
+            inner: Mutex::new(value),
+        };
+    }
+
+    pub fn lock(&self) -> IrqMutexGuard<'_, T> {
+        let was_enabled = interrupts::are_enabled();
+        interrupts::disable();
+        return IrqMutexGuard {
+            guard: ManuallyDrop::new(self.inner.lock()),
+            was_enabled,
+        };
+    }
+
+    pub fn try_lock(&self) -> Option<IrqMutexGuard<'_, T>> {
+        let was_enabled = interrupts::are_enabled();
+        interrupts::disable();
+        match self.inner.try_lock() {
+            Some(guard) => {
+                return Some(IrqMutexGuard {
+                    guard: ManuallyDrop::new(guard),
+                    was_enabled,
+                })
+            }
+            None => {
+                if was_enabled {
+                    interrupts::enable();
+                }
+                return None;
+            }
+        }
+    }
+}
+
+impl<'a, T> Deref for IrqMutexGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        return &self.guard;
+    }
+}
+
+impl<'a, T> DerefMut for IrqMutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        return &mut self.guard;
+    }
+}
+
+impl<'a, T> Drop for IrqMutexGuard<'a, T> {
+    fn drop(&mut self) {
+        // the lock must be released before interrupts are enabled
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+        if self.was_enabled {
+            interrupts::enable();
+        }
+    }
+}
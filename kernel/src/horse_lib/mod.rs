@@ -1,6 +1,11 @@
 pub mod bit_macros;
 pub mod bytes;
 pub mod fd;
+pub mod fixed_string;
 pub mod io;
+pub mod irq_mutex;
 pub mod rbtree;
+pub mod simd;
 pub mod storage;
+pub mod time;
+pub mod wait_queue;
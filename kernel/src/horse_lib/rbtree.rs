@@ -2,6 +2,7 @@ use alloc::boxed::Box;
 
 use core::{
     cmp::Ordering,
+    marker::PhantomData,
     ops::Index,
     ptr
 };
@@ -116,6 +117,21 @@ impl<K: Ord, V> NodePtr<K, V> {
         return temp
     }
 
+    // the next node in the order of the keys
+    #[inline(always)]
+    fn successor(self) -> NodePtr<K, V> {
+        if !self.right().is_null() {
+            return self.right().min_node()
+        }
+        let mut node = self;
+        let mut parent = self.parent();
+        while !parent.is_null() && node == parent.right() {
+            node = parent;
+            parent = parent.parent();
+        }
+        return parent
+    }
+
     #[inline(always)]
     fn set_parent(&mut self, parent: Self) {
         if self.is_null() {
@@ -175,12 +191,36 @@ impl<K: Ord, V> NodePtr<K, V> {
     }
 }
 
+/* Iter */
+pub struct Iter<'a, K: Ord, V> {
+    next: NodePtr<K, V>,
+    _marker: PhantomData<&'a RBTree<K, V>>
+}
+
+impl<'a, K: Ord, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next.is_null() {
+            return None
+        }
+        let node = self.next;
+        self.next = node.successor();
+        unsafe { Some((&(*node.0).key, &(*node.0).value)) }
+    }
+}
+
 /* RBTree */
 pub struct RBTree<K: Ord, V> {
     root: NodePtr<K, V>,
     len: usize
 }
 
+impl<K: Ord, V> Drop for RBTree<K, V> {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
 impl<'a, K, V> Index<&'a K> for RBTree<K, V> 
 where
     K: Ord 
@@ -200,6 +240,24 @@ impl<K: Ord, V> RBTree<K, V> {
         }
     }
 
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // the entries in the order of the keys
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter {
+            next: self.first_child(),
+            _marker: PhantomData
+        }
+    }
+
     #[inline(always)]
     unsafe fn left_rotate(&mut self, mut node: NodePtr<K, V>) {
         let mut temp = node.right();
@@ -481,6 +539,7 @@ impl<K: Ord, V> RBTree<K, V> {
     pub fn clear(&mut self) {
         let root = self.root;
         self.root = NodePtr::null();
+        self.len = 0;
         self.clear_recurse(root);
     }
 
@@ -636,4 +695,66 @@ impl<K: Ord, V> RBTree<K, V> {
         let obj = Box::from_raw(node.0);
         return obj.pair()
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::{rc::Rc, vec::Vec};
+
+    #[test]
+    fn len_follows_inserts_and_removes() {
+        let mut tree = RBTree::new();
+        assert!(tree.is_empty());
+        for i in 0..10 {
+            tree.insert(i, i * 10);
+        }
+        assert_eq!(tree.len(), 10);
+        assert!(!tree.is_empty());
+        assert_eq!(tree.remove(&3), Some(30));
+        assert_eq!(tree.remove(&3), None);
+        assert_eq!(tree.len(), 9);
+        assert_eq!(tree.get(&4).copied(), Some(40));
+        assert!(!tree.contains_key(&3));
+    }
+
+    #[test]
+    fn iter_is_in_the_order_of_the_keys() {
+        let mut tree = RBTree::new();
+        for k in [5, 1, 9, 3, 7, 2, 8, 0, 6, 4] {
+            tree.insert(k, ());
+        }
+        tree.remove(&6);
+        let keys: Vec<i32> = tree.iter().map(|(k, _)| *k).collect();
+        assert_eq!(keys, [0, 1, 2, 3, 4, 5, 7, 8, 9]);
+    }
+
+    #[test]
+    fn clear_frees_the_values_and_resets_the_length() {
+        let value = Rc::new(());
+        let mut tree = RBTree::new();
+        for i in 0..8 {
+            tree.insert(i, value.clone());
+        }
+        tree.clear();
+        assert_eq!(Rc::strong_count(&value), 1);
+        assert_eq!(tree.len(), 0);
+        assert!(tree.is_empty());
+        assert!(tree.iter().next().is_none());
+        tree.insert(1, value.clone());
+        assert_eq!(tree.len(), 1);
+    }
+
+    #[test]
+    fn drop_frees_the_values() {
+        let value = Rc::new(());
+        let mut tree = RBTree::new();
+        for i in 0..8 {
+            tree.insert(i, value.clone());
+        }
+        tree.remove(&0);
+        assert_eq!(Rc::strong_count(&value), 8);
+        drop(tree);
+        assert_eq!(Rc::strong_count(&value), 1);
+    }
+}
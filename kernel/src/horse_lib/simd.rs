@@ -0,0 +1,93 @@
+use core::{arch::asm, cmp::min};
+use x86_64::registers::control::{Cr0, Cr0Flags, Cr4, Cr4Flags};
+
+// the firmware usually enables SSE already, but the kernel shouldn't depend on it.
+// the interrupt handlers save the XMM registers they use, and the contexts of the processes
+// are switched with fxsave, so SSE can be used anywhere in the kernel
+pub fn enable_sse() {
+    unsafe {
+        Cr0::update(|flags| {
+            flags.remove(Cr0Flags::EMULATE_COPROCESSOR);
+            flags.insert(Cr0Flags::MONITOR_COPROCESSOR);
+        });
+        Cr4::update(|flags| {
+            flags.insert(Cr4Flags::OSFXSR | Cr4Flags::OSXMMEXCPT_ENABLE);
+        });
+    }
+}
+
+// copy len bytes 16 bytes at a time. the head before dst gets aligned and the tail are copied by bytes
+pub unsafe fn fast_copy(dst: *mut u8, src: *const u8, len: usize) {
+    let head = min(dst.align_offset(16), len);
+    for i in 0..head {
+        *dst.add(i) = *src.add(i);
+    }
+    let mut i = head;
+    while i + 64 <= len {
+        asm!(
+            "movdqu {a}, [{src}]",
+            "movdqu {b}, [{src} + 16]",
+            "movdqu {c}, [{src} + 32]",
+            "movdqu {d}, [{src} + 48]",
+            "movdqa [{dst}], {a}",
+            "movdqa [{dst} + 16], {b}",
+            "movdqa [{dst} + 32], {c}",
+            "movdqa [{dst} + 48], {d}",
+            src = in(reg) src.add(i),
+            dst = in(reg) dst.add(i),
+            a = out(xmm_reg) _,
+            b = out(xmm_reg) _,
+            c = out(xmm_reg) _,
+            d = out(xmm_reg) _,
+            options(nostack, preserves_flags),
+        );
+        i += 64;
+    }
+    while i + 16 <= len {
+        asm!(
+            "movdqu {a}, [{src}]",
+            "movdqa [{dst}], {a}",
+            src = in(reg) src.add(i),
+            dst = in(reg) dst.add(i),
+            a = out(xmm_reg) _,
+            options(nostack, preserves_flags),
+        );
+        i += 16;
+    }
+    for i in i..len {
+        *dst.add(i) = *src.add(i);
+    }
+}
+
+// fill len bytes with the 4 bytes pattern in little endian, e.g. a pixel.
+// the pattern starts at dst even when len isn't a multiple of 4
+pub unsafe fn fast_fill(dst: *mut u8, pattern: u32, len: usize) {
+    let bytes = pattern.to_le_bytes();
+    let head = min(dst.align_offset(16), len);
+    for i in 0..head {
+        *dst.add(i) = bytes[i % 4];
+    }
+    // the pattern seen from the aligned address
+    let aligned = pattern.rotate_right(8 * (head % 4) as u32);
+    let mut i = head;
+    if i + 16 <= len {
+        asm!(
+            "movd {v}, {pattern:e}",
+            "pshufd {v}, {v}, 0",
+            "2:",
+            "movdqa [{dst}], {v}",
+            "add {dst}, 16",
+            "sub {n}, 1",
+            "jnz 2b",
+            pattern = in(reg) aligned,
+            dst = inout(reg) dst.add(i) => _,
+            n = inout(reg) (len - i) / 16 => _,
+            v = out(xmm_reg) _,
+            options(nostack),
+        );
+        i += (len - i) / 16 * 16;
+    }
+    for i in i..len {
+        *dst.add(i) = bytes[i % 4];
+    }
+}
@@ -1,4 +1,14 @@
+use crate::status::StatusCode;
+
+// both methods return the number of bytes actually transferred
 pub trait Storage {
-    fn read(&mut self, buf: &mut [u8], lba: u32, nbytes: usize) -> u8;
-    fn write(&mut self, buf: &[u8], lba: u32, nbytes: usize) -> u8;
+    fn read(&mut self, buf: &mut [u8], lba: u32, nbytes: usize) -> Result<usize, StatusCode>;
+    fn write(&mut self, buf: &[u8], lba: u32, nbytes: usize) -> Result<usize, StatusCode>;
+    // controllers which have multiple drives override this
+    fn select_drive(&mut self, drive: usize) -> Result<(), StatusCode> {
+        if drive == 0 {
+            return Ok(());
+        }
+        return Err(StatusCode::NoDevice);
+    }
 }
@@ -0,0 +1,119 @@
+use core::ops::{Add, AddAssign, Sub, SubAssign};
+
+use crate::drivers::timer::{current_tick, ticks_to_duration};
+
+// core's Duration already has the constructors, the arithmetic and the comparison
+pub use core::time::Duration;
+
+// a point of the monotonic clock, which counts from the start of the LAPIC timer.
+// the resolution is a timer tick
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(Duration);
+
+impl Instant {
+    pub fn now() -> Self {
+        return Self(ticks_to_duration(current_tick()));
+    }
+
+    // zero if earlier is later than self
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        return self.0.saturating_sub(earlier.0);
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        return Self::now().duration_since(*self);
+    }
+
+    pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+        return self.0.checked_add(duration).map(Self);
+    }
+
+    pub fn checked_sub(&self, duration: Duration) -> Option<Instant> {
+        return self.0.checked_sub(duration).map(Self);
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, other: Duration) -> Instant {
+        return self
+            .checked_add(other)
+            .expect("overflow when adding duration to instant");
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, other: Duration) {
+        *self = *self + other;
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+
+    fn sub(self, other: Duration) -> Instant {
+        return self
+            .checked_sub(other)
+            .expect("overflow when subtracting duration from instant");
+    }
+}
+
+impl SubAssign<Duration> for Instant {
+    fn sub_assign(&mut self, other: Duration) {
+        *self = *self - other;
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, other: Instant) -> Duration {
+        return self.duration_since(other);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at_millis(millis: u64) -> Instant {
+        return Instant(Duration::from_millis(millis));
+    }
+
+    #[test]
+    fn instant_moves_by_duration() {
+        let mut instant = at_millis(1000);
+        assert_eq!(instant + Duration::from_millis(500), at_millis(1500));
+        assert_eq!(instant - Duration::from_secs(1), at_millis(0));
+        instant += Duration::from_millis(20);
+        instant -= Duration::from_millis(10);
+        assert_eq!(instant, at_millis(1010));
+    }
+
+    #[test]
+    fn instants_are_compared() {
+        assert!(at_millis(1) < at_millis(2));
+        assert_eq!(at_millis(2).max(at_millis(1)), at_millis(2));
+    }
+
+    #[test]
+    fn difference_saturates_at_zero() {
+        assert_eq!(at_millis(1500) - at_millis(1000), Duration::from_millis(500));
+        assert_eq!(at_millis(1000) - at_millis(1500), Duration::ZERO);
+        assert_eq!(at_millis(1000).duration_since(at_millis(1500)), Duration::ZERO);
+    }
+
+    #[test]
+    fn checked_arithmetic_detects_overflow() {
+        assert_eq!(at_millis(0).checked_sub(Duration::from_nanos(1)), None);
+        assert_eq!(Instant(Duration::MAX).checked_add(Duration::from_nanos(1)), None);
+        assert_eq!(at_millis(5).checked_sub(Duration::from_millis(5)), Some(at_millis(0)));
+    }
+
+    #[test]
+    #[should_panic]
+    fn subtraction_below_zero_panics() {
+        let _ = at_millis(0) - Duration::from_millis(1);
+    }
+}
@@ -0,0 +1,42 @@
+use alloc::vec::Vec;
+
+use crate::proc::PROCESS_MANAGER;
+
+// ids of the processes sleeping until an object gets ready.
+// they are woken up all at once and have to check the object again
+pub struct WaitQueue {
+    waiters: Vec<usize>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        return Self {
+            waiters: Vec::new(),
+        };
+    }
+
+    pub fn add(&mut self, id: usize) {
+        if !self.waiters.contains(&id) {
+            self.waiters.push(id);
+        }
+    }
+
+    // false after the process is woken up
+    pub fn contains(&self, id: usize) -> bool {
+        return self.waiters.contains(&id);
+    }
+
+    pub fn remove(&mut self, id: usize) {
+        self.waiters.retain(|&waiter| waiter != id);
+    }
+
+    pub fn wake_all(&mut self) {
+        if self.waiters.is_empty() {
+            return;
+        }
+        let manager = unsafe { PROCESS_MANAGER.get_mut().unwrap() };
+        for id in self.waiters.drain(..) {
+            manager.id_wake_up(id);
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use crate::{
+    console::{active_terminal, scroll_terminal, switch_terminal, VT_COUNT},
+    debug,
+    horse_lib::{irq_mutex::IrqMutex, wait_queue::WaitQueue},
+    keyboard_layout::{active_layout, Keysym},
+    queue::ArrayQueue,
+    StatusCode,
+};
+
+const STDIN_CAPACITY: usize = 256;
+
+// bits of the HID modifier byte
+pub const MODIFIER_LEFT_SHIFT: u8 = 1 << 1;
+pub const MODIFIER_LEFT_ALT: u8 = 1 << 2;
+pub const MODIFIER_RIGHT_SHIFT: u8 = 1 << 5;
+pub const MODIFIER_RIGHT_ALT: u8 = 1 << 6;
+const SHIFT_MASK: u8 = MODIFIER_LEFT_SHIFT | MODIFIER_RIGHT_SHIFT;
+const ALT_MASK: u8 = MODIFIER_LEFT_ALT | MODIFIER_RIGHT_ALT;
+
+// HID usage IDs of the keys which are handled here
+pub const KEY_CAPS_LOCK: u8 = 0x39;
+pub const KEY_F1: u8 = 0x3a;
+pub const KEY_PAGE_UP: u8 = 0x4b;
+pub const KEY_PAGE_DOWN: u8 = 0x4e;
+// the modifier keys are from 0xe0 (Left Ctrl) to 0xe7 (Right GUI) in the order of the modifier bits
+pub const KEY_LEFT_CTRL: u8 = 0xe0;
+pub const KEY_RIGHT_GUI: u8 = 0xe7;
+
+// Caps Lock is shared by all the keyboards
+static CAPS_LOCK: AtomicBool = AtomicBool::new(false);
+// the dead key waiting for the next character, or 0
+static DEAD_KEY: AtomicU32 = AtomicU32::new(0);
+
+// a key pressed or released on any keyboard
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyEvent {
+    // HID usage ID. the keyboards which use other codes translate them to this
+    pub keycode: u8,
+    // the HID modifier bits after the event
+    pub modifier: u8,
+    pub pressed: bool,
+}
+
+impl KeyEvent {
+    pub fn shift(&self) -> bool {
+        return self.modifier & SHIFT_MASK != 0;
+    }
+
+    pub fn alt(&self) -> bool {
+        return self.modifier & ALT_MASK != 0;
+    }
+
+    pub fn altgr(&self) -> bool {
+        return self.modifier & MODIFIER_RIGHT_ALT != 0;
+    }
+}
+
+// the character typed by the key is passed to stdin of the active virtual terminal
+pub fn push_key_event(event: KeyEvent) {
+    if !event.pressed {
+        return;
+    }
+    // Alt+F1..F4 switches the virtual terminal
+    if event.alt() && (KEY_F1..KEY_F1 + VT_COUNT as u8).contains(&event.keycode) {
+        switch_terminal((event.keycode - KEY_F1) as usize);
+        return;
+    }
+    // Shift+PageUp and Shift+PageDown scroll the active virtual terminal
+    if event.shift() && (event.keycode == KEY_PAGE_UP || event.keycode == KEY_PAGE_DOWN) {
+        scroll_terminal(event.keycode == KEY_PAGE_UP);
+        return;
+    }
+    if event.keycode == KEY_CAPS_LOCK {
+        CAPS_LOCK.fetch_xor(true, Ordering::Relaxed);
+        return;
+    }
+    // Caps Lock only affects letters
+    let is_letter = (0x04..=0x1d).contains(&event.keycode);
+    let caps = CAPS_LOCK.load(Ordering::Relaxed) && is_letter;
+    let layout = active_layout();
+    let keysym = layout.keysym(event.keycode, event.shift() ^ caps, event.altgr());
+    debug!(
+        "key down: {:?} (mod: {:02x}, key: {:02x})",
+        keysym, event.modifier, event.keycode
+    );
+    // the modifier keys type nothing, and the dead key keeps waiting
+    let keysym = match keysym {
+        Some(keysym) => keysym,
+        None => return,
+    };
+    let dead = char::from_u32(DEAD_KEY.swap(0, Ordering::Relaxed)).filter(|&c| c != '\0');
+    match (dead, keysym) {
+        (None, Keysym::Char(c)) => push_char(c),
+        (None, Keysym::Dead(c)) => DEAD_KEY.store(c as u32, Ordering::Relaxed),
+        // the dead key followed by space or itself types the accent
+        (Some(dead), Keysym::Char(' ')) => push_char(dead),
+        (Some(dead), Keysym::Dead(c)) if dead == c => push_char(dead),
+        (Some(dead), Keysym::Dead(c)) => {
+            push_char(dead);
+            DEAD_KEY.store(c as u32, Ordering::Relaxed);
+        }
+        (Some(dead), Keysym::Char(c)) => match layout.compose(dead, c) {
+            Some(composed) => push_char(composed),
+            None => {
+                push_char(dead);
+                push_char(c);
+            }
+        },
+    }
+}
+
+// stdin is a byte stream, so the characters beyond ASCII are pushed in UTF-8
+fn push_char(c: char) {
+    let mut buf = [0u8; 4];
+    let mut stdin = STDIN[active_terminal()].lock();
+    for &b in c.encode_utf8(&mut buf).as_bytes() {
+        stdin.push(b);
+    }
+}
+
+// characters typed on the keyboard, which are read through fd 0
+pub struct Stdin {
+    queue: ArrayQueue<u8, STDIN_CAPACITY>,
+    pub waiters: WaitQueue,
+}
+
+// one for each virtual terminal
+pub static STDIN: [IrqMutex<Stdin>; VT_COUNT] = [const { IrqMutex::new(Stdin::new()) }; VT_COUNT];
+
+impl Stdin {
+    const fn new() -> Self {
+        return Self {
+            queue: ArrayQueue::new(),
+            waiters: WaitQueue::new(),
+        };
+    }
+
+    pub fn initialize(&mut self) {
+        self.queue.initialize(0);
+    }
+
+    // the input is dropped while the queue is full
+    pub fn push(&mut self, c: u8) {
+        if let StatusCode::Full = self.queue.push(c) {
+            return;
+        }
+        self.waiters.wake_all();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.queue.count == 0;
+    }
+
+    // returns the number of bytes read without waiting
+    pub fn read(&mut self, buf: &mut [u8]) -> usize {
+        let mut nread = 0;
+        while nread < buf.len() {
+            match self.queue.pop() {
+                Ok(c) => buf[nread] = c,
+                Err(_) => break,
+            }
+            nread += 1;
+        }
+        return nread;
+    }
+}
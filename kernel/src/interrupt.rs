@@ -1,15 +1,104 @@
-use core::ptr::write_volatile;
+use crate::{
+    error,
+    lapic::LocalApic,
+    paging::load_kernel_page_table,
+    proc::{KERNEL_TASK_ID, PROCESS_MANAGER},
+};
+
+use core::arch::asm;
 use spin::Mutex;
-pub use x86_64::structures::idt::InterruptDescriptorTable;
+use x86_64::registers::control::Cr2;
+pub use x86_64::structures::idt::{
+    InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode,
+};
 
 pub static IDT: Mutex<InterruptDescriptorTable> = Mutex::new(InterruptDescriptorTable::new());
 
 pub enum InterruptVector {
     Xhci = 0x40,
     LAPICTimer = 0x41,
+    Nvme = 0x42,
+    PS2Keyboard = 0x43,
 }
 
 pub unsafe fn notify_end_of_interrupt() {
-    let end_of_interrupt: *mut u32 = 0xfee000b0 as *mut u32;
-    write_volatile(end_of_interrupt, 0);
+    LocalApic::end_of_interrupt();
+}
+
+pub extern "x86-interrupt" fn handler_double_fault(
+    stack_frame: InterruptStackFrame,
+    _error_code: u64,
+) -> ! {
+    error!("EXCEPTION: double fault\n{:#?}", stack_frame);
+    loop {
+        unsafe { asm!("cli", "hlt") }
+    }
+}
+
+// runs on its own IST stack, so that a fault on the stack guard page can be handled.
+// faults in user mode and stack overflows only terminate the process, and the others are fatal
+pub extern "x86-interrupt" fn handler_page_fault(
+    stack_frame: InterruptStackFrame,
+    error_code: PageFaultErrorCode,
+) {
+    let addr = Cr2::read_raw();
+    let user = error_code.contains(PageFaultErrorCode::USER_MODE);
+    if let Some(manager) = unsafe { PROCESS_MANAGER.get_mut() } {
+        unsafe { load_kernel_page_table() };
+        let current = manager.current();
+        // the program is loaded on the first access, which may be from a syscall
+        let not_present = !error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION);
+        if not_present && current.try_borrow().map_or(false, |proc| proc.fault_in(addr)) {
+            return;
+        }
+        let faulting = match current.try_borrow() {
+            Ok(proc) if proc.id() != KERNEL_TASK_ID => {
+                Some((proc.id(), proc.is_stack_guard(addr)))
+            }
+            _ => None,
+        };
+        drop(current);
+        match faulting {
+            Some((id, true)) => {
+                error!("stack overflow in process {} (address: {:#018x})", id, addr);
+                manager.prepare_terminate(id);
+            }
+            Some((id, false)) if user => {
+                error!(
+                    "process {} is terminated by page fault (address: {:#018x}, error code: {:?}, rip: {:#018x})",
+                    id,
+                    addr,
+                    error_code,
+                    stack_frame.instruction_pointer.as_u64()
+                );
+                manager.prepare_terminate(id);
+            }
+            _ => {}
+        }
+    }
+    error!(
+        "EXCEPTION: page fault (address: {:#018x}, error code: {:?})\n{:#?}",
+        addr, error_code, stack_frame
+    );
+    loop {
+        unsafe { asm!("cli", "hlt") }
+    }
+}
+
+pub extern "x86-interrupt" fn handler_general_protection_fault(
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
+) {
+    // the error code is the segment selector index which caused the fault, or 0
+    error!(
+        "EXCEPTION: general protection fault (selector: {:#x}, index: {}, table: {}, external: {})",
+        error_code,
+        (error_code >> 3) & 0x1fff,
+        ["GDT", "IDT", "LDT", "IDT"][((error_code >> 1) & 0b11) as usize],
+        error_code & 1
+    );
+    error!("{:#?}", stack_frame);
+    loop {
+        unsafe { asm!("cli", "hlt") }
+    }
 }
@@ -0,0 +1,256 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+// what a key types
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Keysym {
+    Char(char),
+    // combined with the next character, e.g. ' and e type é
+    Dead(char),
+}
+
+// the mapping from the HID usage IDs to the characters, which differs in each country
+pub trait KeyboardLayout: Sync {
+    fn name(&self) -> &'static str;
+    // shift already includes Caps Lock for the letters. AltGr is Right Alt
+    fn keysym(&self, keycode: u8, shift: bool, altgr: bool) -> Option<Keysym>;
+    // the character typed by the dead key and the next character, if they are combined
+    fn compose(&self, _dead: char, _c: char) -> Option<char> {
+        return None;
+    }
+}
+
+// the first one is the default
+static LAYOUTS: [&dyn KeyboardLayout; 3] = [&Us, &Jis, &UsInternational];
+static ACTIVE_LAYOUT: AtomicUsize = AtomicUsize::new(0);
+
+pub fn active_layout() -> &'static dyn KeyboardLayout {
+    return LAYOUTS[ACTIVE_LAYOUT.load(Ordering::Relaxed)];
+}
+
+// an unknown name selects US, and false is returned
+pub fn set_layout(name: &str) -> bool {
+    let index = LAYOUTS.iter().position(|layout| layout.name() == name);
+    ACTIVE_LAYOUT.store(index.unwrap_or(0), Ordering::Relaxed);
+    return index.is_some();
+}
+
+pub fn layout_names() -> impl Iterator<Item = &'static str> {
+    return LAYOUTS.iter().map(|layout| layout.name());
+}
+
+// the keys which are the same in every layout
+fn common_keysym(keycode: u8, shift: bool) -> Option<char> {
+    return match keycode {
+        // letters
+        0x04..=0x1d => {
+            let c = (b'a' + keycode - 0x04) as char;
+            Some(if shift { c.to_ascii_uppercase() } else { c })
+        }
+        0x28 => Some('\n'),
+        0x2a => Some('\x08'), // backspace
+        0x2c => Some(' '),
+        _ => None,
+    };
+}
+
+pub struct Us;
+
+impl KeyboardLayout for Us {
+    fn name(&self) -> &'static str {
+        return "us";
+    }
+
+    fn keysym(&self, keycode: u8, shift: bool, _altgr: bool) -> Option<Keysym> {
+        if let Some(c) = common_keysym(keycode, shift) {
+            return Some(Keysym::Char(c));
+        }
+        let c = match (shift, keycode) {
+            (false, 0x1e..=0x26) => (b'1' + keycode - 0x1e) as char,
+            (false, 0x27) => '0',
+            (true, 0x1e) => '!',
+            (true, 0x1f) => '@',
+            (true, 0x20) => '#',
+            (true, 0x21) => '$',
+            (true, 0x22) => '%',
+            (true, 0x23) => '^',
+            (true, 0x24) => '&',
+            (true, 0x25) => '*',
+            (true, 0x26) => '(',
+            (true, 0x27) => ')',
+            (false, 0x2d) => '-',
+            (true, 0x2d) => '_',
+            (false, 0x2e) => '=',
+            (true, 0x2e) => '+',
+            (false, 0x2f) => '[',
+            (true, 0x2f) => '{',
+            (false, 0x30) => ']',
+            (true, 0x30) => '}',
+            // 0x64 is the extra key next to Left Shift on the ISO keyboards
+            (false, 0x31 | 0x64) => '\\',
+            (true, 0x31 | 0x64) => '|',
+            (false, 0x33) => ';',
+            (true, 0x33) => ':',
+            (false, 0x34) => '\'',
+            (true, 0x34) => '"',
+            (false, 0x35) => '`',
+            (true, 0x35) => '~',
+            (false, 0x36) => ',',
+            (true, 0x36) => '<',
+            (false, 0x37) => '.',
+            (true, 0x37) => '>',
+            (false, 0x38) => '/',
+            (true, 0x38) => '?',
+            _ => return None,
+        };
+        return Some(Keysym::Char(c));
+    }
+}
+
+// Japanese 106/109 keys. the Kana keys type nothing because there is no input method
+pub struct Jis;
+
+impl KeyboardLayout for Jis {
+    fn name(&self) -> &'static str {
+        return "jis";
+    }
+
+    fn keysym(&self, keycode: u8, shift: bool, _altgr: bool) -> Option<Keysym> {
+        if let Some(c) = common_keysym(keycode, shift) {
+            return Some(Keysym::Char(c));
+        }
+        let c = match (shift, keycode) {
+            (false, 0x1e..=0x26) => (b'1' + keycode - 0x1e) as char,
+            (false, 0x27) => '0',
+            (true, 0x1e) => '!',
+            (true, 0x1f) => '"',
+            (true, 0x20) => '#',
+            (true, 0x21) => '$',
+            (true, 0x22) => '%',
+            (true, 0x23) => '&',
+            (true, 0x24) => '\'',
+            (true, 0x25) => '(',
+            (true, 0x26) => ')',
+            (false, 0x2d) => '-',
+            (true, 0x2d) => '=',
+            (false, 0x2e) => '^',
+            (true, 0x2e) => '~',
+            (false, 0x2f) => '@',
+            (true, 0x2f) => '`',
+            (false, 0x30) => '[',
+            (true, 0x30) => '{',
+            // some keyboards report the key next to Enter as 0x31, others as 0x32
+            (false, 0x31 | 0x32) => ']',
+            (true, 0x31 | 0x32) => '}',
+            (false, 0x33) => ';',
+            (true, 0x33) => '+',
+            (false, 0x34) => ':',
+            (true, 0x34) => '*',
+            (false, 0x36) => ',',
+            (true, 0x36) => '<',
+            (false, 0x37) => '.',
+            (true, 0x37) => '>',
+            (false, 0x38) => '/',
+            (true, 0x38) => '?',
+            // International1, the key left of Right Shift
+            (false, 0x87) => '\\',
+            (true, 0x87) => '_',
+            // International3, the yen key. it types backslash as the Japanese fonts show it as yen
+            (false, 0x89) => '\\',
+            (true, 0x89) => '|',
+            _ => return None,
+        };
+        return Some(Keysym::Char(c));
+    }
+}
+
+// US with the dead keys for the accents and AltGr for the common Latin letters
+pub struct UsInternational;
+
+// (dead key, base letter, composed letter) for the lowercase letters
+const COMPOSITIONS: [(char, char, char); 26] = [
+    ('\'', 'a', 'á'),
+    ('\'', 'e', 'é'),
+    ('\'', 'i', 'í'),
+    ('\'', 'o', 'ó'),
+    ('\'', 'u', 'ú'),
+    ('\'', 'y', 'ý'),
+    ('\'', 'c', 'ç'),
+    ('`', 'a', 'à'),
+    ('`', 'e', 'è'),
+    ('`', 'i', 'ì'),
+    ('`', 'o', 'ò'),
+    ('`', 'u', 'ù'),
+    ('^', 'a', 'â'),
+    ('^', 'e', 'ê'),
+    ('^', 'i', 'î'),
+    ('^', 'o', 'ô'),
+    ('^', 'u', 'û'),
+    ('~', 'a', 'ã'),
+    ('~', 'o', 'õ'),
+    ('~', 'n', 'ñ'),
+    ('"', 'a', 'ä'),
+    ('"', 'e', 'ë'),
+    ('"', 'i', 'ï'),
+    ('"', 'o', 'ö'),
+    ('"', 'u', 'ü'),
+    ('"', 'y', 'ÿ'),
+];
+
+impl KeyboardLayout for UsInternational {
+    fn name(&self) -> &'static str {
+        return "us-intl";
+    }
+
+    fn keysym(&self, keycode: u8, shift: bool, altgr: bool) -> Option<Keysym> {
+        if altgr {
+            let c = match (shift, keycode) {
+                (false, 0x1e) => '¡',
+                (false, 0x1f) => '²',
+                (false, 0x20) => '³',
+                (false, 0x38) => '¿',
+                (false, 0x14) => 'ä',
+                (true, 0x14) => 'Ä',
+                (false, 0x1a) => 'å',
+                (true, 0x1a) => 'Å',
+                (false, 0x08) => 'é',
+                (true, 0x08) => 'É',
+                (false, 0x1c) => 'ü',
+                (true, 0x1c) => 'Ü',
+                (false, 0x12) => 'ó',
+                (true, 0x12) => 'Ó',
+                (false, 0x13) => 'ö',
+                (true, 0x13) => 'Ö',
+                (false, 0x04) => 'á',
+                (true, 0x04) => 'Á',
+                (false, 0x16) => 'ß',
+                (false, 0x1d) => 'æ',
+                (true, 0x1d) => 'Æ',
+                (false, 0x11) => 'ñ',
+                (true, 0x11) => 'Ñ',
+                (false, 0x36) => 'ç',
+                (true, 0x36) => 'Ç',
+                _ => return None,
+            };
+            return Some(Keysym::Char(c));
+        }
+        return match (shift, keycode) {
+            (false, 0x34) => Some(Keysym::Dead('\'')),
+            (true, 0x34) => Some(Keysym::Dead('"')),
+            (false, 0x35) => Some(Keysym::Dead('`')),
+            (true, 0x35) => Some(Keysym::Dead('~')),
+            (true, 0x23) => Some(Keysym::Dead('^')),
+            _ => Us.keysym(keycode, shift, false),
+        };
+    }
+
+    fn compose(&self, dead: char, c: char) -> Option<char> {
+        let lower = c.to_ascii_lowercase();
+        let (_, _, composed) = COMPOSITIONS
+            .iter()
+            .find(|&&(d, base, _)| d == dead && base == lower)?;
+        if c.is_ascii_uppercase() {
+            return composed.to_uppercase().next();
+        }
+        return Some(*composed);
+    }
+}
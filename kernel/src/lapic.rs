@@ -0,0 +1,99 @@
+use core::{
+    ptr::{read_volatile, write_volatile},
+    sync::atomic::{AtomicBool, Ordering},
+};
+use x86_64::registers::model_specific::Msr;
+
+use crate::{
+    cpuid::{has_feature, Feature},
+    info,
+};
+
+const XAPIC_BASE: u64 = 0xfee00000;
+const X2APIC_MSR_BASE: u32 = 0x800;
+const IA32_APIC_BASE: u32 = 0x1b;
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+const APIC_BASE_X2APIC: u64 = 1 << 10;
+
+static X2APIC_ENABLED: AtomicBool = AtomicBool::new(false);
+
+// offsets in the xAPIC MMIO space. x2APIC MSR is 0x800 + offset / 0x10
+#[derive(Clone, Copy)]
+pub enum LapicRegister {
+    Id = 0x020,
+    EndOfInterrupt = 0x0b0,
+    InterruptCommandLow = 0x300,
+    InterruptCommandHigh = 0x310,
+    LvtTimer = 0x320,
+    InitialCount = 0x380,
+    CurrentCount = 0x390,
+    DivideConfig = 0x3e0,
+}
+
+pub struct LocalApic;
+
+impl LocalApic {
+    pub fn is_x2apic() -> bool {
+        return X2APIC_ENABLED.load(Ordering::Relaxed);
+    }
+
+    pub fn read(reg: LapicRegister) -> u32 {
+        if Self::is_x2apic() {
+            let msr = Msr::new(X2APIC_MSR_BASE + reg as u32 / 0x10);
+            return unsafe { msr.read() } as u32;
+        }
+        return unsafe { read_volatile((XAPIC_BASE + reg as u64) as *const u32) };
+    }
+
+    pub fn write(reg: LapicRegister, value: u32) {
+        if Self::is_x2apic() {
+            let mut msr = Msr::new(X2APIC_MSR_BASE + reg as u32 / 0x10);
+            unsafe { msr.write(value as u64) };
+            return;
+        }
+        unsafe { write_volatile((XAPIC_BASE + reg as u64) as *mut u32, value) };
+    }
+
+    pub fn id() -> u32 {
+        // xAPIC holds the 8-bit id in the top byte, x2APIC uses the whole register
+        if Self::is_x2apic() {
+            return Self::read(LapicRegister::Id);
+        }
+        return Self::read(LapicRegister::Id) >> 24;
+    }
+
+    // ICR is a single 64-bit MSR in x2APIC mode
+    pub fn send_ipi(destination: u32, command: u32) {
+        if Self::is_x2apic() {
+            let mut msr = Msr::new(X2APIC_MSR_BASE + LapicRegister::InterruptCommandLow as u32 / 0x10);
+            unsafe { msr.write((destination as u64) << 32 | command as u64) };
+            return;
+        }
+        Self::write(LapicRegister::InterruptCommandHigh, destination << 24);
+        Self::write(LapicRegister::InterruptCommandLow, command);
+        // wait until the IPI is delivered
+        while Self::read(LapicRegister::InterruptCommandLow) & (1 << 12) != 0 {}
+    }
+
+    pub fn end_of_interrupt() {
+        Self::write(LapicRegister::EndOfInterrupt, 0);
+    }
+}
+
+// switch to x2APIC only when the CPU supports it and the APIC isn't disabled by the firmware
+pub fn initialize_lapic() {
+    let mut apic_base = Msr::new(IA32_APIC_BASE);
+    let value = unsafe { apic_base.read() };
+    if value & APIC_BASE_X2APIC != 0 {
+        // the firmware has already enabled x2APIC, and it can't be turned back to xAPIC directly
+        X2APIC_ENABLED.store(true, Ordering::Relaxed);
+    } else if has_feature(Feature::X2APIC) && value & APIC_BASE_ENABLE != 0 {
+        unsafe { apic_base.write(value | APIC_BASE_X2APIC) };
+        X2APIC_ENABLED.store(true, Ordering::Relaxed);
+    }
+    info!(
+        "Local APIC: {} mode, id: {}",
+        if LocalApic::is_x2apic() { "x2APIC" } else { "xAPIC" },
+        LocalApic::id()
+    );
+}
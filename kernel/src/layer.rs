@@ -1,6 +1,10 @@
 use crate::{
     error,
-    graphics::{Coord, FrameBufferWriter, PixelWriter},
+    graphics::{
+        BackgroundMode, Coord, FrameBufferWriter, Graphics, ImageInfo, PixelColor, PixelWriter,
+        Rect,
+    },
+    status::StatusCode,
     window::Window,
     FrameBuffer, FrameBufferConfig,
 };
@@ -52,6 +56,35 @@ impl Layer {
     pub fn draw_to(&self, fb: &mut FrameBuffer) {
         self.window.draw_to(fb, self.pos);
     }
+
+    fn area(&self) -> Rect {
+        let (width, height) = self.window.writer.size();
+        return Rect::new(self.pos, Coord::new(width, height));
+    }
+}
+
+// the bottom layer, under all the layers in the stack
+pub enum Background {
+    Color(PixelColor),
+    Image {
+        image: Vec<u8>,
+        info: ImageInfo,
+        mode: BackgroundMode,
+        color: PixelColor,
+    },
+}
+
+impl Background {
+    fn draw(&self, area: Rect) -> Result<(), StatusCode> {
+        let graphics = Graphics::instance();
+        match self {
+            Self::Color(color) => graphics.clear_rect(area, color),
+            Self::Image { image, info, mode, color } => {
+                graphics.draw_background(image, *info, *mode, color, area)?
+            }
+        }
+        return Ok(());
+    }
 }
 
 #[derive(PartialEq)]
@@ -77,6 +110,9 @@ pub struct LayerManager {
     layers: Vec<Arc<RefCell<Layer>>>,
     layer_stack: Vec<Arc<RefCell<Layer>>>,
     layer_id: u32,
+    background: Background,
+    // the area which a layer has left since the last draw, where the background is drawn again
+    dirty: Option<Rect>,
 }
 
 impl LayerManager {
@@ -86,9 +122,31 @@ impl LayerManager {
             layers: vec![],
             layer_stack: vec![],
             layer_id: 0,
+            background: Background::Color(PixelColor::default()),
+            dirty: None,
         };
     }
 
+    // the whole background is drawn at once, and the layers over it
+    pub fn set_background(&mut self, background: Background) -> Result<(), StatusCode> {
+        let (width, height) = Graphics::instance().resolution();
+        background.draw(Rect::new(Coord::new(0, 0), Coord::new(width, height)))?;
+        self.background = background;
+        self.dirty = None;
+        self.draw();
+        return Ok(());
+    }
+
+    fn mark_dirty(&mut self, area: Rect) {
+        self.dirty = Some(match self.dirty {
+            Some(dirty) => {
+                let pos = dirty.pos.elem_min(area.pos);
+                Rect::new(pos, dirty.end().elem_max(area.end()) - pos)
+            }
+            None => area,
+        });
+    }
+
     pub fn new_layer(&mut self) -> Arc<RefCell<Layer>> {
         self.layer_id += 1;
         let layer = Arc::new(RefCell::new(Layer::new(self.layer_id)));
@@ -97,20 +155,27 @@ impl LayerManager {
     }
 
     pub fn draw(&mut self) {
+        if let Some(dirty) = self.dirty.take() {
+            if let Err(code) = self.background.draw(dirty) {
+                error!("the background isn't drawn: {}", code);
+            }
+        }
         for layer in &self.layer_stack {
             layer.borrow().draw_to(&mut self.fb);
         }
     }
 
     pub fn move_absolute(&mut self, id: u32, new_position: Coord) -> Result<(), ()> {
-        self.find_layer(id)?
-            .borrow_mut()
-            .move_absolute(new_position);
+        let layer = self.find_layer(id)?;
+        self.mark_dirty(layer.borrow().area());
+        layer.borrow_mut().move_absolute(new_position);
         Ok(())
     }
 
     pub fn move_relative(&mut self, id: u32, pos_diff: Coord) -> Result<(), ()> {
-        self.find_layer(id)?.borrow_mut().move_relative(pos_diff);
+        let layer = self.find_layer(id)?;
+        self.mark_dirty(layer.borrow().area());
+        layer.borrow_mut().move_relative(pos_diff);
         Ok(())
     }
 
@@ -138,7 +203,8 @@ impl LayerManager {
 
     pub fn hide(&mut self, id: u32) -> Result<(), ()> {
         if let Some(pos) = self.find_ord(id) {
-            self.layer_stack.remove(pos);
+            let layer = self.layer_stack.remove(pos);
+            self.mark_dirty(layer.borrow().area());
         } else {
             return Err(());
         }
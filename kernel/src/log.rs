@@ -1,8 +1,25 @@
-use crate::LAYER_MANAGER;
-use core::fmt::Write;
+use crate::{
+    console::{active_terminal, terminals},
+    drivers::{serial::_serial_print, timer::current_tick},
+    horse_lib::irq_mutex::IrqMutex,
+    LAYER_MANAGER,
+};
+use core::{
+    fmt::Write,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+const LOG_BUFFER_SIZE: usize = 16384;
 
 static LOG_LEVEL_DISPLAY: [&str; 6] = ["OFF", "ERROR", "WARN", "INFO", "DEBUG", "TRACE"];
-pub static LOG_LEVEL: spin::Mutex<LogLevel> = spin::Mutex::new(LogLevel::Debug);
+// ANSI color codes for each level
+static LOG_LEVEL_COLOR: [&str; 6] = ["", "\x1b[31m", "\x1b[33m", "\x1b[32m", "\x1b[36m", "\x1b[35m"];
+const COLOR_RESET: &str = "\x1b[0m";
+static LOG_COLOR: AtomicBool = AtomicBool::new(true);
+#[cfg(debug_assertions)]
+static LOG_LEVEL: AtomicUsize = AtomicUsize::new(LogLevel::Trace as usize);
+#[cfg(not(debug_assertions))]
+static LOG_LEVEL: AtomicUsize = AtomicUsize::new(LogLevel::Info as usize);
 
 #[repr(usize)]
 #[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
@@ -19,8 +36,78 @@ impl LogLevel {
     pub fn as_str(&self) -> &'static str {
         LOG_LEVEL_DISPLAY[*self as usize]
     }
+    pub fn color(&self) -> &'static str {
+        if !LOG_COLOR.load(Ordering::Relaxed) {
+            return "";
+        }
+        LOG_LEVEL_COLOR[*self as usize]
+    }
+}
+
+impl TryFrom<usize> for LogLevel {
+    type Error = ();
+    fn try_from(value: usize) -> Result<Self, Self::Error> {
+        return match value {
+            0 => Ok(LogLevel::Off),
+            1 => Ok(LogLevel::Error),
+            2 => Ok(LogLevel::Warn),
+            3 => Ok(LogLevel::Info),
+            4 => Ok(LogLevel::Debug),
+            5 => Ok(LogLevel::Trace),
+            _ => Err(()),
+        };
+    }
+}
+
+// keeps recent log messages. the oldest bytes are overwritten when it's full
+pub struct LogBuffer {
+    buf: [u8; LOG_BUFFER_SIZE],
+    // position of the oldest byte
+    start: usize,
+    len: usize,
+}
+
+impl LogBuffer {
+    pub const fn new() -> Self {
+        return Self {
+            buf: [0; LOG_BUFFER_SIZE],
+            start: 0,
+            len: 0,
+        };
+    }
+
+    fn push(&mut self, byte: u8) {
+        let end = (self.start + self.len) % LOG_BUFFER_SIZE;
+        self.buf[end] = byte;
+        if self.len == LOG_BUFFER_SIZE {
+            self.start = (self.start + 1) % LOG_BUFFER_SIZE;
+        } else {
+            self.len += 1;
+        }
+    }
+
+    // copy the newest messages into buf, and return the number of bytes copied
+    pub fn read(&self, buf: &mut [u8]) -> usize {
+        let n = core::cmp::min(buf.len(), self.len);
+        let skip = self.len - n;
+        for (i, byte) in buf[..n].iter_mut().enumerate() {
+            *byte = self.buf[(self.start + skip + i) % LOG_BUFFER_SIZE];
+        }
+        return n;
+    }
+}
+
+impl Write for LogBuffer {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for byte in s.bytes() {
+            self.push(byte);
+        }
+        return Ok(());
+    }
 }
 
+pub static KERNEL_LOG: IrqMutex<LogBuffer> = IrqMutex::new(LogBuffer::new());
+
 #[macro_export]
 macro_rules! print {
     ($($arg:tt)*) => ($crate::_print(format_args!($($arg)*)));
@@ -35,15 +122,15 @@ macro_rules! println {
 #[macro_export(local_inner_macro)]
 macro_rules! log {
     (level: $level:expr, $fmt:expr) => {
-        if $level <= $crate::_log_level() {
-            $crate::print!("[ {} ]", $level.as_str());
-            $crate::print!(core::concat!($fmt, "\n"));
+        if $level as usize <= $crate::_log_level() {
+            $crate::print!("{}[ {} ]{}", $level.color(), $level.as_str(), $crate::log::color_reset());
+            $crate::_log($level, format_args!(core::concat!($fmt, "\n")));
         }
     };
     (level: $level:expr, $fmt:expr, $($arg:tt)*) => {
-        if $level <= $crate::_log_level() {
-            $crate::print!("[ {} ]", $level.as_str());
-            $crate::print!(core::concat!($fmt, "\n"), $($arg)*);
+        if $level as usize <= $crate::_log_level() {
+            $crate::print!("{}[ {} ]{}", $level.color(), $level.as_str(), $crate::log::color_reset());
+            $crate::_log($level, format_args!(core::concat!($fmt, "\n"), $($arg)*));
         }
     };
 }
@@ -53,11 +140,11 @@ macro_rules! status_log {
     ($status:expr ,$fmt:expr) => {
         match $status {
             StatusCode::Success => {
-                crate::print!("[ OK ]");
+                crate::print!("{}[ OK ]{}", $crate::LogLevel::Info.color(), $crate::log::color_reset());
                 crate::print!(core::concat!($fmt, "\n"))
             },
             _ => {
-                crate::print!("[ Error ]");
+                crate::print!("{}[ Error ]{}", $crate::LogLevel::Error.color(), $crate::log::color_reset());
                 crate::print!(core::concat!($fmt, "\n"))
             }
         }
@@ -65,11 +152,11 @@ macro_rules! status_log {
     ($status:expr, $fmt:expr, $($arg:tt)*) => {
         match $status {
             StatusCode::Success => {
-                crate::print!("[ OK ]");
+                crate::print!("{}[ OK ]{}", $crate::LogLevel::Info.color(), $crate::log::color_reset());
                 crate::print!(core::concat!($fmt, "\n"), $($arg)*)
             },
             _ => {
-                crate::print!("[ Error ]");
+                crate::print!("{}[ Error ]{}", $crate::LogLevel::Error.color(), $crate::log::color_reset());
                 crate::print!(core::concat!($fmt, "\n"), $($arg)*)
             }
         }
@@ -101,15 +188,70 @@ macro_rules! trace {
     ($($arg:tt)*) => ($crate::log!(level: $crate::LogLevel::Trace, $($arg)*));
 }
 
+// the kernel prints to the active virtual terminal
 pub fn _print(args: core::fmt::Arguments) {
-    let mut locked_console = crate::console::Console::instance();
-    let console = locked_console.as_mut().unwrap();
+    _print_to(active_terminal(), args);
+}
+
+// falls back to serial until the console is initialized
+pub fn _print_to(vt: usize, args: core::fmt::Arguments) {
+    let mut locked_terminals = terminals();
+    let console = match locked_terminals.as_mut().and_then(|terminals| terminals.get_mut(vt)) {
+        Some(console) => console,
+        None => {
+            drop(locked_terminals);
+            _serial_print(args);
+            return;
+        }
+    };
     console.write_fmt(args).unwrap();
+    drop(locked_terminals);
+    // the background terminals are drawn when they are switched to
+    if vt != active_terminal() {
+        return;
+    }
     unsafe {
-        LAYER_MANAGER.get_mut().unwrap().draw();
+        if let Some(layer_manager) = LAYER_MANAGER.get_mut() {
+            layer_manager.draw();
+        }
+    }
+}
+
+fn is_console_ready() -> bool {
+    return terminals().is_some();
+}
+
+pub fn _log(level: LogLevel, args: core::fmt::Arguments) {
+    let tick = current_tick();
+    let mut log = KERNEL_LOG.lock();
+    let _ = write!(log, "[{:>8}] [ {} ] ", tick, level.as_str());
+    let _ = log.write_fmt(args);
+    drop(log);
+    // the whole message already goes to serial while the console isn't ready
+    if is_console_ready() {
+        _serial_print(format_args!("[ {} ] {}", level.as_str(), args));
+    }
+    _print(args);
+}
+
+// the ring buffer always keeps plain text, this only affects the console
+pub fn set_log_color(enable: bool) {
+    LOG_COLOR.store(enable, Ordering::Relaxed);
+}
+
+pub fn color_reset() -> &'static str {
+    if !LOG_COLOR.load(Ordering::Relaxed) {
+        return "";
     }
+    COLOR_RESET
+}
+
+pub fn set_log_level(level: LogLevel) {
+    LOG_LEVEL.store(level as usize, Ordering::Relaxed);
 }
 
-pub fn _log_level() -> LogLevel {
-    *LOG_LEVEL.lock()
+// called by every log macro, so this must stay a single load
+#[inline(always)]
+pub fn _log_level() -> usize {
+    LOG_LEVEL.load(Ordering::Relaxed)
 }
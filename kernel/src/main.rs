@@ -1,37 +1,52 @@
-#![no_std]
-#![no_main]
+// the unit tests are built for the host with std, see the test task in Makefile.toml
+#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(test), no_main)]
 #![feature(abi_x86_interrupt)]
+#![feature(alloc_error_handler)]
 #![feature(core_intrinsics)]
 #![feature(type_name_of_val)]
 
 mod acpi;
 mod ascii_font;
+mod exec;
 mod memory_allocator;
 mod paging;
 mod queue;
 mod segment;
+mod shell;
+mod shm;
+mod smp;
+mod symbols;
+mod watchdog;
 
 pub mod console;
+pub mod cpuid;
 pub mod drivers;
 pub mod fixed_vec;
 pub mod framebuffer;
 pub mod graphics;
 pub mod interrupt;
+pub mod lapic;
 pub mod layer;
 pub mod horse_lib;
+pub mod input;
+pub mod keyboard_layout;
 pub mod log;
 pub mod memory_manager;
 pub mod mouse;
 pub mod proc;
 pub mod status;
+pub mod syscall;
 pub mod volatile;
 pub mod window;
 
 use acpi::*;
-use console::Console;
+use console::{Console, VT_COUNT};
 use drivers::{
     detect_dev::initialize_pci_devices,
     pci::*,
+    ps2,
+    serial::initialize_serial,
     timer::*,
     usb::{classdriver::mouse::MOUSE_CURSOR, memory::*},
     fs::init::initialize_filesystem,
@@ -44,25 +59,26 @@ use log::*;
 use memory_allocator::KernelMemoryAllocator;
 use memory_manager::*;
 use mouse::{draw_mouse_cursor, MOUSE_CURSOR_HEIGHT, MOUSE_CURSOR_WIDTH, MOUSE_TRANSPARENT_COLOR};
+use paging::CacheType;
 use proc::{PROCESS_MANAGER, initialize_process_manager};
-use queue::ArrayQueue;
+use queue::SpscQueue;
 use segment::{KERNEL_CS, KERNEL_SS};
 use status::StatusCode;
 use window::*;
 
 extern crate libloader;
-use libloader::MemoryMap;
+use libloader::{MemoryMap, SymbolTable};
 
 extern crate alloc;
-use alloc::sync::Arc;
+use alloc::{sync::Arc, vec::Vec};
 use core::{arch::asm, panic::PanicInfo};
-use spin::{once::Once, Mutex};
 use uefi::table::{Runtime, SystemTable};
 use x86_64::{
     instructions::interrupts::{
         disable, //cli
         enable,  //sti
     },
+    registers::control::{Cr2, Cr3},
     structures::idt::InterruptStackFrame,
 };
 
@@ -76,12 +92,19 @@ pub enum Message {
     NoInterruption,
     InterruptXHCI,
     TimerTimeout { timeout: u64, value: i32 },
+    // the process waits for a timeout. it can't be woken up from the timer interrupt,
+    // since the interrupted code may be using the process
+    WakeUp { id: usize },
+    PS2Keyboard { scancode: u8 },
+    Periodic { handle: PeriodicHandle },
+    // the watchdog fired. what was running is kept in the watchdog
+    Watchdog,
 }
 
-pub static XHC: Mutex<Once<usize>> = Mutex::new(Once::new());
-pub static INTERRUPTION_QUEUE: Mutex<ArrayQueue<Message, 32>> = Mutex::new(ArrayQueue::new());
-#[global_allocator]
-static ALLOCATOR: KernelMemoryAllocator = KernelMemoryAllocator::new();
+// pushed only by the interrupt handlers on the BSP, which don't nest, and popped only by the main loop
+pub static INTERRUPTION_QUEUE: SpscQueue<Message, 32> = SpscQueue::new();
+#[cfg_attr(not(test), global_allocator)]
+pub static ALLOCATOR: KernelMemoryAllocator = KernelMemoryAllocator::new();
 
 fn welcome_message() {
     print!(
@@ -95,7 +118,8 @@ fn welcome_message() {
   /__/   /__/ /______/ /_/     /___/ /_____/
 "
     );
-    println!("Horse is the OS made by Momma Watasu. This OS is distributed under the MIT license.")
+    println!("Horse is the OS made by Momma Watasu. This OS is distributed under the MIT license.");
+    println!("CPU: {}", cpuid::brand_string());
 }
 
 fn initialize(fb_config: *mut FrameBufferConfig) {
@@ -103,15 +127,25 @@ fn initialize(fb_config: *mut FrameBufferConfig) {
     let resolution = fb_config_ref.resolution;
     unsafe { Graphics::initialize_instance(fb_config_ref) }
     let graphics = Graphics::instance();
-    graphics.clear(&BG_COLOR);
+    // the screen is written much faster with write-combining than with write-back
+    let fb = graphics.frame_buffer();
+    if let Err(code) = unsafe { paging::map_range(fb.config.fb as u64, fb.size(), CacheType::WriteCombining) } {
+        warn!("the framebuffer isn't write-combining: {}", code);
+    }
 
-    let mut bgwindow = Arc::new(Window::new(
-        resolution.0,
-        resolution.1,
-        fb_config_ref.format,
-    ));
-    let bgwriter = Arc::get_mut(&mut bgwindow).unwrap().writer();
-    Console::initialize(bgwriter, resolution, &FG_COLOR, &BG_COLOR);
+    // every virtual terminal has a full screen window
+    let mut consoles = Vec::new();
+    let mut vt_windows = Vec::new();
+    for _ in 0..VT_COUNT {
+        let mut window = Arc::new(Window::new(
+            resolution.0,
+            resolution.1,
+            fb_config_ref.format,
+        ));
+        let writer = Arc::get_mut(&mut window).unwrap().writer();
+        consoles.push(Console::new(writer, resolution, &FG_COLOR, &BG_COLOR));
+        vt_windows.push(window);
+    }
 
     let mut mouse_window = Arc::new(Window::new(
         MOUSE_CURSOR_WIDTH,
@@ -128,13 +162,19 @@ fn initialize(fb_config: *mut FrameBufferConfig) {
 
     unsafe { LAYER_MANAGER.call_once(|| LayerManager::new(fb_config_ref)) };
     let layer_manager = unsafe { LAYER_MANAGER.get_mut().unwrap() };
+    let _ = layer_manager.set_background(Background::Color(BG_COLOR));
 
-    let bglayer_id = layer_manager
-        .new_layer()
-        .borrow_mut()
-        .set_window(bgwindow)
-        .move_absolute(Coord::new(0, 0))
-        .id();
+    let vt_layer_ids: Vec<u32> = vt_windows
+        .into_iter()
+        .map(|window| {
+            layer_manager
+                .new_layer()
+                .borrow_mut()
+                .set_window(window)
+                .move_absolute(Coord::new(0, 0))
+                .id()
+        })
+        .collect();
 
     let mouse_layer_id = layer_manager
         .new_layer()
@@ -144,23 +184,43 @@ fn initialize(fb_config: *mut FrameBufferConfig) {
         .id();
 
     MOUSE_CURSOR.lock().set_layer_id(mouse_layer_id);
-    layer_manager.up_down(bglayer_id, LayerHeight::Height(0));
+    console::initialize_terminals(consoles, vt_layer_ids);
     layer_manager.up_down(mouse_layer_id, LayerHeight::Height(1));
     layer_manager.draw();
 }
 
 extern "x86-interrupt" fn handler_xhci(_: InterruptStackFrame) {
-    INTERRUPTION_QUEUE.lock().push(Message::InterruptXHCI);
+    INTERRUPTION_QUEUE.push(Message::InterruptXHCI);
+    unsafe {
+        notify_end_of_interrupt();
+    }
+}
+
+// NVMe completions are polled, so the interrupt is only acknowledged
+extern "x86-interrupt" fn handler_nvme(_: InterruptStackFrame) {
+    unsafe {
+        notify_end_of_interrupt();
+    }
+}
+
+extern "x86-interrupt" fn handler_ps2_keyboard(_: InterruptStackFrame) {
+    if let Some(scancode) = ps2::read_scancode() {
+        INTERRUPTION_QUEUE.push(Message::PS2Keyboard { scancode });
+    }
     unsafe {
         notify_end_of_interrupt();
     }
 }
 
-extern "x86-interrupt" fn handler_lapic_timer(_: InterruptStackFrame) {
+extern "x86-interrupt" fn handler_lapic_timer(stack_frame: InterruptStackFrame) {
     let proc = TIMER_MANAGER.lock().get_mut().unwrap().tick();
+    let stuck = watchdog::tick(&stack_frame);
     unsafe {
+        if let Some(manager) = PROCESS_MANAGER.get() {
+            manager.charge_tick();
+        }
         notify_end_of_interrupt();
-        if proc {
+        if proc || stuck {
             PROCESS_MANAGER.get_mut().unwrap().switch_process(false);
         }
     }
@@ -171,13 +231,21 @@ extern "sysv64" fn kernel_main_virt(
     st: SystemTable<Runtime>,
     fb_config: *mut FrameBufferConfig,
     memory_map: *const MemoryMap,
+    symbol_table: *const SymbolTable,
 ) -> ! {
+    // serial comes first so that panics during the early boot can be seen
+    let serial_available = initialize_serial();
+    // before anything relies on the features. the panic message goes to serial
+    cpuid::check_required_features();
+    symbols::initialize(symbol_table);
+    horse_lib::simd::enable_sse();
     //setup memory allocator
     segment::initialize();
     unsafe {
         paging::initialize();
     }
     frame_manager_instance().initialize(unsafe { *memory_map });
+    smp::reserve_trampoline();
     //initialize allocator for usb
     initialize_usballoc();
 
@@ -185,34 +253,73 @@ extern "sysv64" fn kernel_main_virt(
     initialize(fb_config);
 
     welcome_message();
+    if !serial_available {
+        warn!("COM1 isn't available, the log won't be mirrored to serial");
+    }
+    if !syscall::initialize_syscall() {
+        warn!("the syscall instruction isn't supported");
+    }
     unsafe { debug!("fb: {:?}", (*fb_config).fb) };
 
+    lapic::initialize_lapic();
     initialize_acpi(st);
+    // how much write-combining helps, see initialize. the layers are drawn again after that
+    let elapsed = measure(|| Graphics::instance().clear(&BG_COLOR));
+    unsafe { LAYER_MANAGER.get_mut().unwrap().draw() };
+    info!("full-screen clear took {}us", elapsed.as_micros());
+    smp::start_application_processors();
 
     let pci_devices = find_pci_devices();
     let mut xhc = initialize_pci_devices(&pci_devices).unwrap();
+    xhc.publish_port_infos();
     initialize_filesystem();
 
     FILE_DESCRIPTOR_TABLE.lock().initialize();
+    for stdin in &input::STDIN {
+        stdin.lock().initialize();
+    }
 
     //set the IDT entry
     IDT.lock()[InterruptVector::Xhci as usize].set_handler_fn(handler_xhci);
     IDT.lock()[InterruptVector::LAPICTimer as usize].set_handler_fn(handler_lapic_timer);
+    IDT.lock()[InterruptVector::Nvme as usize].set_handler_fn(handler_nvme);
+    IDT.lock()[InterruptVector::PS2Keyboard as usize].set_handler_fn(handler_ps2_keyboard);
+    unsafe {
+        IDT.lock()
+            .double_fault
+            .set_handler_fn(handler_double_fault)
+            .set_stack_index(segment::DOUBLE_FAULT_IST_INDEX);
+    }
+    IDT.lock()
+        .general_protection_fault
+        .set_handler_fn(handler_general_protection_fault);
+    unsafe {
+        IDT.lock()
+            .page_fault
+            .set_handler_fn(handler_page_fault)
+            .set_stack_index(segment::PAGE_FAULT_IST_INDEX);
+    }
     unsafe {
         IDT.lock().load_unsafe();
     }
-    INTERRUPTION_QUEUE
-        .lock()
-        .initialize(Message::NoInterruption);
+    // the fallback for the machines whose USB keyboard doesn't work
+    if !ps2::initialize_ps2_keyboard() {
+        info!("PS/2 keyboard controller isn't found");
+    }
 
     initialize_process_manager();
+    shell::start();
     loop {
+        watchdog::feed();
+        unsafe { PROCESS_MANAGER.get_mut().unwrap().reap_terminated() };
         disable();
-        if INTERRUPTION_QUEUE.lock().count == 0 {
-            unsafe { asm!("sti", "hlt") }; //don't touch this line!These instructions must be in a row.
-            continue;
-        }
-        let msg = INTERRUPTION_QUEUE.lock().pop().unwrap();
+        let msg = match INTERRUPTION_QUEUE.pop() {
+            Some(msg) => msg,
+            None => {
+                unsafe { asm!("sti", "hlt") }; //don't touch this line!These instructions must be in a row.
+                continue;
+            }
+        };
         enable();
 
         match msg {
@@ -222,19 +329,154 @@ extern "sysv64" fn kernel_main_virt(
                         error!("Error occurs during processing event: {:?}", e);
                     }
                 }
+                xhc.publish_port_infos();
             }
             Message::TimerTimeout { timeout: _, value } => {
                 if value != -1 {
                     println!("Timer timeout: {}", value)
                 };
+                if let Err(e) = xhc.poll_timeouts() {
+                    error!("USB transfer has been cancelled: {:?}", e);
+                }
+                // a timed out port may be reset for the retry
+                xhc.publish_port_infos();
             }
+            Message::WakeUp { id } => unsafe {
+                PROCESS_MANAGER.get_mut().unwrap().id_wake_up(id);
+            },
+            Message::PS2Keyboard { scancode } => {
+                if let Some(event) = ps2::PS2_KEYBOARD.lock().on_scancode(scancode) {
+                    input::push_key_event(event);
+                }
+            }
+            Message::Periodic { handle } => {
+                // the lock is released first, so the callback can use the timer
+                let callback = TIMER_MANAGER.lock().get().unwrap().periodic_callback(handle);
+                if let Some(callback) = callback {
+                    callback();
+                }
+            }
+            Message::Watchdog => watchdog::report(),
             Message::NoInterruption => {}
         }
     }
 }
 
+// the heap usage tells whether the memory leaked or the request was just too large
+#[cfg(not(test))]
+#[alloc_error_handler]
+fn out_of_memory(layout: core::alloc::Layout) -> ! {
+    error!(
+        "heap: {} bytes allocated, {} bytes free, peak {} bytes",
+        ALLOCATOR.bytes_allocated(),
+        ALLOCATOR.bytes_free(),
+        ALLOCATOR.peak_usage()
+    );
+    panic!(
+        "Ran out of free memory while trying to allocate {:?}",
+        layout
+    );
+}
+
+#[cfg(not(test))]
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
+    disable();
     error!("{}", info);
-    loop {}
+    dump_registers();
+    #[cfg(debug_assertions)]
+    print_backtrace();
+    loop {
+        unsafe { asm!("hlt") }
+    }
+}
+
+#[cfg(not(test))]
+fn dump_registers() {
+    let (rip, rsp, rbp): (u64, u64, u64);
+    unsafe {
+        asm!(
+            "lea {rip}, [rip]",
+            "mov {rsp}, rsp",
+            "mov {rbp}, rbp",
+            rip = out(reg) rip,
+            rsp = out(reg) rsp,
+            rbp = out(reg) rbp,
+        );
+    }
+    let cr2 = Cr2::read_raw();
+    let (cr3, _) = Cr3::read_raw();
+    error!("RIP: {:#018x} RSP: {:#018x} RBP: {:#018x}", rip, rsp, rbp);
+    error!("CR2: {:#018x} CR3: {:#018x}", cr2, cr3.start_address().as_u64());
+}
+
+// asm.s is only assembled for the kernel target, so the unit tests get these instead.
+// none of them is called by the tests
+#[cfg(test)]
+mod asm_stub {
+    #[no_mangle]
+    extern "C" fn switch_context(_next_ctx: u64, _current_ctx: u64) {
+        unreachable!();
+    }
+    #[no_mangle]
+    extern "C" fn get_cr3() -> u64 {
+        unreachable!();
+    }
+    #[no_mangle]
+    extern "C" fn set_cr3(_value: u64) {
+        unreachable!();
+    }
+    #[no_mangle]
+    extern "C" fn load_gdt(_limit: u16, _offset: usize) {
+        unreachable!();
+    }
+    #[no_mangle]
+    extern "C" fn set_ds_all(_value: u16) {
+        unreachable!();
+    }
+    #[no_mangle]
+    extern "C" fn set_cs_ss(_cs: u16, _ss: u16) {
+        unreachable!();
+    }
+    #[no_mangle]
+    extern "C" fn syscall_entry() {
+        unreachable!();
+    }
+    #[no_mangle]
+    static ap_trampoline_start: u8 = 0;
+    #[no_mangle]
+    static ap_trampoline_end: u8 = 0;
+    #[no_mangle]
+    static ap_trampoline_cr3: u8 = 0;
+    #[no_mangle]
+    static ap_trampoline_stack: u8 = 0;
+    #[no_mangle]
+    static ap_trampoline_entry: u8 = 0;
+}
+
+// follow the saved frame pointers. the walk is bounded because the stack may be corrupted
+#[cfg(all(debug_assertions, not(test)))]
+fn print_backtrace() {
+    const MAX_BACKTRACE_DEPTH: usize = 16;
+    let mut rbp: u64;
+    unsafe { asm!("mov {}, rbp", out(reg) rbp) };
+    error!("backtrace:");
+    for depth in 0..MAX_BACKTRACE_DEPTH {
+        if rbp == 0 || rbp % 8 != 0 {
+            break;
+        }
+        let (next, ret) = unsafe { (*(rbp as *const u64), *((rbp + 8) as *const u64)) };
+        if ret == 0 {
+            break;
+        }
+        match symbols::resolve(ret) {
+            Some((name, offset)) => error!("  #{:<2} {:#018x} {}+{:#x}", depth, ret, name, offset),
+            None => error!("  #{:<2} {:#018x}", depth, ret),
+        }
+        // the caller's frame must be above the current one
+        if next <= rbp {
+            break;
+        }
+        rbp = next;
+    }
 }
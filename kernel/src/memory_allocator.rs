@@ -3,9 +3,12 @@ use crate::{memory_manager::*, status_log, StatusCode};
 use core::{
     alloc::{GlobalAlloc, Layout},
     ptr,
+    sync::atomic::{AtomicUsize, Ordering},
 };
 use spin::mutex::Mutex;
 
+// a block is aligned to its size since the frame is split from its start,
+// so only the frames need to care about the alignment
 enum AllocateMode {
     Block(usize),
     Frame(usize),
@@ -23,17 +26,52 @@ impl From<Layout> for AllocateMode {
 
 const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048];
 
+impl AllocateMode {
+    // the bytes actually taken for the allocation
+    fn bytes(&self) -> usize {
+        match self {
+            Self::Block(index) => BLOCK_SIZES[*index],
+            Self::Frame(n_frames) => n_frames * BYTES_PER_FRAME,
+        }
+    }
+}
+
 pub struct KernelMemoryAllocator {
     available_blocks: Mutex<[*mut u8; BLOCK_SIZES.len()]>,
+    // atomics, so that they are updated without another lock in the allocator
+    allocated: AtomicUsize,
+    peak: AtomicUsize,
 }
 
 impl KernelMemoryAllocator {
     pub const fn new() -> Self {
         Self {
             available_blocks: Mutex::new([ptr::null_mut(); BLOCK_SIZES.len()]),
+            allocated: AtomicUsize::new(0),
+            peak: AtomicUsize::new(0),
         }
     }
 
+    // the bytes in use, including the rounding up to the block size or the frame
+    pub fn bytes_allocated(&self) -> usize {
+        self.allocated.load(Ordering::Relaxed)
+    }
+
+    // the bytes of the free frames. the free blocks in the frames already split aren't included.
+    // this locks the frame manager, so it mustn't be called while it's locked
+    pub fn bytes_free(&self) -> usize {
+        frame_manager_instance().check_free_memory()
+    }
+
+    pub fn peak_usage(&self) -> usize {
+        self.peak.load(Ordering::Relaxed)
+    }
+
+    fn count_alloc(&self, bytes: usize) {
+        let allocated = self.allocated.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        self.peak.fetch_max(allocated, Ordering::Relaxed);
+    }
+
     fn allocate_frame_for_block(index: usize) -> *mut u8 {
         let block_size = BLOCK_SIZES[index];
         let n_blocks_per_frame = BYTES_PER_FRAME / block_size;
@@ -61,7 +99,9 @@ unsafe impl Sync for KernelMemoryAllocator {}
 
 unsafe impl GlobalAlloc for KernelMemoryAllocator {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        match layout.into() {
+        let mode: AllocateMode = layout.into();
+        let bytes = mode.bytes();
+        let ptr = match mode {
             AllocateMode::Block(index) => {
                 let mut available_blocks = self.available_blocks.lock();
                 let mut ptr = available_blocks[index];
@@ -71,20 +111,62 @@ unsafe impl GlobalAlloc for KernelMemoryAllocator {
                 if !ptr.is_null() {
                     available_blocks[index] = (ptr as *mut u64).read() as *mut u8;
                 }
+                ptr
+            }
+            AllocateMode::Frame(n_frames) => {
+                let align_frames = (layout.align() / BYTES_PER_FRAME).max(1);
+                match frame_manager_instance().allocate_aligned(n_frames, align_frames) {
+                    Ok(frame) => frame.phys_addr(),
+                    Err(status) => {
+                        status_log!(status, "KernelAllocator failed to allocate frame");
+                        ptr::null_mut()
+                    }
+                }
+            }
+        };
+        if !ptr.is_null() {
+            self.count_alloc(bytes);
+        }
+        return ptr;
+    }
+
+    // the frames are grown or shrunk in place when possible
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        match (layout.into(), new_layout.into()) {
+            // the block has the room already
+            (AllocateMode::Block(index), AllocateMode::Block(new_index)) if index == new_index => {
                 return ptr;
             }
-            AllocateMode::Frame(n_frames) => match frame_manager_instance().allocate(n_frames) {
-                Ok(frame) => frame.phys_addr(),
-                Err(status) => {
-                    status_log!(status, "KernelAllocator failed to allocate frame");
-                    ptr::null_mut()
+            (AllocateMode::Frame(n_frames), AllocateMode::Frame(new_n_frames)) => {
+                let start_frame = FrameID::from_phys_addr(ptr);
+                if new_n_frames <= n_frames {
+                    let unused = n_frames - new_n_frames;
+                    frame_manager_instance()
+                        .free(FrameID::new(start_frame.id() + new_n_frames), unused);
+                    self.allocated
+                        .fetch_sub(unused * BYTES_PER_FRAME, Ordering::Relaxed);
+                    return ptr;
+                }
+                if frame_manager_instance().extend(start_frame, n_frames, new_n_frames) {
+                    self.count_alloc((new_n_frames - n_frames) * BYTES_PER_FRAME);
+                    return ptr;
                 }
-            },
+            }
+            _ => {}
+        }
+        let new_ptr = self.alloc(new_layout);
+        if !new_ptr.is_null() {
+            ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+            self.dealloc(ptr, layout);
         }
+        return new_ptr;
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        match layout.into() {
+        let mode: AllocateMode = layout.into();
+        self.allocated.fetch_sub(mode.bytes(), Ordering::Relaxed);
+        match mode {
             AllocateMode::Block(index) => {
                 let mut available_blocks = self.available_blocks.lock();
                 let next = available_blocks[index];
@@ -97,3 +179,120 @@ unsafe impl GlobalAlloc for KernelMemoryAllocator {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use core::arch::asm;
+    use std::sync::Once;
+
+    // the frames are used at their physical addresses, so the frame manager is given
+    // memory mapped at a fixed address of the test process, which is below its limit
+    const TEST_MEMORY: usize = 0x10_0000_0000;
+    const TEST_MEMORY_BYTES: usize = 16 * 1024 * 1024;
+    static SETUP: Once = Once::new();
+
+    fn setup() {
+        SETUP.call_once(|| {
+            // mmap(TEST_MEMORY, TEST_MEMORY_BYTES, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS | MAP_FIXED_NOREPLACE, -1, 0)
+            let addr: usize;
+            unsafe {
+                asm!(
+                    "syscall",
+                    inlateout("rax") 9usize => addr,
+                    in("rdi") TEST_MEMORY,
+                    in("rsi") TEST_MEMORY_BYTES,
+                    in("rdx") 0x3,
+                    in("r10") 0x100022,
+                    in("r8") -1isize,
+                    in("r9") 0,
+                    out("rcx") _,
+                    out("r11") _,
+                );
+            }
+            assert_eq!(addr, TEST_MEMORY, "failed to map the memory for the frames");
+            frame_manager_instance().set_memory_range(
+                FrameID::new(TEST_MEMORY / BYTES_PER_FRAME),
+                FrameID::new((TEST_MEMORY + TEST_MEMORY_BYTES) / BYTES_PER_FRAME),
+            );
+        });
+    }
+
+    fn fill(ptr: *mut u8, len: usize) {
+        for i in 0..len {
+            unsafe { ptr.add(i).write(i as u8) };
+        }
+    }
+
+    fn check(ptr: *mut u8, len: usize) {
+        for i in 0..len {
+            assert_eq!(unsafe { ptr.add(i).read() }, i as u8, "byte {}", i);
+        }
+    }
+
+    #[test]
+    fn frames_are_aligned() {
+        setup();
+        let allocator = KernelMemoryAllocator::new();
+        for (size, align) in [(4096, 4096), (3 * 4096, 4096), (8192, 16384), (100, 65536)] {
+            let layout = Layout::from_size_align(size, align).unwrap();
+            let ptr = unsafe { allocator.alloc(layout) };
+            assert!(!ptr.is_null());
+            assert_eq!(ptr as usize % align, 0, "size {} align {}", size, align);
+            unsafe { allocator.dealloc(ptr, layout) };
+        }
+        assert_eq!(allocator.bytes_allocated(), 0);
+    }
+
+    #[test]
+    fn blocks_are_aligned() {
+        setup();
+        let allocator = KernelMemoryAllocator::new();
+        for (size, align) in [(1, 1), (24, 64), (8, 256), (1000, 2048)] {
+            let layout = Layout::from_size_align(size, align).unwrap();
+            // the second one comes from the middle of the frame
+            let ptrs = unsafe { [allocator.alloc(layout), allocator.alloc(layout)] };
+            for ptr in ptrs {
+                assert!(!ptr.is_null());
+                assert_eq!(ptr as usize % align, 0, "size {} align {}", size, align);
+                unsafe { allocator.dealloc(ptr, layout) };
+            }
+        }
+        assert_eq!(allocator.bytes_allocated(), 0);
+    }
+
+    #[test]
+    fn realloc_keeps_contents() {
+        setup();
+        let allocator = KernelMemoryAllocator::new();
+        let mut layout = Layout::from_size_align(40, 8).unwrap();
+        let mut ptr = unsafe { allocator.alloc(layout) };
+        fill(ptr, 40);
+        // in the block, to a larger block, to frames and to more frames
+        for new_size in [60, 500, 3 * 4096, 10 * 4096] {
+            ptr = unsafe { allocator.realloc(ptr, layout, new_size) };
+            assert!(!ptr.is_null());
+            check(ptr, 40);
+            layout = Layout::from_size_align(new_size, 8).unwrap();
+        }
+        unsafe { allocator.dealloc(ptr, layout) };
+        assert_eq!(allocator.bytes_allocated(), 0);
+    }
+
+    #[test]
+    fn shrinking_frames_is_in_place() {
+        setup();
+        let allocator = KernelMemoryAllocator::new();
+        let layout = Layout::from_size_align(4 * 4096, 4096).unwrap();
+        let ptr = unsafe { allocator.alloc(layout) };
+        fill(ptr, 4 * 4096);
+        let new_ptr = unsafe { allocator.realloc(ptr, layout, 4096 + 1) };
+        assert_eq!(new_ptr, ptr);
+        check(ptr, 4096 + 1);
+        assert_eq!(allocator.bytes_allocated(), 2 * 4096);
+        unsafe { allocator.dealloc(ptr, Layout::from_size_align(4096 + 1, 4096).unwrap()) };
+        assert_eq!(allocator.bytes_allocated(), 0);
+    }
+}
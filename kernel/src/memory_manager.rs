@@ -1,6 +1,6 @@
-use crate::{MemoryMap, StatusCode};
+use crate::{fixed_vec::FixedVec, warn, MemoryMap, StatusCode};
 use core::{marker::Sync, mem::size_of};
-use libloader::is_available;
+use libloader::{is_available, is_reclaimable};
 use spin::mutex::{Mutex, MutexGuard};
 
 type MapLineType = usize;
@@ -11,8 +11,9 @@ const MAX_PHYSICS_MEMORY_BYTES: usize = 128 * 1024 * 1024 * 1024; //16GiB
 const FRAME_COUNT: usize = MAX_PHYSICS_MEMORY_BYTES / BYTES_PER_FRAME;
 const BITS_PER_MAP_LINE: usize = 8 * size_of::<MapLineType>(); //8 * sizeof::<MapLineType>
 const MAP_LINE_COUNT: usize = FRAME_COUNT / BITS_PER_MAP_LINE;
+const MAX_RECLAIMABLE_REGIONS: usize = 32;
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub struct FrameID(usize);
 
 impl FrameID {
@@ -27,11 +28,17 @@ impl FrameID {
     pub fn from_phys_addr(ptr: *mut u8) -> Self {
         Self(ptr as usize / BYTES_PER_FRAME)
     }
-    fn id(&self) -> usize {
+    pub fn id(&self) -> usize {
         self.0
     }
 }
 
+#[derive(Clone, Copy)]
+pub struct MemoryRegion {
+    pub start: FrameID,
+    pub n_frames: usize,
+}
+
 static MEMORY_MANAGER: Mutex<BitmapMemoryManager> = Mutex::new(BitmapMemoryManager::new());
 pub fn frame_manager_instance() -> MutexGuard<'static, BitmapMemoryManager> {
     MEMORY_MANAGER.lock()
@@ -41,6 +48,7 @@ pub struct BitmapMemoryManager {
     alloc_map: [MapLineType; MAP_LINE_COUNT],
     range_begin: FrameID,
     range_end: FrameID,
+    reclaimable: FixedVec<MemoryRegion, MAX_RECLAIMABLE_REGIONS>,
 }
 
 unsafe impl Sync for BitmapMemoryManager {}
@@ -51,6 +59,7 @@ impl BitmapMemoryManager {
             alloc_map: [0; MAP_LINE_COUNT],
             range_begin: FrameID::MIN,
             range_end: FrameID::MAX,
+            reclaimable: FixedVec::new(),
         }
     }
 
@@ -65,6 +74,17 @@ impl BitmapMemoryManager {
             }
 
             let phys_end = desc.phys_start + desc.page_count * UEFI_PAGE_SIZE;
+            if is_reclaimable(desc.ty) {
+                // these frames are freed by reclaim after the ACPI tables are parsed.
+                // the regions which don't fit just stay allocated
+                let region = MemoryRegion {
+                    start: FrameID::new(desc.phys_start as usize / BYTES_PER_FRAME),
+                    n_frames: (desc.page_count * UEFI_PAGE_SIZE) as usize / BYTES_PER_FRAME,
+                };
+                if self.reclaimable.push(region).is_err() {
+                    warn!("too many reclaimable regions: {:#x} is not reclaimed", desc.phys_start);
+                }
+            }
             if is_available(desc.ty) {
                 available_end = phys_end;
             } else {
@@ -81,7 +101,17 @@ impl BitmapMemoryManager {
     }
 
     pub fn allocate(&mut self, n_frames: usize) -> Result<FrameID, StatusCode> {
-        let mut start_frame_id = self.range_begin.id();
+        return self.allocate_aligned(n_frames, 1);
+    }
+
+    // the first frame id is a multiple of align_frames, which must be a power of 2
+    pub fn allocate_aligned(
+        &mut self,
+        n_frames: usize,
+        align_frames: usize,
+    ) -> Result<FrameID, StatusCode> {
+        let align_up = |id: usize| (id + align_frames - 1) & !(align_frames - 1);
+        let mut start_frame_id = align_up(self.range_begin.id());
         let mut i: usize;
         loop {
             i = 0;
@@ -98,10 +128,28 @@ impl BitmapMemoryManager {
                 self.mark_allocated(FrameID::new(start_frame_id), n_frames);
                 return Ok(FrameID::new(start_frame_id));
             }
-            start_frame_id += i + 1;
+            start_frame_id = align_up(start_frame_id + i + 1);
         }
     }
 
+    // allocate the frames right after the allocated ones if they are free
+    pub fn extend(&mut self, start_frame: FrameID, n_frames: usize, new_n_frames: usize) -> bool {
+        let end = start_frame.id() + new_n_frames;
+        if end > self.range_end.id() {
+            return false;
+        }
+        for id in start_frame.id() + n_frames..end {
+            if self.get_bit(FrameID::new(id)) {
+                return false;
+            }
+        }
+        self.mark_allocated(
+            FrameID::new(start_frame.id() + n_frames),
+            new_n_frames - n_frames,
+        );
+        return true;
+    }
+
     pub fn free(&mut self, start_frame: FrameID, n_frames: usize) -> StatusCode {
         for i in 0..n_frames {
             self.set_bit(FrameID::new(start_frame.id() + i), false);
@@ -109,6 +157,27 @@ impl BitmapMemoryManager {
         return StatusCode::Success;
     }
 
+    // free the region which was reserved by the firmware and return the number of recovered frames
+    pub fn reclaim(&mut self, region: MemoryRegion) -> usize {
+        let mut count = 0;
+        for i in 0..region.n_frames {
+            let frame = FrameID::new(region.start.id() + i);
+            if self.get_bit(frame) {
+                self.set_bit(frame, false);
+                count += 1;
+            }
+        }
+        let region_end = region.start.id() + region.n_frames;
+        if self.range_end.id() < region_end {
+            self.range_end = FrameID::new(region_end);
+        }
+        return count;
+    }
+
+    pub fn pop_reclaimable_region(&mut self) -> Option<MemoryRegion> {
+        return self.reclaimable.pop();
+    }
+
     pub fn mark_allocated(&mut self, start_frame: FrameID, n_frames: usize) {
         for i in 0..n_frames {
             self.set_bit(FrameID::new(start_frame.id() + i), true);
@@ -134,7 +203,7 @@ impl BitmapMemoryManager {
         if allocated {
             self.alloc_map[line_idx] |= (1 as MapLineType) << bit_idx
         } else {
-            self.alloc_map[line_idx] &= (1 as MapLineType) << bit_idx
+            self.alloc_map[line_idx] &= !((1 as MapLineType) << bit_idx)
         }
     }
 
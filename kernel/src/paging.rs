@@ -1,13 +1,66 @@
 use core::{
+    arch::asm,
     mem::MaybeUninit,
     ops::{Index, IndexMut},
 };
+use x86_64::{
+    instructions::tlb,
+    registers::model_specific::{Efer, EferFlags, Msr},
+    VirtAddr,
+};
+
+use crate::{
+    cpuid::{has_feature, Feature},
+    memory_manager::frame_manager_instance,
+    status::StatusCode,
+};
 
 const PAGE_DIRECTORY_COUNT: usize = 64;
 const PAGE_SIZE_4K: usize = 4096;
 const PAGE_SIZE_2M: usize = 512 * PAGE_SIZE_4K;
 const PAGE_SIZE_1G: usize = 512 * PAGE_SIZE_2M;
 
+const PAGE_PRESENT: u64 = 0x001;
+const PAGE_WRITABLE: u64 = 0x002;
+const PAGE_USER: u64 = 0x004;
+const PAGE_WRITE_THROUGH: u64 = 0x008;
+const PAGE_CACHE_DISABLE: u64 = 0x010;
+const PAGE_HUGE: u64 = 0x080;
+const PAGE_ADDR_MASK: u64 = 0x000f_ffff_ffff_f000;
+const PAGE_NO_EXECUTE: u64 = 1 << 63;
+
+const IA32_PAT: u32 = 0x277;
+// memory types of the PAT entries
+const PAT_UC: u64 = 0x00;
+const PAT_WC: u64 = 0x01;
+const PAT_WB: u64 = 0x06;
+const PAT_UC_MINUS: u64 = 0x07;
+// the power-on default except that the entry 1 is write-combining instead of write-through.
+// the entries 0-3 are selected by PWT and PCD only, so the same flags work for every page size
+// though the PAT bit of the huge pages is at another place
+const PAT_LOW: u64 = PAT_WB | PAT_WC << 8 | PAT_UC_MINUS << 16 | PAT_UC << 24;
+const PAT_VALUE: u64 = PAT_LOW | PAT_LOW << 32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CacheType {
+    WriteBack,
+    WriteCombining,
+    UncachedMinus,
+    Uncached,
+}
+
+impl CacheType {
+    // PWT and PCD of the page entry, which select the PAT entry of the type
+    fn page_flags(&self) -> u64 {
+        return match self {
+            CacheType::WriteBack => 0,
+            CacheType::WriteCombining => PAGE_WRITE_THROUGH,
+            CacheType::UncachedMinus => PAGE_CACHE_DISABLE,
+            CacheType::Uncached => PAGE_CACHE_DISABLE | PAGE_WRITE_THROUGH,
+        };
+    }
+}
+
 #[repr(align(4096))]
 #[derive(Clone, Copy)]
 struct PageTable {
@@ -42,7 +95,20 @@ static mut PAGE_DIRECTORY: [PageTable; PAGE_DIRECTORY_COUNT] =
     [PageTable::new(); PAGE_DIRECTORY_COUNT];
 
 pub unsafe fn initialize() {
+    // NO_EXECUTE bit in page entries is reserved unless EFER.NXE is set
+    if has_feature(Feature::NX) {
+        Efer::update(|flags| flags.insert(EferFlags::NO_EXECUTE_ENABLE));
+    }
+    load_pat();
+
     PML4_TABLE[0].write(&PDP_TABLE[0] as *const MaybeUninit<u64> as u64 | 0x003);
+    if has_feature(Feature::Page1GB) {
+        for i_pdpt in 0..PAGE_DIRECTORY_COUNT {
+            PDP_TABLE[i_pdpt].write((i_pdpt * PAGE_SIZE_1G) as u64 | 0x083);
+        }
+        set_cr3(&PML4_TABLE[0] as *const MaybeUninit<u64> as u64);
+        return;
+    }
     for i_pdpt in 0..PAGE_DIRECTORY_COUNT {
         PDP_TABLE[i_pdpt].write(&PAGE_DIRECTORY[i_pdpt] as *const PageTable as u64 | 0x003);
         for i_pd in 0..512 {
@@ -53,6 +119,146 @@ pub unsafe fn initialize() {
     set_cr3(&PML4_TABLE[0] as *const MaybeUninit<u64> as u64);
 }
 
+// PAT is per CPU, so the application processors have to load it too
+pub fn load_pat() {
+    if has_feature(Feature::PAT) {
+        unsafe { Msr::new(IA32_PAT).write(PAT_VALUE) };
+    }
+}
+
+// the process manager and the kernel heap are only guaranteed to be mapped in the kernel page table
+pub unsafe fn load_kernel_page_table() {
+    set_cr3(&PML4_TABLE[0] as *const MaybeUninit<u64> as u64);
+}
+
+// replace a huge page entry with a table of the smaller pages which map the same range
+unsafe fn split_huge_page(entry: &mut MaybeUninit<u64>, page_size: usize) -> Result<(), StatusCode> {
+    let value = entry.assume_init();
+    if value & PAGE_HUGE == 0 {
+        return Ok(());
+    }
+    let frame = frame_manager_instance().allocate(1)?;
+    let table = &mut *(frame.phys_addr() as *mut PageTable);
+    let base = value & PAGE_ADDR_MASK;
+    let sub_page_size = page_size / 512;
+    // bit 7 means PAT in the 4KiB page entries
+    let flags = if sub_page_size == PAGE_SIZE_4K {
+        value & !PAGE_ADDR_MASK & !PAGE_HUGE
+    } else {
+        value & !PAGE_ADDR_MASK
+    };
+    for i in 0..512 {
+        table[i].write((base + (i * sub_page_size) as u64) | flags);
+    }
+    entry.write(frame.phys_addr() as u64 | 0x003);
+    return Ok(());
+}
+
+// find the 2MiB page directory entry for the address, splitting the 1GiB page on the way
+unsafe fn page_entry_2m(addr: u64) -> Result<&'static mut MaybeUninit<u64>, StatusCode> {
+    let i_pdpt = addr as usize / PAGE_SIZE_1G;
+    if i_pdpt >= PAGE_DIRECTORY_COUNT {
+        return Err(StatusCode::IndexOutOfRange);
+    }
+    split_huge_page(&mut PDP_TABLE[i_pdpt], PAGE_SIZE_1G)?;
+    let pd = &mut *((PDP_TABLE[i_pdpt].assume_init() & PAGE_ADDR_MASK) as *mut PageTable);
+    let i_pd = addr as usize % PAGE_SIZE_1G / PAGE_SIZE_2M;
+    return Ok(&mut pd[i_pd]);
+}
+
+// find the 4KiB page entry for the address, splitting the huge pages on the way
+unsafe fn page_entry_4k(addr: u64) -> Result<&'static mut MaybeUninit<u64>, StatusCode> {
+    let pd_entry = page_entry_2m(addr)?;
+    split_huge_page(pd_entry, PAGE_SIZE_2M)?;
+    let pt = &mut *((pd_entry.assume_init() & PAGE_ADDR_MASK) as *mut PageTable);
+    let i_pt = addr as usize % PAGE_SIZE_2M / PAGE_SIZE_4K;
+    return Ok(&mut pt[i_pt]);
+}
+
+// make the 4KiB page at the address inaccessible, e.g. for a stack guard page
+pub unsafe fn unmap_page(addr: u64) -> Result<(), StatusCode> {
+    let entry = page_entry_4k(addr)?;
+    entry.write(entry.assume_init() & !PAGE_PRESENT);
+    tlb::flush(VirtAddr::new(addr));
+    return Ok(());
+}
+
+// restore the identity mapping of the page removed by unmap_page
+pub unsafe fn remap_page(addr: u64) -> Result<(), StatusCode> {
+    let entry = page_entry_4k(addr)?;
+    entry.write(entry.assume_init() | PAGE_PRESENT);
+    tlb::flush(VirtAddr::new(addr));
+    return Ok(());
+}
+
+// map the identity mapped range again with the cache type.
+// the 2MiB pages are kept where the range covers the whole page
+pub unsafe fn map_range(start: u64, bytes: usize, cache: CacheType) -> Result<(), StatusCode> {
+    // without PAT, the flags of write-combining select write-through
+    if matches!(cache, CacheType::WriteCombining) && !has_feature(Feature::PAT) {
+        return Err(StatusCode::NoPAT);
+    }
+    let end = start + bytes as u64;
+    let mut addr = start & !(PAGE_SIZE_4K as u64 - 1);
+    while addr < end {
+        let pd_entry = page_entry_2m(addr)?;
+        let covered = addr % PAGE_SIZE_2M as u64 == 0 && addr + PAGE_SIZE_2M as u64 <= end;
+        let (entry, page_size) = if covered && pd_entry.assume_init() & PAGE_HUGE != 0 {
+            (pd_entry, PAGE_SIZE_2M)
+        } else {
+            (page_entry_4k(addr)?, PAGE_SIZE_4K)
+        };
+        let flags = entry.assume_init() & !(PAGE_WRITE_THROUGH | PAGE_CACHE_DISABLE);
+        entry.write(flags | cache.page_flags());
+        addr += page_size as u64;
+    }
+    // no line may stay in the cache with the old type
+    asm!("wbinvd");
+    tlb::flush_all();
+    return Ok(());
+}
+
+// allow or forbid ring 3 to access the 4KiB page at the address.
+// the upper level entries are left user accessible, the page entry decides the access.
+// the page is made present, writable and executable again when it's forbidden
+pub unsafe fn set_user_accessible(addr: u64, user: bool) -> Result<(), StatusCode> {
+    let entry = page_entry_4k(addr)?;
+    if user {
+        let pml4 = PML4_TABLE[0].assume_init();
+        PML4_TABLE[0].write(pml4 | PAGE_USER);
+        let i_pdpt = addr as usize / PAGE_SIZE_1G;
+        let pdpt = PDP_TABLE[i_pdpt].assume_init();
+        PDP_TABLE[i_pdpt].write(pdpt | PAGE_USER);
+        let pd = &mut *((pdpt & PAGE_ADDR_MASK) as *mut PageTable);
+        let i_pd = addr as usize % PAGE_SIZE_1G / PAGE_SIZE_2M;
+        let pde = pd[i_pd].assume_init();
+        pd[i_pd].write(pde | PAGE_USER);
+        entry.write(entry.assume_init() | PAGE_USER);
+    } else {
+        let flags = entry.assume_init() & !(PAGE_USER | PAGE_NO_EXECUTE);
+        entry.write(flags | PAGE_PRESENT | PAGE_WRITABLE);
+    }
+    tlb::flush(VirtAddr::new(addr));
+    return Ok(());
+}
+
+// restrict the access to the 4KiB page at the address.
+// the kernel can't write to the page either while the write protection of CR0 is enabled
+pub unsafe fn set_page_permissions(addr: u64, writable: bool, executable: bool) -> Result<(), StatusCode> {
+    let entry = page_entry_4k(addr)?;
+    let mut flags = entry.assume_init() & !(PAGE_WRITABLE | PAGE_NO_EXECUTE);
+    if writable {
+        flags |= PAGE_WRITABLE;
+    }
+    // the bit is reserved unless EFER.NXE is set
+    if !executable && has_feature(Feature::NX) {
+        flags |= PAGE_NO_EXECUTE;
+    }
+    entry.write(flags);
+    tlb::flush(VirtAddr::new(addr));
+    return Ok(());
+}
+
 //assembly function in asm.s
 extern "C" {
     fn set_cr3(value: u64);
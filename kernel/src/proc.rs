@@ -2,9 +2,10 @@ use alloc::sync::Arc;
 use alloc::{
     vec,
     collections::VecDeque,
+    string::String,
     vec::Vec,
 };
-use core::{arch::asm, mem::size_of};
+use core::{arch::asm, mem::take, ops::Range, ptr::write_bytes};
 use core::cmp::{Ord, Ordering};
 use spin::{
     Mutex,
@@ -12,11 +13,29 @@ use spin::{
 };
 use core::cell::{Cell, RefCell, Ref};
 
-use crate::{drivers::timer::TIMER_MANAGER, segment::{KERNEL_CS, KERNEL_SS}};
+use x86_64::instructions::interrupts::{self, without_interrupts};
+
+use crate::{
+    console::active_terminal,
+    exec::LazyImage,
+    horse_lib::fd::ProcessFds,
+    drivers::timer::{ticks_to_duration, TIMER_MANAGER},
+    memory_manager::{frame_manager_instance, FrameID, BYTES_PER_FRAME},
+    paging::{remap_page, set_user_accessible, unmap_page},
+    segment::{KERNEL_CS, KERNEL_SS, USER_CS, USER_SS},
+    shm::SharedMemory,
+    status::StatusCode,
+    syscall::{close_files, set_syscall_stack, SyscallFrame},
+};
 
 const DEFAULT_CONTEXT: ContextWrapper = ContextWrapper(ProcessContext { cr3: 0, rip: 0, rflags: 0, reserved1: 0, cs: 0, ss: 0, fs: 0, gs: 0, rax: 0, rbx: 0, rcx: 0, rdx: 0, rdi: 0, rsi: 0, rsp: 0, rbp: 0, r8: 0, r9: 0, r10: 0, r11: 0, r12: 0, r13: 0, r14: 0, r15: 0, fxsave_area: [0; 512] });
 pub static mut PROCESS_MANAGER: Once<ProcessManager> = Once::new();
 
+// the task which runs kernel_main_virt. it can't be terminated
+pub const KERNEL_TASK_ID: usize = 1;
+pub const NSIG: usize = 32;
+pub const SIGCHLD: usize = 17;
+
 extern "C" {
     pub fn switch_context(next_ctx: u64, current_ctx: u64);
     pub fn get_cr3() -> u64;
@@ -72,7 +91,9 @@ pub struct ProcessContext {
 pub struct ProcessManager {
     latest_id: usize,
     run_queue: VecDeque<Arc<RefCell<Process>>>,
-    pending_queue: Vec<Arc<RefCell<Process>>>
+    pending_queue: Vec<Arc<RefCell<Process>>>,
+    // the stack of a terminated process may still be in use, so they are dropped later by reap_terminated
+    terminated: Vec<Arc<RefCell<Process>>>
 }
 
 impl ProcessManager {
@@ -81,30 +102,49 @@ impl ProcessManager {
             latest_id: 0,
             run_queue: VecDeque::new(),
             pending_queue: Vec::new(),
+            terminated: Vec::new(),
         };
         manager.new_proc();
         manager.id_wake_up(1);
         return manager
     }
+    // the run queue is also mutated by the timer interrupt, so every mutation has to be done with interrupts disabled
     pub fn new_proc(&mut self) -> Arc<RefCell<Process>> {
-        self.latest_id += 1;
-        let proc = Arc::new(RefCell::new(Process::new(self.latest_id)));
-        self.pending_queue.push(proc.clone());
-        return proc
+        return without_interrupts(|| {
+            self.latest_id += 1;
+            let mut proc = Process::new(self.latest_id);
+            // the creator is the parent and shares its terminal, working directory and limits with the child
+            let parent = self.run_queue.front().and_then(|x| x.try_borrow().ok().map(|x| (x.id, x.terminal, x.cwd.clone(), x.limits)));
+            if let Some((parent, terminal, cwd, limits)) = parent {
+                proc.parent = parent;
+                proc.terminal = terminal;
+                proc.cwd = cwd;
+                proc.limits = limits;
+            }
+            let proc = Arc::new(RefCell::new(proc));
+            self.pending_queue.push(proc.clone());
+            proc
+        })
     }
     pub fn wake_up(&mut self, proc: Arc<RefCell<Process>>) {
-        if let Some(idx) = self.pending_queue.iter().position(|x| *x == proc) {
-            self.run_queue.push_back(proc);
-            self.pending_queue.remove(idx);
-        }
+        without_interrupts(|| {
+            if let Some(idx) = self.pending_queue.iter().position(|x| *x == proc) {
+                self.run_queue.push_back(proc);
+                self.pending_queue.remove(idx);
+            }
+        })
     }
     pub fn id_wake_up(&mut self, id: usize) {
-        if let Some(idx) = self.pending_queue.iter().position(|x| x.borrow().id() == id) {
-            self.run_queue.push_back(self.pending_queue[idx].clone());
-            self.pending_queue.remove(idx);
-        }
+        without_interrupts(|| {
+            if let Some(idx) = self.pending_queue.iter().position(|x| x.borrow().id() == id) {
+                self.run_queue.push_back(self.pending_queue[idx].clone());
+                self.pending_queue.remove(idx);
+            }
+        })
     }
     pub fn sleep(&mut self, proc: Arc<RefCell<Process>>) {
+        let was_enabled = interrupts::are_enabled();
+        interrupts::disable();
         if let Some(idx) = self.run_queue.iter().position(|x| *x == proc) {
             if idx == 0 {
                 self.switch_process(true);
@@ -113,57 +153,454 @@ impl ProcessManager {
                 self.pending_queue.push(proc);
             }
         }
+        if was_enabled {
+            interrupts::enable();
+        }
     }
     pub fn id_sleep(&mut self, id: usize) {
+        let was_enabled = interrupts::are_enabled();
+        interrupts::disable();
         if let Some(idx) = self.run_queue.iter().position(|x| x.borrow().id() == id) {
-            self.pending_queue.push(self.run_queue[idx].clone());
-            self.run_queue.remove(idx);
             if idx == 0 {
                 self.switch_process(true);
+            } else {
+                self.pending_queue.push(self.run_queue[idx].clone());
+                self.run_queue.remove(idx);
             }
         }
+        if was_enabled {
+            interrupts::enable();
+        }
     }
-    pub fn switch_process(&mut self, sleep: bool) {
+    // the children which aren't terminated yet
+    pub fn count_children(&self, id: usize) -> usize {
+        return without_interrupts(|| {
+            self.run_queue.iter().chain(self.pending_queue.iter()).filter(|x| x.borrow().parent == id).count()
+        })
+    }
+    pub fn is_alive(&self, id: usize) -> bool {
+        return without_interrupts(|| {
+            self.run_queue.iter().chain(self.pending_queue.iter()).any(|x| x.borrow().id() == id)
+        })
+    }
+    // sleep the current process until the process is terminated.
+    // only the parent is woken up by the termination, so the process should be a child
+    pub fn wait_terminated(&mut self, id: usize) {
+        let current = self.current().borrow().id();
+        loop {
+            // checked with interrupts disabled, so the termination can't come before sleeping
+            let alive = without_interrupts(|| {
+                let alive = self.is_alive(id);
+                if alive {
+                    self.id_sleep(current);
+                }
+                alive
+            });
+            if !alive {
+                return
+            }
+        }
+    }
+    // the parent gets SIGCHLD and is woken up to notice the termination
+    fn notify_parent(&mut self, proc: &Arc<RefCell<Process>>) {
+        let parent = proc.borrow().parent;
+        if parent != 0 {
+            self.send_signal(parent, SIGCHLD);
+            self.id_wake_up(parent);
+        }
+    }
+    // remove the process from the queues. this never returns when the process is the current one
+    pub fn prepare_terminate(&mut self, id: usize) {
+        assert!(id != KERNEL_TASK_ID, "the kernel task can't be terminated");
+        let was_enabled = interrupts::are_enabled();
+        interrupts::disable();
+        if let Some(idx) = self.pending_queue.iter().position(|x| x.borrow().id() == id) {
+            let proc = self.pending_queue.remove(idx);
+            self.notify_parent(&proc);
+            self.terminated.push(proc);
+        } else if let Some(idx) = self.run_queue.iter().position(|x| x.borrow().id() == id) {
+            let proc = self.run_queue.remove(idx).unwrap();
+            self.notify_parent(&proc);
+            self.terminated.push(proc);
+            if idx == 0 {
+                // the context of the terminated process is never restored
+                let mut scratch = DEFAULT_CONTEXT;
+                let next_ptr = {
+                    let mut next_proc = self.run_queue.front_mut().unwrap().borrow_mut();
+                    if let Some(stack) = next_proc.stack.as_ref() {
+                        set_syscall_stack(stack.end());
+                    }
+                    next_proc.context().as_ptr()
+                };
+                unsafe { switch_context(next_ptr, scratch.as_ptr()) }
+                unreachable!()
+            }
+        }
+        if was_enabled {
+            interrupts::enable();
+        }
+    }
+    // the fds are closed here rather than on the termination, because closing may write to the disk
+    pub fn reap_terminated(&mut self) {
+        if self.terminated.is_empty() {
+            return
+        }
+        let terminated = without_interrupts(|| take(&mut self.terminated));
+        for proc in &terminated {
+            let files = proc.borrow_mut().fds_mut().take_all();
+            close_files(&files);
+        }
+        without_interrupts(|| drop(terminated))
+    }
+    // rotate the run queue and return the contexts to switch (next, current)
+    fn next_context(&mut self, sleep: bool) -> (u64, u64) {
         let current_proc = self.run_queue.pop_front().unwrap();
         let current_proc_ptr = current_proc.borrow_mut().context().as_ptr();
-        if !sleep {
-            self.run_queue.push_back(current_proc)
+        if sleep {
+            self.pending_queue.push(current_proc);
+        } else {
+            self.run_queue.push_back(current_proc);
+        }
+        let mut next_proc = self.run_queue.front_mut().unwrap().borrow_mut();
+        if let Some(stack) = next_proc.stack.as_ref() {
+            set_syscall_stack(stack.end());
+        }
+        let next_proc_ptr = next_proc.context().as_ptr();
+        return (next_proc_ptr, current_proc_ptr)
+    }
+    // charge the tick to the running process. called from the timer interrupt,
+    // so the time spent in interrupt handlers is also charged to the interrupted process.
+    // a tick is a second (TICKS_PER_SECOND), so the cpu time is sampled once a second:
+    // only the process running at the tick is charged, however long the others ran before it
+    pub fn charge_tick(&self) {
+        if let Some(proc) = self.run_queue.front() {
+            // the process may be borrowed by the interrupted code
+            if let Ok(mut proc) = proc.try_borrow_mut() {
+                proc.cpu_ticks += 1;
+            }
+        }
+    }
+    pub fn current(&self) -> Arc<RefCell<Process>> {
+        return self.run_queue.front().unwrap().clone()
+    }
+    // snapshot of every process. the running one comes first
+    pub fn processes(&self) -> Vec<ProcessInfo> {
+        return without_interrupts(|| {
+            let running = self.run_queue.iter().map(|x| (x, true));
+            let sleeping = self.pending_queue.iter().map(|x| (x, false));
+            running
+                .chain(sleeping)
+                .map(|(x, running)| {
+                    let proc = x.borrow();
+                    ProcessInfo {
+                        id: proc.id,
+                        parent: proc.parent,
+                        running,
+                        cpu_ticks: proc.cpu_ticks,
+                        terminal: proc.terminal,
+                    }
+                })
+                .collect()
+        })
+    }
+    pub fn send_signal(&mut self, id: usize, signal: usize) {
+        without_interrupts(|| {
+            if let Some(proc) = self.run_queue.iter().chain(self.pending_queue.iter()).find(|x| x.borrow().id() == id) {
+                proc.borrow_mut().pending_signals |= 1 << signal;
+            }
+        })
+    }
+    pub fn switch_process(&mut self, sleep: bool) {
+        // interrupts stay disabled until switch_context restores RFLAGS of the next process,
+        // so the timer can't switch again while the queue and contexts are half updated
+        let was_enabled = interrupts::are_enabled();
+        interrupts::disable();
+        let (next_ptr, current_ptr) = self.next_context(sleep);
+        unsafe { switch_context(next_ptr, current_ptr) }
+        if was_enabled {
+            interrupts::enable();
         }
-        let next_proc = self.run_queue.front_mut().unwrap();
+    }
+}
+
+// stack on its own frames with an unmapped guard page below it
+#[derive(Eq, PartialEq)]
+struct ProcessStack {
+    start: FrameID,
+    n_frames: usize,
+}
+
+impl ProcessStack {
+    const GUARD_FRAMES: usize = 1;
+
+    fn new(bytes: usize) -> Self {
+        let n_frames = (bytes + BYTES_PER_FRAME - 1) / BYTES_PER_FRAME + Self::GUARD_FRAMES;
+        let start = frame_manager_instance().allocate(n_frames).expect("no memory for the process stack");
+        unsafe { unmap_page(start.phys_addr() as u64).expect("failed to unmap the stack guard page") };
+        return Self { start, n_frames }
+    }
+    fn guard(&self) -> Range<u64> {
+        let start = self.start.phys_addr() as u64;
+        return start..start + (Self::GUARD_FRAMES * BYTES_PER_FRAME) as u64
+    }
+    fn end(&self) -> u64 {
+        return self.start.phys_addr() as u64 + (self.n_frames * BYTES_PER_FRAME) as u64
+    }
+}
 
-        unsafe { switch_context(next_proc.borrow_mut().context().as_ptr(), current_proc_ptr) }
+impl Drop for ProcessStack {
+    fn drop(&mut self) {
+        unsafe { remap_page(self.start.phys_addr() as u64).unwrap() };
+        frame_manager_instance().free(self.start, self.n_frames);
+    }
+}
+
+// memory which ring 3 can access. it's identity mapped as the rest of the memory
+#[derive(Eq, PartialEq)]
+pub struct UserMemory {
+    start: FrameID,
+    n_frames: usize,
+}
+
+impl UserMemory {
+    // zero filled
+    pub fn new(bytes: usize) -> Result<Self, StatusCode> {
+        let memory = Self::allocate(bytes)?;
+        unsafe { write_bytes(memory.start.phys_addr(), 0, memory.n_frames * BYTES_PER_FRAME) };
+        return Ok(memory)
+    }
+    // the pages aren't present until they are mapped by remap_page
+    pub fn reserve(bytes: usize) -> Result<Self, StatusCode> {
+        let memory = Self::allocate(bytes)?;
+        for page in memory.pages() {
+            unsafe { unmap_page(page)? };
+        }
+        return Ok(memory)
+    }
+    fn allocate(bytes: usize) -> Result<Self, StatusCode> {
+        let n_frames = (bytes + BYTES_PER_FRAME - 1) / BYTES_PER_FRAME;
+        let start = frame_manager_instance().allocate(n_frames)?;
+        let memory = Self { start, n_frames };
+        for page in memory.pages() {
+            unsafe { set_user_accessible(page, true)? };
+        }
+        return Ok(memory)
+    }
+    pub fn start(&self) -> u64 {
+        return self.start.phys_addr() as u64
+    }
+    pub fn end(&self) -> u64 {
+        return self.start() + (self.n_frames * BYTES_PER_FRAME) as u64
+    }
+    fn pages(&self) -> impl Iterator<Item = u64> {
+        return (self.start()..self.end()).step_by(BYTES_PER_FRAME)
+    }
+}
+
+impl Drop for UserMemory {
+    fn drop(&mut self) {
+        for page in self.pages() {
+            unsafe { set_user_accessible(page, false).unwrap() };
+        }
+        frame_manager_instance().free(self.start, self.n_frames);
+    }
+}
+
+// what ps shows
+pub struct ProcessInfo {
+    pub id: usize,
+    // 0 for the processes created by nobody
+    pub parent: usize,
+    // false while it's sleeping
+    pub running: bool,
+    pub cpu_ticks: u64,
+    pub terminal: usize,
+}
+
+pub const RLIMIT_NPROC: u64 = 6;
+pub const RLIMIT_NOFILE: u64 = 7;
+pub const RLIMIT_AS: u64 = 9;
+pub const RLIM_INFINITY: u64 = u64::MAX;
+
+// same layout as struct rlimit of Linux
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Rlimit {
+    pub cur: u64,
+    pub max: u64,
+}
+
+impl Rlimit {
+    const fn new(limit: u64) -> Self {
+        return Self { cur: limit, max: limit }
+    }
+    pub fn allows(&self, amount: u64) -> bool {
+        return amount <= self.cur
+    }
+}
+
+// the limits are inherited by the children, so they also bound the processes created by them
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub struct ResourceLimits {
+    // bytes of the user memory of a program, which is allocated when it's spawned,
+    // and of the shared memory mapped by it
+    pub memory: Rlimit,
+    pub open_files: Rlimit,
+    // the children which aren't terminated yet
+    pub children: Rlimit,
+}
+
+impl ResourceLimits {
+    const DEFAULT: Self = Self {
+        memory: Rlimit::new(256 * 1024 * 1024),
+        open_files: Rlimit::new(64),
+        children: Rlimit::new(32),
+    };
+    // the limit by RLIMIT_*. None for the resources which aren't limited
+    pub fn get_mut(&mut self, resource: u64) -> Option<&mut Rlimit> {
+        return match resource {
+            RLIMIT_AS => Some(&mut self.memory),
+            RLIMIT_NOFILE => Some(&mut self.open_files),
+            RLIMIT_NPROC => Some(&mut self.children),
+            _ => None
+        }
     }
 }
 
 #[derive(Eq, PartialEq)]
 pub struct Process {
     id: usize,
-    stack: Vec<u64>,
-    context: ContextWrapper
+    // 0 for the processes created by nobody
+    parent: usize,
+    // None for the kernel task, which runs on the boot stack
+    stack: Option<ProcessStack>,
+    // the program image and the stack of a process running in ring 3
+    user_memory: Vec<UserMemory>,
+    // the segments which aren't loaded yet. None when the whole program is loaded
+    image: Option<LazyImage>,
+    context: ContextWrapper,
+    pending_signals: u32,
+    signal_actions: [SignalAction; NSIG],
+    // the registers of the user program while a signal handler runs
+    signal_context: Option<SignalContext>,
+    cpu_ticks: u64,
+    // the virtual terminal for stdio. it's the one of the parent, or the active one when there's no parent
+    terminal: usize,
+    // the working directory, which is absolute and has no "." or ".."
+    cwd: String,
+    limits: ResourceLimits,
+    fds: ProcessFds,
+    // the shared memory mapped by the process. it's unmapped when the process is dropped
+    shared_memory: Vec<Arc<SharedMemory>>,
+    // set while a kernel task calls a syscall with pointers to its own memory, see syscall::kernel_dispatch
+    kernel_pointers: bool
 }
 
 impl Process {
-    const DEFAULT_STACK_BYTES: usize = 4096;
+    // the kernel tasks format strings and read files on it
+    const DEFAULT_STACK_BYTES: usize = 16 * 1024;
     pub fn new(id: usize) -> Self {
         return Self {
             id,
-            stack: Vec::new(),
-            context: DEFAULT_CONTEXT
+            parent: 0,
+            stack: None,
+            user_memory: Vec::new(),
+            image: None,
+            context: DEFAULT_CONTEXT,
+            pending_signals: 0,
+            signal_actions: [SignalAction::DEFAULT; NSIG],
+            signal_context: None,
+            cpu_ticks: 0,
+            terminal: active_terminal(),
+            cwd: String::from("/"),
+            limits: ResourceLimits::DEFAULT,
+            fds: ProcessFds::with_stdio(),
+            shared_memory: Vec::new(),
+            kernel_pointers: false
         }
     }
     pub fn id(&self) -> usize { self.id }
+    pub fn terminal(&self) -> usize { self.terminal }
+    pub fn cwd(&self) -> &str { &self.cwd }
+    pub fn set_cwd(&mut self, cwd: String) { self.cwd = cwd }
+    pub fn limits(&self) -> &ResourceLimits { &self.limits }
+    pub fn limits_mut(&mut self) -> &mut ResourceLimits { &mut self.limits }
+    pub fn fds(&self) -> &ProcessFds { &self.fds }
+    pub fn fds_mut(&mut self) -> &mut ProcessFds { &mut self.fds }
+    // returns the address. mapping the same segment again doesn't add a reference
+    pub fn map_shared(&mut self, segment: Arc<SharedMemory>) -> u64 {
+        let addr = segment.addr();
+        if !self.shared_memory.iter().any(|mapped| mapped.id() == segment.id()) {
+            self.shared_memory.push(segment);
+        }
+        return addr
+    }
+    // returns false when the segment isn't mapped by the process
+    pub fn unmap_shared(&mut self, id: usize) -> bool {
+        let idx = match self.shared_memory.iter().position(|mapped| mapped.id() == id) {
+            Some(idx) => idx,
+            None => return false
+        };
+        self.shared_memory.remove(idx);
+        return true
+    }
+    // the program, its stack and the shared memory, which RLIMIT_AS limits
+    pub fn memory_bytes(&self) -> u64 {
+        let own: u64 = self.user_memory.iter().map(|memory| memory.end() - memory.start()).sum();
+        let shared: u64 = self.shared_memory.iter().map(|segment| segment.size() as u64).sum();
+        return own + shared
+    }
+    pub fn kernel_pointers(&self) -> bool { self.kernel_pointers }
+    pub fn set_kernel_pointers(&mut self, kernel_pointers: bool) { self.kernel_pointers = kernel_pointers }
+    // the end of the block of the user memory or the shared memory which has the address.
+    // None when the process can't access the address
+    pub fn user_range_end(&self, addr: u64) -> Option<u64> {
+        let image = self.user_memory.iter().map(|memory| (memory.start(), memory.end()));
+        let shared = self.shared_memory.iter().map(|segment| (segment.addr(), segment.addr() + segment.size() as u64));
+        return image.chain(shared).find(|(start, end)| (*start..*end).contains(&addr)).map(|(_, end)| end)
+    }
+    // whether the process can access [start, end)
+    pub fn is_user_range(&self, start: u64, end: u64) -> bool {
+        return self.user_range_end(start).map_or(false, |range_end| end <= range_end)
+    }
+    // whether the address is in the guard page below the stack
+    pub fn is_stack_guard(&self, addr: u64) -> bool {
+        return self.stack.as_ref().map_or(false, |stack| stack.guard().contains(&addr))
+    }
+    // load the page of the program at the address on the first access.
+    // returns false when the address isn't in the pages to be loaded
+    pub fn fault_in(&self, addr: u64) -> bool {
+        return self.image.as_ref().map_or(false, |image| image.fault_in(addr))
+    }
+    // timer ticks while this process was running
+    pub fn cpu_ticks(&self) -> u64 { self.cpu_ticks }
     pub fn init_context(&mut self, f: fn()) {
-        let stack_size = Self::DEFAULT_STACK_BYTES / size_of::<u64>();
-        self.stack.resize(stack_size, 0);
-        let stack_end = self.stack.as_ptr() as u64 + stack_size as u64;
-
+        let stack_end = self.allocate_stack();
+        self.init_registers(KERNEL_CS, KERNEL_SS, f as *const () as u64, stack_end);
+    }
+    // run the program in ring 3. the kernel stack is used by the syscalls and the interrupts,
+    // and the memory is freed when the process is dropped
+    pub fn init_user_context(&mut self, entry: u64, user_stack_end: u64, memory: Vec<UserMemory>, image: Option<LazyImage>) {
+        self.allocate_stack();
+        self.user_memory = memory;
+        self.image = image;
+        self.init_registers(USER_CS, USER_SS, entry, user_stack_end);
+    }
+    // returns the end of the stack
+    fn allocate_stack(&mut self) -> u64 {
+        let stack = ProcessStack::new(Self::DEFAULT_STACK_BYTES);
+        let stack_end = stack.end();
+        self.stack = Some(stack);
+        return stack_end
+    }
+    fn init_registers(&mut self, cs: u16, ss: u16, rip: u64, stack_end: u64) {
         let ctx = self.context.unwrap();
         ctx.cr3 = unsafe { get_cr3() };
         ctx.rflags = 0x202;
-        ctx.cs = KERNEL_CS as u64;
-        ctx.ss = KERNEL_SS as u64;
+        ctx.cs = cs as u64;
+        ctx.ss = ss as u64;
         ctx.rsp = (stack_end & !0xfu64) - 8;
-        ctx.rip = f as *const () as u64;
+        ctx.rip = rip;
         
         ctx.fxsave_area[25] = 0x8;
         ctx.fxsave_area[26] = 0xf;
@@ -172,6 +609,51 @@ impl Process {
     pub fn context(&mut self) -> &mut ContextWrapper {
         return &mut self.context
     }
+    pub fn set_signal_action(&mut self, signal: usize, action: SignalAction) {
+        self.signal_actions[signal] = action;
+    }
+    // take a pending signal which has a handler. the others are discarded.
+    // None while a handler is running, so the handlers aren't nested
+    pub fn take_signal(&mut self) -> Option<(usize, SignalAction)> {
+        if self.signal_context.is_some() {
+            return None
+        }
+        while self.pending_signals != 0 {
+            let signal = self.pending_signals.trailing_zeros() as usize;
+            self.pending_signals &= !(1 << signal);
+            let action = self.signal_actions[signal];
+            if action.handler != 0 {
+                return Some((signal, action))
+            }
+        }
+        return None
+    }
+    pub fn enter_signal_handler(&mut self, context: SignalContext) {
+        self.signal_context = Some(context);
+    }
+    // None when no handler is running
+    pub fn leave_signal_handler(&mut self) -> Option<SignalContext> {
+        return self.signal_context.take()
+    }
+}
+
+// the handler is called with the signal number on the user stack, and returns into the restorer,
+// which has to call sigreturn. 0 for the handler ignores the signal
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub struct SignalAction {
+    pub handler: u64,
+    pub restorer: u64,
+}
+
+impl SignalAction {
+    pub const DEFAULT: Self = Self { handler: 0, restorer: 0 };
+}
+
+// the user registers and the return value of the syscall which the signal interrupted
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub struct SignalContext {
+    pub registers: SyscallFrame,
+    pub ret: isize,
 }
 
 pub fn initialize_process_manager() {
@@ -179,15 +661,61 @@ pub fn initialize_process_manager() {
 
     unsafe {
         asm!("cli");
-        TIMER_MANAGER.lock().get_mut().unwrap().add_timer(2, -1, true);
+        TIMER_MANAGER.lock().get_mut().unwrap().add_timer(ticks_to_duration(2), -1, true);
         asm!("sti");
     }
 }
 
+// recurse until the stack reaches the guard page, which terminates the task. run by the overflow command
+pub fn task_stack_overflow() {
+    fn recurse(depth: u64) -> u64 {
+        let frame = [depth; 16];
+        return core::hint::black_box(recurse(depth + 1)) + frame[(depth % 16) as usize]
+    }
+    recurse(0);
+}
+
 pub fn taskb() {
     let mut count: u64 = 0;
     loop {
         crate::println!("TaskB is running! - count: {}", count);
         count += 1;
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manager(running: &Arc<RefCell<Process>>, sleeping: &Arc<RefCell<Process>>) -> ProcessManager {
+        return ProcessManager {
+            latest_id: 2,
+            run_queue: VecDeque::from([running.clone()]),
+            pending_queue: vec![sleeping.clone()],
+            terminated: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn only_the_running_process_is_charged() {
+        let running = Arc::new(RefCell::new(Process::new(1)));
+        let sleeping = Arc::new(RefCell::new(Process::new(2)));
+        let manager = manager(&running, &sleeping);
+        for _ in 0..3 {
+            manager.charge_tick();
+        }
+        assert_eq!(running.borrow().cpu_ticks(), 3);
+        assert_eq!(sleeping.borrow().cpu_ticks(), 0);
+    }
+
+    #[test]
+    fn borrowed_process_is_not_charged() {
+        let running = Arc::new(RefCell::new(Process::new(1)));
+        let sleeping = Arc::new(RefCell::new(Process::new(2)));
+        let manager = manager(&running, &sleeping);
+        let borrowed = running.borrow();
+        manager.charge_tick();
+        drop(borrowed);
+        assert_eq!(running.borrow().cpu_ticks(), 0);
+    }
+}
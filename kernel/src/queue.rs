@@ -1,5 +1,10 @@
 use crate::StatusCode;
-use core::{marker::Copy, mem::MaybeUninit};
+use core::{
+    cell::UnsafeCell,
+    marker::Copy,
+    mem::MaybeUninit,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
 #[derive(Debug)]
 pub struct ArrayQueue<T, const N: usize> {
@@ -52,3 +57,161 @@ impl<T: Copy, const N: usize> ArrayQueue<T, N> {
         return Ok(value);
     }
 }
+
+// lock-free ring buffer for a single producer and a single consumer.
+// head and tail count up forever and wrap around at usize::MAX, so N must be a power of 2.
+// only the producer writes tail and only the consumer writes head
+pub struct SpscQueue<T, const N: usize> {
+    data: UnsafeCell<[MaybeUninit<T>; N]>,
+    // the position to pop next
+    head: AtomicUsize,
+    // the position to push next
+    tail: AtomicUsize,
+}
+
+// the slots between head and tail are owned by the consumer and the others by the producer
+unsafe impl<T: Send, const N: usize> Sync for SpscQueue<T, N> {}
+
+impl<T, const N: usize> SpscQueue<T, N> {
+    pub const fn new() -> Self {
+        assert!(N.is_power_of_two());
+        return Self {
+            data: UnsafeCell::new([const { MaybeUninit::uninit() }; N]),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        };
+    }
+
+    // must not be called from two contexts at the same time
+    pub fn push(&self, value: T) -> StatusCode {
+        let tail = self.tail.load(Ordering::Relaxed);
+        // the slot freed by pop must not be overwritten before its value is read
+        let head = self.head.load(Ordering::Acquire);
+        if tail.wrapping_sub(head) == N {
+            return StatusCode::Full;
+        }
+        unsafe {
+            (*self.data.get())[tail % N].write(value);
+        }
+        // publish the value written above
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+        return StatusCode::Success;
+    }
+
+    // must not be called from two contexts at the same time
+    pub fn pop(&self) -> Option<T> {
+        let head = self.head.load(Ordering::Relaxed);
+        let tail = self.tail.load(Ordering::Acquire);
+        if head == tail {
+            return None;
+        }
+        let value = unsafe { (*self.data.get())[head % N].assume_init_read() };
+        self.head.store(head.wrapping_add(1), Ordering::Release);
+        return Some(value);
+    }
+
+    pub fn len(&self) -> usize {
+        let tail = self.tail.load(Ordering::Acquire);
+        let head = self.head.load(Ordering::Acquire);
+        return tail.wrapping_sub(head);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.len() == 0;
+    }
+}
+
+impl<T, const N: usize> Drop for SpscQueue<T, N> {
+    fn drop(&mut self) {
+        while self.pop().is_some() {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use alloc::{rc::Rc, vec::Vec};
+    use std::{sync::Arc, thread};
+
+    #[test]
+    fn full_queue_rejects_push() {
+        let queue = SpscQueue::<u32, 4>::new();
+        for i in 0..4 {
+            assert!(matches!(queue.push(i), StatusCode::Success));
+        }
+        assert!(matches!(queue.push(4), StatusCode::Full));
+        assert_eq!(queue.len(), 4);
+        assert_eq!(queue.pop(), Some(0));
+        assert!(matches!(queue.push(4), StatusCode::Success));
+        assert_eq!((1..5).map(|_| queue.pop().unwrap()).collect::<Vec<_>>(), [1, 2, 3, 4]);
+        assert_eq!(queue.pop(), None);
+    }
+
+    #[test]
+    fn slots_wrap_around() {
+        let queue = SpscQueue::<u32, 4>::new();
+        for i in 0..10 {
+            assert!(matches!(queue.push(2 * i), StatusCode::Success));
+            assert!(matches!(queue.push(2 * i + 1), StatusCode::Success));
+            assert_eq!(queue.pop(), Some(2 * i));
+            assert_eq!(queue.pop(), Some(2 * i + 1));
+        }
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn counters_wrap_around() {
+        let queue = SpscQueue::<u32, 4>::new();
+        queue.head.store(usize::MAX - 1, Ordering::Relaxed);
+        queue.tail.store(usize::MAX - 1, Ordering::Relaxed);
+        for i in 0..4 {
+            assert!(matches!(queue.push(i), StatusCode::Success));
+        }
+        assert!(matches!(queue.push(4), StatusCode::Full));
+        assert_eq!(queue.len(), 4);
+        for i in 0..4 {
+            assert_eq!(queue.pop(), Some(i));
+        }
+        assert_eq!(queue.pop(), None);
+    }
+
+    #[test]
+    fn drop_releases_the_values() {
+        let value = Rc::new(());
+        let queue = SpscQueue::<Rc<()>, 4>::new();
+        queue.push(value.clone());
+        queue.push(value.clone());
+        drop(queue);
+        assert_eq!(Rc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn values_pass_between_threads_in_order() {
+        const COUNT: u32 = 100_000;
+        let queue = Arc::new(SpscQueue::<u32, 8>::new());
+        let producer = {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                for i in 0..COUNT {
+                    while let StatusCode::Full = queue.push(i) {
+                        thread::yield_now();
+                    }
+                }
+            })
+        };
+        let mut expected = 0;
+        while expected < COUNT {
+            match queue.pop() {
+                Some(value) => {
+                    assert_eq!(value, expected);
+                    expected += 1;
+                }
+                None => thread::yield_now(),
+            }
+        }
+        producer.join().unwrap();
+        assert!(queue.is_empty());
+    }
+}
@@ -1,8 +1,28 @@
-use crate::{bit_setter, trace};
+use crate::{bit_setter, fixed_vec::FixedVec, trace};
 
-use core::mem::size_of;
+use core::{mem::size_of, ptr::addr_of};
+use spin::Mutex;
+use x86_64::{
+    instructions::tables::load_tss,
+    structures::{gdt::SegmentSelector, tss::TaskStateSegment},
+    PrivilegeLevel, VirtAddr,
+};
 
-static mut GDT: [SegmentDescriptor; 3] = [SegmentDescriptor::new(); 3];
+// a TSS for each CPU. TSS descriptor occupies two entries
+const MAX_TSS_COUNT: usize = 16;
+// null, kernel code, kernel data, user data, user code and the TSSs
+const GDT_CAPACITY: usize = 5 + 2 * MAX_TSS_COUNT;
+// the GDT is built at runtime and never moves, so the entries can be added after lgdt
+static GDT: Mutex<FixedVec<SegmentDescriptor, GDT_CAPACITY>> = Mutex::new(FixedVec::new());
+// TSS of the boot CPU
+static mut TSS: TaskStateSegment = TaskStateSegment::new();
+
+pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+// page faults caused by a stack overflow can't be handled on the overflowed stack
+pub const PAGE_FAULT_IST_INDEX: u16 = 1;
+const IST_STACK_SIZE: usize = 4096 * 5;
+static mut DOUBLE_FAULT_STACK: [u8; IST_STACK_SIZE] = [0; IST_STACK_SIZE];
+static mut PAGE_FAULT_STACK: [u8; IST_STACK_SIZE] = [0; IST_STACK_SIZE];
 
 enum DescriptorType {
     Upper8Bytes = 0,
@@ -80,26 +100,104 @@ fn setup_data_segment(
     descriptor.default_operation_size(1);
 }
 
-unsafe fn setup_segments() {
-    // TODO: GDT needs to be created for each processor.
-    trace!("INITIALIZING segmentation");
-    setup_code_segment(&mut GDT[1], DescriptorType::ExecuteRead, 0, 0, 0xfffff);
-    setup_data_segment(&mut GDT[2], DescriptorType::ReadWrite, 0, 0, 0xfffff);
-    load_gdt(
-        (size_of::<[SegmentDescriptor; 3]>()) as u16 - 1,
-        &GDT[0] as *const SegmentDescriptor as usize,
+fn setup_tss_descriptor(descriptors: &mut [SegmentDescriptor], base: u64, limit: u32) {
+    let descriptor = &mut descriptors[0];
+    descriptor.data = 0;
+
+    descriptor.base_low(base as u16);
+    descriptor.base_middle((base >> 16) as u8);
+    descriptor.base_high((base >> 24) as u8);
+
+    descriptor.limit_low(limit as u16);
+    descriptor.limit_high(((limit >> 16) & 0xf) as u8);
+
+    descriptor.ty(DescriptorType::TSSAvailable as u8);
+    descriptor.system_segment(0);
+    descriptor.descriptor_privilege_level(0);
+    descriptor.present(1);
+
+    // upper 32 bits of the base address
+    descriptors[1].data = base >> 32;
+}
+
+fn push_descriptors(gdt: &mut FixedVec<SegmentDescriptor, GDT_CAPACITY>, descriptors: &[SegmentDescriptor]) -> u16 {
+    let index = gdt.len();
+    if index + descriptors.len() > gdt.capacity() {
+        panic!("GDT is full");
+    }
+    for descriptor in descriptors {
+        gdt.push(*descriptor).unwrap();
+    }
+    return index as u16;
+}
+
+// lgdt has to be done again on each CPU after the GDT grows, since the limit is changed
+fn reload_gdt(gdt: &FixedVec<SegmentDescriptor, GDT_CAPACITY>) {
+    unsafe {
+        load_gdt(
+            (size_of::<SegmentDescriptor>() * gdt.len()) as u16 - 1,
+            gdt.as_slice().as_ptr() as usize,
+        )
+    };
+}
+
+// install the TSS descriptor and return its selector for ltr
+pub fn add_tss(tss: &'static TaskStateSegment) -> SegmentSelector {
+    let mut descriptors = [SegmentDescriptor::new(); 2];
+    setup_tss_descriptor(
+        &mut descriptors,
+        tss as *const TaskStateSegment as u64,
+        size_of::<TaskStateSegment>() as u32 - 1,
     );
+    let mut gdt = GDT.lock();
+    let index = push_descriptors(&mut gdt, &descriptors);
+    reload_gdt(&gdt);
+    return SegmentSelector::new(index, PrivilegeLevel::Ring0);
+}
+
+unsafe fn setup_tss() -> SegmentSelector {
+    let stack_start = VirtAddr::from_ptr(&DOUBLE_FAULT_STACK as *const u8);
+    TSS.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = stack_start + IST_STACK_SIZE;
+    let stack_start = VirtAddr::from_ptr(&PAGE_FAULT_STACK as *const u8);
+    TSS.interrupt_stack_table[PAGE_FAULT_IST_INDEX as usize] = stack_start + IST_STACK_SIZE;
+    return add_tss(&*addr_of!(TSS));
+}
+
+// the stack which interrupts from ring 3 switch to. only the boot CPU runs processes for now
+pub fn set_kernel_stack(top: u64) {
+    unsafe { TSS.privilege_stack_table[0] = VirtAddr::new(top) };
+}
+
+unsafe fn setup_segments() -> SegmentSelector {
+    trace!("INITIALIZING segmentation");
+    let mut descriptors = [SegmentDescriptor::new(); 5];
+    setup_code_segment(&mut descriptors[1], DescriptorType::ExecuteRead, 0, 0, 0xfffff);
+    setup_data_segment(&mut descriptors[2], DescriptorType::ReadWrite, 0, 0, 0xfffff);
+    // SYSRET expects the user data segment right before the user code segment
+    setup_data_segment(&mut descriptors[3], DescriptorType::ReadWrite, 3, 0, 0xfffff);
+    setup_code_segment(&mut descriptors[4], DescriptorType::ExecuteRead, 3, 0, 0xfffff);
+    {
+        let mut gdt = GDT.lock();
+        gdt.clear();
+        push_descriptors(&mut gdt, &descriptors);
+    }
+    return setup_tss();
 }
 
 pub const KERNEL_CS: u16 = 1 << 3;
 pub const KERNEL_SS: u16 = 2 << 3;
+pub const USER_SS: u16 = 3 << 3 | 3;
+pub const USER_CS: u16 = 4 << 3 | 3;
 const KERNEL_DS: u16 = 0;
+const KERNEL_TSS: u16 = 5 << 3;
 
 pub fn initialize() {
     unsafe {
-        setup_segments();
+        let tss = setup_segments();
+        assert_eq!(tss.0, KERNEL_TSS, "the boot TSS must be right after the user segments");
         set_ds_all(KERNEL_DS);
         set_cs_ss(KERNEL_CS, KERNEL_SS);
+        load_tss(tss);
     }
 }
 
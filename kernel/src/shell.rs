@@ -0,0 +1,316 @@
+use alloc::{
+    format,
+    string::{String, ToString},
+};
+use core::fmt::{self, Write};
+
+use crate::{
+    drivers::{
+        fs::init::{read_dir, read_file, unmount},
+        timer::ticks_to_duration,
+        usb::xhci::PORT_INFOS,
+    },
+    exec,
+    graphics::{BackgroundMode, ImageInfo},
+    horse_lib::fd::absolute_path,
+    keyboard_layout::{active_layout, layout_names},
+    layer::{Background, LAYER_MANAGER},
+    proc::{task_stack_overflow, PROCESS_MANAGER},
+    syscall::{kernel_dispatch, SyscallNumber},
+    ALLOCATOR, BG_COLOR,
+};
+use libloader::PixelFormat;
+
+const PROMPT: &str = "horse> ";
+
+// the shell uses stdio through the syscalls as the user programs do
+struct Stdout;
+
+impl Write for Stdout {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        kernel_dispatch(SyscallNumber::Write as u64, 1, s.as_ptr() as u64, s.len() as u64, 0, 0, 0);
+        return Ok(());
+    }
+}
+
+macro_rules! out {
+    ($($arg:tt)*) => ({ let _ = write!(Stdout, $($arg)*); });
+}
+
+macro_rules! outln {
+    ($($arg:tt)*) => ({ let _ = writeln!(Stdout, $($arg)*); });
+}
+
+// start the shell as a kernel task on the active terminal
+pub fn start() {
+    let manager = unsafe { PROCESS_MANAGER.get_mut().unwrap() };
+    let proc = manager.new_proc();
+    proc.borrow_mut().init_context(main);
+    manager.wake_up(proc);
+}
+
+fn main() {
+    let mut line = String::new();
+    loop {
+        out!("{}", PROMPT);
+        if !read_line(&mut line) {
+            break;
+        }
+        let mut args = line.split_whitespace();
+        match args.next() {
+            Some("help") => help(),
+            Some("ls") => ls(&resolve(args.next().unwrap_or("."))),
+            Some("cat") => match args.next() {
+                Some(path) => cat(&resolve(path)),
+                None => outln!("usage: cat <file>"),
+            },
+            Some("cd") => cd(args.next().unwrap_or("/")),
+            Some("pwd") => outln!("{}", cwd()),
+            Some("umount") => match args.next() {
+                Some(path) => umount(&resolve(path)),
+                None => outln!("usage: umount <path>"),
+            },
+            Some("ps") => ps(),
+            Some("mem") => mem(),
+            Some("lsusb") => lsusb(),
+            Some("layout") => layout(args.next()),
+            Some("background") => {
+                let path = args.next();
+                let width = args.next().and_then(|width| width.parse().ok());
+                let height = args.next().and_then(|height| height.parse().ok());
+                match (path, width, height) {
+                    (Some(path), Some(width), Some(height)) => {
+                        background(&resolve(path), width, height, args.next())
+                    }
+                    _ => outln!("usage: background <file> <width> <height> [center|tile|stretch]"),
+                }
+            }
+            Some("run") => match args.next() {
+                Some(path) => run(&resolve(path)),
+                None => outln!("usage: run <program>"),
+            },
+            Some("overflow") => overflow(),
+            Some("wait") => match args.next().and_then(|id| id.parse().ok()) {
+                Some(id) => wait(id),
+                None => outln!("usage: wait <pid>"),
+            },
+            Some(command) => outln!("{}: command not found", command),
+            None => {}
+        }
+    }
+    // stdin is closed
+    let manager = unsafe { PROCESS_MANAGER.get_mut().unwrap() };
+    let id = manager.current().borrow().id();
+    manager.prepare_terminate(id);
+}
+
+// read a line with echo. returns false when stdin can't be read
+fn read_line(line: &mut String) -> bool {
+    line.clear();
+    loop {
+        let mut c = [0u8; 1];
+        let nread = kernel_dispatch(SyscallNumber::Read as u64, 0, c.as_mut_ptr() as u64, 1, 0, 0, 0);
+        if nread < 0 {
+            return false;
+        }
+        match c[0] {
+            b'\n' => {
+                outln!();
+                return true;
+            }
+            // backspace
+            0x08 => {
+                if line.pop().is_some() {
+                    out!("\x08");
+                }
+            }
+            c => {
+                line.push(c as char);
+                out!("{}", c as char);
+            }
+        }
+    }
+}
+
+fn help() {
+    outln!("help            show this message");
+    outln!("ls [path]       list the directory");
+    outln!("cat <file>      print the file");
+    outln!("cd [path]       change the directory, / by default");
+    outln!("pwd             show the current directory");
+    outln!("umount <path>   unmount the file system, e.g. /tmp");
+    outln!("ps              list the processes");
+    outln!("mem             show the memory usage");
+    outln!("lsusb           list the USB devices");
+    outln!("layout [name]   show or set the keyboard layout");
+    outln!("background <file> <width> <height> [mode]");
+    outln!("                set the background image, center by default");
+    outln!("run <program>   run the program in the background");
+    outln!("wait <pid>      wait until the process exits");
+    outln!("overflow        overflow the stack of a task to test the guard page");
+}
+
+fn cwd() -> String {
+    let manager = unsafe { PROCESS_MANAGER.get().unwrap() };
+    return String::from(manager.current().borrow().cwd());
+}
+
+// the commands are given paths relative to the current directory
+fn resolve(path: &str) -> String {
+    return absolute_path(&cwd(), path);
+}
+
+fn cd(path: &str) {
+    let mut cpath = String::from(path);
+    cpath.push('\0');
+    let ret = kernel_dispatch(SyscallNumber::Chdir as u64, cpath.as_ptr() as u64, 0, 0, 0, 0, 0);
+    if ret < 0 {
+        outln!("cd: {}: error {}", path, -ret);
+    }
+}
+
+fn ls(path: &str) {
+    match read_dir(path) {
+        Ok(entries) => {
+            for entry in entries {
+                if entry.is_dir {
+                    outln!("{:>10} {}/", "", entry.name);
+                } else {
+                    outln!("{:>10} {}", entry.size, entry.name);
+                }
+            }
+        }
+        Err(errno) => outln!("ls: {}: error {}", path, errno),
+    }
+}
+
+fn cat(path: &str) {
+    match read_file(path) {
+        Ok(bytes) => out!("{}", String::from_utf8_lossy(&bytes)),
+        Err(errno) => outln!("cat: {}: error {}", path, errno),
+    }
+}
+
+fn umount(path: &str) {
+    if let Err(errno) = unmount(path) {
+        outln!("umount: {}: error {}", path, errno);
+    }
+}
+
+fn ps() {
+    let manager = unsafe { PROCESS_MANAGER.get().unwrap() };
+    outln!("{:>5} {:>5} {:>8} {:>3} {:>8}", "PID", "PPID", "STATE", "TTY", "TIME");
+    for proc in manager.processes() {
+        outln!(
+            "{:>5} {:>5} {:>8} {:>3} {:>7}s",
+            proc.id,
+            proc.parent,
+            if proc.running { "running" } else { "sleeping" },
+            proc.terminal,
+            ticks_to_duration(proc.cpu_ticks).as_secs()
+        );
+    }
+}
+
+fn mem() {
+    outln!("heap allocated: {} bytes", ALLOCATOR.bytes_allocated());
+    outln!("heap peak:      {} bytes", ALLOCATOR.peak_usage());
+    outln!("free memory:    {} bytes", ALLOCATOR.bytes_free());
+}
+
+// the controller is owned by the main loop, so this shows what it published last
+fn lsusb() {
+    // cloned to release the lock before printing
+    let infos = match PORT_INFOS.lock().clone() {
+        Some(infos) => infos,
+        None => {
+            outln!("lsusb: xHC isn't found");
+            return;
+        }
+    };
+    outln!("{:>4} {:>4} {:>20} {:>5}  {}", "PORT", "SLOT", "PHASE", "RETRY", "LAST FAILURE");
+    for info in infos {
+        let slot = info.slot_id.map_or(String::from("-"), |id| id.to_string());
+        out!("{:>4} {:>4} {:>20} {:>5}  ", info.port_num, slot, format!("{:?}", info.phase), info.retries);
+        match info.failure {
+            Some(failure) => outln!("{} in {:?}", failure.error, failure.phase),
+            None => outln!("-"),
+        }
+    }
+}
+
+fn layout(name: Option<&str>) {
+    let name = match name {
+        Some(name) => name,
+        None => {
+            outln!("{}", active_layout().name());
+            return;
+        }
+    };
+    let mut cname = String::from(name);
+    cname.push('\0');
+    let ret = kernel_dispatch(SyscallNumber::SetKeyboardLayout as u64, cname.as_ptr() as u64, 0, 0, 0, 0, 0);
+    if ret < 0 {
+        out!("layout: {}: unknown layout, using us. available:", name);
+        for name in layout_names() {
+            out!(" {}", name);
+        }
+        outln!();
+    }
+}
+
+// the file has the raw pixels in BGRX, e.g. made by `convert image.png bgra:image.raw`
+fn background(path: &str, width: usize, height: usize, mode: Option<&str>) {
+    let mode = match mode.unwrap_or("center") {
+        "center" => BackgroundMode::Center,
+        "tile" => BackgroundMode::Tile,
+        "stretch" => BackgroundMode::Stretch,
+        mode => {
+            outln!("background: {}: unknown mode", mode);
+            return;
+        }
+    };
+    let image = match read_file(path) {
+        Ok(bytes) => bytes,
+        Err(errno) => {
+            outln!("background: {}: error {}", path, errno);
+            return;
+        }
+    };
+    let info = ImageInfo { width, height, format: PixelFormat::Bgr };
+    let background = Background::Image { image, info, mode, color: BG_COLOR };
+    if let Err(code) = unsafe { LAYER_MANAGER.get_mut().unwrap() }.set_background(background) {
+        outln!("background: {}: {}", path, code);
+    }
+}
+
+// the shell keeps running while the program runs
+fn run(path: &str) {
+    match exec::spawn(path) {
+        Ok(id) => outln!("[{}] {}", id, path),
+        Err(errno) => outln!("run: {}: error {}", path, errno),
+    }
+}
+
+// the task recurses into the guard page below its stack. only the task is terminated
+fn overflow() {
+    let manager = unsafe { PROCESS_MANAGER.get_mut().unwrap() };
+    let proc = manager.new_proc();
+    let id = proc.borrow().id();
+    proc.borrow_mut().init_context(task_stack_overflow);
+    manager.wake_up(proc);
+    manager.wait_terminated(id);
+    outln!("[{}] terminated by the stack overflow", id);
+}
+
+fn wait(id: usize) {
+    let manager = unsafe { PROCESS_MANAGER.get_mut().unwrap() };
+    let shell_id = manager.current().borrow().id();
+    // only the parent is woken up by the termination
+    if !manager.processes().iter().any(|proc| proc.id == id && proc.parent == shell_id) {
+        outln!("wait: {}: not a child of the shell", id);
+        return;
+    }
+    manager.wait_terminated(id);
+    outln!("[{}] done", id);
+}
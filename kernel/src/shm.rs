@@ -0,0 +1,59 @@
+use alloc::{
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+
+use crate::{
+    proc::UserMemory,
+    syscall::errno::{EINVAL, ENOMEM},
+};
+
+// the segments by the ids. the processes which map a segment hold it, so it's freed by the last unmap
+static SEGMENTS: Mutex<Vec<(usize, Weak<SharedMemory>)>> = Mutex::new(Vec::new());
+static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+
+// memory shared by the processes and the kernel, e.g. a pixel buffer drawn by an app and blitted by the kernel.
+// all the processes share the identity mapped page table, so every mapper sees the same address.
+// for the same reason, mapping is advisory: the pages are user accessible from the creation until
+// the segment is freed, and unmapping only stops the syscalls accepting the pointers to the segment
+// and counting it in RLIMIT_AS of the process
+#[derive(Eq, PartialEq)]
+pub struct SharedMemory {
+    id: usize,
+    memory: UserMemory,
+}
+
+impl SharedMemory {
+    pub fn id(&self) -> usize {
+        return self.id;
+    }
+    pub fn addr(&self) -> u64 {
+        return self.memory.start();
+    }
+    // rounded up to the frames
+    pub fn size(&self) -> usize {
+        return (self.memory.end() - self.memory.start()) as usize;
+    }
+}
+
+// zero filled. the caller has to keep the segment until it's mapped, or it's freed at once
+pub fn create(size: usize) -> Result<Arc<SharedMemory>, i32> {
+    if size == 0 {
+        return Err(EINVAL);
+    }
+    let memory = UserMemory::new(size).map_err(|_| ENOMEM)?;
+    let segment = Arc::new(SharedMemory { id: NEXT_ID.fetch_add(1, Ordering::Relaxed), memory });
+    let mut segments = SEGMENTS.lock();
+    // the ids of the freed segments are forgotten here
+    segments.retain(|(_, segment)| segment.strong_count() > 0);
+    segments.push((segment.id, Arc::downgrade(&segment)));
+    return Ok(segment);
+}
+
+// None when the segment doesn't exist or all the mappers have unmapped it
+pub fn get(id: usize) -> Option<Arc<SharedMemory>> {
+    let segments = SEGMENTS.lock();
+    return segments.iter().find(|(segment_id, _)| *segment_id == id)?.1.upgrade();
+}
@@ -0,0 +1,119 @@
+use core::{
+    ptr::{addr_of, copy_nonoverlapping, write_volatile},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{
+    acpi::PROCESSOR_APIC_IDS,
+    drivers::timer::sleep,
+    horse_lib::time::Duration,
+    info,
+    lapic::LocalApic,
+    memory_manager::{frame_manager_instance, FrameID, BYTES_PER_FRAME},
+    paging,
+    proc::get_cr3,
+    warn,
+};
+
+// must match AP_TRAMPOLINE_BASE in asm.s
+const AP_TRAMPOLINE_BASE: u64 = 0x8000;
+const AP_STACK_FRAMES: usize = 4;
+const IPI_INIT: u32 = 0x00004500;
+const IPI_STARTUP: u32 = 0x00004600;
+
+// the number of processors running including BSP
+static ONLINE_CPUS: AtomicUsize = AtomicUsize::new(1);
+
+//symbols in asm.s
+extern "C" {
+    static ap_trampoline_start: u8;
+    static ap_trampoline_end: u8;
+    static ap_trampoline_cr3: u8;
+    static ap_trampoline_stack: u8;
+    static ap_trampoline_entry: u8;
+}
+
+pub fn online_cpus() -> usize {
+    return ONLINE_CPUS.load(Ordering::SeqCst);
+}
+
+// the trampoline is started in real mode, so it has to be placed under 1MiB
+pub fn reserve_trampoline() {
+    frame_manager_instance().mark_allocated(
+        FrameID::from_phys_addr(AP_TRAMPOLINE_BASE as *mut u8),
+        1,
+    );
+}
+
+unsafe fn trampoline_param(symbol: *const u8) -> *mut u64 {
+    let offset = symbol as u64 - addr_of!(ap_trampoline_start) as u64;
+    return (AP_TRAMPOLINE_BASE + offset) as *mut u64;
+}
+
+extern "sysv64" fn ap_main() -> ! {
+    paging::load_pat();
+    ONLINE_CPUS.fetch_add(1, Ordering::SeqCst);
+    // TODO: join the scheduler instead of parking
+    loop {
+        unsafe { core::arch::asm!("cli", "hlt") }
+    }
+}
+
+pub fn start_application_processors() {
+    let ids = match PROCESSOR_APIC_IDS.get() {
+        Some(ids) => ids,
+        None => {
+            warn!("SMP: processors are unknown");
+            return;
+        }
+    };
+    unsafe {
+        let size = addr_of!(ap_trampoline_end) as usize - addr_of!(ap_trampoline_start) as usize;
+        copy_nonoverlapping(
+            addr_of!(ap_trampoline_start),
+            AP_TRAMPOLINE_BASE as *mut u8,
+            size,
+        );
+        write_volatile(trampoline_param(addr_of!(ap_trampoline_cr3)), get_cr3());
+        write_volatile(
+            trampoline_param(addr_of!(ap_trampoline_entry)),
+            ap_main as *const () as u64,
+        );
+    }
+
+    let bsp_id = LocalApic::id();
+    for &id in ids.iter() {
+        if id == bsp_id {
+            continue;
+        }
+        let stack = match frame_manager_instance().allocate(AP_STACK_FRAMES) {
+            Ok(frame) => frame,
+            Err(_) => {
+                warn!("SMP: failed to allocate the stack for the processor {}", id);
+                break;
+            }
+        };
+        let stack_end = stack.phys_addr() as u64 + (AP_STACK_FRAMES * BYTES_PER_FRAME) as u64;
+        unsafe { write_volatile(trampoline_param(addr_of!(ap_trampoline_stack)), stack_end) };
+
+        let online = online_cpus();
+        // INIT-SIPI-SIPI sequence
+        LocalApic::send_ipi(id, IPI_INIT);
+        sleep(Duration::from_millis(10));
+        for _ in 0..2 {
+            LocalApic::send_ipi(id, IPI_STARTUP | (AP_TRAMPOLINE_BASE >> 12) as u32);
+            sleep(Duration::from_millis(1));
+        }
+        // the next processor shares the trampoline, so wait for this one to leave it
+        for _ in 0..100 {
+            if online_cpus() > online {
+                break;
+            }
+            sleep(Duration::from_millis(1));
+        }
+        if online_cpus() == online {
+            warn!("SMP: the processor {} didn't respond", id);
+        }
+    }
+    info!("SMP: {} of {} processors are online", online_cpus(), ids.len());
+}
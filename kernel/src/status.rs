@@ -1,4 +1,4 @@
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum StatusCode {
     Success,
     Failure,
@@ -20,13 +20,29 @@ pub enum StatusCode {
     UnsupportedInterface,
     NoCorrespondingSetupStage,
     TransferFailed { slot_id: u8 },
+    TransferTimeout { slot_id: u8 },
     CommandCompletionFailed { slot_id: u8 },
     TooManyWaiters,
+    TooManyEndpoints,
+    TooManyDrivers,
     InvalidPhase,
     UnknownXHCISpeedID,
     UnknownPixelFormat,
     NoPCIMSI,
+    NoPAT,
     NoWaiter,
+    NoDevice,
+    DeviceFault,
+    NoAddressMark,
+    MediaError,
+    CommandAborted,
+    IdMarkNotFound,
+    UncorrectableData,
+    BadSectors,
+    ReadsNothing,
+    WriteProtected,
+    Timeout,
+    NvmeCommandFailed { status: u16 },
     LastOfCode,
 }
 
@@ -53,13 +69,29 @@ impl StatusCode {
             StatusCode::UnsupportedInterface => "UnsupportedInterface",
             StatusCode::NoCorrespondingSetupStage => "NoCorrespondingSetupStage",
             StatusCode::TransferFailed { slot_id: _ } => "TransferFailed",
+            StatusCode::TransferTimeout { slot_id: _ } => "TransferTimeout",
             StatusCode::CommandCompletionFailed { slot_id: _ } => "CommandCompletionFailed",
             StatusCode::TooManyWaiters => "TooManyWaiters",
+            StatusCode::TooManyEndpoints => "TooManyEndpoints",
+            StatusCode::TooManyDrivers => "TooManyDrivers",
             StatusCode::InvalidPhase => "InvalidPhase",
             StatusCode::UnknownXHCISpeedID => "UnknownXHCISpeedID",
             StatusCode::UnknownPixelFormat => "UnknownPixelFormat",
             StatusCode::NoPCIMSI => "NoPCIMSI",
+            StatusCode::NoPAT => "NoPAT",
             StatusCode::NoWaiter => "NoWaiter",
+            StatusCode::NoDevice => "NoDevice",
+            StatusCode::DeviceFault => "DeviceFault",
+            StatusCode::NoAddressMark => "NoAddressMark",
+            StatusCode::MediaError => "MediaError",
+            StatusCode::CommandAborted => "CommandAborted",
+            StatusCode::IdMarkNotFound => "IdMarkNotFound",
+            StatusCode::UncorrectableData => "UncorrectableData",
+            StatusCode::BadSectors => "BadSectors",
+            StatusCode::ReadsNothing => "ReadsNothing",
+            StatusCode::WriteProtected => "WriteProtected",
+            StatusCode::Timeout => "Timeout",
+            StatusCode::NvmeCommandFailed { status: _ } => "NvmeCommandFailed",
             StatusCode::LastOfCode => "LastOfCode",
         }
     }
@@ -83,4 +115,6 @@ pub enum PortConfigPhase {
     InitializingDevice,
     ConfiguringEndpoints,
     Configured,
+    // the enumeration has failed even after the retry
+    Failed,
 }
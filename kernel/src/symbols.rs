@@ -0,0 +1,18 @@
+use libloader::SymbolTable;
+use spin::Once;
+
+// the function symbols passed by the bootloader. empty when the kernel is stripped
+static SYMBOLS: Once<SymbolTable> = Once::new();
+
+// the table is copied because the pointer refers to the stack of the bootloader
+pub fn initialize(symbols: *const SymbolTable) {
+    let table = if symbols.is_null() { SymbolTable::EMPTY } else { unsafe { *symbols } };
+    SYMBOLS.call_once(|| table);
+}
+
+// the name of the function containing the address and the offset from its start
+pub fn resolve(addr: u64) -> Option<(&'static str, usize)> {
+    let table = SYMBOLS.get()?;
+    let symbol = table.lookup(addr)?;
+    return Some((table.name(symbol)?, (addr - symbol.addr) as usize));
+}
@@ -0,0 +1,1023 @@
+use alloc::string::String;
+use core::{
+    mem::{align_of, size_of},
+    ptr::addr_of_mut,
+    slice, str,
+};
+use x86_64::{
+    instructions::interrupts::without_interrupts,
+    registers::{
+        model_specific::{Efer, EferFlags, KernelGsBase, LStar, SFMask, Star},
+        rflags::RFlags,
+    },
+    structures::gdt::SegmentSelector,
+    VirtAddr,
+};
+
+use crate::{
+    cpuid::{has_feature, Feature},
+    drivers::fs::{
+        core::FILE_DESCRIPTOR_TABLE,
+        init::{find_filesystem, read_dir, stat, FILESYSTEM_TABLE},
+    },
+    drivers::timer::{current_tick, duration_to_ticks, TICKS_PER_SECOND, TIMER_MANAGER},
+    error,
+    horse_lib::fd::{absolute_path, OpenFlags, ProcessFds, Stdio},
+    horse_lib::time::Duration,
+    horse_lib::irq_mutex::IrqMutex,
+    input::{Stdin, STDIN},
+    keyboard_layout::set_layout,
+    log::{_print_to, set_log_level, LogLevel, KERNEL_LOG},
+    memory_manager::BYTES_PER_FRAME,
+    proc::{Rlimit, SignalAction, SignalContext, KERNEL_TASK_ID, PROCESS_MANAGER, SIGCHLD},
+    segment::{set_kernel_stack, KERNEL_CS, KERNEL_SS, USER_CS, USER_SS},
+    shm,
+    warn, ALLOCATOR,
+};
+
+// error numbers share their values with Linux
+pub mod errno {
+    pub const EPERM: i32 = 1;
+    pub const ENOENT: i32 = 2;
+    pub const EIO: i32 = 5;
+    pub const ENOEXEC: i32 = 8;
+    pub const EBADF: i32 = 9;
+    pub const EAGAIN: i32 = 11;
+    pub const ENOMEM: i32 = 12;
+    pub const EFAULT: i32 = 14;
+    pub const EBUSY: i32 = 16;
+    pub const EXDEV: i32 = 18;
+    pub const ENODEV: i32 = 19;
+    pub const ENOTDIR: i32 = 20;
+    pub const EISDIR: i32 = 21;
+    pub const EINVAL: i32 = 22;
+    pub const EMFILE: i32 = 24;
+    pub const EFBIG: i32 = 27;
+    pub const ENOSPC: i32 = 28;
+    pub const ESPIPE: i32 = 29;
+    pub const ERANGE: i32 = 34;
+    pub const ENAMETOOLONG: i32 = 36;
+    pub const ENOSYS: i32 = 38;
+    pub const ENOTEMPTY: i32 = 39;
+}
+use errno::*;
+
+// each handler returns the value for RAX or the errno
+pub type SyscallResult = Result<isize, i32>;
+type SyscallHandler = fn(u64, u64, u64, u64, u64, u64) -> SyscallResult;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u64)]
+pub enum SyscallNumber {
+    Read = 0,
+    Write = 1,
+    Open = 2,
+    Close = 3,
+    Stat = 4,
+    Poll = 7,
+    Lseek = 8,
+    Mmap = 9,
+    Ftruncate = 77,
+    Getcwd = 79,
+    Chdir = 80,
+    Rename = 82,
+    Unlink = 87,
+    Sigaction = 13,
+    Sigreturn = 15,
+    Exit = 60,
+    Getrlimit = 97,
+    Getrusage = 98,
+    Setrlimit = 160,
+    Dmesg = 103,
+    // Horse specific syscalls
+    SetLogLevel = 512,
+    HeapStats = 513,
+    SetKeyboardLayout = 514,
+    ShmCreate = 515,
+    ShmMap = 516,
+    ShmUnmap = 517,
+}
+
+impl TryFrom<u64> for SyscallNumber {
+    type Error = i32;
+    fn try_from(value: u64) -> Result<Self, Self::Error> {
+        return match value {
+            0 => Ok(SyscallNumber::Read),
+            1 => Ok(SyscallNumber::Write),
+            2 => Ok(SyscallNumber::Open),
+            3 => Ok(SyscallNumber::Close),
+            4 => Ok(SyscallNumber::Stat),
+            7 => Ok(SyscallNumber::Poll),
+            8 => Ok(SyscallNumber::Lseek),
+            9 => Ok(SyscallNumber::Mmap),
+            77 => Ok(SyscallNumber::Ftruncate),
+            79 => Ok(SyscallNumber::Getcwd),
+            80 => Ok(SyscallNumber::Chdir),
+            82 => Ok(SyscallNumber::Rename),
+            87 => Ok(SyscallNumber::Unlink),
+            13 => Ok(SyscallNumber::Sigaction),
+            15 => Ok(SyscallNumber::Sigreturn),
+            60 => Ok(SyscallNumber::Exit),
+            97 => Ok(SyscallNumber::Getrlimit),
+            98 => Ok(SyscallNumber::Getrusage),
+            160 => Ok(SyscallNumber::Setrlimit),
+            103 => Ok(SyscallNumber::Dmesg),
+            512 => Ok(SyscallNumber::SetLogLevel),
+            513 => Ok(SyscallNumber::HeapStats),
+            514 => Ok(SyscallNumber::SetKeyboardLayout),
+            515 => Ok(SyscallNumber::ShmCreate),
+            516 => Ok(SyscallNumber::ShmMap),
+            517 => Ok(SyscallNumber::ShmUnmap),
+            _ => Err(ENOSYS),
+        };
+    }
+}
+
+impl SyscallNumber {
+    pub fn handler(&self) -> SyscallHandler {
+        return match self {
+            SyscallNumber::Read => sys_read,
+            SyscallNumber::Write => sys_write,
+            SyscallNumber::Open => sys_open,
+            SyscallNumber::Close => sys_close,
+            SyscallNumber::Stat => sys_stat,
+            SyscallNumber::Poll => sys_poll,
+            SyscallNumber::Lseek => sys_lseek,
+            SyscallNumber::Mmap => sys_mmap,
+            SyscallNumber::Ftruncate => sys_ftruncate,
+            SyscallNumber::Getcwd => sys_getcwd,
+            SyscallNumber::Chdir => sys_chdir,
+            SyscallNumber::Rename => sys_rename,
+            SyscallNumber::Unlink => sys_unlink,
+            SyscallNumber::Sigaction => sys_sigaction,
+            SyscallNumber::Sigreturn => sys_sigreturn,
+            SyscallNumber::Exit => sys_exit,
+            SyscallNumber::Getrlimit => sys_getrlimit,
+            SyscallNumber::Getrusage => sys_getrusage,
+            SyscallNumber::Setrlimit => sys_setrlimit,
+            SyscallNumber::Dmesg => sys_dmesg,
+            SyscallNumber::SetLogLevel => sys_set_log_level,
+            SyscallNumber::HeapStats => sys_heap_stats,
+            SyscallNumber::SetKeyboardLayout => sys_set_keyboard_layout,
+            SyscallNumber::ShmCreate => sys_shm_create,
+            SyscallNumber::ShmMap => sys_shm_map,
+            SyscallNumber::ShmUnmap => sys_shm_unmap,
+        };
+    }
+}
+
+// the layout is shared with syscall_entry in asm.s
+#[repr(C)]
+struct SyscallCpuData {
+    kernel_stack: u64,
+    user_stack: u64,
+}
+
+const SYSCALL_STACK_SIZE: usize = 64 * 1024;
+
+#[repr(align(16))]
+struct SyscallStack([u8; SYSCALL_STACK_SIZE]);
+
+// used until a process with its own stack is switched to. only the boot CPU has it for now
+static mut SYSCALL_STACK: SyscallStack = SyscallStack([0; SYSCALL_STACK_SIZE]);
+static mut SYSCALL_CPU_DATA: SyscallCpuData = SyscallCpuData {
+    kernel_stack: 0,
+    user_stack: 0,
+};
+
+extern "C" {
+    fn syscall_entry();
+}
+
+// enable the syscall instruction. returns false when the CPU doesn't support it
+pub fn initialize_syscall() -> bool {
+    if !has_feature(Feature::Syscall) {
+        return false;
+    }
+    unsafe {
+        let stack = addr_of_mut!(SYSCALL_STACK) as u64;
+        SYSCALL_CPU_DATA.kernel_stack = stack + SYSCALL_STACK_SIZE as u64;
+        KernelGsBase::write(VirtAddr::from_ptr(addr_of_mut!(SYSCALL_CPU_DATA)));
+        LStar::write(VirtAddr::new(syscall_entry as usize as u64));
+        Star::write(
+            SegmentSelector(USER_CS),
+            SegmentSelector(USER_SS),
+            SegmentSelector(KERNEL_CS),
+            SegmentSelector(KERNEL_SS),
+        )
+        .unwrap();
+        // interrupts are enabled again after the stack is switched
+        SFMask::write(RFlags::INTERRUPT_FLAG | RFlags::DIRECTION_FLAG | RFlags::TRAP_FLAG);
+        Efer::update(|flags| flags.insert(EferFlags::SYSTEM_CALL_EXTENSIONS));
+    }
+    return true;
+}
+
+// the stack which the next syscall runs on. each process has to have its own,
+// otherwise a syscall preempted by the timer is broken by the syscall of the next process.
+// interrupts from ring 3 use the same stack
+pub fn set_syscall_stack(top: u64) {
+    unsafe { SYSCALL_CPU_DATA.kernel_stack = top };
+    set_kernel_stack(top);
+}
+
+#[no_mangle]
+extern "sysv64" fn syscall_dispatch(
+    number: u64,
+    arg1: u64,
+    arg2: u64,
+    arg3: u64,
+    arg4: u64,
+    arg5: u64,
+    arg6: u64,
+) -> isize {
+    let ret = dispatch(number, arg1, arg2, arg3, arg4, arg5, arg6);
+    return deliver_signal(ret);
+}
+
+// the registers of the user program pushed by syscall_entry, from the top of the kernel stack downward
+#[derive(Clone, Copy, Eq, PartialEq)]
+#[repr(C)]
+pub struct SyscallFrame {
+    pub r9: u64,
+    pub r8: u64,
+    pub r10: u64,
+    pub rdx: u64,
+    pub rsi: u64,
+    pub rdi: u64,
+    pub rflags: u64,
+    pub rip: u64,
+    pub rsp: u64,
+}
+
+// the frame of the syscall which the current process is running. sysret restores the registers from it
+fn syscall_frame() -> &'static mut SyscallFrame {
+    let top = unsafe { SYSCALL_CPU_DATA.kernel_stack };
+    return unsafe { &mut *((top - size_of::<SyscallFrame>() as u64) as *mut SyscallFrame) };
+}
+
+// a pending signal is delivered as the syscall returns to the user program. the handler runs in ring 3
+// on the user stack and returns into the restorer given by sigaction, which calls sigreturn
+fn deliver_signal(ret: isize) -> isize {
+    let manager = unsafe { PROCESS_MANAGER.get_mut().unwrap() };
+    let current = manager.current();
+    let (signal, action) = match current.borrow_mut().take_signal() {
+        Some(signal) => signal,
+        None => return ret,
+    };
+    let frame = syscall_frame();
+    // skip the red zone and keep the stack aligned as the handler is called
+    let rsp = (frame.rsp.wrapping_sub(128) & !0xfu64).wrapping_sub(8);
+    let restorer = match user_ptr::<u64>(rsp) {
+        Ok(restorer) => restorer,
+        Err(_) => {
+            // the handler can't be called, so the process is terminated as SIGSEGV does on Linux
+            let id = current.borrow().id();
+            drop(current);
+            error!("process {} is terminated because the stack can't hold signal {}", id, signal);
+            manager.prepare_terminate(id);
+            unreachable!()
+        }
+    };
+    // the process isn't borrowed here, so the page fault of a lazily loaded page can be handled
+    unsafe { restorer.write_unaligned(action.restorer) };
+    current.borrow_mut().enter_signal_handler(SignalContext { registers: *frame, ret });
+    frame.rsp = rsp;
+    frame.rip = action.handler;
+    frame.rdi = signal as u64;
+    return ret;
+}
+
+// common path for every syscall entry. errors are returned as -errno
+pub fn dispatch(number: u64, arg1: u64, arg2: u64, arg3: u64, arg4: u64, arg5: u64, arg6: u64) -> isize {
+    let result = SyscallNumber::try_from(number)
+        .and_then(|syscall| syscall.handler()(arg1, arg2, arg3, arg4, arg5, arg6));
+    return match result {
+        Ok(value) => value,
+        Err(errno) => -(errno as isize),
+    };
+}
+
+// the kernel tasks, e.g. the shell, call the syscalls with pointers to their own memory.
+// they aren't checked as the ones from the user programs are
+pub fn kernel_dispatch(number: u64, arg1: u64, arg2: u64, arg3: u64, arg4: u64, arg5: u64, arg6: u64) -> isize {
+    let manager = unsafe { PROCESS_MANAGER.get().unwrap() };
+    let current = manager.current();
+    current.borrow_mut().set_kernel_pointers(true);
+    let ret = dispatch(number, arg1, arg2, arg3, arg4, arg5, arg6);
+    current.borrow_mut().set_kernel_pointers(false);
+    return ret;
+}
+
+// whether the current process passed pointers to kernel memory by kernel_dispatch
+fn kernel_pointers() -> bool {
+    let manager = unsafe { PROCESS_MANAGER.get().unwrap() };
+    return manager.current().borrow().kernel_pointers();
+}
+
+// [ptr, ptr + len) must be in the memory of the current process
+fn check_user_range(ptr: u64, len: u64) -> Result<(), i32> {
+    let end = ptr.checked_add(len).ok_or(EFAULT)?;
+    if ptr == 0 {
+        return Err(EFAULT);
+    }
+    let manager = unsafe { PROCESS_MANAGER.get().unwrap() };
+    let current = manager.current();
+    let proc = current.borrow();
+    if proc.kernel_pointers() || proc.is_user_range(ptr, end) {
+        return Ok(());
+    }
+    return Err(EFAULT);
+}
+
+fn user_buffer<'a>(ptr: u64, len: u64) -> Result<&'a mut [u8], i32> {
+    check_user_range(ptr, len)?;
+    return Ok(unsafe { slice::from_raw_parts_mut(ptr as *mut u8, len as usize) });
+}
+
+// an array of the structs in the memory of the current process
+fn user_slice<'a, T>(ptr: u64, len: u64) -> Result<&'a mut [T], i32> {
+    let bytes = len.checked_mul(size_of::<T>() as u64).ok_or(EFAULT)?;
+    if ptr % align_of::<T>() as u64 != 0 {
+        return Err(EFAULT);
+    }
+    check_user_range(ptr, bytes)?;
+    return Ok(unsafe { slice::from_raw_parts_mut(ptr as *mut T, len as usize) });
+}
+
+// the struct may not be aligned, so access it by read_unaligned or write_unaligned
+fn user_ptr<T>(ptr: u64) -> Result<*mut T, i32> {
+    check_user_range(ptr, size_of::<T>() as u64)?;
+    return Ok(ptr as *mut T);
+}
+
+// the string must end in the block of the memory where it starts
+fn user_str(ptr: u64) -> Result<String, i32> {
+    if ptr == 0 {
+        return Err(EFAULT);
+    }
+    let end = if kernel_pointers() {
+        u64::MAX
+    } else {
+        let manager = unsafe { PROCESS_MANAGER.get().unwrap() };
+        let end = manager.current().borrow().user_range_end(ptr);
+        end.ok_or(EFAULT)?
+    };
+    let mut len = 0;
+    loop {
+        if ptr + len >= end {
+            return Err(EFAULT);
+        }
+        if unsafe { *((ptr + len) as *const u8) } == 0 {
+            break;
+        }
+        len += 1;
+    }
+    let bytes = unsafe { slice::from_raw_parts(ptr as *const u8, len as usize) };
+    return str::from_utf8(bytes).map(String::from).map_err(|_| EINVAL);
+}
+
+// the relative paths are seen from the working directory of the current process
+fn user_path(ptr: u64) -> Result<String, i32> {
+    let path = user_str(ptr)?;
+    let manager = unsafe { PROCESS_MANAGER.get().unwrap() };
+    return Ok(absolute_path(manager.current().borrow().cwd(), &path));
+}
+
+// the entry of FILE_DESCRIPTOR_TABLE which the fd of the current process refers to
+fn open_file(fd: u64) -> Result<i32, i32> {
+    let manager = unsafe { PROCESS_MANAGER.get().unwrap() };
+    return manager.current().borrow().fds().get(fd as i32).ok_or(EBADF);
+}
+
+// find the file system which the entry of FILE_DESCRIPTOR_TABLE belongs to
+fn filesystem_of(file: i32) -> Result<usize, i32> {
+    let table = FILE_DESCRIPTOR_TABLE.lock();
+    if !table.is_open(file) {
+        return Err(EBADF);
+    }
+    let path = table.get(file).path.as_string();
+    drop(table);
+    return find_filesystem(&path).ok_or(ENOENT);
+}
+
+// the virtual terminal of the current process
+fn current_terminal() -> usize {
+    let manager = unsafe { PROCESS_MANAGER.get().unwrap() };
+    return manager.current().borrow().terminal();
+}
+
+fn current_stdin() -> &'static IrqMutex<Stdin> {
+    return &STDIN[current_terminal()];
+}
+
+// sleep the current process until `ready` returns Some, or returns None after the timeout.
+// only stdin can be waited for now, so the process waits on it
+fn block_until<T>(timeout: Option<Duration>, mut ready: impl FnMut() -> Option<T>) -> Option<T> {
+    let manager = unsafe { PROCESS_MANAGER.get_mut().unwrap() };
+    let id = manager.current().borrow().id();
+    let stdin = current_stdin();
+    let deadline = timeout.map(|timeout| {
+        let mut timer = TIMER_MANAGER.lock();
+        let timer = timer.get_mut().unwrap();
+        timer.add_wakeup(timeout, id);
+        timer.current_tick() + duration_to_ticks(timeout)
+    });
+    let timed_out = || deadline.map_or(false, |deadline| current_tick() >= deadline);
+
+    let result = loop {
+        // registered before checking, so the input which comes after the check isn't missed
+        stdin.lock().waiters.add(id);
+        if let Some(value) = ready() {
+            break Some(value);
+        }
+        if timed_out() {
+            break None;
+        }
+        // the input and the timeout are delivered by the kernel task, which can't run until this sleeps
+        without_interrupts(|| {
+            if stdin.lock().waiters.contains(id) && !timed_out() {
+                manager.id_sleep(id);
+            }
+        });
+    };
+    stdin.lock().waiters.remove(id);
+    if timeout.is_some() {
+        TIMER_MANAGER.lock().get_mut().unwrap().cancel_wakeup(id);
+    }
+    return result;
+}
+
+// the kernel task delivers the input, so it must not sleep waiting for it
+fn can_block() -> bool {
+    let manager = unsafe { PROCESS_MANAGER.get().unwrap() };
+    return manager.current().borrow().id() != KERNEL_TASK_ID;
+}
+
+// wait until at least one byte is typed
+fn read_stdin(buf: &mut [u8]) -> SyscallResult {
+    if buf.is_empty() {
+        return Ok(0);
+    }
+    let stdin = current_stdin();
+    let nread = stdin.lock().read(buf);
+    if nread > 0 {
+        return Ok(nread as isize);
+    }
+    if !can_block() {
+        return Err(EAGAIN);
+    }
+    let nread = block_until(None, || match stdin.lock().read(buf) {
+        0 => None,
+        nread => Some(nread),
+    });
+    return Ok(nread.unwrap() as isize);
+}
+
+fn sys_read(fd: u64, buf: u64, count: u64, _: u64, _: u64, _: u64) -> SyscallResult {
+    let buf = user_buffer(buf, count)?;
+    let file = open_file(fd)?;
+    // stdio is decided by the entry, the fd may have been reused for a file
+    let stdio = FILE_DESCRIPTOR_TABLE.lock().stdio(file);
+    match stdio {
+        Some(Stdio::In) => return read_stdin(buf),
+        Some(Stdio::Out) => return Err(EBADF),
+        None => {}
+    }
+    let idx = filesystem_of(file)?;
+    let nread = unsafe { FILESYSTEM_TABLE.lock()[idx].read(file, buf, count as usize) };
+    if nread < 0 {
+        return Err(EIO);
+    }
+    return Ok(nread);
+}
+
+fn sys_write(fd: u64, buf: u64, count: u64, _: u64, _: u64, _: u64) -> SyscallResult {
+    let buf = user_buffer(buf, count)?;
+    let file = open_file(fd)?;
+    let stdio = FILE_DESCRIPTOR_TABLE.lock().stdio(file);
+    match stdio {
+        Some(Stdio::Out) => {
+            _print_to(current_terminal(), format_args!("{}", String::from_utf8_lossy(buf)));
+            return Ok(count as isize);
+        }
+        Some(Stdio::In) => return Err(EBADF),
+        None => {
+            let idx = filesystem_of(file)?;
+            let nwritten = unsafe { FILESYSTEM_TABLE.lock()[idx].write(file, buf, count as usize) };
+            if nwritten < 0 {
+                return Err(EPERM);
+            }
+            return Ok(nwritten);
+        }
+    }
+}
+
+fn sys_open(path: u64, flags: u64, _: u64, _: u64, _: u64, _: u64) -> SyscallResult {
+    let path = user_path(path)?;
+    let manager = unsafe { PROCESS_MANAGER.get().unwrap() };
+    let current = manager.current();
+    {
+        let proc = current.borrow();
+        check_fd_limit(proc.fds(), &proc.limits().open_files)?;
+    }
+    let idx = find_filesystem(&path).ok_or(ENOENT)?;
+    let fd = unsafe { FILESYSTEM_TABLE.lock()[idx].open(&path, flags as u32) };
+    // -1 means the fd table is full, and the other negative values are errno
+    if fd == -1 {
+        return Err(EMFILE);
+    } else if fd < 0 {
+        return Err(-fd);
+    }
+    let fd = current.borrow_mut().fds_mut().add(fd);
+    return Ok(fd as isize);
+}
+
+// RLIMIT_NOFILE is checked before the file is opened, so nothing has to be undone
+fn check_fd_limit(fds: &ProcessFds, limit: &Rlimit) -> Result<(), i32> {
+    if !limit.allows(fds.len() as u64 + 1) {
+        return Err(EMFILE);
+    }
+    return Ok(());
+}
+
+// the fd of the process is freed even when the flush fails, as Linux does
+fn sys_close(fd: u64, _: u64, _: u64, _: u64, _: u64, _: u64) -> SyscallResult {
+    let manager = unsafe { PROCESS_MANAGER.get().unwrap() };
+    let file = manager.current().borrow_mut().fds_mut().remove(fd as i32).ok_or(EBADF)?;
+    close_file(file)?;
+    return Ok(0);
+}
+
+fn close_file(file: i32) -> Result<(), i32> {
+    // stdio is shared by all the processes, so only the fd of the process is detached
+    if FILE_DESCRIPTOR_TABLE.lock().stdio(file).is_some() {
+        return Ok(());
+    }
+    let idx = filesystem_of(file)?;
+    let last = FILE_DESCRIPTOR_TABLE.lock().is_last_reference(file);
+    let table = unsafe { FILESYSTEM_TABLE.lock() };
+    if last && table[idx].flush(file) < 0 {
+        return Err(EIO);
+    }
+    table[idx].close(file);
+    return Ok(());
+}
+
+// the files which a terminated process left open. a failed flush is only logged, nobody can get it
+pub fn close_files(files: &[i32]) {
+    for &file in files {
+        if let Err(errno) = close_file(file) {
+            warn!("the open file {} isn't closed cleanly: error {}", file, errno);
+        }
+    }
+}
+
+fn sys_ftruncate(fd: u64, length: u64, _: u64, _: u64, _: u64, _: u64) -> SyscallResult {
+    if (length as i64) < 0 {
+        return Err(EINVAL);
+    }
+    let file = open_file(fd)?;
+    let table = FILE_DESCRIPTOR_TABLE.lock();
+    // the fd must be open for writing, and the console can't be truncated
+    if table.stdio(file).is_some() || table.get(file).f_mode & (OpenFlags::WROnly as u32 | OpenFlags::RDWR as u32) == 0 {
+        return Err(EBADF);
+    }
+    drop(table);
+    let idx = filesystem_of(file)?;
+    unsafe { FILESYSTEM_TABLE.lock()[idx].truncate(file, length as usize)? };
+    return Ok(0);
+}
+
+// the devices of DevFS are the only files which can seek now
+fn sys_lseek(fd: u64, offset: u64, whence: u64, _: u64, _: u64, _: u64) -> SyscallResult {
+    let file = open_file(fd)?;
+    if FILE_DESCRIPTOR_TABLE.lock().stdio(file).is_some() {
+        return Err(ESPIPE);
+    }
+    let idx = filesystem_of(file)?;
+    let offset = unsafe { FILESYSTEM_TABLE.lock()[idx].seek(file, offset as i64, whence as u32)? };
+    return Ok(offset as isize);
+}
+
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+
+// same layout as struct stat of Linux. only st_mode and st_size are filled
+#[repr(C)]
+struct Stat {
+    dev: u64,
+    ino: u64,
+    nlink: u64,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    pad: u32,
+    rdev: u64,
+    size: i64,
+    rest: [i64; 11],
+}
+
+fn sys_stat(path: u64, statbuf: u64, _: u64, _: u64, _: u64, _: u64) -> SyscallResult {
+    let path = user_path(path)?;
+    let statbuf = user_ptr::<Stat>(statbuf)?;
+    let entry = stat(&path)?;
+    let value = Stat {
+        dev: 0,
+        ino: 0,
+        nlink: 1,
+        mode: if entry.is_dir { S_IFDIR } else { S_IFREG },
+        uid: 0,
+        gid: 0,
+        pad: 0,
+        rdev: 0,
+        size: entry.size as i64,
+        rest: [0; 11],
+    };
+    unsafe { statbuf.write_unaligned(value) };
+    return Ok(0);
+}
+
+// only the files which the file system can map, e.g. /dev/fb0, are mapped at the address the file system
+// decides. the address hint, the protection and the flags are ignored, and munmap doesn't exist
+fn sys_mmap(_addr: u64, len: u64, _prot: u64, _flags: u64, fd: u64, offset: u64) -> SyscallResult {
+    if len == 0 {
+        return Err(EINVAL);
+    }
+    let file = open_file(fd)?;
+    if FILE_DESCRIPTOR_TABLE.lock().stdio(file).is_some() {
+        return Err(ENODEV);
+    }
+    let idx = filesystem_of(file)?;
+    let addr = unsafe { FILESYSTEM_TABLE.lock()[idx].mmap(file, offset as usize, len as usize)? };
+    return Ok(addr as isize);
+}
+
+fn sys_unlink(path: u64, _: u64, _: u64, _: u64, _: u64, _: u64) -> SyscallResult {
+    let path = user_path(path)?;
+    let idx = find_filesystem(&path).ok_or(ENOENT)?;
+    unsafe { FILESYSTEM_TABLE.lock()[idx].unlink(&path)? };
+    return Ok(0);
+}
+
+fn sys_rename(from: u64, to: u64, _: u64, _: u64, _: u64, _: u64) -> SyscallResult {
+    let from = user_path(from)?;
+    let to = user_path(to)?;
+    let idx = find_filesystem(&from).ok_or(ENOENT)?;
+    if find_filesystem(&to) != Some(idx) {
+        return Err(EXDEV);
+    }
+    unsafe { FILESYSTEM_TABLE.lock()[idx].rename(&from, &to)? };
+    return Ok(0);
+}
+
+// returns the length with the terminating null, as Linux does
+fn sys_getcwd(buf: u64, size: u64, _: u64, _: u64, _: u64, _: u64) -> SyscallResult {
+    let buf = user_buffer(buf, size)?;
+    let manager = unsafe { PROCESS_MANAGER.get().unwrap() };
+    let current = manager.current();
+    let proc = current.borrow();
+    let cwd = proc.cwd().as_bytes();
+    if cwd.len() + 1 > buf.len() {
+        return Err(ERANGE);
+    }
+    buf[..cwd.len()].copy_from_slice(cwd);
+    buf[cwd.len()] = 0;
+    return Ok(cwd.len() as isize + 1);
+}
+
+fn sys_chdir(path: u64, _: u64, _: u64, _: u64, _: u64, _: u64) -> SyscallResult {
+    let path = user_path(path)?;
+    // only the directories can be listed. this fails with ENOTDIR for a file
+    read_dir(&path)?;
+    let manager = unsafe { PROCESS_MANAGER.get().unwrap() };
+    manager.current().borrow_mut().set_cwd(path);
+    return Ok(0);
+}
+
+pub const POLLIN: i16 = 0x1;
+pub const POLLOUT: i16 = 0x4;
+pub const POLLERR: i16 = 0x8;
+pub const POLLHUP: i16 = 0x10;
+
+// same layout as struct pollfd of Linux
+#[repr(C)]
+struct PollFd {
+    fd: i32,
+    events: i16,
+    revents: i16,
+}
+
+const MAX_POLL_FDS: u64 = 1024;
+
+// set revents and return the number of the ready fds
+fn poll_fds(fds: &mut [PollFd]) -> usize {
+    let stdin_ready = !current_stdin().lock().is_empty();
+    let manager = unsafe { PROCESS_MANAGER.get().unwrap() };
+    let current = manager.current();
+    let proc = current.borrow();
+    let table = FILE_DESCRIPTOR_TABLE.lock();
+    let mut nready = 0;
+    for pollfd in fds.iter_mut() {
+        // negative fds are ignored as Linux does
+        if pollfd.fd < 0 {
+            pollfd.revents = 0;
+            continue;
+        }
+        let ready = match proc.fds().get(pollfd.fd) {
+            None => POLLHUP,
+            Some(file) => match table.stdio(file) {
+                Some(Stdio::In) if stdin_ready => POLLIN,
+                Some(Stdio::In) => 0,
+                Some(Stdio::Out) => POLLOUT,
+                // files never block
+                None => POLLIN | POLLOUT,
+            },
+        };
+        // POLLHUP and POLLERR are always reported
+        pollfd.revents = ready & (pollfd.events | POLLHUP | POLLERR);
+        if pollfd.revents != 0 {
+            nready += 1;
+        }
+    }
+    return nready;
+}
+
+// timeout is in milliseconds. 0 only checks the fds and a negative value waits forever
+fn sys_poll(fds: u64, nfds: u64, timeout: u64, _: u64, _: u64, _: u64) -> SyscallResult {
+    if nfds > MAX_POLL_FDS {
+        return Err(EINVAL);
+    }
+    // no fds may be passed with a null pointer
+    let fds = if nfds == 0 { &mut [] } else { user_slice::<PollFd>(fds, nfds)? };
+    let nready = poll_fds(fds);
+    let timeout = timeout as i32;
+    if nready > 0 || timeout == 0 || !can_block() {
+        return Ok(nready as isize);
+    }
+
+    let timeout = if timeout < 0 {
+        None
+    } else {
+        Some(Duration::from_millis(timeout as u64))
+    };
+    let nready = block_until(timeout, || match poll_fds(fds) {
+        0 => None,
+        nready => Some(nready),
+    });
+    return Ok(nready.unwrap_or(0) as isize);
+}
+
+// only SIGCHLD is supported for now. the handler returns into the restorer, which calls sigreturn.
+// both must be in the memory of the process, and 0 for the handler ignores the signal
+fn sys_sigaction(signal: u64, handler: u64, restorer: u64, _: u64, _: u64, _: u64) -> SyscallResult {
+    if signal as usize != SIGCHLD {
+        return Err(EINVAL);
+    }
+    if handler != 0 {
+        check_user_range(handler, 1)?;
+        check_user_range(restorer, 1)?;
+    }
+    let manager = unsafe { PROCESS_MANAGER.get_mut().unwrap() };
+    manager.current().borrow_mut().set_signal_action(signal as usize, SignalAction { handler, restorer });
+    return Ok(0);
+}
+
+// restore the registers which were saved as the signal was delivered.
+// the return value is the one of the syscall which the signal interrupted
+fn sys_sigreturn(_: u64, _: u64, _: u64, _: u64, _: u64, _: u64) -> SyscallResult {
+    let manager = unsafe { PROCESS_MANAGER.get().unwrap() };
+    let context = manager.current().borrow_mut().leave_signal_handler().ok_or(EINVAL)?;
+    *syscall_frame() = context.registers;
+    return Ok(context.ret);
+}
+
+// nobody can get the status yet, so it's ignored
+fn sys_exit(_status: u64, _: u64, _: u64, _: u64, _: u64, _: u64) -> SyscallResult {
+    let manager = unsafe { PROCESS_MANAGER.get_mut().unwrap() };
+    let id = manager.current().borrow().id();
+    if id == KERNEL_TASK_ID {
+        return Err(EPERM);
+    }
+    manager.prepare_terminate(id);
+    unreachable!()
+}
+
+const RUSAGE_SELF: i64 = 0;
+
+#[repr(C)]
+struct Timeval {
+    sec: i64,
+    usec: i64,
+}
+
+// same layout as struct rusage of Linux. only ru_utime is filled
+#[repr(C)]
+struct Rusage {
+    utime: Timeval,
+    stime: Timeval,
+    rest: [i64; 14],
+}
+
+// the time in the kernel isn't counted separately, so the whole cpu time is reported as ru_utime.
+// the time is counted in ticks, so it's in whole seconds while TICKS_PER_SECOND is 1
+fn sys_getrusage(who: u64, usage: u64, _: u64, _: u64, _: u64, _: u64) -> SyscallResult {
+    if who as i64 != RUSAGE_SELF {
+        return Err(EINVAL);
+    }
+    let usage = user_ptr::<Rusage>(usage)?;
+    let manager = unsafe { PROCESS_MANAGER.get().unwrap() };
+    let ticks = manager.current().borrow().cpu_ticks();
+    let value = Rusage {
+        utime: Timeval {
+            sec: (ticks / TICKS_PER_SECOND) as i64,
+            usec: ((ticks % TICKS_PER_SECOND) * 1_000_000 / TICKS_PER_SECOND) as i64,
+        },
+        stime: Timeval { sec: 0, usec: 0 },
+        rest: [0; 14],
+    };
+    unsafe { usage.write_unaligned(value) };
+    return Ok(0);
+}
+
+fn sys_getrlimit(resource: u64, rlim: u64, _: u64, _: u64, _: u64, _: u64) -> SyscallResult {
+    let rlim = user_ptr::<Rlimit>(rlim)?;
+    let manager = unsafe { PROCESS_MANAGER.get().unwrap() };
+    let limit = *manager.current().borrow_mut().limits_mut().get_mut(resource).ok_or(EINVAL)?;
+    unsafe { rlim.write_unaligned(limit) };
+    return Ok(0);
+}
+
+// there are no privileged users, so the hard limit can only be lowered.
+// the new limits are checked at the next allocation, even if the usage is already over them
+fn sys_setrlimit(resource: u64, rlim: u64, _: u64, _: u64, _: u64, _: u64) -> SyscallResult {
+    let new = unsafe { user_ptr::<Rlimit>(rlim)?.read_unaligned() };
+    if new.cur > new.max {
+        return Err(EINVAL);
+    }
+    let manager = unsafe { PROCESS_MANAGER.get().unwrap() };
+    let current = manager.current();
+    let mut proc = current.borrow_mut();
+    let limit = proc.limits_mut().get_mut(resource).ok_or(EINVAL)?;
+    if new.max > limit.max {
+        return Err(EPERM);
+    }
+    *limit = new;
+    return Ok(0);
+}
+
+// copy the newest kernel messages into the buffer
+fn sys_dmesg(buf: u64, len: u64, _: u64, _: u64, _: u64, _: u64) -> SyscallResult {
+    let buf = user_buffer(buf, len)?;
+    let nread = KERNEL_LOG.lock().read(buf);
+    return Ok(nread as isize);
+}
+
+fn sys_set_log_level(level: u64, _: u64, _: u64, _: u64, _: u64, _: u64) -> SyscallResult {
+    let level = LogLevel::try_from(level as usize).map_err(|_| EINVAL)?;
+    set_log_level(level);
+    return Ok(0);
+}
+
+// usage of the kernel heap in bytes
+#[repr(C)]
+struct HeapStats {
+    allocated: u64,
+    free: u64,
+    peak: u64,
+}
+
+fn sys_heap_stats(stats: u64, _: u64, _: u64, _: u64, _: u64, _: u64) -> SyscallResult {
+    let stats = user_ptr::<HeapStats>(stats)?;
+    let value = HeapStats {
+        allocated: ALLOCATOR.bytes_allocated() as u64,
+        free: ALLOCATOR.bytes_free() as u64,
+        peak: ALLOCATOR.peak_usage() as u64,
+    };
+    unsafe { stats.write_unaligned(value) };
+    return Ok(0);
+}
+
+// an unknown layout selects US and returns EINVAL
+fn sys_set_keyboard_layout(name: u64, _: u64, _: u64, _: u64, _: u64, _: u64) -> SyscallResult {
+    if !set_layout(&user_str(name)?) {
+        return Err(EINVAL);
+    }
+    return Ok(0);
+}
+
+// the creator maps the segment at once, so it lives until the creator unmaps it or exits
+fn sys_shm_create(size: u64, _: u64, _: u64, _: u64, _: u64, _: u64) -> SyscallResult {
+    let manager = unsafe { PROCESS_MANAGER.get().unwrap() };
+    let current = manager.current();
+    // rounded up to the frames, as the memory which the process already has
+    let frame = BYTES_PER_FRAME as u64;
+    let bytes = size.checked_add(frame - 1).ok_or(ENOMEM)? / frame * frame;
+    {
+        let proc = current.borrow();
+        if !proc.limits().memory.allows(proc.memory_bytes() + bytes) {
+            return Err(ENOMEM);
+        }
+    }
+    let segment = shm::create(size as usize)?;
+    let id = segment.id();
+    current.borrow_mut().map_shared(segment);
+    return Ok(id as isize);
+}
+
+// returns the address, which is the same in every process
+fn sys_shm_map(id: u64, _: u64, _: u64, _: u64, _: u64, _: u64) -> SyscallResult {
+    let segment = shm::get(id as usize).ok_or(EINVAL)?;
+    let manager = unsafe { PROCESS_MANAGER.get().unwrap() };
+    let current = manager.current();
+    let mut proc = current.borrow_mut();
+    // mapping it again doesn't add to the memory
+    let mapped = proc.user_range_end(segment.addr()).is_some();
+    if !mapped && !proc.limits().memory.allows(proc.memory_bytes() + segment.size() as u64) {
+        return Err(ENOMEM);
+    }
+    let addr = proc.map_shared(segment);
+    return Ok(addr as isize);
+}
+
+// the frames are freed when the last process unmaps the segment.
+// the pages stay accessible from ring 3 until then, see shm
+fn sys_shm_unmap(id: u64, _: u64, _: u64, _: u64, _: u64, _: u64) -> SyscallResult {
+    let manager = unsafe { PROCESS_MANAGER.get().unwrap() };
+    if !manager.current().borrow_mut().unmap_shared(id as usize) {
+        return Err(EINVAL);
+    }
+    return Ok(0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_numbers_map_to_their_handlers() {
+        let table: [(u64, SyscallNumber, SyscallHandler); 26] = [
+            (0, SyscallNumber::Read, sys_read),
+            (1, SyscallNumber::Write, sys_write),
+            (2, SyscallNumber::Open, sys_open),
+            (3, SyscallNumber::Close, sys_close),
+            (4, SyscallNumber::Stat, sys_stat),
+            (7, SyscallNumber::Poll, sys_poll),
+            (8, SyscallNumber::Lseek, sys_lseek),
+            (9, SyscallNumber::Mmap, sys_mmap),
+            (13, SyscallNumber::Sigaction, sys_sigaction),
+            (15, SyscallNumber::Sigreturn, sys_sigreturn),
+            (60, SyscallNumber::Exit, sys_exit),
+            (77, SyscallNumber::Ftruncate, sys_ftruncate),
+            (79, SyscallNumber::Getcwd, sys_getcwd),
+            (80, SyscallNumber::Chdir, sys_chdir),
+            (82, SyscallNumber::Rename, sys_rename),
+            (87, SyscallNumber::Unlink, sys_unlink),
+            (97, SyscallNumber::Getrlimit, sys_getrlimit),
+            (98, SyscallNumber::Getrusage, sys_getrusage),
+            (103, SyscallNumber::Dmesg, sys_dmesg),
+            (160, SyscallNumber::Setrlimit, sys_setrlimit),
+            (512, SyscallNumber::SetLogLevel, sys_set_log_level),
+            (513, SyscallNumber::HeapStats, sys_heap_stats),
+            (514, SyscallNumber::SetKeyboardLayout, sys_set_keyboard_layout),
+            (515, SyscallNumber::ShmCreate, sys_shm_create),
+            (516, SyscallNumber::ShmMap, sys_shm_map),
+            (517, SyscallNumber::ShmUnmap, sys_shm_unmap),
+        ];
+        for (number, syscall, handler) in table {
+            assert_eq!(SyscallNumber::try_from(number), Ok(syscall));
+            assert_eq!(syscall as u64, number);
+            assert_eq!(syscall.handler() as usize, handler as usize, "{:?}", syscall);
+        }
+    }
+
+    #[test]
+    fn unknown_numbers_return_enosys() {
+        for number in [5, 511, 518, u64::MAX] {
+            assert_eq!(SyscallNumber::try_from(number), Err(ENOSYS));
+            assert_eq!(dispatch(number, 0, 0, 0, 0, 0, 0), -(ENOSYS as isize));
+        }
+    }
+
+    #[test]
+    fn stat_has_the_layout_of_linux() {
+        assert_eq!(size_of::<Stat>(), 144);
+        assert_eq!(core::mem::offset_of!(Stat, mode), 24);
+        assert_eq!(core::mem::offset_of!(Stat, size), 48);
+    }
+
+    #[test]
+    fn open_fails_with_emfile_at_the_fd_limit() {
+        let limit = Rlimit { cur: 5, max: 5 };
+        let mut fds = ProcessFds::with_stdio();
+        let fd = fds.add(3);
+        assert!(check_fd_limit(&fds, &limit).is_ok());
+        fds.add(4);
+        assert_eq!(check_fd_limit(&fds, &limit), Err(EMFILE));
+        // closing one allows a new open
+        fds.remove(fd);
+        assert!(check_fd_limit(&fds, &limit).is_ok());
+        assert_eq!(fds.add(5), fd);
+        assert_eq!(check_fd_limit(&fds, &limit), Err(EMFILE));
+    }
+}
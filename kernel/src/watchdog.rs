@@ -0,0 +1,59 @@
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use x86_64::structures::idt::InterruptStackFrame;
+
+use crate::{proc::PROCESS_MANAGER, warn, Message, INTERRUPTION_QUEUE};
+
+// timer ticks without feeding before the main loop is regarded as hung
+const DEFAULT_THRESHOLD: u64 = 5;
+
+static COUNTER: AtomicU64 = AtomicU64::new(0);
+static THRESHOLD: AtomicU64 = AtomicU64::new(DEFAULT_THRESHOLD);
+static FORCE_SWITCH: AtomicBool = AtomicBool::new(false);
+// what was running when the watchdog fired last. the process is 0 when it's unknown
+static STUCK_COUNT: AtomicU64 = AtomicU64::new(0);
+static STUCK_RIP: AtomicU64 = AtomicU64::new(0);
+static STUCK_PROCESS: AtomicUsize = AtomicUsize::new(0);
+
+// called by the main loop every time it runs
+pub fn feed() {
+    COUNTER.store(0, Ordering::Relaxed);
+}
+
+pub fn set_threshold(ticks: u64) {
+    THRESHOLD.store(ticks, Ordering::Relaxed);
+}
+
+// switch away from the running process when the watchdog fires
+pub fn set_force_switch(enable: bool) {
+    FORCE_SWITCH.store(enable, Ordering::Relaxed);
+}
+
+// called from the timer interrupt, so a process spinning with interrupts enabled can't stop it.
+// it only records what was running, and the main loop logs it by report. the lock of the console can be
+// held by the interrupted code. returns true when the caller should switch the process
+pub fn tick(stack_frame: &InterruptStackFrame) -> bool {
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed) + 1;
+    let threshold = THRESHOLD.load(Ordering::Relaxed);
+    if threshold == 0 || count % threshold != 0 {
+        return false;
+    }
+    // the interrupted code may be borrowing the process
+    let process = unsafe { PROCESS_MANAGER.get() }
+        .and_then(|manager| manager.current().try_borrow().map(|proc| proc.id()).ok())
+        .unwrap_or(0);
+    STUCK_COUNT.store(count, Ordering::Relaxed);
+    STUCK_RIP.store(stack_frame.instruction_pointer.as_u64(), Ordering::Relaxed);
+    STUCK_PROCESS.store(process, Ordering::Relaxed);
+    INTERRUPTION_QUEUE.push(Message::Watchdog);
+    return FORCE_SWITCH.load(Ordering::Relaxed);
+}
+
+// called by the main loop for Message::Watchdog
+pub fn report() {
+    let count = STUCK_COUNT.load(Ordering::Relaxed);
+    let rip = STUCK_RIP.load(Ordering::Relaxed);
+    match STUCK_PROCESS.load(Ordering::Relaxed) {
+        0 => warn!("watchdog: not fed for {} ticks, RIP: {:#x}", count, rip),
+        process => warn!("watchdog: not fed for {} ticks, process: {}, RIP: {:#x}", count, process, rip),
+    }
+}
@@ -1,10 +1,10 @@
 use alloc::{vec, vec::Vec};
-use core::ptr::null_mut;
+use core::ptr::{copy_nonoverlapping, null_mut};
 
 use crate::{
     container_of,
     framebuffer::{FrameBuffer, FrameBufferConfig},
-    graphics::{Coord, PixelColor, PixelWriter},
+    graphics::{Coord, PixelColor, PixelWriter, Rect},
 };
 use libloader::PixelFormat;
 
@@ -28,6 +28,92 @@ impl WindowWriter {
     pub fn size(&self) -> (usize, usize) {
         (self.0, self.1)
     }
+
+    // the pixels out of the window are ignored
+    fn write_clipped(&self, x: isize, y: isize, c: &PixelColor) {
+        if x < 0 || y < 0 || x as usize >= self.0 || y as usize >= self.1 {
+            return;
+        }
+        self.write(x as usize, y as usize, c);
+    }
+
+    // Bresenham's line algorithm
+    pub fn draw_line(&self, start: Coord, end: Coord, c: &PixelColor) {
+        let (mut x, mut y) = (start.x as isize, start.y as isize);
+        let (x1, y1) = (end.x as isize, end.y as isize);
+        let dx = (x1 - x).abs();
+        let dy = -(y1 - y).abs();
+        let sx = if x < x1 { 1 } else { -1 };
+        let sy = if y < y1 { 1 } else { -1 };
+        let mut err = dx + dy;
+        loop {
+            self.write_clipped(x, y, c);
+            if x == x1 && y == y1 {
+                break;
+            }
+            let e2 = 2 * err;
+            if e2 >= dy {
+                err += dy;
+                x += sx;
+            }
+            if e2 <= dx {
+                err += dx;
+                y += sy;
+            }
+        }
+    }
+
+    pub fn fill_rect(&self, pos: Coord, size: Coord, c: &PixelColor) {
+        let end = (pos + size).elem_min(Coord::new(self.0, self.1));
+        if pos.x >= end.x || pos.y >= end.y {
+            return;
+        }
+        let window = container_of!(self, mutable Window, writer);
+        for y in pos.y..end.y {
+            for x in pos.x..end.x {
+                window.data[x][y] = *c;
+            }
+            window
+                .shadow_buffer
+                .writer
+                .write_row(pos.x, y, end.x - pos.x, c);
+        }
+    }
+
+    pub fn draw_rect(&self, pos: Coord, size: Coord, c: &PixelColor) {
+        if size.x == 0 || size.y == 0 {
+            return;
+        }
+        self.fill_rect(pos, Coord::new(size.x, 1), c);
+        self.fill_rect(Coord::new(pos.x, pos.y + size.y - 1), Coord::new(size.x, 1), c);
+        self.fill_rect(pos, Coord::new(1, size.y), c);
+        self.fill_rect(Coord::new(pos.x + size.x - 1, pos.y), Coord::new(1, size.y), c);
+    }
+
+    // midpoint circle algorithm
+    pub fn draw_circle(&self, center: Coord, radius: usize, c: &PixelColor) {
+        let (cx, cy) = (center.x as isize, center.y as isize);
+        let mut x = radius as isize;
+        let mut y = 0;
+        let mut err = 1 - x;
+        while x >= y {
+            self.write_clipped(cx + x, cy + y, c);
+            self.write_clipped(cx + y, cy + x, c);
+            self.write_clipped(cx - y, cy + x, c);
+            self.write_clipped(cx - x, cy + y, c);
+            self.write_clipped(cx - x, cy - y, c);
+            self.write_clipped(cx - y, cy - x, c);
+            self.write_clipped(cx + y, cy - x, c);
+            self.write_clipped(cx + x, cy - y, c);
+            y += 1;
+            if err < 0 {
+                err += 2 * y + 1;
+            } else {
+                x -= 1;
+                err += 2 * (y - x) + 1;
+            }
+        }
+    }
 }
 
 #[derive(Clone, Default, Debug, PartialEq)]
@@ -71,6 +157,114 @@ impl Window {
         &self.data[x][y]
     }
 
+    fn set(&mut self, x: usize, y: usize, c: &PixelColor) {
+        self.data[x][y] = *c;
+        self.shadow_buffer.writer.write(x, y, c);
+    }
+
+    fn format(&self) -> PixelFormat {
+        self.shadow_buffer.config.format
+    }
+
+    // the part of the rect at pos which is in the window
+    fn clip(&self, pos: Coord, size: Coord) -> Option<Coord> {
+        let end = (pos + size).elem_min(Coord::new(self.width, self.height));
+        if pos.x >= end.x || pos.y >= end.y {
+            return None;
+        }
+        Some(end - pos)
+    }
+
+    // draws the pixels in src, which have the pixel format of this window. src_stride is in pixels.
+    // the source pixels of the transparent color of this window are skipped
+    pub fn blit(&mut self, src: &[u8], src_stride: usize, dst: Coord, size: Coord) {
+        let size = match self.clip(dst, size) {
+            Some(size) => size,
+            None => return,
+        };
+        let format = self.format();
+        let bpp = FrameBuffer::bytes_per_pixel(format);
+        let src = &src[..bpp * (src_stride * (size.y - 1) + size.x)];
+
+        if self.transparent_color.is_none() {
+            unsafe {
+                let dst_buf = FrameBuffer::frame_addr_at(dst, &self.shadow_buffer.config);
+                if src_stride == size.x && size.x == self.shadow_buffer.config.stride {
+                    // the rows are contiguous on both sides
+                    copy_nonoverlapping(src.as_ptr(), dst_buf, src.len());
+                } else {
+                    let dst_bpsl = bpp * self.shadow_buffer.config.stride;
+                    for dy in 0..size.y {
+                        copy_nonoverlapping(
+                            src.as_ptr().add(bpp * src_stride * dy),
+                            dst_buf.add(dst_bpsl * dy),
+                            bpp * size.x,
+                        );
+                    }
+                }
+            }
+            for dy in 0..size.y {
+                for dx in 0..size.x {
+                    let i = bpp * (src_stride * dy + dx);
+                    self.data[dst.x + dx][dst.y + dy] = PixelColor::decode(format, &src[i..i + bpp]);
+                }
+            }
+        } else {
+            for dy in 0..size.y {
+                for dx in 0..size.x {
+                    let i = bpp * (src_stride * dy + dx);
+                    let c = PixelColor::decode(format, &src[i..i + bpp]);
+                    if Some(c) != self.transparent_color {
+                        self.set(dst.x + dx, dst.y + dy, &c);
+                    }
+                }
+            }
+        }
+    }
+
+    // copies src_rect of other to dst. the pixels of the transparent color of other are skipped
+    pub fn copy_from(&mut self, other: &Window, src_rect: Rect, dst: Coord) {
+        let size = match other.clip(src_rect.pos, src_rect.size) {
+            Some(size) => size,
+            None => return,
+        };
+        let size = match self.clip(dst, size) {
+            Some(size) => size,
+            None => return,
+        };
+        let src = src_rect.pos;
+
+        if other.transparent_color.is_none() && other.format() == self.format() {
+            let bpp = FrameBuffer::bytes_per_pixel(self.format());
+            let src_bpsl = bpp * other.shadow_buffer.config.stride;
+            let dst_bpsl = bpp * self.shadow_buffer.config.stride;
+            unsafe {
+                let src_buf = FrameBuffer::frame_addr_at(src, &other.shadow_buffer.config);
+                let dst_buf = FrameBuffer::frame_addr_at(dst, &self.shadow_buffer.config);
+                for dy in 0..size.y {
+                    copy_nonoverlapping(
+                        src_buf.add(src_bpsl * dy),
+                        dst_buf.add(dst_bpsl * dy),
+                        bpp * size.x,
+                    );
+                }
+            }
+            for dx in 0..size.x {
+                self.data[dst.x + dx][dst.y..dst.y + size.y]
+                    .copy_from_slice(&other.data[src.x + dx][src.y..src.y + size.y]);
+            }
+        } else {
+            for dy in 0..size.y {
+                for dx in 0..size.x {
+                    let c = *other.at(src.x + dx, src.y + dy);
+                    if Some(c) != other.transparent_color {
+                        self.set(dst.x + dx, dst.y + dy, &c);
+                    }
+                }
+            }
+        }
+    }
+
     pub fn draw_to(&self, fb: &mut FrameBuffer, position: Coord) {
         if self.transparent_color.is_none() {
             unsafe {
@@ -91,3 +285,45 @@ impl Window {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: PixelColor = PixelColor(255, 0, 0);
+
+    // the bytes of the pixel in the shadow buffer, which is BGR
+    fn shadow_pixel(window: &Window, x: usize, y: usize) -> [u8; 3] {
+        let addr = unsafe { FrameBuffer::frame_addr_at(Coord::new(x, y), &window.shadow_buffer.config) };
+        return unsafe { [*addr, *addr.add(1), *addr.add(2)] };
+    }
+
+    fn is_red(window: &Window, x: usize, y: usize) -> bool {
+        return *window.at(x, y) == RED && shadow_pixel(window, x, y) == [0, 0, 255];
+    }
+
+    #[test]
+    fn filled_rect_has_corner_pixels() {
+        let mut window = Window::new(8, 6, PixelFormat::Bgr);
+        window.writer().fill_rect(Coord::new(2, 1), Coord::new(3, 4), &RED);
+        for (x, y) in [(2, 1), (4, 1), (2, 4), (4, 4)] {
+            assert!(is_red(&window, x, y), "({}, {})", x, y);
+        }
+        // just outside the corners
+        for (x, y) in [(1, 1), (5, 1), (2, 0), (2, 5), (5, 4), (4, 5)] {
+            assert!(!is_red(&window, x, y), "({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    fn filled_rect_is_clipped() {
+        let mut window = Window::new(8, 6, PixelFormat::Bgr);
+        window.writer().fill_rect(Coord::new(6, 4), Coord::new(10, 10), &RED);
+        for (x, y) in [(6, 4), (7, 4), (6, 5), (7, 5)] {
+            assert!(is_red(&window, x, y), "({}, {})", x, y);
+        }
+        assert!(!is_red(&window, 5, 5));
+        window.writer().fill_rect(Coord::new(8, 0), Coord::new(2, 2), &RED);
+        assert!(!is_red(&window, 7, 0));
+    }
+}
@@ -0,0 +1,440 @@
+use core::ops::Range;
+
+const ELF_MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const EM_X86_64: u16 = 0x3e;
+const ELF64_HEADER_SIZE: usize = 64;
+const ELF64_PHDR_SIZE: usize = 56;
+
+pub const ET_EXEC: u16 = 2;
+pub const ET_DYN: u16 = 3;
+pub const PT_LOAD: u32 = 1;
+pub const PT_DYNAMIC: u32 = 2;
+
+const DT_NULL: u64 = 0;
+const DT_RELA: u64 = 7;
+const DT_RELASZ: u64 = 8;
+const DT_RELAENT: u64 = 9;
+const ELF64_DYN_SIZE: usize = 16;
+const ELF64_RELA_SIZE: usize = 24;
+const R_X86_64_NONE: u32 = 0;
+const R_X86_64_RELATIVE: u32 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfError {
+    TooShort,
+    InvalidMagic,
+    NotElf64,
+    NotLittleEndian,
+    UnsupportedMachine(u16),
+    UnsupportedType(u16),
+    InvalidProgramHeader,
+    SegmentOutOfFile { index: usize },
+    SegmentSizeMismatch { index: usize },
+    SegmentInKernelSpace { index: usize },
+    InvalidDynamic,
+    UnsupportedRelocation(u32),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ProgramHeader {
+    pub p_type: u32,
+    pub p_flags: u32,
+    pub p_offset: u64,
+    pub p_vaddr: u64,
+    pub p_filesz: u64,
+    pub p_memsz: u64,
+    pub p_align: u64,
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    return u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap());
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    return u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    return u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap());
+}
+
+pub fn elf_type(bytes: &[u8]) -> u16 {
+    return read_u16(bytes, 16);
+}
+
+pub fn entry(bytes: &[u8]) -> u64 {
+    return read_u64(bytes, 24);
+}
+
+// the caller must validate the header before iterating program headers
+pub fn program_headers(bytes: &[u8]) -> impl Iterator<Item = ProgramHeader> + '_ {
+    let phoff = read_u64(bytes, 32) as usize;
+    let phentsize = read_u16(bytes, 54) as usize;
+    let phnum = read_u16(bytes, 56) as usize;
+    return (0..phnum).map(move |i| {
+        let ph = phoff + i * phentsize;
+        ProgramHeader {
+            p_type: read_u32(bytes, ph),
+            p_flags: read_u32(bytes, ph + 4),
+            p_offset: read_u64(bytes, ph + 8),
+            p_vaddr: read_u64(bytes, ph + 16),
+            p_filesz: read_u64(bytes, ph + 32),
+            p_memsz: read_u64(bytes, ph + 40),
+            p_align: read_u64(bytes, ph + 48),
+        }
+    });
+}
+
+fn validate_header(bytes: &[u8]) -> Result<(), ElfError> {
+    if bytes.len() < ELF64_HEADER_SIZE {
+        return Err(ElfError::TooShort);
+    }
+    if bytes[0..4] != ELF_MAGIC {
+        return Err(ElfError::InvalidMagic);
+    }
+    if bytes[4] != ELFCLASS64 {
+        return Err(ElfError::NotElf64);
+    }
+    if bytes[5] != ELFDATA2LSB {
+        return Err(ElfError::NotLittleEndian);
+    }
+    let machine = read_u16(bytes, 18);
+    if machine != EM_X86_64 {
+        return Err(ElfError::UnsupportedMachine(machine));
+    }
+    let ty = elf_type(bytes);
+    if ty != ET_EXEC && ty != ET_DYN {
+        return Err(ElfError::UnsupportedType(ty));
+    }
+
+    let phoff = read_u64(bytes, 32) as usize;
+    let phentsize = read_u16(bytes, 54) as usize;
+    let phnum = read_u16(bytes, 56) as usize;
+    let table_end = phoff.checked_add(phentsize * phnum);
+    if phentsize != ELF64_PHDR_SIZE || table_end.map_or(true, |end| end > bytes.len()) {
+        return Err(ElfError::InvalidProgramHeader);
+    }
+    return Ok(());
+}
+
+// check the header and that every PT_LOAD segment can be loaded from the buffer
+pub fn validate(bytes: &[u8]) -> Result<(), ElfError> {
+    validate_header(bytes)?;
+    for (index, ph) in program_headers(bytes).enumerate() {
+        if ph.p_type != PT_LOAD {
+            continue;
+        }
+        let file_end = ph.p_offset.checked_add(ph.p_filesz);
+        if file_end.map_or(true, |end| end > bytes.len() as u64) {
+            return Err(ElfError::SegmentOutOfFile { index });
+        }
+        if ph.p_filesz > ph.p_memsz || ph.p_vaddr.checked_add(ph.p_memsz).is_none() {
+            return Err(ElfError::SegmentSizeMismatch { index });
+        }
+    }
+    return Ok(());
+}
+
+// for programs loaded by the kernel, every PT_LOAD segment must be out of the kernel space
+pub fn validate_outside(bytes: &[u8], kernel_space: Range<u64>) -> Result<(), ElfError> {
+    validate(bytes)?;
+    for (index, ph) in program_headers(bytes).enumerate() {
+        if ph.p_type != PT_LOAD {
+            continue;
+        }
+        let end = ph.p_vaddr + ph.p_memsz;
+        if ph.p_vaddr < kernel_space.end && kernel_space.start < end {
+            return Err(ElfError::SegmentInKernelSpace { index });
+        }
+    }
+    return Ok(());
+}
+
+pub fn is_pie(bytes: &[u8]) -> bool {
+    return elf_type(bytes) == ET_DYN;
+}
+
+// the offset added to every vaddr. ET_EXEC is always loaded at its fixed addresses.
+// it wraps when the base is below the lowest vaddr, so add it by wrapping_add
+pub fn load_bias(bytes: &[u8], base: u64) -> u64 {
+    if !is_pie(bytes) {
+        return 0;
+    }
+    let lowest = program_headers(bytes)
+        .filter(|ph| ph.p_type == PT_LOAD)
+        .map(|ph| ph.p_vaddr & !0xfff)
+        .min()
+        .unwrap_or(0);
+    return (base & !0xfff).wrapping_sub(lowest);
+}
+
+// translate a vaddr to the offset in the file through the PT_LOAD segments
+fn vaddr_to_offset(bytes: &[u8], vaddr: u64) -> Option<usize> {
+    return program_headers(bytes)
+        .filter(|ph| ph.p_type == PT_LOAD)
+        .find(|ph| ph.p_vaddr <= vaddr && vaddr < ph.p_vaddr + ph.p_filesz)
+        .map(|ph| (vaddr - ph.p_vaddr + ph.p_offset) as usize);
+}
+
+// call f with the offset and the addend of every R_X86_64_RELATIVE relocation in .rela.dyn.
+// the relocated value is the addend plus the load bias
+pub fn relative_relocations(bytes: &[u8], mut f: impl FnMut(u64, u64)) -> Result<(), ElfError> {
+    let dynamic = match program_headers(bytes).find(|ph| ph.p_type == PT_DYNAMIC) {
+        Some(ph) => ph,
+        // statically linked without relocations
+        None => return Ok(()),
+    };
+    let start = dynamic.p_offset as usize;
+    let end = match start.checked_add(dynamic.p_filesz as usize) {
+        Some(end) if end <= bytes.len() => end,
+        _ => return Err(ElfError::InvalidDynamic),
+    };
+
+    let (mut rela, mut relasz, mut relaent) = (None, 0, ELF64_RELA_SIZE);
+    for entry in (start..end).step_by(ELF64_DYN_SIZE).take_while(|e| e + ELF64_DYN_SIZE <= end) {
+        let tag = read_u64(bytes, entry);
+        let value = read_u64(bytes, entry + 8);
+        match tag {
+            DT_NULL => break,
+            DT_RELA => rela = Some(value),
+            DT_RELASZ => relasz = value as usize,
+            DT_RELAENT => relaent = value as usize,
+            _ => {}
+        }
+    }
+    let rela = match rela {
+        Some(vaddr) => vaddr_to_offset(bytes, vaddr).ok_or(ElfError::InvalidDynamic)?,
+        None => return Ok(()),
+    };
+    let rela_end = match rela.checked_add(relasz) {
+        Some(end) if end <= bytes.len() => end,
+        _ => return Err(ElfError::InvalidDynamic),
+    };
+    // a partial entry at the end would be read out of the table
+    if relaent != ELF64_RELA_SIZE || relasz % ELF64_RELA_SIZE != 0 {
+        return Err(ElfError::InvalidDynamic);
+    }
+
+    for entry in (rela..rela_end).step_by(ELF64_RELA_SIZE) {
+        let offset = read_u64(bytes, entry);
+        let ty = read_u64(bytes, entry + 8) as u32;
+        let addend = read_u64(bytes, entry + 16);
+        match ty {
+            R_X86_64_NONE => {}
+            R_X86_64_RELATIVE => f(offset, addend),
+            _ => return Err(ElfError::UnsupportedRelocation(ty)),
+        }
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::{vec, vec::Vec};
+
+    const DYNAMIC: usize = ELF64_HEADER_SIZE + 2 * ELF64_PHDR_SIZE;
+    const RELA: usize = DYNAMIC + 4 * ELF64_DYN_SIZE;
+    const IMAGE_SIZE: usize = RELA + ELF64_RELA_SIZE;
+
+    fn write_u16(bytes: &mut [u8], offset: usize, value: u16) {
+        bytes[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn write_u32(bytes: &mut [u8], offset: usize, value: u32) {
+        bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn write_u64(bytes: &mut [u8], offset: usize, value: u64) {
+        bytes[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn write_phdr(bytes: &mut [u8], index: usize, p_type: u32, offset: u64, filesz: u64, memsz: u64) {
+        let ph = ELF64_HEADER_SIZE + index * ELF64_PHDR_SIZE;
+        write_u32(bytes, ph, p_type);
+        write_u64(bytes, ph + 8, offset);
+        // vaddr is the same as the offset
+        write_u64(bytes, ph + 16, offset);
+        write_u64(bytes, ph + 32, filesz);
+        write_u64(bytes, ph + 40, memsz);
+        write_u64(bytes, ph + 48, 0x1000);
+    }
+
+    // ET_DYN whose PT_LOAD covers the whole file, with a PT_DYNAMIC and one R_X86_64_RELATIVE
+    fn minimal_pie() -> Vec<u8> {
+        let mut bytes = vec![0; IMAGE_SIZE];
+        bytes[0..4].copy_from_slice(&ELF_MAGIC);
+        bytes[4] = ELFCLASS64;
+        bytes[5] = ELFDATA2LSB;
+        write_u16(&mut bytes, 16, ET_DYN);
+        write_u16(&mut bytes, 18, EM_X86_64);
+        write_u64(&mut bytes, 24, 0x100);
+        write_u64(&mut bytes, 32, ELF64_HEADER_SIZE as u64);
+        write_u16(&mut bytes, 54, ELF64_PHDR_SIZE as u16);
+        write_u16(&mut bytes, 56, 2);
+        write_phdr(&mut bytes, 0, PT_LOAD, 0, IMAGE_SIZE as u64, 0x2000);
+        write_phdr(&mut bytes, 1, PT_DYNAMIC, DYNAMIC as u64, 4 * ELF64_DYN_SIZE as u64, 4 * ELF64_DYN_SIZE as u64);
+        let dynamic = [(DT_RELA, RELA as u64), (DT_RELASZ, ELF64_RELA_SIZE as u64), (DT_RELAENT, ELF64_RELA_SIZE as u64), (DT_NULL, 0)];
+        for (i, (tag, value)) in dynamic.into_iter().enumerate() {
+            write_u64(&mut bytes, DYNAMIC + i * ELF64_DYN_SIZE, tag);
+            write_u64(&mut bytes, DYNAMIC + i * ELF64_DYN_SIZE + 8, value);
+        }
+        write_u64(&mut bytes, RELA, 0x1008);
+        write_u64(&mut bytes, RELA + 8, R_X86_64_RELATIVE as u64);
+        write_u64(&mut bytes, RELA + 16, 0x200);
+        return bytes;
+    }
+
+    fn relocations(bytes: &[u8]) -> Result<Vec<(u64, u64)>, ElfError> {
+        let mut relocations = Vec::new();
+        relative_relocations(bytes, |offset, addend| relocations.push((offset, addend)))?;
+        return Ok(relocations);
+    }
+
+    #[test]
+    fn minimal_pie_is_valid() {
+        let bytes = minimal_pie();
+        assert_eq!(validate(&bytes), Ok(()));
+        assert!(is_pie(&bytes));
+        assert_eq!(entry(&bytes), 0x100);
+    }
+
+    #[test]
+    fn fixed_executable_is_not_pie() {
+        let mut bytes = minimal_pie();
+        write_u16(&mut bytes, 16, ET_EXEC);
+        assert_eq!(validate(&bytes), Ok(()));
+        assert!(!is_pie(&bytes));
+    }
+
+    #[test]
+    fn truncated_header_is_too_short() {
+        let bytes = minimal_pie();
+        assert_eq!(validate(&bytes[..ELF64_HEADER_SIZE - 1]), Err(ElfError::TooShort));
+        assert_eq!(validate(&[]), Err(ElfError::TooShort));
+    }
+
+    #[test]
+    fn header_fields_are_checked() {
+        let mut bytes = minimal_pie();
+        bytes[0] = 0;
+        assert_eq!(validate(&bytes), Err(ElfError::InvalidMagic));
+        let mut bytes = minimal_pie();
+        bytes[4] = 1;
+        assert_eq!(validate(&bytes), Err(ElfError::NotElf64));
+        let mut bytes = minimal_pie();
+        write_u16(&mut bytes, 18, 0x28);
+        assert_eq!(validate(&bytes), Err(ElfError::UnsupportedMachine(0x28)));
+        let mut bytes = minimal_pie();
+        write_u16(&mut bytes, 16, 1);
+        assert_eq!(validate(&bytes), Err(ElfError::UnsupportedType(1)));
+    }
+
+    #[test]
+    fn truncated_program_headers_are_invalid() {
+        let bytes = minimal_pie();
+        assert_eq!(validate(&bytes[..DYNAMIC - 1]), Err(ElfError::InvalidProgramHeader));
+    }
+
+    #[test]
+    fn bad_phoff_is_invalid() {
+        let mut bytes = minimal_pie();
+        write_u64(&mut bytes, 32, IMAGE_SIZE as u64);
+        assert_eq!(validate(&bytes), Err(ElfError::InvalidProgramHeader));
+        // the end of the table overflows
+        write_u64(&mut bytes, 32, u64::MAX);
+        assert_eq!(validate(&bytes), Err(ElfError::InvalidProgramHeader));
+    }
+
+    #[test]
+    fn bad_phnum_is_invalid() {
+        let mut bytes = minimal_pie();
+        write_u16(&mut bytes, 56, 100);
+        assert_eq!(validate(&bytes), Err(ElfError::InvalidProgramHeader));
+        write_u16(&mut bytes, 56, 2);
+        write_u16(&mut bytes, 54, ELF64_PHDR_SIZE as u16 + 8);
+        assert_eq!(validate(&bytes), Err(ElfError::InvalidProgramHeader));
+    }
+
+    #[test]
+    fn segments_must_be_in_the_file() {
+        let mut bytes = minimal_pie();
+        write_phdr(&mut bytes, 0, PT_LOAD, 0, IMAGE_SIZE as u64 + 1, 0x2000);
+        assert_eq!(validate(&bytes), Err(ElfError::SegmentOutOfFile { index: 0 }));
+        write_phdr(&mut bytes, 0, PT_LOAD, 0, IMAGE_SIZE as u64, 0x10);
+        assert_eq!(validate(&bytes), Err(ElfError::SegmentSizeMismatch { index: 0 }));
+    }
+
+    #[test]
+    fn program_headers_are_read() {
+        let bytes = minimal_pie();
+        let headers: Vec<ProgramHeader> = program_headers(&bytes).collect();
+        assert_eq!(headers.len(), 2);
+        assert_eq!(headers[0].p_type, PT_LOAD);
+        assert_eq!((headers[0].p_offset, headers[0].p_filesz, headers[0].p_memsz), (0, IMAGE_SIZE as u64, 0x2000));
+        assert_eq!(headers[1].p_type, PT_DYNAMIC);
+        assert_eq!(headers[1].p_vaddr, DYNAMIC as u64);
+    }
+
+    #[test]
+    fn relative_relocations_are_found() {
+        assert_eq!(relocations(&minimal_pie()), Ok(vec![(0x1008, 0x200)]));
+    }
+
+    #[test]
+    fn no_dynamic_segment_has_no_relocations() {
+        let mut bytes = minimal_pie();
+        write_u16(&mut bytes, 56, 1);
+        assert_eq!(relocations(&bytes), Ok(vec![]));
+    }
+
+    #[test]
+    fn other_relocations_are_unsupported() {
+        let mut bytes = minimal_pie();
+        // R_X86_64_64
+        write_u64(&mut bytes, RELA + 8, 1);
+        assert_eq!(relocations(&bytes), Err(ElfError::UnsupportedRelocation(1)));
+    }
+
+    #[test]
+    fn relocations_out_of_the_file_are_invalid() {
+        let mut bytes = minimal_pie();
+        write_u64(&mut bytes, DYNAMIC + ELF64_DYN_SIZE + 8, 2 * ELF64_RELA_SIZE as u64);
+        assert_eq!(relocations(&bytes), Err(ElfError::InvalidDynamic));
+        let mut bytes = minimal_pie();
+        write_u64(&mut bytes, DYNAMIC + 2 * ELF64_DYN_SIZE + 8, 16);
+        assert_eq!(relocations(&bytes), Err(ElfError::InvalidDynamic));
+    }
+
+    #[test]
+    fn partial_relocation_is_invalid() {
+        let mut bytes = minimal_pie();
+        write_u64(&mut bytes, DYNAMIC + ELF64_DYN_SIZE + 8, ELF64_RELA_SIZE as u64 - 8);
+        assert_eq!(relocations(&bytes), Err(ElfError::InvalidDynamic));
+    }
+
+    #[test]
+    fn overflowing_sizes_are_invalid() {
+        let mut bytes = minimal_pie();
+        write_u64(&mut bytes, DYNAMIC + ELF64_DYN_SIZE + 8, u64::MAX - 7);
+        assert_eq!(relocations(&bytes), Err(ElfError::InvalidDynamic));
+        let mut bytes = minimal_pie();
+        write_phdr(&mut bytes, 1, PT_DYNAMIC, DYNAMIC as u64, u64::MAX, u64::MAX);
+        assert_eq!(relocations(&bytes), Err(ElfError::InvalidDynamic));
+    }
+
+    #[test]
+    fn load_bias_wraps_below_the_lowest_vaddr() {
+        let mut bytes = minimal_pie();
+        assert_eq!(load_bias(&bytes, 0x40_0123), 0x40_0000);
+        write_phdr(&mut bytes, 0, PT_LOAD, 0, IMAGE_SIZE as u64, 0x2000);
+        write_u64(&mut bytes, ELF64_HEADER_SIZE + 16, 0x1000);
+        assert_eq!(load_bias(&bytes, 0), 0u64.wrapping_sub(0x1000));
+        write_u16(&mut bytes, 16, ET_EXEC);
+        assert_eq!(load_bias(&bytes, 0x40_0000), 0);
+    }
+}
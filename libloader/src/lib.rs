@@ -1,5 +1,7 @@
 #![no_std]
 
+pub mod elf;
+
 use uefi::table::boot::{
     MemoryDescriptor,
     MemoryMapSize,
@@ -54,6 +56,76 @@ pub fn is_available(ty: MemoryType) -> bool {
     || ty == MemoryType::CONVENTIONAL
 }
 
+// ACPI_NON_VOLATILE must be preserved, so only ACPI_RECLAIM is reclaimable
+pub fn is_reclaimable(ty: MemoryType) -> bool {
+    ty == MemoryType::ACPI_RECLAIM
+}
+
+//Symbols
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct Symbol {
+    pub addr: u64,
+    // 0 when the size is unknown
+    pub size: u64,
+    // the range of the name in SymbolTable::names
+    pub name_offset: u32,
+    pub name_len: u32,
+}
+
+/// the function symbols of the kernel sorted by the address.
+/// the bootloader keeps them in LOADER_DATA, which the kernel never reuses
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct SymbolTable {
+    pub symbols: *const Symbol,
+    pub len: usize,
+    pub names: *const u8,
+    pub names_len: usize,
+}
+
+// the table is never written after the boot
+unsafe impl Send for SymbolTable {}
+unsafe impl Sync for SymbolTable {}
+
+impl SymbolTable {
+    pub const EMPTY: Self = Self {
+        symbols: core::ptr::null(),
+        len: 0,
+        names: core::ptr::null(),
+        names_len: 0,
+    };
+
+    pub fn symbols(&self) -> &[Symbol] {
+        if self.symbols.is_null() {
+            return &[];
+        }
+        unsafe { from_raw_parts(self.symbols, self.len) }
+    }
+
+    pub fn name(&self, symbol: &Symbol) -> Option<&str> {
+        if self.names.is_null() {
+            return None;
+        }
+        let names = unsafe { from_raw_parts(self.names, self.names_len) };
+        let start = symbol.name_offset as usize;
+        let bytes = names.get(start..start + symbol.name_len as usize)?;
+        return core::str::from_utf8(bytes).ok();
+    }
+
+    /// the symbol which contains the address, or the nearest one before it when the size is unknown
+    pub fn lookup(&self, addr: u64) -> Option<&Symbol> {
+        let symbols = self.symbols();
+        let index = symbols.partition_point(|symbol| symbol.addr <= addr);
+        let symbol = symbols.get(index.checked_sub(1)?)?;
+        // a return address may be just after the last call of a function which never returns
+        if symbol.size != 0 && symbol.addr + symbol.size < addr {
+            return None;
+        }
+        return Some(symbol);
+    }
+}
+
 //Graphics
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]